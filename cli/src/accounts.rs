@@ -1,21 +1,30 @@
-use anchor_lang::{AccountDeserialize, Discriminator};
+use std::sync::Arc;
+
 use anyhow::{anyhow, Error};
-use log::info;
+use clap::ValueEnum;
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
 use pye_core_cpi::pye_core::accounts::SoloValidatorBond as SoloValidatorPyeAccount;
 use solana_account_decoder_client_types::UiAccountEncoding;
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
 use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
-use solana_sdk::account::from_account;
+use solana_sdk::account::{from_account, Account, ReadableAccount};
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::stake::state::StakeStateV2;
 use solana_sdk::stake_history::StakeHistory;
 use solana_sdk::sysvar::{slot_history, stake_history};
+use tokio::sync::OnceCell;
+
+use crate::bond_schema::{decode_solo_validator_bond, known_discriminators, BondSchemaVersion};
+use crate::exit_code::rpc_error;
+use crate::metrics::datapoint_warn;
 
 pub async fn fetch_stake_history(client: &RpcClient) -> Result<StakeHistory, Error> {
     let account_data = client
         .get_account(&stake_history::ID)
         .await
-        .map_err(|e| anyhow!("Failed to fetch StakeHistory: {}", e))?;
+        .map_err(|e| rpc_error("Failed to fetch StakeHistory", e))?;
     let stake_history: StakeHistory = from_account::<StakeHistory, _>(&account_data)
         .ok_or_else(|| anyhow!("Failed to deserialize StakeHistory"))?;
     Ok(stake_history)
@@ -25,51 +34,330 @@ pub async fn fetch_slot_history(client: &RpcClient) -> Result<slot_history::Slot
     let account_data = client
         .get_account(&slot_history::ID)
         .await
-        .map_err(|e| anyhow!("Failed to fetch SlotHistory: {}", e))?;
+        .map_err(|e| rpc_error("Failed to fetch SlotHistory", e))?;
     let slot_history = from_account::<slot_history::SlotHistory, _>(&account_data)
         .ok_or_else(|| anyhow!("Failed to deserialize SlotHistory"))?;
     Ok(slot_history)
 }
 
+/// The StakeHistory and SlotHistory sysvars, each fetched at most once for the epoch being
+/// processed and shared by every stake account's activation and the block reward scan.
+#[derive(Debug, Default)]
+pub struct EpochSysvars {
+    stake_history: OnceCell<Arc<StakeHistory>>,
+    slot_history: OnceCell<Arc<slot_history::SlotHistory>>,
+}
+
+impl EpochSysvars {
+    pub async fn stake_history(&self, client: &RpcClient) -> Result<Arc<StakeHistory>, Error> {
+        self.stake_history
+            .get_or_try_init(|| async { fetch_stake_history(client).await.map(Arc::new) })
+            .await
+            .cloned()
+    }
+
+    pub async fn slot_history(
+        &self,
+        client: &RpcClient,
+    ) -> Result<Arc<slot_history::SlotHistory>, Error> {
+        self.slot_history
+            .get_or_try_init(|| async { fetch_slot_history(client).await.map(Arc::new) })
+            .await
+            .cloned()
+    }
+}
+
+/// Size of a stake account's data.
+const STAKE_ACCOUNT_SIZE: u64 = 200;
+/// Offset of the delegation's voter pubkey in a stake account's data.
+const STAKE_VOTER_OFFSET: usize = 124;
+
+/// Every stake account delegated to `vote_pubkey`, whether active, activating or deactivating.
+pub async fn fetch_delegated_stake_accounts(
+    client: &RpcClient,
+    vote_pubkey: &Pubkey,
+) -> Result<Vec<(Pubkey, Account)>, Error> {
+    let filters = vec![
+        RpcFilterType::DataSize(STAKE_ACCOUNT_SIZE),
+        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            STAKE_VOTER_OFFSET,
+            vote_pubkey.as_ref(),
+        )),
+    ];
+    get_program_accounts(client, &solana_sdk::stake::program::ID, filters)
+        .await
+        .map_err(|e| {
+            anyhow!(
+                "Failed to fetch the stake accounts of {}: {}",
+                vote_pubkey,
+                e
+            )
+        })
+}
+
+/// Deserializes a stake account's state. Anyone can create an account holding stake account
+/// data, so accounts not owned by the stake program are refused.
+pub fn stake_account_state(
+    stake_pubkey: &Pubkey,
+    account: &impl ReadableAccount,
+) -> Result<StakeStateV2, Error> {
+    if *account.owner() != solana_sdk::stake::program::ID {
+        return Err(anyhow!(
+            "Stake account {} is owned by {}, not the stake program",
+            stake_pubkey,
+            account.owner()
+        ));
+    }
+    bincode::deserialize(account.data()).map_err(|e| {
+        anyhow!(
+            "Failed to deserialize stake account {}: {}",
+            stake_pubkey,
+            e
+        )
+    })
+}
+
+/// Why a bond discovered as `discovered` no longer warrants payment, judging from its `account`
+/// as fetched right before the transfer (None once closed).
+pub fn bond_unpayable_reason(
+    program_id: &Pubkey,
+    vote_pubkey: &Pubkey,
+    discovered: &SoloValidatorPyeAccount,
+    account: Option<&Account>,
+) -> Option<String> {
+    let Some(account) = account.filter(|account| account.lamports > 0) else {
+        return Some("the bond account was closed".to_string());
+    };
+    if account.owner != *program_id {
+        return Some(format!(
+            "the bond account is now owned by {}",
+            account.owner
+        ));
+    }
+    let bond = match decode_solo_validator_bond(&account.data) {
+        Ok((_, bond)) => bond,
+        Err(e) => return Some(format!("the bond account no longer deserializes: {}", e)),
+    };
+    if bond.validator_vote_account != *vote_pubkey {
+        Some(format!(
+            "the bond now delegates to vote account {}",
+            bond.validator_vote_account
+        ))
+    } else if bond.stake_account != discovered.stake_account {
+        Some(format!(
+            "the bond's stake account changed to {}",
+            bond.stake_account
+        ))
+    } else if bond.stake_withdrawn {
+        Some("the bond's stake was withdrawn".to_string())
+    } else if bond.maturity_handled {
+        Some("the bond matured".to_string())
+    } else {
+        None
+    }
+}
+
+/// Fetches a SoloValidatorPyeAccount together with the program that owns it, which must be one
+/// of `program_ids`.
 pub async fn fetch_solo_validator_pye_account(
     client: &RpcClient,
+    program_ids: &[Pubkey],
     pye_account_pubkey: &Pubkey,
-) -> Result<SoloValidatorPyeAccount, Error> {
-    let account_data = client
-        .get_account_data(&pye_account_pubkey)
+) -> Result<(Pubkey, SoloValidatorPyeAccount), Error> {
+    let account = client
+        .get_account(&pye_account_pubkey)
         .await
-        .map_err(|e| anyhow!("Failed to fetch SoloValidatorPyeAccount: {}", e))?;
-    let pye_account = SoloValidatorPyeAccount::try_deserialize(&mut account_data.as_slice())
-        .map_err(|e| anyhow!("Failed to deserialize SoloValidatorPyeAccount: {}", e))?;
-    Ok(pye_account)
+        .map_err(|e| rpc_error("Failed to fetch SoloValidatorPyeAccount", e))?;
+    if !program_ids.contains(&account.owner) {
+        return Err(anyhow!(
+            "SoloValidatorPyeAccount {} is owned by {}, not the pye program {:?}",
+            pye_account_pubkey,
+            account.owner,
+            program_ids
+        ));
+    }
+    let (version, pye_account) = decode_solo_validator_bond(&account.data).map_err(|e| {
+        anyhow!(
+            "Failed to deserialize SoloValidatorPyeAccount {}: {}",
+            pye_account_pubkey,
+            e
+        )
+    })?;
+    if version != BondSchemaVersion::Current {
+        info!(
+            "Decoded SoloValidatorPyeAccount {} with {} schema",
+            pye_account_pubkey, version
+        );
+    }
+    Ok((account.owner, pye_account))
+}
+
+/// Offset of the first byte of `stake_account`. The stake account is a PDA of the bond, so its
+/// first byte is uniformly distributed and splits a fleet into even chunks.
+const CHUNK_PREFIX_OFFSET: usize = 40;
+
+/// How getProgramAccounts queries for bonds are split up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum GpaChunking {
+    /// Try a single query and fall back to chunked queries if it fails.
+    #[default]
+    Auto,
+    /// Always split queries into 256 chunks by stake account prefix.
+    Always,
+    /// Only ever send a single query.
+    Never,
+}
+
+/// Options for enumerating bonds with getProgramAccounts.
+#[derive(Clone, Copy, Debug)]
+pub struct PyeAccountFetchOptions {
+    /// Abort instead of skipping accounts that can't be deserialized.
+    pub strict: bool,
+    pub chunking: GpaChunking,
+    /// Maximum chunked queries to send concurrently.
+    pub concurrency: usize,
 }
 
-pub async fn fetch_active_solo_validator_pye_accounts_by_vote_key_and_issuer(
+/// Fetches bonds delegating to `vote_pubkey`, optionally restricted to a single issuer and to
+/// bonds whose maturity hasn't been handled yet.
+pub async fn fetch_solo_validator_pye_accounts_by_vote_key(
     client: &RpcClient,
     program_id: &Pubkey,
     vote_pubkey: &Pubkey,
-    issuer_pubkey: &Pubkey,
+    issuer_pubkey: Option<&Pubkey>,
+    active_only: bool,
+    options: &PyeAccountFetchOptions,
 ) -> Result<Vec<(Pubkey, SoloValidatorPyeAccount)>, Error> {
-    let discriminator_filter = RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
-        0,
-        SoloValidatorPyeAccount::DISCRIMINATOR,
-    ));
+    let mut accounts = Vec::new();
+    // One query per known layout, since gPA filters can only match a single discriminator.
+    for discriminator in known_discriminators() {
+        let filters = pye_accounts_filters(discriminator, vote_pubkey, issuer_pubkey, active_only);
+        accounts.extend(fetch_program_accounts(client, program_id, filters, options).await?);
+    }
+    match issuer_pubkey {
+        Some(issuer_pubkey) => info!(
+            "Fetched {} pye-accounts for issuer {} under program {}",
+            accounts.len(),
+            issuer_pubkey,
+            program_id
+        ),
+        None => info!(
+            "Fetched {} pye-accounts for vote account {} under program {}",
+            accounts.len(),
+            vote_pubkey,
+            program_id
+        ),
+    }
+
+    decode_pye_accounts(program_id, accounts, options.strict)
+}
+
+/// gPA filters matching bonds delegating to `vote_pubkey` with the `discriminator` layout.
+pub fn pye_accounts_filters(
+    discriminator: &[u8],
+    vote_pubkey: &Pubkey,
+    issuer_pubkey: Option<&Pubkey>,
+    active_only: bool,
+) -> Vec<RpcFilterType> {
+    let discriminator_filter = RpcFilterType::Memcmp(Memcmp::new_base58_encoded(0, discriminator));
     let vote_pubkey_filter = RpcFilterType::Memcmp(Memcmp::new(
         8,
         MemcmpEncodedBytes::Base58(vote_pubkey.to_string()),
     ));
-    let issuer_pubkey_filter = RpcFilterType::Memcmp(Memcmp::new(
-        240,
-        MemcmpEncodedBytes::Base58(issuer_pubkey.to_string()),
-    ));
-    let not_matured_filter = RpcFilterType::Memcmp(Memcmp::new_base58_encoded(185, &[0]));
+    let mut filters = vec![discriminator_filter, vote_pubkey_filter];
+    if active_only {
+        let not_matured_filter = RpcFilterType::Memcmp(Memcmp::new_base58_encoded(185, &[0]));
+        filters.push(not_matured_filter);
+    }
+    if let Some(issuer_pubkey) = issuer_pubkey {
+        let issuer_pubkey_filter = RpcFilterType::Memcmp(Memcmp::new(
+            240,
+            MemcmpEncodedBytes::Base58(issuer_pubkey.to_string()),
+        ));
+        filters.push(issuer_pubkey_filter);
+    }
+    filters
+}
+
+/// Adds a one byte stake account prefix filter to `filters` for each of the 256 chunks.
+fn chunk_filters(filters: &[RpcFilterType]) -> Vec<Vec<RpcFilterType>> {
+    (0..=u8::MAX)
+        .map(|prefix| {
+            let mut chunk = filters.to_vec();
+            chunk.push(RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                CHUNK_PREFIX_OFFSET,
+                &[prefix],
+            )));
+            chunk
+        })
+        .collect()
+}
+
+async fn fetch_program_accounts(
+    client: &RpcClient,
+    program_id: &Pubkey,
+    filters: Vec<RpcFilterType>,
+    options: &PyeAccountFetchOptions,
+) -> Result<Vec<(Pubkey, Account)>, Error> {
+    match options.chunking {
+        GpaChunking::Never => get_program_accounts(client, program_id, filters).await,
+        GpaChunking::Always => {
+            fetch_program_accounts_chunked(client, program_id, &filters, options.concurrency).await
+        }
+        GpaChunking::Auto => {
+            match get_program_accounts(client, program_id, filters.clone()).await {
+                Ok(accounts) => Ok(accounts),
+                Err(e) => {
+                    warn!("{}. Retrying in chunks", e);
+                    fetch_program_accounts_chunked(
+                        client,
+                        program_id,
+                        &filters,
+                        options.concurrency,
+                    )
+                    .await
+                }
+            }
+        }
+    }
+}
+
+async fn fetch_program_accounts_chunked(
+    client: &RpcClient,
+    program_id: &Pubkey,
+    filters: &[RpcFilterType],
+    concurrency: usize,
+) -> Result<Vec<(Pubkey, Account)>, Error> {
+    let chunks = chunk_filters(filters);
+    let total_chunks = chunks.len();
+    let mut fetched_chunks = 0;
+    let mut accounts = Vec::new();
+    let mut results = stream::iter(chunks)
+        .map(|chunk| get_program_accounts(client, program_id, chunk))
+        .buffer_unordered(concurrency.max(1));
+    while let Some(result) = results.next().await {
+        accounts.extend(result?);
+        fetched_chunks += 1;
+        if fetched_chunks % 32 == 0 || fetched_chunks == total_chunks {
+            info!(
+                "Fetched {}/{} chunks from program {} ({} accounts so far)",
+                fetched_chunks,
+                total_chunks,
+                program_id,
+                accounts.len()
+            );
+        }
+    }
+    Ok(accounts)
+}
+
+async fn get_program_accounts(
+    client: &RpcClient,
+    program_id: &Pubkey,
+    filters: Vec<RpcFilterType>,
+) -> Result<Vec<(Pubkey, Account)>, Error> {
     let config = RpcProgramAccountsConfig {
-        filters: Some(vec![
-            discriminator_filter,
-            vote_pubkey_filter,
-            not_matured_filter,
-            issuer_pubkey_filter,
-        ]),
+        filters: Some(filters),
         account_config: RpcAccountInfoConfig {
             encoding: Some(UiAccountEncoding::Base64Zstd),
             data_slice: None,
@@ -79,22 +367,175 @@ pub async fn fetch_active_solo_validator_pye_accounts_by_vote_key_and_issuer(
         with_context: None,
         sort_results: None,
     };
-    let accounts = client
+    client
         .get_program_accounts_with_config(program_id, config)
         .await
-        .map_err(|e| anyhow!("Failed to fetch SoloValidatorPyeAccount: {}", e))?;
-    info!(
-        "Fetched {} active pye-accounts for issuer {}",
-        accounts.len(),
-        issuer_pubkey
-    );
-
-    Ok(accounts
-        .into_iter()
-        .map(|(pubkey, account)| {
-            let mut data: &[u8] = &account.data;
-            let pye_account = SoloValidatorPyeAccount::try_deserialize(&mut data).unwrap();
-            (pubkey, pye_account)
-        })
-        .collect())
+        .map_err(|e| rpc_error("Failed to fetch SoloValidatorPyeAccount", e))
+}
+
+/// Deserializes fetched program accounts. Accounts that fail to decode or aren't owned by
+/// `program_id`, i.e. merely match the filters, are logged and skipped so one malformed account
+/// can't halt processing of the rest, unless `strict` is set.
+pub fn decode_pye_accounts(
+    program_id: &Pubkey,
+    accounts: Vec<(Pubkey, Account)>,
+    strict: bool,
+) -> Result<Vec<(Pubkey, SoloValidatorPyeAccount)>, Error> {
+    let mut pye_accounts = Vec::with_capacity(accounts.len());
+    for (pubkey, account) in accounts {
+        if account.owner != *program_id {
+            if strict {
+                return Err(anyhow!(
+                    "SoloValidatorPyeAccount {} is owned by {}, not program {}",
+                    pubkey,
+                    account.owner,
+                    program_id
+                ));
+            }
+            warn!(
+                "Skipping SoloValidatorPyeAccount {} owned by {}, not program {}",
+                pubkey, account.owner, program_id
+            );
+            datapoint_warn!(
+                "spoofed_pye_account",
+                ("program_id", program_id.to_string(), String),
+                ("pye_account", pubkey.to_string(), String),
+                ("owner", account.owner.to_string(), String),
+            );
+            continue;
+        }
+        match decode_solo_validator_bond(&account.data) {
+            Ok((BondSchemaVersion::Current, pye_account)) => {
+                pye_accounts.push((pubkey, pye_account))
+            }
+            Ok((version, pye_account)) => {
+                info!(
+                    "Decoded SoloValidatorPyeAccount {} with {} schema",
+                    pubkey, version
+                );
+                pye_accounts.push((pubkey, pye_account));
+            }
+            Err(e) if strict => {
+                return Err(anyhow!(
+                    "Failed to deserialize SoloValidatorPyeAccount {}: {}",
+                    pubkey,
+                    e
+                ))
+            }
+            Err(e) => {
+                warn!(
+                    "Skipping SoloValidatorPyeAccount {} that failed to deserialize: {}",
+                    pubkey, e
+                );
+                datapoint_warn!(
+                    "corrupt_pye_account",
+                    ("program_id", program_id.to_string(), String),
+                    ("pye_account", pubkey.to_string(), String),
+                    ("data_len", account.data.len() as i64, i64),
+                    ("error", e.to_string(), String),
+                );
+            }
+        }
+    }
+    Ok(pye_accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn garbage_account(owner: &Pubkey) -> (Pubkey, Account) {
+        let account = Account {
+            data: vec![0xff; 64],
+            owner: *owner,
+            ..Account::default()
+        };
+        (Pubkey::new_unique(), account)
+    }
+
+    #[test]
+    fn test_chunk_filters_cover_every_prefix() {
+        let chunks = chunk_filters(&[]);
+        assert_eq!(chunks.len(), 256);
+        assert!(chunks.iter().all(|chunk| chunk.len() == 1));
+    }
+
+    #[test]
+    fn test_decode_skips_corrupt_accounts() {
+        let program_id = Pubkey::new_unique();
+        let decoded = decode_pye_accounts(&program_id, vec![garbage_account(&program_id)], false);
+        assert!(decoded.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decode_strict_fails_on_corrupt_account() {
+        let program_id = Pubkey::new_unique();
+        let decoded = decode_pye_accounts(&program_id, vec![garbage_account(&program_id)], true);
+        assert!(decoded.is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_accounts_of_other_programs() {
+        let program_id = Pubkey::new_unique();
+        let mut data = vec![0u8; crate::bond_schema::BOND_LEN];
+        data[..8].copy_from_slice(known_discriminators()[0]);
+        let spoofed = Account {
+            data,
+            owner: Pubkey::new_unique(),
+            ..Account::default()
+        };
+        let accounts = vec![(Pubkey::new_unique(), spoofed)];
+        assert!(decode_pye_accounts(&program_id, accounts.clone(), false)
+            .unwrap()
+            .is_empty());
+        assert!(decode_pye_accounts(&program_id, accounts, true).is_err());
+    }
+
+    #[test]
+    fn test_bond_unpayable_reason() {
+        use anchor_lang::AccountSerialize;
+
+        let (program_id, vote_pubkey) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut discovered = crate::bond_schema::zeroed_bond();
+        discovered.validator_vote_account = vote_pubkey;
+        discovered.stake_account = Pubkey::new_unique();
+        let account = |bond: &SoloValidatorPyeAccount| {
+            let mut data = Vec::new();
+            bond.try_serialize(&mut data).unwrap();
+            Account {
+                lamports: 1_000_000,
+                data,
+                owner: program_id,
+                ..Account::default()
+            }
+        };
+        let reason = |bond: &SoloValidatorPyeAccount| {
+            bond_unpayable_reason(&program_id, &vote_pubkey, &discovered, Some(&account(bond)))
+        };
+
+        assert_eq!(reason(&discovered), None);
+        let mut matured = discovered.clone();
+        matured.maturity_handled = true;
+        assert_eq!(reason(&matured).as_deref(), Some("the bond matured"));
+        let mut redelegated = discovered.clone();
+        redelegated.validator_vote_account = Pubkey::new_unique();
+        assert!(reason(&redelegated).unwrap().contains("vote account"));
+        assert_eq!(
+            bond_unpayable_reason(&program_id, &vote_pubkey, &discovered, None).as_deref(),
+            Some("the bond account was closed")
+        );
+    }
+
+    #[test]
+    fn test_stake_account_state_requires_stake_program() {
+        let stake_pubkey = Pubkey::new_unique();
+        let mut account =
+            Account::new_data(1, &StakeStateV2::Uninitialized, &Pubkey::new_unique()).unwrap();
+        assert!(stake_account_state(&stake_pubkey, &account).is_err());
+        account.owner = solana_sdk::stake::program::ID;
+        assert_eq!(
+            stake_account_state(&stake_pubkey, &account).unwrap(),
+            StakeStateV2::Uninitialized
+        );
+    }
 }
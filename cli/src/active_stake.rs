@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use anyhow::{anyhow, Result};
 use log::{info, warn};
 use regex::Regex;
@@ -5,10 +7,19 @@ use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_client::rpc_request::RpcError;
 use solana_client::rpc_response::StakeActivationState;
 use solana_sdk::account::{Account, ReadableAccount};
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::stake::state::StakeStateV2;
+use solana_sdk::stake_history::StakeHistory;
 use solana_stake_program::stake_state::StakeActivationStatus;
 
+use crate::accounts::{fetch_delegated_stake_accounts, stake_account_state, EpochSysvars};
+use crate::exit_code::rpc_error;
+use crate::metrics::datapoint_error;
+use crate::rewards::inflation_model::LocalInflationModel;
+use crate::rewards::inflation_rewards::InflationRewardCache;
+use crate::sol_format::format_amount;
+
 #[derive(Debug)]
 pub struct StakeActivation {
     pub state: StakeActivationState,
@@ -16,10 +27,10 @@ pub struct StakeActivation {
     pub inactive: u64,
 }
 
-async fn fetch_stake_for_epoch(
-    client: &RpcClient,
+fn fetch_stake_for_epoch(
     stake_account: &Account,
     stake_state: &StakeStateV2,
+    stake_history: &StakeHistory,
     target_epoch: u64,
 ) -> Result<StakeActivation> {
     let delegation = stake_state
@@ -29,12 +40,11 @@ async fn fetch_stake_for_epoch(
         .meta()
         .ok_or(anyhow!("No rent exempt reserve data for stake found"))?
         .rent_exempt_reserve;
-    let stake_history = crate::accounts::fetch_stake_history(client).await?;
     let StakeActivationStatus {
         effective,
         activating,
         deactivating,
-    } = delegation.stake_activating_and_deactivating(target_epoch, &stake_history, None);
+    } = delegation.stake_activating_and_deactivating(target_epoch, stake_history, None);
     let stake_activation_state = if deactivating > 0 {
         StakeActivationState::Deactivating
     } else if activating > 0 {
@@ -58,62 +68,56 @@ async fn fetch_stake_for_epoch(
 
 pub async fn fetch_pye_account_active_stake(
     client: &RpcClient,
+    sysvars: &EpochSysvars,
+    inflation_rewards: &InflationRewardCache,
     stake_account_key: &Pubkey,
     transient_stake_account_key: &Pubkey,
+    linked_stake_accounts: &[Pubkey],
+    redelegated: &HashSet<Pubkey>,
     target_epoch: u64,
     current_epoch: u64,
 ) -> Result<u64> {
     if target_epoch != current_epoch - 1 {
         return Err(anyhow!("Unsupported target epoch delta"));
     }
-    let maybe_stake_account = &client.get_account(stake_account_key).await;
-    let stake_account = match maybe_stake_account {
+    let stake_account = &match client.get_account(stake_account_key).await {
         Ok(account) => account,
-        Err(err) => match &err.kind {
-            solana_client::client_error::ClientErrorKind::RpcError(rpc_error) => match rpc_error {
-                RpcError::RpcResponseError {
-                    code: _,
-                    message,
-                    data: _,
-                } => {
-                    // SoloValidatorPyeAccount's initialize stake accounts on the first deposit. So in
-                    // the case where a pye-account was created, but no deposits were made, the RPC will
-                    // error with account not found. In this case, we short circuit and return 0
-                    // as the active stake.
-                    let account_not_found_match = Regex::new(r"^AccountNotFound").unwrap();
-                    if account_not_found_match.is_match(&message) {
-                        warn!("Fetch stake account {} error {:?}", stake_account_key, err);
-                        return Ok(0);
-                    } else {
-                        return Err(anyhow!("Failed to fetch StakeAccount: {}", err));
-                    }
-                }
-                _ => {
-                    return Err(anyhow!("Failed to fetch StakeAccount: {}", err));
-                }
-            },
-            _ => {
-                return Err(anyhow!("Failed to fetch StakeAccount: {}", err));
+        Err(err) => {
+            // SoloValidatorPyeAccount's initialize stake accounts on the first deposit. So in
+            // the case where a pye-account was created, but no deposits were made, the RPC will
+            // error with account not found. In this case, we short circuit and return 0
+            // as the active stake.
+            let account_not_found = matches!(
+                &err.kind,
+                solana_client::client_error::ClientErrorKind::RpcError(
+                    RpcError::RpcResponseError { message, .. }
+                ) if Regex::new(r"^AccountNotFound").unwrap().is_match(message)
+            );
+            if account_not_found {
+                warn!("Fetch stake account {} error {:?}", stake_account_key, err);
+                return Ok(0);
             }
-        },
+            return Err(rpc_error("Failed to fetch StakeAccount", err));
+        }
     };
-    let stake_state = &stake_account.deserialize_data::<StakeStateV2>()?;
+    let stake_state = &stake_account_state(stake_account_key, stake_account)?;
+    let stake_history = sysvars.stake_history(client).await?;
     // Fetch inflation rewards for the target epoch
-    let maybe_inflation_rewards = &client
-        .get_inflation_reward(&[*stake_account_key], Some(target_epoch))
-        .await?;
-    let (inflation_rewards, post_balance) = maybe_inflation_rewards[0]
-        .as_ref()
+    let (inflation_reward, post_balance) = inflation_rewards
+        .get(client, stake_account_key)
+        .await?
         .map(|x| (x.amount, x.post_balance))
         .unwrap_or((0, 0));
     let active_stake_for_current_epoch =
-        fetch_stake_for_epoch(client, stake_account, stake_state, target_epoch).await?;
+        fetch_stake_for_epoch(stake_account, stake_state, &stake_history, target_epoch)?;
     info!(
         "Current Stake Account: {:?}",
         active_stake_for_current_epoch
     );
-    let mut pye_account_active_stake = if active_stake_for_current_epoch.active >= inflation_rewards {
-        active_stake_for_current_epoch.active - inflation_rewards
+    let mut pye_account_active_stake = if redelegated.contains(stake_account_key) {
+        0
+    } else if active_stake_for_current_epoch.active >= inflation_reward {
+        active_stake_for_current_epoch.active - inflation_reward
     } else {
         //  If the account was decativated or merged in, then the current active amount can be 0.
         //  This is used to determine the base MEV earned by the pye_account (since we only have total
@@ -121,30 +125,34 @@ pub async fn fetch_pye_account_active_stake(
         //  because it includes additional lamports than it makes
         //.    A) makes base and expected MEV look higher, which should be proportional and net out.
         //.    B) makes expected block rewards highe, which is in favor of the stakers
-        post_balance - inflation_rewards
+        post_balance - inflation_reward
     };
     info!(
         "Active stake for epoch {}: {}",
         target_epoch, pye_account_active_stake
     );
 
-    if !transient_stake_account_key.eq(&Pubkey::default()) {
+    if !transient_stake_account_key.eq(&Pubkey::default())
+        && !redelegated.contains(transient_stake_account_key)
+    {
         let transient_account = &client
             .get_account(&transient_stake_account_key)
             .await
-            .map_err(|e| anyhow!("Failed to fetch Transient StakeAccount: {}", e))?;
-        let transient_state = &transient_account.deserialize_data::<StakeStateV2>()?;
-        let transient_amount =
-            fetch_stake_for_epoch(client, transient_account, transient_state, target_epoch).await?;
-        let maybe_inflation_rewards = &client
-            .get_inflation_reward(&[*stake_account_key], Some(target_epoch))
-            .await?;
-        let (inflation_rewards, post_balance) = maybe_inflation_rewards[0]
-            .as_ref()
+            .map_err(|e| rpc_error("Failed to fetch Transient StakeAccount", e))?;
+        let transient_state = &stake_account_state(transient_stake_account_key, transient_account)?;
+        let transient_amount = fetch_stake_for_epoch(
+            transient_account,
+            transient_state,
+            &stake_history,
+            target_epoch,
+        )?;
+        let (inflation_reward, post_balance) = inflation_rewards
+            .get(client, stake_account_key)
+            .await?
             .map(|x| (x.amount, x.post_balance))
             .unwrap_or((0, 0));
-        let transient_stake_at_target_epoch = if transient_amount.active >= inflation_rewards {
-            transient_amount.active - inflation_rewards
+        let transient_stake_at_target_epoch = if transient_amount.active >= inflation_reward {
+            transient_amount.active - inflation_reward
         } else {
             //  If the account was decativated or merged in, then the current active amount can be 0.
             //  This is used to determine the base MEV earned by the pye_account (since we only have total
@@ -152,7 +160,7 @@ pub async fn fetch_pye_account_active_stake(
             //  because it includes additional lamports than it makes
             //.    A) makes base and expected MEV look higher, which should be proportional and net out.
             //.    B) makes expected block rewards highe, which is in favor of the stakers
-            post_balance - inflation_rewards
+            post_balance - inflation_reward
         };
         info!(
             "Transient Stake Account: {:?}",
@@ -165,6 +173,313 @@ pub async fn fetch_pye_account_active_stake(
         pye_account_active_stake += transient_stake_at_target_epoch;
     }
 
+    for linked_key in linked_stake_accounts
+        .iter()
+        .filter(|key| !redelegated.contains(key))
+    {
+        let linked_stake = fetch_linked_active_stake(
+            client,
+            &stake_history,
+            inflation_rewards,
+            linked_key,
+            target_epoch,
+        )
+        .await?;
+        info!(
+            "Linked stake account {} active stake for epoch {}: {}",
+            linked_key, target_epoch, linked_stake
+        );
+        pye_account_active_stake += linked_stake;
+    }
+
     info!("Total pye-account Active Stake: {}\n", pye_account_active_stake);
     Ok(pye_account_active_stake)
 }
+
+/// The vote account a stake account was delegated to instead of `vote_pubkey` during
+/// `target_epoch`, if any. A delegation to another validator that only activates after
+/// `target_epoch` replaced the one in force then, so the account isn't reported.
+pub fn delegated_elsewhere(
+    stake_state: &StakeStateV2,
+    vote_pubkey: &Pubkey,
+    stake_history: &StakeHistory,
+    target_epoch: u64,
+) -> Option<Pubkey> {
+    let delegation = stake_state.delegation()?;
+    if delegation.voter_pubkey == *vote_pubkey {
+        return None;
+    }
+    let StakeActivationStatus {
+        effective,
+        activating,
+        deactivating,
+    } = delegation.stake_activating_and_deactivating(target_epoch, stake_history, None);
+    (effective > 0 || activating > 0 || deactivating > 0).then_some(delegation.voter_pubkey)
+}
+
+/// Checks that each of a bond's stake accounts was delegated to `vote_pubkey` during
+/// `target_epoch`, returning the ones whose stake was with another validator then. Their stake and
+/// rewards aren't attributed to the validator, and each is reported with a warning and a
+/// `stake_redelegated` datapoint. Accounts that no longer exist or aren't delegated are left to
+/// the stake calculation.
+pub async fn check_delegations(
+    client: &RpcClient,
+    sysvars: &EpochSysvars,
+    stake_accounts: &[Pubkey],
+    vote_pubkey: &Pubkey,
+    target_epoch: u64,
+) -> Result<HashSet<Pubkey>> {
+    let accounts = client
+        .get_multiple_accounts(stake_accounts)
+        .await
+        .map_err(|e| rpc_error("Failed to fetch stake accounts", e))?;
+    let stake_history = sysvars.stake_history(client).await?;
+    let mut redelegated = HashSet::new();
+    for (stake_pubkey, account) in stake_accounts.iter().zip(accounts) {
+        let Some(stake_state) = account.and_then(|a| stake_account_state(stake_pubkey, &a).ok())
+        else {
+            continue;
+        };
+        let Some(voter) =
+            delegated_elsewhere(&stake_state, vote_pubkey, &stake_history, target_epoch)
+        else {
+            continue;
+        };
+        let activation_epoch = stake_state
+            .delegation()
+            .map_or(0, |delegation| delegation.activation_epoch);
+        warn!(
+            "Stake account {} is delegated to {} (activated in epoch {}), not {}. Excluding it from epoch {}",
+            stake_pubkey, voter, activation_epoch, vote_pubkey, target_epoch
+        );
+        datapoint_error!(
+            "stake_redelegated",
+            ("vote_pubkey", vote_pubkey.to_string(), String),
+            ("epoch", target_epoch.to_string(), String),
+            ("stake_account", stake_pubkey.to_string(), String),
+            ("delegated_to", voter.to_string(), String),
+            ("activation_epoch", activation_epoch as i64, i64),
+        );
+        redelegated.insert(*stake_pubkey);
+    }
+    Ok(redelegated)
+}
+
+/// The active stake at `target_epoch` of a stake account linked to the bond by its history, net
+/// of the inflation reward credited since. Accounts that no longer exist were merged into another
+/// account, which carries their stake, and count as zero.
+async fn fetch_linked_active_stake(
+    client: &RpcClient,
+    stake_history: &StakeHistory,
+    inflation_rewards: &InflationRewardCache,
+    stake_account_key: &Pubkey,
+    target_epoch: u64,
+) -> Result<u64> {
+    let Some(account) = client
+        .get_account_with_commitment(stake_account_key, CommitmentConfig::confirmed())
+        .await?
+        .value
+    else {
+        return Ok(0);
+    };
+    let Ok(stake_state) = stake_account_state(stake_account_key, &account) else {
+        return Ok(0);
+    };
+    if stake_state.delegation().is_none() {
+        return Ok(0);
+    }
+    let activation = fetch_stake_for_epoch(&account, &stake_state, stake_history, target_epoch)?;
+    let inflation_reward = inflation_rewards
+        .get(client, stake_account_key)
+        .await?
+        .map_or(0, |reward| reward.amount);
+    Ok(activation.active.saturating_sub(inflation_reward))
+}
+
+/// The effective stake at `target_epoch` of a stake account, zero once it no longer exists or
+/// isn't delegated.
+pub async fn fetch_effective_stake(
+    client: &RpcClient,
+    sysvars: &EpochSysvars,
+    stake_account_key: &Pubkey,
+    target_epoch: u64,
+) -> Result<u64> {
+    let Some(account) = client
+        .get_account_with_commitment(stake_account_key, CommitmentConfig::confirmed())
+        .await
+        .map_err(|e| rpc_error("Failed to fetch stake account", e))?
+        .value
+    else {
+        return Ok(0);
+    };
+    let Ok(stake_state) = stake_account_state(stake_account_key, &account) else {
+        return Ok(0);
+    };
+    let Some(delegation) = stake_state.delegation() else {
+        return Ok(0);
+    };
+    let stake_history = sysvars.stake_history(client).await?;
+    Ok(delegation
+        .stake_activating_and_deactivating(target_epoch, &stake_history, None)
+        .effective)
+}
+
+/// Sums the effective stake at `target_epoch` of the accounts delegated to `vote_pubkey`, each
+/// passed through `earning_stake` to take out the rewards credited since.
+fn delegated_stake_at_epoch(
+    accounts: &[(Pubkey, Account)],
+    vote_pubkey: &Pubkey,
+    stake_history: &StakeHistory,
+    target_epoch: u64,
+    earning_stake: impl Fn(u64) -> u64,
+) -> u64 {
+    accounts
+        .iter()
+        .filter_map(|(pubkey, account)| stake_account_state(pubkey, account).ok())
+        .filter_map(|stake_state| stake_state.delegation())
+        .filter(|delegation| delegation.voter_pubkey == *vote_pubkey)
+        .map(|delegation| {
+            let effective = delegation
+                .stake_activating_and_deactivating(target_epoch, stake_history, None)
+                .effective;
+            earning_stake(effective)
+        })
+        .fold(0u64, u64::saturating_add)
+}
+
+/// The validator's active stake at `target_epoch`, computed from every stake account delegated
+/// to it rather than taken from the Jito API. The inflation rewards credited when the epoch ended
+/// are taken out using the local inflation model, or left in if it can't be loaded.
+pub async fn fetch_validator_active_stake(
+    client: &RpcClient,
+    sysvars: &EpochSysvars,
+    vote_pubkey: &Pubkey,
+    target_epoch: u64,
+) -> Result<u64> {
+    let accounts = fetch_delegated_stake_accounts(client, vote_pubkey).await?;
+    let stake_history = sysvars.stake_history(client).await?;
+    let model = LocalInflationModel::load(
+        client,
+        sysvars,
+        vote_pubkey,
+        target_epoch,
+        target_epoch + 1,
+        None,
+    )
+    .await
+    .map_err(|e| {
+        warn!(
+            "{}. The validator's active stake includes the epoch's inflation rewards",
+            e
+        )
+    })
+    .ok();
+    let active_stake = delegated_stake_at_epoch(
+        &accounts,
+        vote_pubkey,
+        &stake_history,
+        target_epoch,
+        |effective| match &model {
+            Some(model) => model.earning_stake(effective),
+            None => effective,
+        },
+    );
+    info!(
+        "Validator active stake for epoch {}: {} over {} stake accounts",
+        target_epoch,
+        format_amount(active_stake),
+        accounts.len()
+    );
+    Ok(active_stake)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::stake::state::{Delegation, Meta, Stake, StakeFlags};
+
+    fn delegated(voter: &Pubkey, stake: u64, activation_epoch: u64) -> (Pubkey, Account) {
+        let state = StakeStateV2::Stake(
+            Meta::default(),
+            Stake {
+                delegation: Delegation::new(voter, stake, activation_epoch),
+                credits_observed: 0,
+            },
+            StakeFlags::empty(),
+        );
+        let account = Account {
+            lamports: stake,
+            data: bincode::serialize(&state).unwrap(),
+            owner: solana_sdk::stake::program::ID,
+            executable: false,
+            rent_epoch: 0,
+        };
+        (Pubkey::new_unique(), account)
+    }
+
+    #[test]
+    fn test_delegated_elsewhere() {
+        let vote_pubkey = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let stake_history = StakeHistory::default();
+        let state = |voter: &Pubkey, activation_epoch: u64| {
+            let (_, account) = delegated(voter, 1_000, activation_epoch);
+            account.deserialize_data::<StakeStateV2>().unwrap()
+        };
+        assert_eq!(
+            delegated_elsewhere(&state(&vote_pubkey, 5), &vote_pubkey, &stake_history, 10),
+            None
+        );
+        assert_eq!(
+            delegated_elsewhere(&state(&other, 5), &vote_pubkey, &stake_history, 10),
+            Some(other)
+        );
+        // Activating elsewhere during the target epoch.
+        assert_eq!(
+            delegated_elsewhere(&state(&other, 10), &vote_pubkey, &stake_history, 10),
+            Some(other)
+        );
+        assert_eq!(
+            delegated_elsewhere(
+                &StakeStateV2::Uninitialized,
+                &vote_pubkey,
+                &stake_history,
+                10
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_redelegated_after_target_epoch_is_kept() {
+        let vote_pubkey = Pubkey::new_unique();
+        let (_, account) = delegated(&Pubkey::new_unique(), 1_000, 12);
+        let stake_state = account.deserialize_data::<StakeStateV2>().unwrap();
+        assert_eq!(
+            delegated_elsewhere(&stake_state, &vote_pubkey, &StakeHistory::default(), 10),
+            None
+        );
+    }
+
+    #[test]
+    fn test_delegated_stake_at_epoch() {
+        let vote_pubkey = Pubkey::new_unique();
+        let accounts = vec![
+            delegated(&vote_pubkey, 1_000, 5),
+            delegated(&vote_pubkey, 2_000, 5),
+            // Delegated to another validator.
+            delegated(&Pubkey::new_unique(), 4_000, 5),
+            // Activated after the target epoch.
+            delegated(&vote_pubkey, 8_000, 20),
+        ];
+        let stake_history = StakeHistory::default();
+        assert_eq!(
+            delegated_stake_at_epoch(&accounts, &vote_pubkey, &stake_history, 10, |s| s),
+            3_000
+        );
+        assert_eq!(
+            delegated_stake_at_epoch(&accounts, &vote_pubkey, &stake_history, 10, |s| s - 10),
+            2_980
+        );
+    }
+}
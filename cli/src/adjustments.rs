@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+/// Header of an adjustments file, optional.
+const HEADER: &str = "pye_account,epoch,delta_lamports,reason";
+
+/// A manual correction to the excess rewards paid to a bond for an epoch, e.g. a negotiated
+/// settlement or a disputed epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Adjustment {
+    pub delta_lamports: i64,
+    pub reason: String,
+}
+
+/// Sum of the adjustments' deltas.
+pub fn adjustments_total(adjustments: &[Adjustment]) -> i64 {
+    adjustments
+        .iter()
+        .map(|adjustment| adjustment.delta_lamports)
+        .sum()
+}
+
+/// The adjustments of an adjustments file, by bond and epoch.
+#[derive(Debug, Default)]
+pub struct Adjustments {
+    by_bond: HashMap<(Pubkey, u64), Vec<Adjustment>>,
+}
+
+impl Adjustments {
+    /// Reads a CSV file of `pye_account,epoch,delta_lamports,reason` rows. The reason is the rest
+    /// of the line, so it may contain commas. Blank lines and lines starting with `#` are skipped.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&contents)
+            .map_err(|e| anyhow!("Invalid adjustments file {}: {}", path.display(), e))
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mut adjustments = Self::default();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line == HEADER {
+                continue;
+            }
+            let invalid = |what: &str| anyhow!("line {}: {}", index + 1, what);
+            let fields: Vec<&str> = line.splitn(4, ',').map(str::trim).collect();
+            let [pye_account, epoch, delta_lamports, reason] = fields[..] else {
+                return Err(invalid("expected pye_account,epoch,delta_lamports,reason"));
+            };
+            let pye_account = Pubkey::from_str(pye_account)
+                .map_err(|e| invalid(&format!("invalid pye_account {}: {}", pye_account, e)))?;
+            let epoch = epoch
+                .parse()
+                .map_err(|e| invalid(&format!("invalid epoch {}: {}", epoch, e)))?;
+            let delta_lamports = delta_lamports.parse().map_err(|e| {
+                invalid(&format!("invalid delta_lamports {}: {}", delta_lamports, e))
+            })?;
+            if reason.is_empty() {
+                return Err(invalid("every adjustment needs a reason"));
+            }
+            adjustments
+                .by_bond
+                .entry((pye_account, epoch))
+                .or_default()
+                .push(Adjustment {
+                    delta_lamports,
+                    reason: reason.to_string(),
+                });
+        }
+        Ok(adjustments)
+    }
+
+    /// The adjustments of the bond for `epoch`, in file order.
+    pub fn get(&self, pye_account: &Pubkey, epoch: u64) -> &[Adjustment] {
+        self.by_bond
+            .get(&(*pye_account, epoch))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let bond = Pubkey::new_unique();
+        let contents = format!(
+            "{}\n# Negotiated with the issuer\n{},800,-5000,overpaid in epoch 799, see ticket 12\n\n{},800,2000,late MEV\n",
+            HEADER, bond, bond
+        );
+        let adjustments = Adjustments::parse(&contents).unwrap();
+        let bond_adjustments = adjustments.get(&bond, 800);
+        assert_eq!(bond_adjustments.len(), 2);
+        assert_eq!(
+            bond_adjustments[0].reason,
+            "overpaid in epoch 799, see ticket 12"
+        );
+        assert_eq!(adjustments_total(bond_adjustments), -3000);
+        assert!(adjustments.get(&bond, 801).is_empty());
+
+        assert!(Adjustments::parse(&format!("{},800,100,", bond)).is_err());
+        assert!(Adjustments::parse(&format!("{},800,1.5,rounding", bond)).is_err());
+        assert!(Adjustments::parse("not-a-pubkey,800,100,reason").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+
+use clap::Args;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::dashboard::RewardBreakdown;
+use crate::sol_format::format_amount;
+
+/// Thresholds on the computed excess rewards that raise a warning before anything is paid.
+#[derive(Clone, Debug, Default, Args)]
+pub struct AlertThresholdArgs {
+    /// Warn when a bond's excess rewards for an epoch exceed this many lamports.
+    #[arg(long, env)]
+    pub alert_bond_excess_lamports: Option<u64>,
+    /// Warn when the excess rewards owed to all bonds for an epoch exceed this many lamports.
+    #[arg(long, env)]
+    pub alert_epoch_liability_lamports: Option<u64>,
+    /// Warn when a bond's excess MEV rewards are negative by more than this many lamports,
+    /// which usually means the MEV commission or tips were misread.
+    #[arg(long, env)]
+    pub alert_negative_mev_lamports: Option<u64>,
+    /// Pause payments through the control API when a threshold is crossed, until approved with
+    /// `manager-ctl resume`. Paying the epoch then goes through the control API, hence the token.
+    #[arg(long, env, requires = "grpc_token")]
+    pub pause_on_alert: bool,
+}
+
+/// A threshold crossed by an epoch's computed excess rewards.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ThresholdBreach {
+    BondExcess { pye_account: Pubkey, lamports: i64 },
+    EpochLiability { lamports: i64 },
+    NegativeMev { pye_account: Pubkey, lamports: i64 },
+}
+
+impl fmt::Display for ThresholdBreach {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThresholdBreach::BondExcess {
+                pye_account,
+                lamports,
+            } => write!(
+                f,
+                "Excess rewards of {} for {} exceed --alert-bond-excess-lamports",
+                format_amount(*lamports),
+                pye_account
+            ),
+            ThresholdBreach::EpochLiability { lamports } => write!(
+                f,
+                "Excess rewards of {} owed in total exceed --alert-epoch-liability-lamports",
+                format_amount(*lamports)
+            ),
+            ThresholdBreach::NegativeMev {
+                pye_account,
+                lamports,
+            } => write!(
+                f,
+                "Excess MEV rewards of {} for {} are below -(--alert-negative-mev-lamports)",
+                format_amount(*lamports),
+                pye_account
+            ),
+        }
+    }
+}
+
+impl ThresholdBreach {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThresholdBreach::BondExcess { .. } => "bond_excess",
+            ThresholdBreach::EpochLiability { .. } => "epoch_liability",
+            ThresholdBreach::NegativeMev { .. } => "negative_mev",
+        }
+    }
+}
+
+impl AlertThresholdArgs {
+    /// The thresholds crossed by the excess rewards computed for an epoch's bonds.
+    pub fn breaches<'a>(
+        &self,
+        bonds: impl IntoIterator<Item = (&'a Pubkey, &'a RewardBreakdown)>,
+    ) -> Vec<ThresholdBreach> {
+        let mut breaches = Vec::new();
+        let mut liability: i64 = 0;
+        for (pye_account, rewards) in bonds {
+            let total = rewards.total();
+            liability += total.max(0);
+            if self
+                .alert_bond_excess_lamports
+                .is_some_and(|threshold| total > threshold as i64)
+            {
+                breaches.push(ThresholdBreach::BondExcess {
+                    pye_account: *pye_account,
+                    lamports: total,
+                });
+            }
+            if self
+                .alert_negative_mev_lamports
+                .is_some_and(|threshold| rewards.mev < -(threshold as i64))
+            {
+                breaches.push(ThresholdBreach::NegativeMev {
+                    pye_account: *pye_account,
+                    lamports: rewards.mev,
+                });
+            }
+        }
+        if self
+            .alert_epoch_liability_lamports
+            .is_some_and(|threshold| liability > threshold as i64)
+        {
+            breaches.push(ThresholdBreach::EpochLiability {
+                lamports: liability,
+            });
+        }
+        breaches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOL: i64 = 1_000_000_000;
+
+    fn rewards(inflation: i64, mev: i64) -> RewardBreakdown {
+        RewardBreakdown {
+            epoch: 800,
+            inflation,
+            mev,
+            block: 0,
+        }
+    }
+
+    #[test]
+    fn test_breaches() {
+        let thresholds = AlertThresholdArgs {
+            alert_bond_excess_lamports: Some(5 * SOL as u64),
+            alert_epoch_liability_lamports: Some(8 * SOL as u64),
+            alert_negative_mev_lamports: Some(SOL as u64),
+            pause_on_alert: false,
+        };
+        let (large, negative, small) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let bonds = [
+            (large, rewards(6 * SOL, 0)),
+            (negative, rewards(SOL, -2 * SOL)),
+            (small, rewards(3 * SOL, SOL)),
+        ];
+        assert_eq!(
+            thresholds.breaches(bonds.iter().map(|(pubkey, rewards)| (pubkey, rewards))),
+            vec![
+                ThresholdBreach::BondExcess {
+                    pye_account: large,
+                    lamports: 6 * SOL,
+                },
+                ThresholdBreach::NegativeMev {
+                    pye_account: negative,
+                    lamports: -2 * SOL,
+                },
+                // The negative bond owes nothing, so it doesn't offset the others.
+                ThresholdBreach::EpochLiability { lamports: 10 * SOL },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_thresholds() {
+        let pubkey = Pubkey::new_unique();
+        let rewards = rewards(100 * SOL, -100 * SOL);
+        assert!(AlertThresholdArgs::default()
+            .breaches([(&pubkey, &rewards)])
+            .is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use clap::Args;
+use futures::TryStreamExt;
+use log::{error, info};
+use object_store::aws::AmazonS3Builder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::signer::Signer;
+use object_store::{path::Path as ObjectPath, ObjectStore, PutPayload};
+use reqwest::Method;
+use url::Url;
+
+use crate::metrics::datapoint_error;
+use crate::receipts::PaymentReceipt;
+
+/// Options for archiving each epoch's records to object storage.
+#[derive(Clone, Debug, Default, Args)]
+pub struct ArchiveArgs {
+    /// Bucket to archive each processed epoch's report, receipts and attestations to, as
+    /// `s3://bucket/prefix` or `gs://bucket/prefix`. Credentials come from the standard AWS_*
+    /// or GOOGLE_* environment variables; S3-compatible stores set AWS_ENDPOINT.
+    #[arg(long, env)]
+    pub archive_url: Option<Url>,
+    /// Prefix under --archive-url of the epoch reports.
+    #[arg(long, env, default_value = "reports")]
+    pub archive_reports_prefix: String,
+    /// Prefix under --archive-url of the receipts.
+    #[arg(long, env, default_value = "receipts")]
+    pub archive_receipts_prefix: String,
+    /// Prefix under --archive-url of the payment attestations.
+    #[arg(long, env, default_value = "attestations")]
+    pub archive_attestations_prefix: String,
+}
+
+/// Object storage bucket keeping a copy of every epoch's records, so they survive the loss of
+/// the host and can be shared through signed URLs. Each epoch's objects are under
+/// `<prefix>/epoch-<N>/`.
+pub struct Archive {
+    store: Arc<dyn ObjectStore>,
+    signer: Arc<dyn Signer>,
+    root: ObjectPath,
+    args: ArchiveArgs,
+}
+
+impl Archive {
+    /// Returns None when no archive is configured.
+    pub fn from_args(args: &ArchiveArgs) -> Result<Option<Self>> {
+        let Some(url) = &args.archive_url else {
+            return Ok(None);
+        };
+        let invalid = |e: object_store::Error| anyhow!("Invalid --archive-url {}: {}", url, e);
+        let (store, signer): (Arc<dyn ObjectStore>, Arc<dyn Signer>) = match url.scheme() {
+            "s3" => {
+                let store = Arc::new(
+                    AmazonS3Builder::from_env()
+                        .with_url(url.as_str())
+                        .build()
+                        .map_err(invalid)?,
+                );
+                (store.clone(), store)
+            }
+            "gs" => {
+                let store = Arc::new(
+                    GoogleCloudStorageBuilder::from_env()
+                        .with_url(url.as_str())
+                        .build()
+                        .map_err(invalid)?,
+                );
+                (store.clone(), store)
+            }
+            _ => return Err(anyhow!("--archive-url must be an s3:// or gs:// URL")),
+        };
+        let root = ObjectPath::from_url_path(url.path())
+            .map_err(|e| anyhow!("Invalid --archive-url {}: {}", url, e))?;
+        Ok(Some(Self {
+            store,
+            signer,
+            root,
+            args: args.clone(),
+        }))
+    }
+
+    fn epoch_prefix(&self, prefix: &str, epoch: u64) -> ObjectPath {
+        self.root
+            .child(prefix)
+            .child(format!("epoch-{}", epoch).as_str())
+    }
+
+    /// Where attestations are uploaded, for the attestation writer.
+    pub fn attestations(&self) -> (Arc<dyn ObjectStore>, ObjectPath) {
+        (
+            Arc::clone(&self.store),
+            self.root
+                .child(self.args.archive_attestations_prefix.as_str()),
+        )
+    }
+
+    /// Uploads the epoch's report, a JSON array of its receipts as read by `diff-report`, and
+    /// each receipt. Failures are logged and reported but don't stop the manager.
+    pub async fn archive_epoch(&self, vote_pubkey: &str, epoch: u64, receipts: &[PaymentReceipt]) {
+        match self.try_archive_epoch(vote_pubkey, epoch, receipts).await {
+            Ok(()) => info!(
+                "Archived the report and {} receipts of epoch {}",
+                receipts.len(),
+                epoch
+            ),
+            Err(e) => {
+                error!("Failed to archive epoch {}: {}", epoch, e);
+                datapoint_error!(
+                    "archive_error",
+                    ("epoch", epoch.to_string(), String),
+                    ("error", e.to_string(), String),
+                );
+            }
+        }
+    }
+
+    async fn try_archive_epoch(
+        &self,
+        vote_pubkey: &str,
+        epoch: u64,
+        receipts: &[PaymentReceipt],
+    ) -> Result<()> {
+        // Timestamped names keep an epoch processed again from overwriting the first records.
+        let timestamp = Utc::now().timestamp();
+        let report = self
+            .epoch_prefix(&self.args.archive_reports_prefix, epoch)
+            .child(format!("{}-{}.json", vote_pubkey, timestamp).as_str());
+        self.put(&report, serde_json::to_vec_pretty(receipts)?)
+            .await?;
+        let receipts_prefix = self.epoch_prefix(&self.args.archive_receipts_prefix, epoch);
+        for receipt in receipts {
+            let location = receipts_prefix
+                .child(format!("{}-{}.json", receipt.pye_account, timestamp).as_str());
+            self.put(&location, serde_json::to_vec_pretty(receipt)?)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn put(&self, location: &ObjectPath, contents: Vec<u8>) -> Result<()> {
+        self.store
+            .put(location, PutPayload::from(contents))
+            .await
+            .map_err(|e| anyhow!("Failed to upload {}: {}", location, e))?;
+        Ok(())
+    }
+
+    /// Signed GET URLs, valid for `expires_in`, of the epoch's archived receipts and
+    /// attestations, only `pye_account`'s if given. Epoch reports list every bond, so they are
+    /// only included for the whole epoch.
+    pub async fn signed_urls(
+        &self,
+        epoch: u64,
+        pye_account: Option<&str>,
+        expires_in: Duration,
+    ) -> Result<Vec<(ObjectPath, Url)>> {
+        let mut prefixes = vec![
+            &self.args.archive_receipts_prefix,
+            &self.args.archive_attestations_prefix,
+        ];
+        if pye_account.is_none() {
+            prefixes.insert(0, &self.args.archive_reports_prefix);
+        }
+        let mut urls = Vec::new();
+        for prefix in prefixes {
+            let prefix = self.epoch_prefix(prefix, epoch);
+            let objects: Vec<_> = self
+                .store
+                .list(Some(&prefix))
+                .try_collect()
+                .await
+                .map_err(|e| anyhow!("Failed to list {}: {}", prefix, e))?;
+            for object in objects {
+                let shared = pye_account.is_none_or(|pye_account| {
+                    object
+                        .location
+                        .filename()
+                        .is_some_and(|name| name.starts_with(pye_account))
+                });
+                if !shared {
+                    continue;
+                }
+                let url = self
+                    .signer
+                    .signed_url(Method::GET, &object.location, expires_in)
+                    .await
+                    .map_err(|e| anyhow!("Failed to sign {}: {}", object.location, e))?;
+                urls.push((object.location, url));
+            }
+        }
+        Ok(urls)
+    }
+}
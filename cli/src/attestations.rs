@@ -0,0 +1,283 @@
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use clap::Args;
+use log::{error, info};
+use object_store::{path::Path as ObjectPath, ObjectStore, PutPayload};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::signer::Signer;
+use url::Url;
+
+use crate::archive::Archive;
+use crate::control::BondRewardBreakdown;
+use crate::keypairs::SharedSigner;
+use crate::metrics::datapoint_error;
+
+/// Version of the attestation payload layout.
+pub const ATTESTATION_VERSION: u8 = 1;
+
+/// Options controlling where payer-signed payment attestations are written.
+#[derive(Clone, Debug, Default, Args)]
+pub struct AttestationArgs {
+    /// Directory to write payer-signed payment attestations to, one file per bond and epoch.
+    #[arg(long, env, conflicts_with = "dry_run")]
+    pub attestation_dir: Option<PathBuf>,
+    /// S3 URL (s3://bucket/prefix) to upload attestations to. Credentials and region are read
+    /// from the standard AWS_* environment variables.
+    #[arg(long, env, conflicts_with = "dry_run")]
+    pub attestation_s3_url: Option<Url>,
+}
+
+/// What the operator computed and paid to a bond for an epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentAttestation {
+    pub version: u8,
+    pub vote_pubkey: String,
+    pub epoch: u64,
+    pub program_id: String,
+    pub pye_account: String,
+    pub active_stake: u64,
+    pub excess_inflation_rewards: i64,
+    pub excess_mev_rewards: i64,
+    pub excess_block_rewards: i64,
+    pub total_excess_rewards: i64,
+    /// `paid`, or why nothing was paid, e.g. `not_due`.
+    pub payment: String,
+    pub transaction_signature: Option<String>,
+    pub attested_at: String,
+}
+
+impl PaymentAttestation {
+    pub fn new(
+        vote_pubkey: &Pubkey,
+        breakdown: &BondRewardBreakdown,
+        attested_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            version: ATTESTATION_VERSION,
+            vote_pubkey: vote_pubkey.to_string(),
+            epoch: breakdown.rewards.epoch,
+            program_id: breakdown.program_id.to_string(),
+            pye_account: breakdown.pye_account.to_string(),
+            active_stake: breakdown.active_stake,
+            excess_inflation_rewards: breakdown.rewards.inflation,
+            excess_mev_rewards: breakdown.rewards.mev,
+            excess_block_rewards: breakdown.rewards.block,
+            total_excess_rewards: breakdown.rewards.total(),
+            payment: breakdown.payment.to_string(),
+            transaction_signature: breakdown
+                .payment
+                .signature()
+                .map(|signature| signature.to_string()),
+            attested_at: attested_at.to_rfc3339(),
+        }
+    }
+}
+
+/// An attestation with the signer's ed25519 signature over the exact `payload` bytes, so it can
+/// be verified without re-serializing the JSON.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedAttestation {
+    /// The attestation serialized as JSON.
+    pub payload: String,
+    pub signer: String,
+    pub signature: String,
+}
+
+impl SignedAttestation {
+    pub fn sign(attestation: &PaymentAttestation, signer: &dyn Signer) -> Result<Self> {
+        let payload = serde_json::to_string(attestation)?;
+        let signature = signer.try_sign_message(payload.as_bytes())?;
+        Ok(Self {
+            payload,
+            signer: signer.pubkey().to_string(),
+            signature: signature.to_string(),
+        })
+    }
+
+    /// Checks the signature against the embedded signer and returns the attestation.
+    pub fn verify(&self) -> Result<PaymentAttestation> {
+        let signer = Pubkey::from_str(&self.signer)
+            .map_err(|e| anyhow!("Invalid signer {}: {}", self.signer, e))?;
+        let signature = Signature::from_str(&self.signature)
+            .map_err(|e| anyhow!("Invalid signature {}: {}", self.signature, e))?;
+        if !signature.verify(signer.as_ref(), self.payload.as_bytes()) {
+            return Err(anyhow!(
+                "Signature doesn't match the attestation and signer"
+            ));
+        }
+        serde_json::from_str(&self.payload).map_err(|e| anyhow!("Invalid attestation: {}", e))
+    }
+}
+
+/// Directory and file name of a bond's attestation for an epoch, relative to the attestation
+/// root. The timestamp keeps an epoch recomputed and paid again from overwriting the receipt of
+/// the first payment.
+pub fn attestation_path(epoch: u64, pye_account: &Pubkey, attested_at: i64) -> [String; 2] {
+    [
+        format!("epoch-{}", epoch),
+        format!("{}-{}.json", pye_account, attested_at),
+    ]
+}
+
+/// Signs attestations with the payer and writes them to a directory, S3 and/or the archive.
+/// Failures are logged and reported but never abort the manager, the payments have already been
+/// made.
+pub struct AttestationWriter {
+    signer: SharedSigner,
+    vote_pubkey: Pubkey,
+    dir: Option<PathBuf>,
+    stores: Vec<(Arc<dyn ObjectStore>, ObjectPath)>,
+}
+
+impl AttestationWriter {
+    /// Returns None when no attestation destination is configured.
+    pub fn from_args(
+        args: &AttestationArgs,
+        archive: Option<&Archive>,
+        signer: SharedSigner,
+        vote_pubkey: Pubkey,
+    ) -> Result<Option<Self>> {
+        if args.attestation_dir.is_none() && args.attestation_s3_url.is_none() && archive.is_none()
+        {
+            return Ok(None);
+        }
+        let s3 = match &args.attestation_s3_url {
+            Some(url) if url.scheme() != "s3" => {
+                return Err(anyhow!("--attestation-s3-url must be an s3:// URL"));
+            }
+            Some(url) => {
+                // Unknown keys are ignored, the AWS_* variables configure the client.
+                let options = std::env::vars().map(|(key, value)| (key.to_lowercase(), value));
+                let (store, prefix) = object_store::parse_url_opts(url, options)
+                    .map_err(|e| anyhow!("Invalid --attestation-s3-url {}: {}", url, e))?;
+                Some((Arc::from(store), prefix))
+            }
+            None => None,
+        };
+        Ok(Some(Self {
+            signer,
+            vote_pubkey,
+            dir: args.attestation_dir.clone(),
+            stores: s3
+                .into_iter()
+                .chain(archive.map(Archive::attestations))
+                .collect(),
+        }))
+    }
+
+    pub async fn write_epoch(&self, epoch: u64, breakdowns: &[BondRewardBreakdown]) {
+        for breakdown in breakdowns {
+            if let Err(e) = self.write(breakdown).await {
+                error!(
+                    "Failed to write attestation for pye_account {} and epoch {}: {}",
+                    breakdown.pye_account, epoch, e
+                );
+                datapoint_error!(
+                    "attestation_error",
+                    ("epoch", epoch.to_string(), String),
+                    ("pye_account", breakdown.pye_account.to_string(), String),
+                    ("error", e.to_string(), String),
+                );
+            }
+        }
+        info!(
+            "Wrote {} payment attestations for epoch {}",
+            breakdowns.len(),
+            epoch
+        );
+    }
+
+    async fn write(&self, breakdown: &BondRewardBreakdown) -> Result<()> {
+        let attested_at = Utc::now();
+        let attestation = PaymentAttestation::new(&self.vote_pubkey, breakdown, attested_at);
+        let signed = SignedAttestation::sign(&attestation, &*self.signer)?;
+        let contents = serde_json::to_vec_pretty(&signed)?;
+        let [epoch_dir, file_name] = attestation_path(
+            attestation.epoch,
+            &breakdown.pye_account,
+            attested_at.timestamp(),
+        );
+        if let Some(dir) = &self.dir {
+            let epoch_dir = dir.join(&epoch_dir);
+            fs::create_dir_all(&epoch_dir)
+                .map_err(|e| anyhow!("Failed to create {}: {}", epoch_dir.display(), e))?;
+            let file = epoch_dir.join(&file_name);
+            fs::write(&file, &contents)
+                .map_err(|e| anyhow!("Failed to write {}: {}", file.display(), e))?;
+        }
+        for (store, prefix) in &self.stores {
+            let location = prefix.child(epoch_dir.as_str()).child(file_name.as_str());
+            store
+                .put(&location, PutPayload::from(contents.clone()))
+                .await
+                .map_err(|e| anyhow!("Failed to upload {}: {}", location, e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::signer::keypair::Keypair;
+
+    use super::*;
+    use crate::control::PaymentOutcome;
+    use crate::dashboard::RewardBreakdown;
+    use crate::transactions::Payment;
+
+    fn attestation() -> PaymentAttestation {
+        PaymentAttestation::new(
+            &Pubkey::new_unique(),
+            &BondRewardBreakdown {
+                program_id: Pubkey::new_unique(),
+                pye_account: Pubkey::new_unique(),
+                active_stake: 5_000,
+                rewards: RewardBreakdown {
+                    epoch: 800,
+                    inflation: 30,
+                    mev: 20,
+                    block: 10,
+                },
+                payment: PaymentOutcome::Paid(Payment {
+                    signature: Signature::default(),
+                    fee: Some(5_000),
+                    slot: Some(345_600_123),
+                    verification: None,
+                }),
+            },
+            Utc::now(),
+        )
+    }
+
+    #[test]
+    fn test_signed_attestation_round_trip() {
+        let keypair = Keypair::new();
+        let attestation = attestation();
+        let signed = SignedAttestation::sign(&attestation, &keypair).unwrap();
+        assert_eq!(signed.signer, keypair.pubkey().to_string());
+        assert_eq!(signed.verify().unwrap(), attestation);
+        assert_eq!(attestation.total_excess_rewards, 60);
+        assert_eq!(attestation.payment, "paid");
+    }
+
+    #[test]
+    fn test_tampered_attestation_fails_verification() {
+        let keypair = Keypair::new();
+        let mut signed = SignedAttestation::sign(&attestation(), &keypair).unwrap();
+        signed.payload = signed
+            .payload
+            .replace("\"total_excess_rewards\":60", "\"total_excess_rewards\":6");
+        assert!(signed.verify().is_err());
+
+        let mut signed = SignedAttestation::sign(&attestation(), &keypair).unwrap();
+        signed.signer = Keypair::new().pubkey().to_string();
+        assert!(signed.verify().is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use clap::{Args, ValueEnum};
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Order in which the excess rewards computed for an epoch are paid.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PaymentPriority {
+    /// In the order the bonds were fetched, issuer by issuer.
+    #[default]
+    Fetched,
+    /// Alternating between issuers, so an issuer with many bonds can't hold up the others.
+    RoundRobin,
+    /// Bonds with the least active stake first.
+    SmallestFirst,
+    /// Bonds maturing soonest first.
+    EarliestMaturity,
+}
+
+/// Options controlling how the bonds of an epoch are worked through.
+#[derive(Clone, Debug, Args)]
+pub struct BondSchedulingArgs {
+    /// Bonds whose excess rewards are computed concurrently.
+    #[arg(long, env, default_value = "1")]
+    pub bond_concurrency: usize,
+    /// Bonds of a single issuer computed concurrently [default: --bond-concurrency].
+    #[arg(long, env)]
+    pub issuer_concurrency: Option<usize>,
+    /// Order in which the epoch's payments are sent, once every bond's excess rewards are
+    /// computed.
+    #[arg(long, env, value_enum, default_value_t = PaymentPriority::Fetched)]
+    pub payment_priority: PaymentPriority,
+}
+
+impl BondSchedulingArgs {
+    pub fn bond_concurrency(&self) -> usize {
+        self.bond_concurrency.max(1)
+    }
+
+    pub fn issuer_limits(&self) -> IssuerLimits {
+        IssuerLimits::new(
+            self.issuer_concurrency
+                .unwrap_or(self.bond_concurrency)
+                .max(1),
+        )
+    }
+}
+
+/// What bonds are prioritized by.
+#[derive(Clone, Copy, Debug)]
+pub struct BondKey {
+    pub issuer: Pubkey,
+    pub active_stake: u64,
+    pub maturity_ts: i64,
+}
+
+/// Interleaves `items` issuer by issuer, keeping their order within each issuer: the first bond
+/// of every issuer, then the second, and so on.
+pub fn round_robin<T>(items: Vec<T>, issuer: impl Fn(&T) -> Pubkey) -> Vec<T> {
+    let total = items.len();
+    let mut queues: Vec<(Pubkey, VecDeque<T>)> = Vec::new();
+    for item in items {
+        let key = issuer(&item);
+        match queues
+            .iter_mut()
+            .find(|(queue_issuer, _)| *queue_issuer == key)
+        {
+            Some((_, queue)) => queue.push_back(item),
+            None => queues.push((key, VecDeque::from([item]))),
+        }
+    }
+    let mut ordered = Vec::with_capacity(total);
+    while ordered.len() < total {
+        ordered.extend(queues.iter_mut().filter_map(|(_, queue)| queue.pop_front()));
+    }
+    ordered
+}
+
+impl PaymentPriority {
+    /// Sorts `items` by priority. Ties keep their order.
+    pub fn order<T>(self, mut items: Vec<T>, key: impl Fn(&T) -> BondKey) -> Vec<T> {
+        match self {
+            PaymentPriority::Fetched => {}
+            PaymentPriority::RoundRobin => return round_robin(items, |item| key(item).issuer),
+            PaymentPriority::SmallestFirst => items.sort_by_key(|item| key(item).active_stake),
+            PaymentPriority::EarliestMaturity => items.sort_by_key(|item| key(item).maturity_ts),
+        }
+        items
+    }
+}
+
+/// Limits the bonds of each issuer worked on at once.
+pub struct IssuerLimits {
+    per_issuer: usize,
+    semaphores: Mutex<HashMap<Pubkey, Arc<Semaphore>>>,
+}
+
+impl IssuerLimits {
+    pub fn new(per_issuer: usize) -> Self {
+        Self {
+            per_issuer,
+            semaphores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits for a free slot of `issuer`, held until the permit is dropped.
+    pub async fn acquire(&self, issuer: &Pubkey) -> OwnedSemaphorePermit {
+        let semaphore = {
+            let mut semaphores = self
+                .semaphores
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            Arc::clone(
+                semaphores
+                    .entry(*issuer)
+                    .or_insert_with(|| Arc::new(Semaphore::new(self.per_issuer))),
+            )
+        };
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("issuer semaphores are never closed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        let large = Pubkey::new_unique();
+        let small = Pubkey::new_unique();
+        let bond = |issuer, active_stake, maturity_ts| BondKey {
+            issuer,
+            active_stake,
+            maturity_ts,
+        };
+        let bonds = vec![
+            bond(large, 300, 30),
+            bond(large, 100, 20),
+            bond(large, 200, 40),
+            bond(small, 500, 10),
+        ];
+        let stakes = |priority: PaymentPriority| {
+            priority
+                .order(bonds.clone(), |bond| *bond)
+                .iter()
+                .map(|bond| bond.active_stake)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(stakes(PaymentPriority::Fetched), vec![300, 100, 200, 500]);
+        assert_eq!(
+            stakes(PaymentPriority::RoundRobin),
+            vec![300, 500, 100, 200]
+        );
+        assert_eq!(
+            stakes(PaymentPriority::SmallestFirst),
+            vec![100, 200, 300, 500]
+        );
+        assert_eq!(
+            stakes(PaymentPriority::EarliestMaturity),
+            vec![500, 100, 300, 200]
+        );
+    }
+}
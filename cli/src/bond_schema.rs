@@ -0,0 +1,141 @@
+use std::fmt;
+
+use anchor_lang::{AccountDeserialize, Discriminator};
+use pye_core_cpi::pye_core::accounts::SoloValidatorBond as SoloValidatorPyeAccount;
+use thiserror::Error;
+
+/// Size of the current SoloValidatorBond layout with `single_depositor` set.
+pub const BOND_LEN: usize = 448;
+/// Bytes the CLI actually reads, the discriminator through `issuer`. Anything after may be
+/// missing from accounts written by older program versions.
+pub const BOND_REQUIRED_LEN: usize = 272;
+
+/// SoloValidatorBond layouts the CLI knows how to decode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BondSchemaVersion {
+    /// Decodes as-is with the bundled IDL, trailing fields appended by newer programs are ignored.
+    Current,
+    /// Written before the trailing fields of the current layout existed. They decode as zeroed.
+    Legacy,
+}
+
+impl fmt::Display for BondSchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BondSchemaVersion::Current => write!(f, "current"),
+            BondSchemaVersion::Legacy => write!(f, "legacy"),
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum BondDecodeError {
+    #[error("Unknown account discriminator {0:?}, the account may belong to a newer pye-core")]
+    UnknownDiscriminator(Vec<u8>),
+    #[error("Account is {len} bytes, at least {} are required", BOND_REQUIRED_LEN)]
+    TooShort { len: usize },
+    #[error("Malformed {version} SoloValidatorBond: {message}")]
+    Malformed {
+        version: BondSchemaVersion,
+        message: String,
+    },
+}
+
+/// Discriminators of every SoloValidatorBond layout ever deployed. Add an entry here if pye-core
+/// renames the account, the prefix through `issuer` is expected to stay stable.
+pub fn known_discriminators() -> Vec<&'static [u8]> {
+    vec![SoloValidatorPyeAccount::DISCRIMINATOR]
+}
+
+/// Decodes a SoloValidatorBond from any known schema version.
+pub fn decode_solo_validator_bond(
+    data: &[u8],
+) -> Result<(BondSchemaVersion, SoloValidatorPyeAccount), BondDecodeError> {
+    let discriminator = data.get(..8).unwrap_or(data);
+    if !known_discriminators().contains(&discriminator) {
+        return Err(BondDecodeError::UnknownDiscriminator(
+            discriminator.to_vec(),
+        ));
+    }
+    if data.len() < BOND_REQUIRED_LEN {
+        return Err(BondDecodeError::TooShort { len: data.len() });
+    }
+
+    // The current IDL's discriminator is always checked, so normalize renamed accounts onto it.
+    let mut normalized = data.to_vec();
+    normalized[..8].copy_from_slice(SoloValidatorPyeAccount::DISCRIMINATOR);
+    match SoloValidatorPyeAccount::try_deserialize(&mut normalized.as_slice()) {
+        Ok(bond) => return Ok((BondSchemaVersion::Current, bond)),
+        Err(e) if data.len() >= BOND_LEN => {
+            return Err(BondDecodeError::Malformed {
+                version: BondSchemaVersion::Current,
+                message: e.to_string(),
+            })
+        }
+        Err(_) => {}
+    }
+
+    normalized.resize(BOND_LEN, 0);
+    SoloValidatorPyeAccount::try_deserialize(&mut normalized.as_slice())
+        .map(|bond| (BondSchemaVersion::Legacy, bond))
+        .map_err(|e| BondDecodeError::Malformed {
+            version: BondSchemaVersion::Legacy,
+            message: e.to_string(),
+        })
+}
+
+/// A bond decoded from zeroed account data, for tests to fill in the fields they use.
+#[cfg(test)]
+pub fn zeroed_bond() -> SoloValidatorPyeAccount {
+    let mut data = vec![0u8; BOND_LEN];
+    data[..8].copy_from_slice(SoloValidatorPyeAccount::DISCRIMINATOR);
+    decode_solo_validator_bond(&data).unwrap().1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bond_bytes(len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        data[..8].copy_from_slice(SoloValidatorPyeAccount::DISCRIMINATOR);
+        data
+    }
+
+    #[test]
+    fn test_decodes_current_layout() {
+        let (version, _) = decode_solo_validator_bond(&bond_bytes(BOND_LEN)).unwrap();
+        assert_eq!(version, BondSchemaVersion::Current);
+    }
+
+    #[test]
+    fn test_decodes_appended_fields() {
+        let (version, _) = decode_solo_validator_bond(&bond_bytes(BOND_LEN + 64)).unwrap();
+        assert_eq!(version, BondSchemaVersion::Current);
+    }
+
+    #[test]
+    fn test_decodes_legacy_layout() {
+        let (version, bond) = decode_solo_validator_bond(&bond_bytes(BOND_REQUIRED_LEN)).unwrap();
+        assert_eq!(version, BondSchemaVersion::Legacy);
+        assert!(bond.single_depositor.is_none());
+    }
+
+    #[test]
+    fn test_rejects_unknown_discriminator() {
+        let mut data = bond_bytes(BOND_LEN);
+        data[0] ^= 0xff;
+        assert!(matches!(
+            decode_solo_validator_bond(&data),
+            Err(BondDecodeError::UnknownDiscriminator(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_truncated_account() {
+        assert_eq!(
+            decode_solo_validator_bond(&bond_bytes(100)).unwrap_err(),
+            BondDecodeError::TooShort { len: 100 }
+        );
+    }
+}
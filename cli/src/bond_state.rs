@@ -0,0 +1,497 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::installments::Installment;
+use crate::receipts::ExcessRewards;
+use crate::state_db::{StateDb, StateDbWriter};
+
+/// Epochs whose bond states are kept.
+const MAX_EPOCHS: usize = 16;
+
+/// Where a bond is in the processing of an epoch. Stages only move forward, except that a
+/// recompute requested through the control API starts the bond over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BondStage {
+    /// The bond was active and not matured at the end of the epoch.
+    Discovered,
+    /// The bond's active stake over the epoch was fetched.
+    StakeComputed,
+    /// The bond's excess rewards were computed. Payments that are paused or over the cap stay
+    /// here.
+    RewardsComputed,
+    /// The payment cleared the pause and cap checks.
+    PlanApproved,
+    /// The payment transaction was being sent. A bond left here by a crash may or may not have
+    /// been paid.
+    Sent,
+    /// The payment landed.
+    Confirmed,
+    /// No excess rewards were owed.
+    NotDue,
+    /// The payment failed.
+    Failed,
+}
+
+impl BondStage {
+    /// Whether the bond is done with for the epoch.
+    pub fn is_final(self) -> bool {
+        matches!(
+            self,
+            BondStage::Confirmed | BondStage::NotDue | BondStage::Failed
+        )
+    }
+}
+
+impl fmt::Display for BondStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BondStage::Discovered => "discovered",
+            BondStage::StakeComputed => "stake_computed",
+            BondStage::RewardsComputed => "rewards_computed",
+            BondStage::PlanApproved => "plan_approved",
+            BondStage::Sent => "sent",
+            BondStage::Confirmed => "confirmed",
+            BondStage::NotDue => "not_due",
+            BondStage::Failed => "failed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The payment of an epoch a bond state tracks. With --defer-mev a bond is paid twice for an
+/// epoch, every category but MEV first and its MEV once the Jito API has the epoch's data, and
+/// each payment goes through the stages on its own.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BondPass {
+    /// Every reward category.
+    #[default]
+    Full,
+    /// Every reward category but MEV.
+    WithoutMev,
+    /// Only MEV, after the bond's other categories.
+    MevOnly,
+}
+
+impl fmt::Display for BondPass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BondPass::Full => "full",
+            BondPass::WithoutMev => "without_mev",
+            BondPass::MevOnly => "mev_only",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A bond's progress through an epoch, with what was computed so far.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BondState {
+    pub epoch: u64,
+    pub pye_account: String,
+    /// States written before passes were recorded are full passes.
+    #[serde(default)]
+    pub pass: BondPass,
+    pub stage: BondStage,
+    /// Unix time of the last stage change.
+    pub updated_at: i64,
+    #[serde(default)]
+    pub active_stake: Option<u64>,
+    #[serde(default)]
+    pub excess_rewards: Option<ExcessRewards>,
+    #[serde(default)]
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub fee: Option<u64>,
+    /// Slot the payment was confirmed in, when known.
+    #[serde(default)]
+    pub slot: Option<u64>,
+    /// Why the bond is held at its stage, or the error it failed with.
+    #[serde(default)]
+    pub detail: Option<String>,
+    /// Installments of the payment landed so far, when it is split.
+    #[serde(default)]
+    pub installments: Vec<Installment>,
+}
+
+impl BondState {
+    /// Whether a run stopped before the bond was done with, rather than holding it back on
+    /// purpose, e.g. while payments are paused.
+    pub fn is_interrupted(&self) -> bool {
+        !self.stage.is_final() && self.detail.is_none()
+    }
+
+    fn is(&self, epoch: u64, pye_account: &str, pass: BondPass) -> bool {
+        self.epoch == epoch && self.pye_account == pye_account && self.pass == pass
+    }
+
+    pub fn pye_account(&self) -> Result<Pubkey> {
+        Pubkey::from_str(&self.pye_account)
+            .map_err(|e| anyhow!("Invalid pubkey {} in bond state: {}", self.pye_account, e))
+    }
+}
+
+/// The stage of every bond of the recent epochs. Optionally persisted to a file or the state
+/// database after every change, so a restart resumes each bond at the step it reached.
+#[derive(Debug, Default)]
+pub struct BondStates {
+    path: Option<PathBuf>,
+    /// The state database and the vote account whose bonds these are.
+    db: Option<(StateDbWriter, String)>,
+    states: Mutex<Vec<BondState>>,
+}
+
+impl BondStates {
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let mut states = Vec::new();
+        if let Some(path) = path.as_ref().filter(|path| path.exists()) {
+            let contents = fs::read_to_string(path)
+                .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+            states = serde_json::from_str(&contents)
+                .map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))?;
+        }
+        Ok(Self {
+            path,
+            db: None,
+            states: Mutex::new(states),
+        })
+    }
+
+    /// Bond states kept in memory only.
+    pub fn from_states(states: Vec<BondState>) -> Self {
+        Self {
+            states: Mutex::new(states),
+            ..Self::default()
+        }
+    }
+
+    /// Loads the bond states of `vote_pubkey`'s manager from the state database, which then
+    /// records every change.
+    pub async fn load_from_db(
+        db: &StateDb,
+        writer: StateDbWriter,
+        vote_pubkey: &Pubkey,
+    ) -> Result<Self> {
+        let vote_pubkey = vote_pubkey.to_string();
+        let states = db.load_bond_states(&vote_pubkey).await?;
+        Ok(Self {
+            path: None,
+            db: Some((writer, vote_pubkey)),
+            states: Mutex::new(states),
+        })
+    }
+
+    fn states(&self) -> MutexGuard<'_, Vec<BondState>> {
+        self.states
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn get(&self, epoch: u64, pye_account: &Pubkey, pass: BondPass) -> Option<BondState> {
+        let pye_account = pye_account.to_string();
+        self.states()
+            .iter()
+            .find(|state| state.is(epoch, &pye_account, pass))
+            .cloned()
+    }
+
+    /// Bonds not done with yet, oldest epoch first.
+    pub fn unfinished(&self) -> Vec<BondState> {
+        let mut unfinished: Vec<BondState> = self
+            .states()
+            .iter()
+            .filter(|state| !state.stage.is_final())
+            .cloned()
+            .collect();
+        unfinished.sort_by(|a, b| {
+            (a.epoch, a.pass, &a.pye_account).cmp(&(b.epoch, b.pass, &b.pye_account))
+        });
+        unfinished
+    }
+
+    /// Bonds of completed epochs before `current_epoch` that a run stopped processing, by epoch
+    /// and pass.
+    pub fn interrupted(&self, current_epoch: u64) -> BTreeMap<(u64, BondPass), Vec<BondState>> {
+        let mut interrupted: BTreeMap<(u64, BondPass), Vec<BondState>> = BTreeMap::new();
+        for state in self.unfinished() {
+            if state.epoch < current_epoch && state.is_interrupted() {
+                interrupted
+                    .entry((state.epoch, state.pass))
+                    .or_default()
+                    .push(state);
+            }
+        }
+        interrupted
+    }
+
+    /// Starts tracking the bond for `epoch`. With `restart`, or if the bond isn't tracked yet, it
+    /// starts over as discovered; otherwise it keeps the stage it reached. A bond whose payment
+    /// was sent keeps its stage even when restarting, so it isn't paid twice.
+    pub fn discover(&self, epoch: u64, pye_account: &Pubkey, pass: BondPass, restart: bool) {
+        let state = self.get(epoch, pye_account, pass);
+        let sent = state
+            .as_ref()
+            .is_some_and(|state| matches!(state.stage, BondStage::Sent | BondStage::Confirmed));
+        if (restart && !sent) || state.is_none() {
+            self.update(epoch, pye_account, pass, |state| {
+                *state = new_state(epoch, pye_account, pass)
+            });
+        }
+    }
+
+    /// Moves the bond's `pass` to `stage`, recording what `update` sets along with it.
+    pub fn advance(
+        &self,
+        epoch: u64,
+        pye_account: &Pubkey,
+        pass: BondPass,
+        stage: BondStage,
+        update: impl FnOnce(&mut BondState),
+    ) {
+        self.update(epoch, pye_account, pass, |state| {
+            state.stage = stage;
+            state.detail = None;
+            update(state);
+        });
+    }
+
+    fn update(
+        &self,
+        epoch: u64,
+        pye_account: &Pubkey,
+        pass: BondPass,
+        update: impl FnOnce(&mut BondState),
+    ) {
+        let mut states = self.states();
+        let key = pye_account.to_string();
+        let index = match states.iter().position(|state| state.is(epoch, &key, pass)) {
+            Some(index) => index,
+            None => {
+                states.push(new_state(epoch, pye_account, pass));
+                states.len() - 1
+            }
+        };
+        update(&mut states[index]);
+        states[index].updated_at = chrono::Utc::now().timestamp();
+        if let Some((db, vote_pubkey)) = &self.db {
+            db.save_bond_state(vote_pubkey, states[index].clone());
+        }
+
+        let mut epochs: Vec<u64> = states.iter().map(|state| state.epoch).collect();
+        epochs.sort_unstable();
+        epochs.dedup();
+        if let Some(oldest_kept) = epochs.iter().rev().nth(MAX_EPOCHS - 1).copied() {
+            if states.iter().any(|state| state.epoch < oldest_kept) {
+                states.retain(|state| state.epoch >= oldest_kept);
+                if let Some((db, vote_pubkey)) = &self.db {
+                    db.prune_bond_states(vote_pubkey, oldest_kept);
+                }
+            }
+        }
+        if let Err(e) = self.save(&states) {
+            warn!("{}", e);
+        }
+    }
+
+    fn save(&self, states: &[BondState]) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // Written next to the file and renamed over it, so a crash mid-write leaves the previous
+        // states rather than a truncated file.
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let write = || -> io::Result<()> {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(&serde_json::to_vec_pretty(states)?)?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, path)
+        };
+        write().map_err(|e| anyhow!("Failed to write bond states to {}: {}", path.display(), e))
+    }
+
+    /// Waits until every change so far is stored in the file or the state database, failing if
+    /// it couldn't be. A payment must not be sent before its `sent` stage is durable, or a
+    /// restart would pay it again.
+    pub async fn persist(&self) -> Result<()> {
+        let states = self.states().clone();
+        self.save(&states)?;
+        if let Some((db, _)) = &self.db {
+            db.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+fn new_state(epoch: u64, pye_account: &Pubkey, pass: BondPass) -> BondState {
+    BondState {
+        epoch,
+        pye_account: pye_account.to_string(),
+        pass,
+        stage: BondStage::Discovered,
+        updated_at: 0,
+        active_stake: None,
+        excess_rewards: None,
+        signature: None,
+        fee: None,
+        slot: None,
+        detail: None,
+        installments: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sent_stage_must_be_durable() {
+        let (writer, written, task) = StateDbWriter::in_memory();
+        let states = BondStates {
+            path: None,
+            db: Some((writer, "vote".to_string())),
+            states: Mutex::new(Vec::new()),
+        };
+        let pye_account = Pubkey::new_unique();
+        states.advance(800, &pye_account, BondPass::Full, BondStage::Sent, |_| {});
+        states.persist().await.unwrap();
+        assert_eq!(
+            written.lock().unwrap().last().unwrap().stage,
+            BondStage::Sent
+        );
+
+        // The writer dies between the sent stage and the transfer: the transfer is held back.
+        task.abort();
+        let _ = task.await;
+        states.advance(801, &pye_account, BondPass::Full, BondStage::Sent, |_| {});
+        assert!(states.persist().await.is_err());
+    }
+
+    #[test]
+    fn test_states_persist_and_resume() {
+        let path =
+            std::env::temp_dir().join(format!("pye-bond-states-{}.json", std::process::id()));
+        let (paid, stuck, computed) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let states = BondStates::load(Some(path.clone())).unwrap();
+        for pye_account in [&paid, &stuck, &computed] {
+            states.discover(800, pye_account, BondPass::Full, false);
+            states.advance(
+                800,
+                pye_account,
+                BondPass::Full,
+                BondStage::StakeComputed,
+                |state| state.active_stake = Some(1_000),
+            );
+        }
+        states.advance(800, &paid, BondPass::Full, BondStage::Confirmed, |state| {
+            state.signature = Some("sig".to_string())
+        });
+        states.advance(800, &stuck, BondPass::Full, BondStage::Sent, |_| {});
+
+        let states = BondStates::load(Some(path.clone())).unwrap();
+        // Discovering a tracked bond again keeps its stage, unless restarting it.
+        states.discover(800, &paid, BondPass::Full, false);
+        assert_eq!(
+            states.get(800, &paid, BondPass::Full).unwrap().stage,
+            BondStage::Confirmed
+        );
+        let unfinished = states.unfinished();
+        assert_eq!(unfinished.len(), 2);
+        let sent = unfinished
+            .iter()
+            .find(|state| state.pye_account().unwrap() == stuck)
+            .unwrap();
+        assert_eq!(sent.stage, BondStage::Sent);
+        assert_eq!(sent.active_stake, Some(1_000));
+        // Restarting starts the computation over, but never a payment that was sent.
+        states.discover(800, &computed, BondPass::Full, true);
+        assert_eq!(
+            states.get(800, &computed, BondPass::Full).unwrap().stage,
+            BondStage::Discovered
+        );
+        assert_eq!(
+            states
+                .get(800, &computed, BondPass::Full)
+                .unwrap()
+                .active_stake,
+            None
+        );
+        states.discover(800, &paid, BondPass::Full, true);
+        assert_eq!(
+            states.get(800, &paid, BondPass::Full).unwrap().stage,
+            BondStage::Confirmed
+        );
+        states.discover(800, &stuck, BondPass::Full, true);
+        assert_eq!(
+            states.get(800, &stuck, BondPass::Full).unwrap().stage,
+            BondStage::Sent
+        );
+        assert_eq!(states.interrupted(801)[&(800, BondPass::Full)].len(), 2);
+        assert!(states.interrupted(800).is_empty());
+        for pye_account in [&stuck, &computed] {
+            states.advance(
+                800,
+                pye_account,
+                BondPass::Full,
+                BondStage::RewardsComputed,
+                |state| state.detail = Some("payments paused".to_string()),
+            );
+        }
+        assert!(states.interrupted(801).is_empty());
+
+        // Only the most recent epochs are kept.
+        for epoch in 801..(801 + MAX_EPOCHS as u64) {
+            states.discover(epoch, &paid, BondPass::Full, false);
+        }
+        assert!(states.get(800, &paid, BondPass::Full).is_none());
+        assert!(states.get(801, &paid, BondPass::Full).is_some());
+        // Saves are renamed into place, leaving no temporary file behind.
+        assert!(!path.with_extension("json.tmp").exists());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_passes_are_tracked_apart() {
+        let states = BondStates::default();
+        let pye_account = Pubkey::new_unique();
+        states.discover(800, &pye_account, BondPass::WithoutMev, false);
+        states.discover(800, &pye_account, BondPass::MevOnly, false);
+        states.advance(
+            800,
+            &pye_account,
+            BondPass::WithoutMev,
+            BondStage::Confirmed,
+            |_| {},
+        );
+        // The MEV deferred to the second pass is still owed once the first pass is paid.
+        assert_eq!(
+            states
+                .get(800, &pye_account, BondPass::MevOnly)
+                .unwrap()
+                .stage,
+            BondStage::Discovered
+        );
+        assert!(states.get(800, &pye_account, BondPass::Full).is_none());
+        let interrupted = states.interrupted(801);
+        assert_eq!(
+            interrupted.keys().collect::<Vec<_>>(),
+            [&(800, BondPass::MevOnly)]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use solana_sdk::pubkey::Pubkey;
+
+/// Remembers which bonds targeting the validator have already been seen, optionally persisted to
+/// a file so that bonds created while the manager was down are still reported.
+#[derive(Debug, Default)]
+pub struct BondWatcher {
+    path: Option<PathBuf>,
+    known: HashSet<Pubkey>,
+    seeded: bool,
+}
+
+impl BondWatcher {
+    /// Loads previously seen bonds from `path`. Without an existing file the first observation
+    /// only seeds the known set, so a fresh start doesn't report every existing bond as new.
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let mut watcher = Self {
+            path,
+            ..Self::default()
+        };
+        if let Some(path) = watcher.path.as_ref().filter(|path| path.exists()) {
+            let contents = fs::read_to_string(path)
+                .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+            let pubkeys: Vec<String> = serde_json::from_str(&contents)
+                .map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))?;
+            for pubkey in pubkeys {
+                watcher.known.insert(
+                    Pubkey::from_str(&pubkey)
+                        .map_err(|e| anyhow!("Invalid pubkey {} in known bonds: {}", pubkey, e))?,
+                );
+            }
+            watcher.seeded = true;
+        }
+        Ok(watcher)
+    }
+
+    /// Records `pubkeys` as seen and returns the ones that weren't known before.
+    pub fn observe(&mut self, pubkeys: impl IntoIterator<Item = Pubkey>) -> Vec<Pubkey> {
+        let mut new_bonds = Vec::new();
+        for pubkey in pubkeys {
+            if self.known.insert(pubkey) && self.seeded {
+                new_bonds.push(pubkey);
+            }
+        }
+        self.seeded = true;
+        self.save();
+        new_bonds
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut pubkeys: Vec<String> = self.known.iter().map(|pubkey| pubkey.to_string()).collect();
+        pubkeys.sort();
+        let write = serde_json::to_vec_pretty(&pubkeys)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| fs::write(path, bytes).map_err(|e| e.to_string()));
+        if let Err(e) = write {
+            warn!("Failed to write known bonds to {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_observation_seeds_without_reporting() {
+        let mut watcher = BondWatcher::load(None).unwrap();
+        let existing = Pubkey::new_unique();
+        assert!(watcher.observe([existing]).is_empty());
+
+        let created = Pubkey::new_unique();
+        assert_eq!(watcher.observe([existing, created]), vec![created]);
+        assert!(watcher.observe([existing, created]).is_empty());
+    }
+
+    #[test]
+    fn test_persisted_bonds_report_across_runs() {
+        let path =
+            std::env::temp_dir().join(format!("pye-known-bonds-{}.json", std::process::id()));
+        let existing = Pubkey::new_unique();
+        BondWatcher::load(Some(path.clone()))
+            .unwrap()
+            .observe([existing]);
+
+        let created = Pubkey::new_unique();
+        let mut watcher = BondWatcher::load(Some(path.clone())).unwrap();
+        assert_eq!(watcher.observe([existing, created]), vec![created]);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+use anchor_client::Cluster;
+use clap::ValueEnum;
+use pye_core_cpi::pye_core;
+use solana_sdk::pubkey::Pubkey;
+
+const JITO_MAINNET_API_URL: &str = "https://kobe.mainnet.jito.network/api/v1/validators";
+
+/// Cluster presets selecting default endpoints, the pye program ID and the MEV source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ClusterPreset {
+    #[default]
+    Mainnet,
+    Testnet,
+    Devnet,
+    Localnet,
+}
+
+impl ClusterPreset {
+    /// Default RPC endpoint, used when `--rpc` is not given.
+    pub fn rpc_url(&self) -> &'static str {
+        match self {
+            ClusterPreset::Mainnet => "https://api.mainnet-beta.solana.com",
+            ClusterPreset::Testnet => "https://api.testnet.solana.com",
+            ClusterPreset::Devnet => "https://api.devnet.solana.com",
+            ClusterPreset::Localnet => "http://127.0.0.1:8899",
+        }
+    }
+
+    /// Default pye program ID, used when `--program-id` is not given.
+    pub fn program_id(&self) -> Pubkey {
+        // pye-core is deployed at its IDL address on every public cluster, and localnet
+        // deployments are expected to load the program at the same address.
+        pye_core::ID
+    }
+
+    /// Jito's validator API only covers mainnet, other clusters treat MEV rewards as zero.
+    pub fn jito_api_url(&self) -> Option<&'static str> {
+        match self {
+            ClusterPreset::Mainnet => Some(JITO_MAINNET_API_URL),
+            _ => None,
+        }
+    }
+
+    /// Anchor cluster for `rpc`. The preset's own endpoints keep their well-known websocket URLs,
+    /// anything else derives the websocket URL from the RPC URL.
+    pub fn anchor_cluster(&self, rpc: &str) -> Cluster {
+        if rpc != self.rpc_url() {
+            return Cluster::Custom(rpc.to_string(), rpc.replace("http", "ws"));
+        }
+        match self {
+            ClusterPreset::Mainnet => Cluster::Mainnet,
+            ClusterPreset::Testnet => Cluster::Testnet,
+            ClusterPreset::Devnet => Cluster::Devnet,
+            ClusterPreset::Localnet => Cluster::Localnet,
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+
+use crate::receipts::{latest_receipts, load_receipts, PaymentReceipt};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum AnalyticsFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct AnalyticsArgs {
+    /// Receipts directory written by validator-pye-account-manager --receipts-dir.
+    #[arg(long, env)]
+    receipts_dir: PathBuf,
+    /// First epoch aggregated [default: the oldest receipt's]
+    #[arg(long, env)]
+    from_epoch: Option<u64>,
+    /// Last epoch aggregated [default: the latest receipt's]
+    #[arg(long, env)]
+    to_epoch: Option<u64>,
+    /// Output format.
+    #[arg(long, env, value_enum, default_value_t = AnalyticsFormat::Json)]
+    format: AnalyticsFormat,
+}
+
+/// Excess rewards summed over a set of bond-epochs, in lamports.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+struct Totals {
+    bond_epochs: u64,
+    inflation: i64,
+    mev: i64,
+    block: i64,
+    total: i64,
+    /// Sum of the positive totals, i.e. what the bonds were owed.
+    owed: i64,
+    /// Sum of the totals whose payment landed.
+    paid: i64,
+}
+
+impl Totals {
+    fn add(&mut self, receipt: &PaymentReceipt) {
+        let rewards = &receipt.excess_rewards;
+        // A deferred MEV payment belongs to a bond-epoch counted with its first payment.
+        if !receipt.deferred_mev {
+            self.bond_epochs += 1;
+        }
+        self.inflation += rewards.inflation;
+        self.mev += rewards.mev;
+        self.block += rewards.block;
+        self.total += rewards.total;
+        self.owed += rewards.total.max(0);
+        if receipt.payment == "paid" {
+            self.paid += rewards.total;
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct EpochTotals {
+    epoch: u64,
+    #[serde(flatten)]
+    totals: Totals,
+    /// What the trend line gives for the epoch's `owed`.
+    trend: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct GroupTotals {
+    name: String,
+    #[serde(flatten)]
+    totals: Totals,
+}
+
+/// Least-squares line through the amounts owed per epoch.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+struct Trend {
+    /// Change in the amount owed per epoch, in lamports.
+    slope: f64,
+    intercept: f64,
+    /// The amount owed the epoch after `to_epoch` if the trend holds, never below zero.
+    projected_next_epoch: f64,
+}
+
+impl Trend {
+    fn fit(points: &[(u64, i64)], next_epoch: u64) -> Self {
+        let n = points.len() as f64;
+        if points.is_empty() {
+            return Self::default();
+        }
+        let mean_x = points.iter().map(|(x, _)| *x as f64).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| *y as f64).sum::<f64>() / n;
+        let (covariance, variance) =
+            points
+                .iter()
+                .fold((0.0, 0.0), |(covariance, variance), (x, y)| {
+                    let dx = *x as f64 - mean_x;
+                    (covariance + dx * (*y as f64 - mean_y), variance + dx * dx)
+                });
+        // A single epoch has no trend, the amount owed is projected to stay the same.
+        let slope = if variance > 0.0 {
+            covariance / variance
+        } else {
+            0.0
+        };
+        let intercept = mean_y - slope * mean_x;
+        Self {
+            slope,
+            intercept,
+            projected_next_epoch: (slope * next_epoch as f64 + intercept).max(0.0),
+        }
+    }
+
+    fn at(&self, epoch: u64) -> f64 {
+        self.slope * epoch as f64 + self.intercept
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct Analytics {
+    from_epoch: u64,
+    to_epoch: u64,
+    totals: Totals,
+    /// Average amount owed per epoch over the range, epochs without receipts included.
+    average_owed_per_epoch: f64,
+    trend: Trend,
+    epochs: Vec<EpochTotals>,
+    issuers: Vec<GroupTotals>,
+    bonds: Vec<GroupTotals>,
+}
+
+/// Aggregates the latest receipt of each bond-epoch between `from_epoch` and `to_epoch`.
+fn aggregate(receipts: &[PaymentReceipt], from_epoch: u64, to_epoch: u64) -> Analytics {
+    let mut totals = Totals::default();
+    let mut epochs: BTreeMap<u64, Totals> = BTreeMap::new();
+    let mut issuers: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut bonds: BTreeMap<String, Totals> = BTreeMap::new();
+    for receipt in receipts
+        .iter()
+        .filter(|receipt| (from_epoch..=to_epoch).contains(&receipt.epoch))
+    {
+        totals.add(receipt);
+        epochs.entry(receipt.epoch).or_default().add(receipt);
+        issuers
+            .entry(receipt.bond_inputs.issuer.clone())
+            .or_default()
+            .add(receipt);
+        bonds
+            .entry(receipt.pye_account.clone())
+            .or_default()
+            .add(receipt);
+    }
+    let points: Vec<(u64, i64)> = epochs
+        .iter()
+        .map(|(epoch, totals)| (*epoch, totals.owed))
+        .collect();
+    let trend = Trend::fit(&points, to_epoch + 1);
+    let groups = |groups: BTreeMap<String, Totals>| {
+        groups
+            .into_iter()
+            .map(|(name, totals)| GroupTotals { name, totals })
+            .collect()
+    };
+    Analytics {
+        from_epoch,
+        to_epoch,
+        average_owed_per_epoch: totals.owed as f64 / (to_epoch - from_epoch + 1) as f64,
+        totals,
+        epochs: epochs
+            .into_iter()
+            .map(|(epoch, totals)| EpochTotals {
+                epoch,
+                totals,
+                trend: trend.at(epoch),
+            })
+            .collect(),
+        trend,
+        issuers: groups(issuers),
+        bonds: groups(bonds),
+    }
+}
+
+/// One CSV row per aggregate, the `group` column telling which.
+fn to_csv(analytics: &Analytics) -> String {
+    let mut csv =
+        String::from("group,name,bond_epochs,inflation,mev,block,total,owed,paid,trend\n");
+    let mut row = |group: &str, name: &str, totals: &Totals, trend: Option<f64>| {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{}\n",
+            group,
+            name,
+            totals.bond_epochs,
+            totals.inflation,
+            totals.mev,
+            totals.block,
+            totals.total,
+            totals.owed,
+            totals.paid,
+            trend
+                .map(|trend| format!("{:.0}", trend))
+                .unwrap_or_default()
+        ));
+    };
+    let range = format!("{}-{}", analytics.from_epoch, analytics.to_epoch);
+    row("range", &range, &analytics.totals, None);
+    for epoch in &analytics.epochs {
+        row(
+            "epoch",
+            &epoch.epoch.to_string(),
+            &epoch.totals,
+            Some(epoch.trend),
+        );
+    }
+    row(
+        "projection",
+        &(analytics.to_epoch + 1).to_string(),
+        &Totals::default(),
+        Some(analytics.trend.projected_next_epoch),
+    );
+    for issuer in &analytics.issuers {
+        row("issuer", &issuer.name, &issuer.totals, None);
+    }
+    for bond in &analytics.bonds {
+        row("bond", &bond.name, &bond.totals, None);
+    }
+    csv
+}
+
+pub async fn handle_analytics(args: AnalyticsArgs) -> Result<()> {
+    let receipts = latest_receipts(load_receipts(&args.receipts_dir)?);
+    let (Some(oldest), Some(latest)) = (receipts.first(), receipts.last()) else {
+        return Err(anyhow!("No receipts in {}", args.receipts_dir.display()));
+    };
+    let from_epoch = args.from_epoch.unwrap_or(oldest.epoch);
+    let to_epoch = args.to_epoch.unwrap_or(latest.epoch);
+    if from_epoch > to_epoch {
+        return Err(anyhow!(
+            "--from-epoch {} is after --to-epoch {}",
+            from_epoch,
+            to_epoch
+        ));
+    }
+    let analytics = aggregate(&receipts, from_epoch, to_epoch);
+    match args.format {
+        AnalyticsFormat::Json => println!("{}", serde_json::to_string_pretty(&analytics)?),
+        AnalyticsFormat::Csv => print!("{}", to_csv(&analytics)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::PaymentOutcome;
+    use crate::receipts::{sample_receipt, ExcessRewards};
+
+    fn receipt(epoch: u64, pye_account: &str, issuer: &str, total: i64) -> PaymentReceipt {
+        let mut receipt = sample_receipt(Ok(PaymentOutcome::DryRun));
+        receipt.epoch = epoch;
+        receipt.pye_account = pye_account.to_string();
+        receipt.bond_inputs.issuer = issuer.to_string();
+        receipt.excess_rewards = ExcessRewards {
+            inflation: 0,
+            mev: total,
+            block: 0,
+            total,
+        };
+        receipt.payment = if total > 0 { "paid" } else { "not_due" }.to_string();
+        receipt
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let receipts = vec![
+            receipt(799, "bond1", "issuer1", 1_000),
+            receipt(800, "bond1", "issuer1", 100),
+            receipt(800, "bond2", "issuer2", 200),
+            receipt(801, "bond1", "issuer1", 200),
+            receipt(801, "bond2", "issuer2", 300),
+            receipt(802, "bond1", "issuer1", -50),
+        ];
+        let analytics = aggregate(&receipts, 800, 802);
+        assert_eq!(analytics.totals.bond_epochs, 5);
+        assert_eq!(analytics.totals.total, 750);
+        assert_eq!(analytics.totals.owed, 800);
+        assert_eq!(analytics.totals.paid, 800);
+        assert_eq!(analytics.average_owed_per_epoch, 800.0 / 3.0);
+        assert_eq!(analytics.issuers[0].name, "issuer1");
+        assert_eq!(analytics.issuers[0].totals.owed, 300);
+        assert_eq!(analytics.bonds[1].totals.owed, 500);
+        // Owed 300, 500 and 0 over epochs 800 to 802.
+        assert_eq!(analytics.trend.slope, -150.0);
+        assert_eq!(analytics.trend.projected_next_epoch, 0.0);
+
+        let csv = to_csv(&analytics);
+        assert!(csv.starts_with("group,name,"));
+        assert!(csv.contains("\nrange,800-802,5,0,750,0,750,800,800,\n"));
+        assert!(csv.contains("\nepoch,801,2,0,500,0,500,500,500,267\n"));
+    }
+}
@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+
+use crate::archive::{Archive, ArchiveArgs};
+
+#[derive(Clone, Debug, Parser)]
+pub struct ArchiveUrlsArgs {
+    #[command(flatten)]
+    archive: ArchiveArgs,
+    /// Epoch whose archived records to share.
+    #[arg(long, env)]
+    epoch: u64,
+    /// Only share this bond's receipts and attestations, e.g. with its issuer.
+    #[arg(long, env)]
+    pye_account: Option<String>,
+    /// How long the URLs stay valid, in seconds. S3 allows up to 7 days.
+    #[arg(long, env, default_value = "604800")]
+    expires_in_secs: u64,
+}
+
+pub async fn handle_archive_urls(args: ArchiveUrlsArgs) -> Result<()> {
+    let archive =
+        Archive::from_args(&args.archive)?.ok_or_else(|| anyhow!("--archive-url is required"))?;
+    let urls = archive
+        .signed_urls(
+            args.epoch,
+            args.pye_account.as_deref(),
+            Duration::from_secs(args.expires_in_secs),
+        )
+        .await?;
+    if urls.is_empty() {
+        return Err(anyhow!("No archived records for epoch {}", args.epoch));
+    }
+    for (location, url) in urls {
+        println!("{}\n  {}", location, url);
+    }
+    Ok(())
+}
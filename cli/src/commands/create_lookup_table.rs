@@ -0,0 +1,50 @@
+use anyhow::Result;
+use clap::Parser;
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    cluster::ClusterPreset,
+    keypairs::load_payer,
+    lookup_tables::{create_table, extend_table, fetch_lookup_table},
+    rpc_utils::new_rpc_client,
+    transactions::SendOptions,
+};
+
+#[derive(Clone, Debug, Parser)]
+pub struct CreateLookupTableArgs {
+    /// Cluster preset selecting the default RPC endpoint.
+    #[arg(long, env, value_enum, default_value_t = ClusterPreset::Mainnet)]
+    cluster: ClusterPreset,
+    /// RPC Endpoint [default: the cluster preset's endpoint]
+    #[arg(short, long, env)]
+    rpc: Option<String>,
+    /// Keypair that pays for and becomes the authority of the table, or `-` to read it from
+    /// stdin [default: the PAYER_KEYPAIR_JSON environment variable]. Use the fee payer's keypair
+    /// so `--extend-lookup-table` can add to it later.
+    #[arg(short, long, env)]
+    payer: Option<String>,
+    /// Addresses to add to the new table, e.g. the pye accounts and stake accounts paid every
+    /// epoch.
+    #[arg(long = "address", value_delimiter = ',')]
+    addresses: Vec<Pubkey>,
+}
+
+pub async fn handle_create_lookup_table(args: CreateLookupTableArgs) -> Result<()> {
+    let rpc = args
+        .rpc
+        .clone()
+        .unwrap_or_else(|| args.cluster.rpc_url().to_string());
+    let client = new_rpc_client(rpc, CommitmentConfig::confirmed(), None, None);
+    let authority = load_payer(args.payer.as_deref()).await?;
+    let send_options = SendOptions::default();
+
+    let address = create_table(&client, &*authority, &send_options).await?;
+    if !args.addresses.is_empty() {
+        let table = fetch_lookup_table(&client, &address).await?;
+        extend_table(&client, table, &*authority, &args.addresses, &send_options).await?;
+    }
+    println!("Created lookup table {}", address);
+    println!("\n--tx-version v0 --lookup-table {}", address);
+    Ok(())
+}
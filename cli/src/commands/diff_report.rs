@@ -0,0 +1,332 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use serde_json::Value;
+
+use crate::receipts::{latest_receipts, load_receipts, PaymentReceipt};
+
+#[derive(Clone, Debug, Parser)]
+pub struct DiffReportArgs {
+    /// First report: a receipts directory written by --receipts-dir, a single receipt, or a JSON
+    /// array of receipts.
+    a: PathBuf,
+    /// Second report, in any of the same forms.
+    b: PathBuf,
+    /// Differences of up to this many lamports are ignored.
+    #[arg(long, env, default_value_t = 0)]
+    tolerance_lamports: u64,
+}
+
+/// A difference between the two reports.
+#[derive(Clone, Debug, PartialEq)]
+enum Difference {
+    /// An epoch-wide input differs, e.g. the block rewards served by two RPC providers.
+    Input {
+        epoch: u64,
+        input: String,
+        a: Value,
+        b: Value,
+    },
+    /// A bond's active stake or excess rewards in one category differ.
+    Amount {
+        epoch: u64,
+        pye_account: String,
+        category: &'static str,
+        a: i64,
+        b: i64,
+    },
+    /// A bond is only in one of the reports.
+    Missing {
+        epoch: u64,
+        pye_account: String,
+        in_a: bool,
+    },
+}
+
+/// Reads a report, keeping the latest receipt of each bond and epoch.
+fn load_report(path: &Path) -> Result<Vec<PaymentReceipt>> {
+    if path.is_dir() {
+        return Ok(latest_receipts(load_receipts(path)?));
+    }
+    let contents =
+        fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let value: Value = serde_json::from_slice(&contents)
+        .map_err(|e| anyhow!("Invalid report {}: {}", path.display(), e))?;
+    let receipts = match value {
+        Value::Array(_) => serde_json::from_value(value),
+        value => serde_json::from_value(value).map(|receipt| vec![receipt]),
+    }
+    .map_err(|e| anyhow!("Invalid report {}: {}", path.display(), e))?;
+    Ok(latest_receipts(receipts))
+}
+
+/// The compared amounts of a receipt, in lamports.
+fn amounts(receipt: &PaymentReceipt) -> [(&'static str, i64); 5] {
+    let rewards = &receipt.excess_rewards;
+    [
+        ("active_stake", receipt.bond_inputs.active_stake as i64),
+        ("inflation", rewards.inflation),
+        ("mev", rewards.mev),
+        ("block", rewards.block),
+        ("total", rewards.total),
+    ]
+}
+
+/// Whether two input values differ by more than `tolerance`. Non-numeric inputs must match.
+fn input_differs(a: &Value, b: &Value, tolerance: u64) -> bool {
+    match (a.as_i64(), b.as_i64()) {
+        (Some(a), Some(b)) => a.abs_diff(b) > tolerance,
+        _ => a != b,
+    }
+}
+
+/// The differences above `tolerance` between two reports, epoch by epoch, and the number of
+/// differences ignored as within it.
+fn diff(a: &[PaymentReceipt], b: &[PaymentReceipt], tolerance: u64) -> (Vec<Difference>, usize) {
+    let index = |receipts: &[PaymentReceipt]| -> BTreeMap<(u64, String), PaymentReceipt> {
+        let mut index: BTreeMap<(u64, String), PaymentReceipt> = BTreeMap::new();
+        for receipt in receipts {
+            let key = (receipt.epoch, receipt.pye_account.clone());
+            match index.get_mut(&key) {
+                // Sorted after the bond-epoch's first payment, a deferred MEV payment adds to it.
+                Some(first) if receipt.deferred_mev => {
+                    first.excess_rewards.mev += receipt.excess_rewards.mev;
+                    first.excess_rewards.total += receipt.excess_rewards.total;
+                }
+                _ => {
+                    index.insert(key, receipt.clone());
+                }
+            }
+        }
+        index
+    };
+    let (a, b) = (index(a), index(b));
+    let mut differences = Vec::new();
+    let mut ignored = 0;
+
+    // Every bond of an epoch shares its inputs, so they are compared once per epoch.
+    let epoch_inputs = |receipts: &BTreeMap<(u64, String), PaymentReceipt>| {
+        let mut inputs = BTreeMap::new();
+        for ((epoch, _), receipt) in receipts {
+            inputs
+                .entry(*epoch)
+                .or_insert_with(|| serde_json::to_value(&receipt.epoch_inputs).unwrap_or_default());
+        }
+        inputs
+    };
+    let inputs_b = epoch_inputs(&b);
+    for (epoch, inputs_a) in epoch_inputs(&a) {
+        let (Value::Object(inputs_a), Some(Value::Object(inputs_b))) =
+            (inputs_a, inputs_b.get(&epoch))
+        else {
+            continue;
+        };
+        for (input, value_a) in &inputs_a {
+            let value_b = inputs_b.get(input).unwrap_or(&Value::Null);
+            if input_differs(value_a, value_b, tolerance) {
+                differences.push(Difference::Input {
+                    epoch,
+                    input: input.clone(),
+                    a: value_a.clone(),
+                    b: value_b.clone(),
+                });
+            } else if value_a != value_b {
+                ignored += 1;
+            }
+        }
+    }
+
+    for ((epoch, pye_account), receipt_a) in &a {
+        let Some(receipt_b) = b.get(&(*epoch, pye_account.clone())) else {
+            differences.push(Difference::Missing {
+                epoch: *epoch,
+                pye_account: pye_account.clone(),
+                in_a: true,
+            });
+            continue;
+        };
+        for ((category, amount_a), (_, amount_b)) in
+            amounts(receipt_a).into_iter().zip(amounts(receipt_b))
+        {
+            if amount_a.abs_diff(amount_b) > tolerance {
+                differences.push(Difference::Amount {
+                    epoch: *epoch,
+                    pye_account: pye_account.clone(),
+                    category,
+                    a: amount_a,
+                    b: amount_b,
+                });
+            } else if amount_a != amount_b {
+                ignored += 1;
+            }
+        }
+    }
+    for (epoch, pye_account) in b.keys().filter(|key| !a.contains_key(key)) {
+        differences.push(Difference::Missing {
+            epoch: *epoch,
+            pye_account: pye_account.clone(),
+            in_a: false,
+        });
+    }
+    (differences, ignored)
+}
+
+pub async fn handle_diff_report(args: DiffReportArgs) -> Result<()> {
+    let a = load_report(&args.a)?;
+    let b = load_report(&args.b)?;
+    let (differences, ignored) = diff(&a, &b, args.tolerance_lamports);
+    println!(
+        "Compared {} bond-epochs in {} with {} in {}",
+        a.len(),
+        args.a.display(),
+        b.len(),
+        args.b.display()
+    );
+
+    let inputs: Vec<&Difference> = differences
+        .iter()
+        .filter(|difference| matches!(difference, Difference::Input { .. }))
+        .collect();
+    if !inputs.is_empty() {
+        println!(
+            "\n{:<6}  {:<28}  {:>24}  {:>24}",
+            "EPOCH", "INPUT", "A", "B"
+        );
+        for difference in inputs {
+            if let Difference::Input { epoch, input, a, b } = difference {
+                // Values only honor the column width once rendered.
+                let (a, b) = (a.to_string(), b.to_string());
+                println!("{:<6}  {:<28}  {:>24}  {:>24}", epoch, input, a, b);
+            }
+        }
+    }
+
+    let bonds: Vec<&Difference> = differences
+        .iter()
+        .filter(|difference| !matches!(difference, Difference::Input { .. }))
+        .collect();
+    if !bonds.is_empty() {
+        println!(
+            "\n{:<6}  {:<44}  {:<12}  {:>18}  {:>18}  {:>18}",
+            "EPOCH", "BOND", "CATEGORY", "A", "B", "B - A"
+        );
+        for difference in bonds {
+            match difference {
+                Difference::Amount {
+                    epoch,
+                    pye_account,
+                    category,
+                    a,
+                    b,
+                } => println!(
+                    "{:<6}  {:<44}  {:<12}  {:>18}  {:>18}  {:>+18}",
+                    epoch,
+                    pye_account,
+                    category,
+                    a,
+                    b,
+                    i128::from(*b) - i128::from(*a)
+                ),
+                Difference::Missing {
+                    epoch,
+                    pye_account,
+                    in_a,
+                } => println!(
+                    "{:<6}  {:<44}  only in {}",
+                    epoch,
+                    pye_account,
+                    if *in_a { "A" } else { "B" }
+                ),
+                Difference::Input { .. } => {}
+            }
+        }
+    }
+
+    if ignored > 0 {
+        println!(
+            "\n{} differences within {} lamports ignored",
+            ignored, args.tolerance_lamports
+        );
+    }
+    if differences.is_empty() {
+        println!("\nThe reports match");
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} differences above {} lamports",
+            differences.len(),
+            args.tolerance_lamports
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    use crate::control::PaymentOutcome;
+    use crate::receipts::sample_receipt;
+
+    #[test]
+    fn test_diff() {
+        let receipt = |pye_account: &str| {
+            let mut receipt = sample_receipt(Ok(PaymentOutcome::DryRun));
+            receipt.pye_account = pye_account.to_string();
+            receipt
+        };
+        // Both bonds of a earn 10 lamports of block rewards out of 3_000, 60 in total.
+        let a = vec![receipt("bond1"), receipt("bond2")];
+        let mut b = vec![receipt("bond1"), receipt("bond3")];
+        for receipt in &mut b {
+            receipt.epoch_inputs.validator_block_rewards = 3_500;
+            receipt.excess_rewards.block = 15;
+            receipt.excess_rewards.total = 65;
+        }
+        let (differences, ignored) = diff(&a, &b, 0);
+        assert_eq!(ignored, 0);
+        assert_eq!(
+            differences,
+            vec![
+                Difference::Input {
+                    epoch: 800,
+                    input: "validator_block_rewards".to_string(),
+                    a: json!(3_000),
+                    b: json!(3_500),
+                },
+                Difference::Amount {
+                    epoch: 800,
+                    pye_account: "bond1".to_string(),
+                    category: "block",
+                    a: 10,
+                    b: 15,
+                },
+                Difference::Amount {
+                    epoch: 800,
+                    pye_account: "bond1".to_string(),
+                    category: "total",
+                    a: 60,
+                    b: 65,
+                },
+                Difference::Missing {
+                    epoch: 800,
+                    pye_account: "bond2".to_string(),
+                    in_a: true,
+                },
+                Difference::Missing {
+                    epoch: 800,
+                    pye_account: "bond3".to_string(),
+                    in_a: false,
+                },
+            ]
+        );
+
+        // Within the tolerance, only the input and bonds missing from a report remain.
+        let (differences, ignored) = diff(&a, &b, 5);
+        assert_eq!(ignored, 2);
+        assert_eq!(differences.len(), 3);
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use clap::Parser;
+use pye_core_cpi::pye_core::accounts::SoloValidatorBond as SoloValidatorPyeAccount;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    accounts::{
+        fetch_solo_validator_pye_accounts_by_vote_key, GpaChunking, PyeAccountFetchOptions,
+    },
+    cluster::ClusterPreset,
+    rewards::inflation_model::bond_stake_accounts,
+    rpc_utils::new_rpc_client,
+    sol_format::format_sol,
+    table::{Align, Table},
+};
+
+/// getMultipleAccounts accepts at most 100 pubkeys per request.
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+#[derive(Clone, Debug, Parser)]
+pub struct DiscoverIssuersArgs {
+    /// Cluster preset selecting the default RPC endpoint and program ID.
+    #[arg(long, env, value_enum, default_value_t = ClusterPreset::Mainnet)]
+    cluster: ClusterPreset,
+    /// RPC Endpoint [default: the cluster preset's endpoint]
+    #[arg(short, long, env)]
+    rpc: Option<String>,
+    /// The Pye program ID [default: the cluster preset's program ID]. Repeat to scan several
+    /// programs.
+    #[arg(long = "program-id", env = "PROGRAM_ID", value_delimiter = ',')]
+    program_ids: Vec<Pubkey>,
+    /// Validator's vote account
+    #[arg(short, long, env)]
+    vote_pubkey: Pubkey,
+    /// Also count bonds whose maturity has already been handled.
+    #[arg(long, env)]
+    include_matured: bool,
+    /// Maximum RPC requests to send concurrently.
+    #[arg(long, env, default_value = "50")]
+    concurrency: usize,
+    /// How getProgramAccounts queries are split up. Large fleets may time out with a single query.
+    #[arg(long, env, value_enum, default_value_t = GpaChunking::Auto)]
+    gpa_chunking: GpaChunking,
+}
+
+/// Bonds and stake attributed to a single issuer.
+#[derive(Debug, Default)]
+struct IssuerSummary {
+    bonds: usize,
+    stake_lamports: u64,
+}
+
+pub async fn handle_discover_issuers(args: DiscoverIssuersArgs) -> Result<()> {
+    let rpc = args
+        .rpc
+        .clone()
+        .unwrap_or_else(|| args.cluster.rpc_url().to_string());
+    let program_ids = if args.program_ids.is_empty() {
+        vec![args.cluster.program_id()]
+    } else {
+        args.program_ids.clone()
+    };
+    let client = new_rpc_client(rpc, CommitmentConfig::confirmed(), None, None);
+    let fetch_options = PyeAccountFetchOptions {
+        strict: false,
+        chunking: args.gpa_chunking,
+        concurrency: args.concurrency,
+    };
+
+    let mut bonds = Vec::new();
+    for program_id in program_ids.iter() {
+        bonds.extend(
+            fetch_solo_validator_pye_accounts_by_vote_key(
+                &client,
+                program_id,
+                &args.vote_pubkey,
+                None,
+                !args.include_matured,
+                &fetch_options,
+            )
+            .await?,
+        );
+    }
+
+    let stakes = fetch_stake_balances(&client, &bonds).await?;
+    let mut summaries: HashMap<Pubkey, IssuerSummary> = HashMap::new();
+    for (_, bond) in bonds.iter() {
+        let summary = summaries.entry(bond.issuer).or_default();
+        summary.bonds += 1;
+        summary.stake_lamports +=
+            bond_stake_accounts(&bond.stake_account, &bond.transient_stake_account)
+                .iter()
+                .filter_map(|pubkey| stakes.get(pubkey))
+                .sum::<u64>();
+    }
+    let mut summaries: Vec<(Pubkey, IssuerSummary)> = summaries.into_iter().collect();
+    summaries.sort_by(|a, b| b.1.stake_lamports.cmp(&a.1.stake_lamports));
+
+    println!(
+        "Found {} bonds from {} issuers targeting {}\n",
+        bonds.len(),
+        summaries.len(),
+        args.vote_pubkey
+    );
+    let mut table = Table::new(&[
+        ("ISSUER", Align::Left),
+        ("BONDS", Align::Right),
+        ("TOTAL STAKE (SOL)", Align::Right),
+    ]);
+    for (issuer, summary) in summaries.iter() {
+        table.push(vec![
+            issuer.to_string(),
+            summary.bonds.to_string(),
+            format_sol(summary.stake_lamports),
+        ]);
+    }
+    table.print()?;
+    if !summaries.is_empty() {
+        let issuers: Vec<String> = summaries
+            .iter()
+            .map(|(issuer, _)| issuer.to_string())
+            .collect();
+        println!("\n--issuers {}", issuers.join(","));
+    }
+    Ok(())
+}
+
+/// Fetches the lamport balances of every bond's stake and transient stake accounts, when set.
+async fn fetch_stake_balances(
+    client: &RpcClient,
+    bonds: &[(Pubkey, SoloValidatorPyeAccount)],
+) -> Result<HashMap<Pubkey, u64>> {
+    let pubkeys: Vec<Pubkey> = bonds
+        .iter()
+        .flat_map(|(_, bond)| {
+            bond_stake_accounts(&bond.stake_account, &bond.transient_stake_account)
+        })
+        .collect();
+    let mut balances = HashMap::new();
+    for chunk in pubkeys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let accounts = client.get_multiple_accounts(chunk).await?;
+        for (pubkey, account) in chunk.iter().zip(accounts) {
+            if let Some(account) = account {
+                balances.insert(*pubkey, account.lamports);
+            }
+        }
+    }
+    Ok(balances)
+}
@@ -0,0 +1,452 @@
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signer::Signer;
+
+use crate::{
+    accounts::{
+        fetch_solo_validator_pye_accounts_by_vote_key, GpaChunking, PyeAccountFetchOptions,
+    },
+    cluster::ClusterPreset,
+    keypairs::load_signers,
+    rewards::mev_rewards::fetch_mev_data,
+    rpc_utils::new_rpc_client,
+    sol_format::format_amount,
+};
+
+#[derive(Clone, Debug, Parser)]
+pub struct DoctorArgs {
+    /// Cluster preset selecting the default RPC endpoint, program ID and MEV source.
+    #[arg(long, env, value_enum, default_value_t = ClusterPreset::Mainnet)]
+    cluster: ClusterPreset,
+    /// RPC Endpoint [default: the cluster preset's endpoint]
+    #[arg(short, long, env)]
+    rpc: Option<String>,
+    /// The Pye program ID [default: the cluster preset's program ID]. Repeat to check several
+    /// programs.
+    #[arg(long = "program-id", env = "PROGRAM_ID", value_delimiter = ',')]
+    program_ids: Vec<Pubkey>,
+    /// Validator's vote account
+    #[arg(short, long, env)]
+    vote_pubkey: Pubkey,
+    /// Path to payer keypair, or `-` to read it from stdin [default: the PAYER_KEYPAIR_JSON
+    /// environment variable]
+    #[arg(short, long, env)]
+    payer: Option<String>,
+    /// Path to a keypair that pays transaction fees instead of the payer. `-` reads it from stdin.
+    #[arg(long, env)]
+    fee_payer: Option<String>,
+    /// Skip the payer checks, for setups that only run with --dry-run.
+    #[arg(long, env)]
+    skip_payer: bool,
+    /// Balance (in lamports) below which the payer, and the fee payer, are reported as
+    /// underfunded.
+    #[arg(long, env, default_value = "100000000")]
+    min_payer_balance: u64,
+    /// Jito API endpoint used to look up the validator's MEV rewards [default: the cluster
+    /// preset's Jito API]
+    #[arg(long, env)]
+    jito_api_url: Option<String>,
+    /// Maximum RPC requests to send concurrently.
+    #[arg(long, env, default_value = "50")]
+    concurrency: usize,
+    /// How getProgramAccounts queries are split up. Large fleets may time out with a single query.
+    #[arg(long, env, value_enum, default_value_t = GpaChunking::Auto)]
+    gpa_chunking: GpaChunking,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CheckStatus {
+    Pass,
+    /// Works, but something may need attention.
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckStatus::Pass => write!(f, "PASS"),
+            CheckStatus::Warn => write!(f, "WARN"),
+            CheckStatus::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+/// The outcome of a preflight check, with a hint on how to fix it when it didn't pass.
+#[derive(Clone, Debug)]
+struct Check {
+    name: &'static str,
+    status: CheckStatus,
+    detail: String,
+    hint: Option<&'static str>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.status, self.name, self.detail)?;
+        if let Some(hint) = self.hint {
+            write!(f, "\n       {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the RPC still serves the blocks of the epoch the manager computes next, which
+/// starts at `first_needed_slot`.
+fn block_history_check(first_available_slot: u64, first_needed_slot: u64) -> Check {
+    const NAME: &str = "getBlock history";
+    if first_available_slot <= first_needed_slot {
+        Check::pass(
+            NAME,
+            format!(
+                "blocks available from slot {}, the previous epoch starts at {}",
+                first_available_slot, first_needed_slot
+            ),
+        )
+    } else {
+        Check::fail(
+            NAME,
+            format!(
+                "blocks only available from slot {}, the previous epoch starts at {}",
+                first_available_slot, first_needed_slot
+            ),
+            "Use an RPC that keeps at least two epochs of block history, e.g. one backed by BigTable.",
+        )
+    }
+}
+
+pub async fn handle_doctor(args: DoctorArgs) -> Result<()> {
+    let rpc = args
+        .rpc
+        .clone()
+        .unwrap_or_else(|| args.cluster.rpc_url().to_string());
+    let program_ids = if args.program_ids.is_empty() {
+        vec![args.cluster.program_id()]
+    } else {
+        args.program_ids.clone()
+    };
+    let jito_api_url = args
+        .jito_api_url
+        .clone()
+        .or_else(|| args.cluster.jito_api_url().map(str::to_string));
+    let client = new_rpc_client(rpc.clone(), CommitmentConfig::confirmed(), None, None);
+
+    let mut checks = Vec::new();
+    let epoch = match client.get_version().await {
+        Ok(version) => {
+            checks.push(Check::pass(
+                "RPC",
+                format!("{} runs solana-core {}", rpc, version.solana_core),
+            ));
+            match client.get_epoch_info().await {
+                Ok(epoch_info) => Some(epoch_info.epoch),
+                Err(e) => {
+                    checks.push(Check::fail(
+                        "RPC",
+                        format!("getEpochInfo failed: {}", e),
+                        "Check the RPC URL and that the provider allows getEpochInfo.",
+                    ));
+                    None
+                }
+            }
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "RPC",
+                format!("{} is unreachable: {}", rpc, e),
+                "Check --rpc, the network and the RPC provider's API key.",
+            ));
+            None
+        }
+    };
+
+    // Every other check needs the RPC.
+    if let Some(epoch) = epoch {
+        checks.extend(check_rpc_history(&client, &args.vote_pubkey, epoch).await);
+        for program_id in &program_ids {
+            checks.push(check_program(&client, program_id).await);
+        }
+        checks.push(check_vote_account(&client, &args.vote_pubkey).await);
+        checks.push(check_gpa(&client, &program_ids, &args).await);
+        if args.skip_payer {
+            checks.push(Check::pass("Payer", "skipped with --skip-payer"));
+        } else {
+            checks.extend(check_payer(&client, &args).await);
+        }
+        checks.push(check_jito(jito_api_url.as_deref(), &args.vote_pubkey, epoch).await);
+    }
+
+    for check in &checks {
+        println!("{}", check);
+    }
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        return Err(anyhow!("{} of {} checks failed", failed, checks.len()));
+    }
+    println!("\nAll checks passed.");
+    Ok(())
+}
+
+async fn check_rpc_history(client: &RpcClient, vote_pubkey: &Pubkey, epoch: u64) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let target_epoch = epoch.saturating_sub(1);
+    checks.push(
+        match client
+            .get_inflation_reward(&[*vote_pubkey], Some(target_epoch))
+            .await
+        {
+            Ok(rewards) if rewards.first().is_some_and(Option::is_some) => Check::pass(
+                "getInflationReward",
+                format!("rewards of epoch {} are served", target_epoch),
+            ),
+            Ok(_) => Check::warn(
+                "getInflationReward",
+                format!("no reward of the vote account for epoch {}", target_epoch),
+                "Expected for a vote account that earned nothing, otherwise the RPC may not keep rewards history.",
+            ),
+            Err(e) => Check::fail(
+                "getInflationReward",
+                format!("failed for epoch {}: {}", target_epoch, e),
+                "Use an RPC with rewards history for at least the previous epoch.",
+            ),
+        },
+    );
+    let first_needed_slot = match client.get_epoch_schedule().await {
+        Ok(schedule) => schedule.get_first_slot_in_epoch(target_epoch),
+        Err(e) => {
+            checks.push(Check::fail(
+                "getBlock history",
+                format!("getEpochSchedule failed: {}", e),
+                "Check that the RPC provider allows getEpochSchedule.",
+            ));
+            return checks;
+        }
+    };
+    checks.push(match client.get_first_available_block().await {
+        Ok(first_available_slot) => block_history_check(first_available_slot, first_needed_slot),
+        Err(e) => Check::fail(
+            "getBlock history",
+            format!("getFirstAvailableBlock failed: {}", e),
+            "Check that the RPC provider allows getFirstAvailableBlock and getBlock.",
+        ),
+    });
+    checks
+}
+
+async fn check_program(client: &RpcClient, program_id: &Pubkey) -> Check {
+    match client.get_account(program_id).await {
+        Ok(account) if account.executable => {
+            Check::pass("Program", format!("{} is deployed", program_id))
+        }
+        Ok(_) => Check::fail(
+            "Program",
+            format!("{} isn't an executable program", program_id),
+            "Check --program-id and --cluster.",
+        ),
+        Err(e) => Check::fail(
+            "Program",
+            format!("{} not found: {}", program_id, e),
+            "Check --program-id and --cluster, the program may not be deployed on this cluster.",
+        ),
+    }
+}
+
+async fn check_vote_account(client: &RpcClient, vote_pubkey: &Pubkey) -> Check {
+    match client.get_account(vote_pubkey).await {
+        Ok(account) if account.owner == solana_sdk::vote::program::id() => {
+            Check::pass("Vote account", format!("{} exists", vote_pubkey))
+        }
+        Ok(account) => Check::fail(
+            "Vote account",
+            format!(
+                "{} is owned by {}, not the vote program",
+                vote_pubkey, account.owner
+            ),
+            "Pass the vote account to --vote-pubkey, not the validator identity.",
+        ),
+        Err(e) => Check::fail(
+            "Vote account",
+            format!("{} not found: {}", vote_pubkey, e),
+            "Check --vote-pubkey and --cluster.",
+        ),
+    }
+}
+
+async fn check_gpa(client: &RpcClient, program_ids: &[Pubkey], args: &DoctorArgs) -> Check {
+    let options = PyeAccountFetchOptions {
+        strict: false,
+        chunking: args.gpa_chunking,
+        concurrency: args.concurrency,
+    };
+    let mut bonds = 0;
+    for program_id in program_ids {
+        match fetch_solo_validator_pye_accounts_by_vote_key(
+            client,
+            program_id,
+            &args.vote_pubkey,
+            None,
+            false,
+            &options,
+        )
+        .await
+        {
+            Ok(accounts) => bonds += accounts.len(),
+            Err(e) => {
+                return Check::fail(
+                    "getProgramAccounts",
+                    format!("filtered query of {} failed: {}", program_id, e),
+                    "Use an RPC that supports getProgramAccounts with memcmp filters, or try --gpa-chunking always.",
+                )
+            }
+        }
+    }
+    if bonds == 0 {
+        Check::warn(
+            "getProgramAccounts",
+            "no bonds delegate to the vote account",
+            "Expected before the first bond is created, otherwise check --vote-pubkey and --program-id.",
+        )
+    } else {
+        Check::pass(
+            "getProgramAccounts",
+            format!("{} bonds delegate to the vote account", bonds),
+        )
+    }
+}
+
+async fn check_payer(client: &RpcClient, args: &DoctorArgs) -> Vec<Check> {
+    let signers = load_signers(args.payer.as_deref(), args.fee_payer.as_deref()).await;
+    let (payer, fee_payer) = match signers {
+        Ok(signers) => signers,
+        Err(e) => {
+            let hint =
+                "Pass --payer and --fee-payer as for validator-pye-account-manager, or --skip-payer.";
+            return vec![Check::fail("Payer", format!("{:#}", e), hint)];
+        }
+    };
+    let mut checks = vec![check_balance(client, "Payer", &payer.pubkey(), args).await];
+    if let Some(fee_payer) = fee_payer {
+        checks.push(check_balance(client, "Fee payer", &fee_payer.pubkey(), args).await);
+    }
+    checks
+}
+
+async fn check_balance(
+    client: &RpcClient,
+    name: &'static str,
+    pubkey: &Pubkey,
+    args: &DoctorArgs,
+) -> Check {
+    match client.get_balance(pubkey).await {
+        Ok(balance) if balance >= args.min_payer_balance => {
+            Check::pass(name, format!("{} holds {}", pubkey, format_amount(balance)))
+        }
+        Ok(balance) => Check::fail(
+            name,
+            format!(
+                "{} holds {}, below --min-payer-balance of {}",
+                pubkey,
+                format_amount(balance),
+                format_amount(args.min_payer_balance)
+            ),
+            "Fund the account before the epoch boundary.",
+        ),
+        Err(e) => Check::fail(
+            name,
+            format!("failed to read the balance of {}: {}", pubkey, e),
+            "Check that the RPC provider allows getBalance.",
+        ),
+    }
+}
+
+async fn check_jito(jito_api_url: Option<&str>, vote_pubkey: &Pubkey, epoch: u64) -> Check {
+    let Some(jito_api_url) = jito_api_url else {
+        return Check::pass(
+            "Jito API",
+            "no Jito API on this cluster, MEV is treated as zero",
+        );
+    };
+    let target_epoch = epoch.saturating_sub(1);
+    match fetch_mev_data(jito_api_url, target_epoch, None).await {
+        Ok(response)
+            if response
+                .validators
+                .iter()
+                .any(|validator| validator.vote_account == vote_pubkey.to_string()) =>
+        {
+            Check::pass(
+                "Jito API",
+                format!(
+                    "{} serves epoch {} for the validator",
+                    jito_api_url, target_epoch
+                ),
+            )
+        }
+        Ok(_) => Check::warn(
+            "Jito API",
+            format!(
+                "{} has no epoch {} data for the validator",
+                jito_api_url, target_epoch
+            ),
+            "Expected if the validator doesn't run the Jito client, MEV is then treated as zero.",
+        ),
+        Err(e) => Check::fail(
+            "Jito API",
+            format!("{} is unreachable: {}", jito_api_url, e),
+            "Check --jito-api-url and outbound HTTPS access.",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_history_check() {
+        assert_eq!(block_history_check(100, 432_000).status, CheckStatus::Pass);
+        let check = block_history_check(500_000, 432_000);
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert_eq!(
+            check.to_string(),
+            "[FAIL] getBlock history: blocks only available from slot 500000, the previous epoch starts at 432000\n       Use an RPC that keeps at least two epochs of block history, e.g. one backed by BigTable."
+        );
+    }
+}
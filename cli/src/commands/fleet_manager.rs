@@ -0,0 +1,429 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use clap::{CommandFactory, Parser};
+use futures::future::join_all;
+use log::{error, info};
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    commands::validator_pye_account_manager::{
+        handle_validator_pye_account_manager, ValidatorPyeAccountManagerArgs,
+    },
+    exit_code::ConfigError,
+    metrics::{self, datapoint_error, datapoint_info},
+    notifications::{Notification, NotificationArgs, Notifier, Severity},
+    progress,
+};
+
+#[derive(Clone, Debug, Parser)]
+pub struct FleetManagerArgs {
+    /// JSON file listing the validators to manage, see the README.
+    #[arg(long, env)]
+    fleet_config: PathBuf,
+    /// The wait time (in secs) before restarting a validator's manager after it stopped.
+    #[arg(long, env, default_value = "60")]
+    restart_delay_secs: u64,
+    /// Interval (in secs) of the `fleet_status` datapoint.
+    #[arg(long, env, default_value = "60")]
+    fleet_status_secs: u64,
+    /// Notifications about the fleet itself, e.g. a validator's manager stopping.
+    #[command(flatten)]
+    notifications: NotificationArgs,
+}
+
+/// The validators run by one fleet manager.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FleetConfig {
+    /// validator-pye-account-manager flags shared by every validator, e.g. `["--rpc", "<URL>"]`.
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub validators: Vec<ValidatorEntry>,
+}
+
+/// A validator serviced by the fleet, with its own manager, state and notification channel.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ValidatorEntry {
+    /// Name telling the validator apart in logs, notifications and the `validator_name` tag.
+    pub name: String,
+    pub vote_pubkey: Pubkey,
+    #[serde(default)]
+    pub issuers: Vec<Pubkey>,
+    /// Bonds to skip, as accepted by `--exclude-pye-accounts`.
+    #[serde(default)]
+    pub exclude_pye_accounts: Vec<Pubkey>,
+    /// Bonds to process exclusively, as accepted by `--only-pye-accounts`.
+    #[serde(default)]
+    pub only_pye_accounts: Vec<Pubkey>,
+    /// Payer keypair path, as accepted by `--payer`.
+    #[serde(default)]
+    pub payer: Option<String>,
+    #[serde(default)]
+    pub fee_payer: Option<String>,
+    #[serde(default)]
+    pub notify_webhook: Option<String>,
+    #[serde(default)]
+    pub max_payment_lamports: Option<u64>,
+    /// Further validator-pye-account-manager flags for this validator only.
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl ValidatorEntry {
+    /// The validator-pye-account-manager command line of this validator. A flag can only be
+    /// given once, so the shared flags the validator sets itself are left out.
+    fn argv(&self, shared: &[String]) -> Vec<String> {
+        let own = self.own_argv();
+        let command = ValidatorPyeAccountManagerArgs::command();
+        let overridden: HashSet<String> = own
+            .iter()
+            .filter_map(|token| flag_of(&command, token))
+            .map(|(id, _)| id)
+            .collect();
+        let mut argv = vec!["validator-pye-account-manager".to_string()];
+        let mut shared = shared.iter();
+        while let Some(token) = shared.next() {
+            match flag_of(&command, token) {
+                Some((id, takes_value)) if overridden.contains(&id) => {
+                    if takes_value {
+                        shared.next();
+                    }
+                }
+                _ => argv.push(token.clone()),
+            }
+        }
+        argv.extend(own);
+        argv
+    }
+
+    /// The flags of this validator's own settings.
+    fn own_argv(&self) -> Vec<String> {
+        let mut argv = vec!["--vote-pubkey".to_string(), self.vote_pubkey.to_string()];
+        let lists = [
+            ("--issuers", &self.issuers),
+            ("--exclude-pye-accounts", &self.exclude_pye_accounts),
+            ("--only-pye-accounts", &self.only_pye_accounts),
+        ];
+        for (flag, pubkeys) in lists {
+            if !pubkeys.is_empty() {
+                let pubkeys: Vec<String> = pubkeys.iter().map(Pubkey::to_string).collect();
+                argv.extend([flag.to_string(), pubkeys.join(",")]);
+            }
+        }
+        let flags = [
+            ("--payer", self.payer.clone()),
+            ("--fee-payer", self.fee_payer.clone()),
+            ("--notify-webhook", self.notify_webhook.clone()),
+            (
+                "--max-payment-lamports",
+                self.max_payment_lamports.map(|cap| cap.to_string()),
+            ),
+        ];
+        for (flag, value) in flags {
+            if let Some(value) = value {
+                argv.extend([flag.to_string(), value]);
+            }
+        }
+        argv.extend(self.args.iter().cloned());
+        argv
+    }
+}
+
+/// The ID of the manager argument `token` sets, if it is a flag, and whether its value is the
+/// next token rather than given as `--flag=value`.
+fn flag_of(command: &clap::Command, token: &str) -> Option<(String, bool)> {
+    let (name, inline_value) = match token.split_once('=') {
+        Some((name, _)) => (name, true),
+        None => (token, false),
+    };
+    let arg = if let Some(long) = name.strip_prefix("--") {
+        command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long))
+    } else {
+        let mut short = name.strip_prefix('-')?.chars();
+        let (Some(short), None) = (short.next(), short.next()) else {
+            return None;
+        };
+        command
+            .get_arguments()
+            .find(|arg| arg.get_short() == Some(short))
+    }?;
+    Some((
+        arg.get_id().to_string(),
+        !inline_value && arg.get_action().takes_values(),
+    ))
+}
+
+impl FleetConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid fleet config {}: {}", path.display(), e))
+    }
+
+    /// Parses the manager arguments of every validator, rejecting validators that would share
+    /// state or can't run alongside others.
+    pub fn manager_args(&self) -> Result<Vec<(String, ValidatorPyeAccountManagerArgs)>> {
+        if self.validators.is_empty() {
+            return Err(anyhow!("The fleet config lists no validators"));
+        }
+        let mut names = HashSet::new();
+        let mut owners: HashMap<(&'static str, String), String> = HashMap::new();
+        let mut members = Vec::with_capacity(self.validators.len());
+        for entry in &self.validators {
+            if !names.insert(entry.name.as_str()) {
+                return Err(anyhow!("Validator {} is listed twice", entry.name));
+            }
+            let args = ValidatorPyeAccountManagerArgs::try_parse_from(entry.argv(&self.args))
+                .map_err(|e| anyhow!("Invalid arguments for validator {}: {}", entry.name, e))?;
+            if args.tui() {
+                return Err(anyhow!(
+                    "Validator {}: --tui isn't supported in a fleet",
+                    entry.name
+                ));
+            }
+            if args.review_payments() {
+                return Err(anyhow!(
+                    "Validator {}: --review-payments isn't supported in a fleet",
+                    entry.name
+                ));
+            }
+            if args.once() {
+                return Err(anyhow!(
+                    "Validator {}: --once isn't supported in a fleet",
+                    entry.name
+                ));
+            }
+            if args.reads_stdin() {
+                return Err(anyhow!(
+                    "Validator {}: keypairs can't be read from stdin in a fleet",
+                    entry.name
+                ));
+            }
+            for resource in args.exclusive_resources() {
+                if let Some(owner) = owners.get(&resource) {
+                    return Err(anyhow!(
+                        "Validators {} and {} share {} {}, each needs its own",
+                        owner,
+                        entry.name,
+                        resource.0,
+                        resource.1
+                    ));
+                }
+                owners.insert(resource, entry.name.clone());
+            }
+            members.push((entry.name.clone(), args));
+        }
+        Ok(members)
+    }
+}
+
+/// Counters shared by the fleet's managers for the `fleet_status` datapoint.
+#[derive(Default)]
+struct FleetStatus {
+    running: AtomicU64,
+    restarts: AtomicU64,
+}
+
+pub async fn handle_fleet_manager(args: FleetManagerArgs) -> Result<()> {
+    let config = FleetConfig::load(&args.fleet_config).context(ConfigError)?;
+    let members = config.manager_args().context(ConfigError)?;
+    // Progress bars of concurrent managers would draw over each other.
+    progress::disable();
+    info!(
+        "Managing {} validators: {}",
+        members.len(),
+        members
+            .iter()
+            .map(|(name, args)| format!("{} ({})", name, args.vote_pubkey()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let notifier = Notifier::from_args(&args.notifications)?;
+    let status = FleetStatus::default();
+    let validators = members.len();
+    let managers = members.into_iter().map(|(name, manager_args)| {
+        // Tags every datapoint of the validator's manager, and of its restarts, with its name.
+        metrics::with_validator_name(
+            name.clone(),
+            run_member(name, manager_args, &args, &notifier, &status),
+        )
+    });
+    tokio::select! {
+        _ = join_all(managers) => Ok(()),
+        _ = report_status(&status, validators, args.fleet_status_secs) => Ok(()),
+    }
+}
+
+/// Runs a validator's manager, restarting it whenever it stops so one validator's failure
+/// doesn't take the others down.
+async fn run_member(
+    name: String,
+    manager_args: ValidatorPyeAccountManagerArgs,
+    args: &FleetManagerArgs,
+    notifier: &Notifier,
+    status: &FleetStatus,
+) {
+    let vote_pubkey = manager_args.vote_pubkey();
+    loop {
+        status.running.fetch_add(1, Ordering::Relaxed);
+        let result = handle_validator_pye_account_manager(manager_args.clone()).await;
+        status.running.fetch_sub(1, Ordering::Relaxed);
+        let reason = match result {
+            Ok(()) => "exited".to_string(),
+            Err(e) => format!("{:#}", e),
+        };
+        error!(
+            "Manager of validator {} ({}) stopped: {}, restarting in {}s",
+            name, vote_pubkey, reason, args.restart_delay_secs
+        );
+        datapoint_error!(
+            "fleet_validator_exit",
+            ("vote_pubkey", vote_pubkey.to_string(), String),
+            ("error", reason.clone(), String),
+        );
+        notifier
+            .notify(Notification::new(
+                Severity::Critical,
+                format!("Manager of validator {} stopped", name),
+                format!(
+                    "The manager of {} stopped: {}. Restarting in {}s.",
+                    vote_pubkey, reason, args.restart_delay_secs
+                ),
+            ))
+            .await;
+        status.restarts.fetch_add(1, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_secs(args.restart_delay_secs)).await;
+    }
+}
+
+async fn report_status(status: &FleetStatus, validators: usize, interval_secs: u64) {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        datapoint_info!(
+            "fleet_status",
+            ("validators", validators as i64, i64),
+            (
+                "running",
+                status.running.load(Ordering::Relaxed) as i64,
+                i64
+            ),
+            (
+                "restarts",
+                status.restarts.load(Ordering::Relaxed) as i64,
+                i64
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, args: &[&str]) -> ValidatorEntry {
+        ValidatorEntry {
+            name: name.to_string(),
+            vote_pubkey: Pubkey::new_unique(),
+            issuers: vec![Pubkey::new_unique()],
+            exclude_pye_accounts: Vec::new(),
+            only_pye_accounts: Vec::new(),
+            payer: Some(format!("/keys/{}.json", name)),
+            fee_payer: None,
+            notify_webhook: None,
+            max_payment_lamports: Some(5_000_000_000),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_argv() {
+        let mut entry = entry("alpha", &["--dry-run"]);
+        entry.exclude_pye_accounts = vec![Pubkey::new_unique()];
+        assert_eq!(
+            entry.argv(&["--rpc".to_string(), "http://localhost:8899".to_string()]),
+            vec![
+                "validator-pye-account-manager".to_string(),
+                "--rpc".to_string(),
+                "http://localhost:8899".to_string(),
+                "--vote-pubkey".to_string(),
+                entry.vote_pubkey.to_string(),
+                "--issuers".to_string(),
+                entry.issuers[0].to_string(),
+                "--exclude-pye-accounts".to_string(),
+                entry.exclude_pye_accounts[0].to_string(),
+                "--payer".to_string(),
+                "/keys/alpha.json".to_string(),
+                "--max-payment-lamports".to_string(),
+                "5000000000".to_string(),
+                "--dry-run".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_manager_args_rejects_shared_state() {
+        let config = |validators| FleetConfig {
+            args: vec!["--dry-run".to_string()],
+            validators,
+        };
+        assert_eq!(
+            config(vec![entry("alpha", &[]), entry("beta", &[])])
+                .manager_args()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(config(vec![entry("alpha", &[]), entry("alpha", &[])])
+            .manager_args()
+            .is_err());
+        assert!(config(vec![
+            entry("alpha", &["--receipts-dir", "/var/pye/receipts"]),
+            entry("beta", &["--receipts-dir", "/var/pye/receipts"]),
+        ])
+        .manager_args()
+        .is_err());
+        assert!(config(vec![entry("alpha", &["--tui"])])
+            .manager_args()
+            .is_err());
+        assert!(config(vec![entry("alpha", &["--review-payments"])])
+            .manager_args()
+            .is_err());
+        assert!(config(vec![entry("alpha", &["--once"])])
+            .manager_args()
+            .is_err());
+        assert!(config(vec![entry("alpha", &["--fee-payer", "-"])])
+            .manager_args()
+            .is_err());
+    }
+
+    #[test]
+    fn test_entry_overrides_shared_flags() {
+        let config = FleetConfig {
+            args: vec![
+                "--rpc".to_string(),
+                "http://shared:8899".to_string(),
+                "--max-payment-lamports=1".to_string(),
+                "--dry-run".to_string(),
+            ],
+            validators: vec![entry("alpha", &["-r", "http://alpha:8899"])],
+        };
+        let argv = config.validators[0].argv(&config.args);
+        assert!(argv.contains(&"http://alpha:8899".to_string()));
+        assert!(!argv.contains(&"http://shared:8899".to_string()));
+        assert!(!argv.contains(&"--max-payment-lamports=1".to_string()));
+        assert!(argv.contains(&"--dry-run".to_string()));
+        let manager_args = config.manager_args().unwrap();
+        assert_eq!(manager_args.len(), 1);
+    }
+}
@@ -0,0 +1,505 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use crate::bond_state::BondStates;
+use crate::epoch_progress::EpochProgress;
+use crate::receipts::{latest_receipts, load_receipts, PaymentReceipt};
+use crate::sol_format::format_sol;
+use crate::state_db::StateDb;
+use crate::table::{Align, Table};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LedgerFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct LedgerArgs {
+    /// Receipts directory written by validator-pye-account-manager --receipts-dir.
+    #[arg(long, env, required_unless_present = "state_db_url")]
+    receipts_dir: Option<PathBuf>,
+    /// State database the managers record their receipts and bond states in, instead of a
+    /// receipts directory.
+    #[arg(long, env, conflicts_with = "receipts_dir")]
+    state_db_url: Option<String>,
+    #[command(subcommand)]
+    command: LedgerCommand,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum LedgerCommand {
+    /// List the epochs processed, partially processed or skipped per vote account, with the
+    /// amounts paid.
+    Epochs {
+        /// Only list this vote account's epochs.
+        #[arg(long, env)]
+        vote_pubkey: Option<String>,
+        /// First epoch listed [default: the vote account's oldest receipt's]
+        #[arg(long, env)]
+        from_epoch: Option<u64>,
+        /// Last epoch listed [default: the vote account's latest receipt's]
+        #[arg(long, env)]
+        to_epoch: Option<u64>,
+        /// The manager's --bond-state-file, to also count the bonds it is still processing. With
+        /// --state-db-url the bond states are read from the database.
+        #[arg(long, env, requires = "vote_pubkey", conflicts_with = "state_db_url")]
+        bond_state_file: Option<PathBuf>,
+        /// The manager's --epoch-progress-file, to also count the bonds left for a retry.
+        #[arg(long, env, requires = "vote_pubkey")]
+        epoch_progress_file: Option<PathBuf>,
+        /// Also list each payment with its transaction signature, fee and confirmation slot. The
+        /// JSON output always includes them.
+        #[arg(long, env)]
+        payments: bool,
+        /// Output format.
+        #[arg(long, env, value_enum, default_value_t = LedgerFormat::Table)]
+        format: LedgerFormat,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum EpochStatus {
+    /// Every bond was paid or owed nothing.
+    Processed,
+    /// Some bonds weren't paid yet, e.g. their payment failed or was held back.
+    Partial,
+    /// No bond has a receipt for the epoch.
+    Skipped,
+}
+
+impl fmt::Display for EpochStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EpochStatus::Processed => "processed",
+            EpochStatus::Partial => "partial",
+            EpochStatus::Skipped => "skipped",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A landed payment of an epoch, to start an audit from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct LedgerPayment {
+    pye_account: String,
+    /// Lamports paid.
+    lamports: i64,
+    signature: String,
+    /// Fee of the transaction in lamports, when known.
+    fee: Option<u64>,
+    /// Slot the transaction was confirmed in, when known.
+    slot: Option<u64>,
+}
+
+/// What a vote account's bonds were paid for an epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct EpochEntry {
+    vote_pubkey: String,
+    epoch: u64,
+    status: EpochStatus,
+    /// Bonds with a receipt for the epoch.
+    bonds: u64,
+    /// Bonds neither paid nor owing nothing, with the reason, e.g. `failed`.
+    outstanding: BTreeMap<String, String>,
+    /// Lamports paid over every payment of the epoch, a follow-up MEV payment included.
+    paid: i64,
+    /// Fees of those payments in lamports, when known.
+    fees: u64,
+    /// Those payments, in the order they settled.
+    payments: Vec<LedgerPayment>,
+}
+
+impl EpochEntry {
+    fn new(vote_pubkey: &str, epoch: u64) -> Self {
+        Self {
+            vote_pubkey: vote_pubkey.to_string(),
+            epoch,
+            status: EpochStatus::Skipped,
+            bonds: 0,
+            outstanding: BTreeMap::new(),
+            paid: 0,
+            fees: 0,
+            payments: Vec::new(),
+        }
+    }
+}
+
+/// Why a receipt's payment is outstanding, or None once it was paid or nothing was owed. A
+/// payment that didn't check out against the finalized ledger stays outstanding, e.g. as
+/// `paid_mismatch`.
+fn outstanding_reason(receipt: &PaymentReceipt) -> Option<String> {
+    match (
+        receipt.payment.as_str(),
+        receipt.payment_verification.as_deref(),
+    ) {
+        ("paid", None | Some("verified")) | ("not_due", _) => None,
+        ("paid", Some(verification)) => Some(format!("paid_{}", verification)),
+        (payment, _) => Some(payment.to_string()),
+    }
+}
+
+/// The epochs of each vote account between `from_epoch` and `to_epoch`, or its oldest and latest
+/// receipts. Epochs without receipts in between are listed as skipped. `pending` adds the bonds
+/// the manager of `vote_pubkey` hasn't finished processing, by epoch.
+fn epoch_entries(
+    receipts: &[PaymentReceipt],
+    vote_pubkey: Option<&str>,
+    from_epoch: Option<u64>,
+    to_epoch: Option<u64>,
+    pending: &BTreeMap<u64, BTreeMap<String, String>>,
+) -> Vec<EpochEntry> {
+    let mut votes: BTreeMap<&str, BTreeMap<u64, EpochEntry>> = BTreeMap::new();
+    if let Some(vote_pubkey) = vote_pubkey {
+        votes.entry(vote_pubkey).or_default();
+    }
+    let mut signatures = HashSet::new();
+    for receipt in receipts {
+        let entry = votes
+            .entry(&receipt.vote_pubkey)
+            .or_default()
+            .entry(receipt.epoch)
+            .or_insert_with(|| EpochEntry::new(&receipt.vote_pubkey, receipt.epoch));
+        // Retried and recomputed payments leave several receipts per bond, but each payment
+        // counts once.
+        let new_payment = receipt
+            .transaction_signature
+            .as_ref()
+            .filter(|signature| signatures.insert((*signature).clone()));
+        if let Some(signature) = new_payment.filter(|_| receipt.payment == "paid") {
+            entry.paid += receipt.amount_due();
+            entry.fees += receipt.transaction_fee.unwrap_or_default();
+            entry.payments.push(LedgerPayment {
+                pye_account: receipt.pye_account.clone(),
+                lamports: receipt.amount_due(),
+                signature: signature.clone(),
+                fee: receipt.transaction_fee,
+                slot: receipt.transaction_slot,
+            });
+        }
+    }
+    for receipt in latest_receipts(receipts.to_vec()) {
+        let entry = votes
+            .get_mut(receipt.vote_pubkey.as_str())
+            .and_then(|epochs| epochs.get_mut(&receipt.epoch))
+            .expect("every receipt has an entry");
+        // A deferred MEV payment belongs to a bond counted with its first payment.
+        if !receipt.deferred_mev {
+            entry.bonds += 1;
+        }
+        if let Some(reason) = outstanding_reason(&receipt) {
+            entry
+                .outstanding
+                .insert(receipt.pye_account.clone(), reason);
+        }
+    }
+
+    let mut entries = Vec::new();
+    for (vote, mut epochs) in votes {
+        let pending = pending.iter().filter(|_| Some(vote) == vote_pubkey);
+        for (epoch, bonds) in pending {
+            let entry = epochs
+                .entry(*epoch)
+                .or_insert_with(|| EpochEntry::new(vote, *epoch));
+            for (pye_account, reason) in bonds {
+                entry
+                    .outstanding
+                    .entry(pye_account.clone())
+                    .or_insert_with(|| reason.clone());
+            }
+        }
+        let (Some(oldest), Some(latest)) = (epochs.keys().next(), epochs.keys().next_back()) else {
+            continue;
+        };
+        let (from_epoch, to_epoch) = (from_epoch.unwrap_or(*oldest), to_epoch.unwrap_or(*latest));
+        for epoch in from_epoch..=to_epoch {
+            let mut entry = epochs
+                .remove(&epoch)
+                .unwrap_or_else(|| EpochEntry::new(vote, epoch));
+            entry.status = if !entry.outstanding.is_empty() {
+                EpochStatus::Partial
+            } else if entry.bonds > 0 {
+                EpochStatus::Processed
+            } else {
+                EpochStatus::Skipped
+            };
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+/// Bonds a manager's bond states and epoch progress show as not done with, by epoch, with their
+/// stage.
+fn pending_bonds(
+    bond_states: Option<BondStates>,
+    epoch_progress_file: Option<PathBuf>,
+) -> Result<BTreeMap<u64, BTreeMap<String, String>>> {
+    let mut pending: BTreeMap<u64, BTreeMap<String, String>> = BTreeMap::new();
+    for state in bond_states.iter().flat_map(BondStates::unfinished) {
+        pending
+            .entry(state.epoch)
+            .or_default()
+            .insert(state.pye_account, state.stage.to_string());
+    }
+    if epoch_progress_file.is_some() {
+        for unfinished in EpochProgress::load(epoch_progress_file)?.unfinished() {
+            let bonds = pending.entry(unfinished.epoch).or_default();
+            for pye_account in &unfinished.pye_accounts {
+                bonds
+                    .entry(pye_account.clone())
+                    .or_insert_with(|| "unfinished".to_string());
+            }
+        }
+    }
+    Ok(pending)
+}
+
+fn print_table(entries: &[EpochEntry], payments: bool) -> Result<()> {
+    let mut table = Table::new(&[
+        ("VOTE ACCOUNT", Align::Left),
+        ("EPOCH", Align::Right),
+        ("STATUS", Align::Left),
+        ("BONDS", Align::Right),
+        ("OUTSTANDING", Align::Right),
+        ("PAID (SOL)", Align::Right),
+    ]);
+    for entry in entries {
+        table.push(vec![
+            entry.vote_pubkey.clone(),
+            entry.epoch.to_string(),
+            entry.status.to_string(),
+            entry.bonds.to_string(),
+            entry.outstanding.len().to_string(),
+            format_sol(entry.paid),
+        ]);
+    }
+    table.print()?;
+    let outstanding: BTreeSet<(u64, &str, &str)> = entries
+        .iter()
+        .flat_map(|entry| {
+            entry
+                .outstanding
+                .iter()
+                .map(|(pye_account, reason)| (entry.epoch, pye_account.as_str(), reason.as_str()))
+        })
+        .collect();
+    if !outstanding.is_empty() {
+        println!("\nOutstanding bonds:");
+        for (epoch, pye_account, reason) in outstanding {
+            println!("  epoch {:>6}  {:<44}  {}", epoch, pye_account, reason);
+        }
+    }
+    let paid: Vec<(u64, &LedgerPayment)> = entries
+        .iter()
+        .flat_map(|entry| entry.payments.iter().map(|payment| (entry.epoch, payment)))
+        .collect();
+    if payments && !paid.is_empty() {
+        println!("\nPayments:");
+        for (epoch, payment) in paid {
+            println!(
+                "  epoch {:>6}  {:<44}  {:>15} SOL  {:<88}  slot {:>10}  fee {}",
+                epoch,
+                payment.pye_account,
+                format_sol(payment.lamports),
+                payment.signature,
+                payment
+                    .slot
+                    .map_or("unknown".to_string(), |slot| slot.to_string()),
+                payment
+                    .fee
+                    .map_or("unknown".to_string(), |fee| fee.to_string()),
+            );
+        }
+    }
+    Ok(())
+}
+
+pub async fn handle_ledger(args: LedgerArgs) -> Result<()> {
+    match args.command {
+        LedgerCommand::Epochs {
+            vote_pubkey,
+            from_epoch,
+            to_epoch,
+            bond_state_file,
+            epoch_progress_file,
+            payments,
+            format,
+        } => {
+            if let (Some(from_epoch), Some(to_epoch)) = (from_epoch, to_epoch) {
+                if from_epoch > to_epoch {
+                    return Err(anyhow!(
+                        "--from-epoch {} is after --to-epoch {}",
+                        from_epoch,
+                        to_epoch
+                    ));
+                }
+            }
+            let (receipts, bond_states) = match (&args.receipts_dir, &args.state_db_url) {
+                (_, Some(url)) => {
+                    let db = StateDb::connect(url).await?;
+                    let bond_states = match &vote_pubkey {
+                        Some(vote_pubkey) => Some(BondStates::from_states(
+                            db.load_bond_states(vote_pubkey).await?,
+                        )),
+                        None => None,
+                    };
+                    (db.load_receipts(vote_pubkey.as_deref()).await?, bond_states)
+                }
+                (Some(dir), None) => {
+                    let bond_states = match bond_state_file {
+                        Some(path) => Some(BondStates::load(Some(path))?),
+                        None => None,
+                    };
+                    (load_receipts(dir)?, bond_states)
+                }
+                (None, None) => {
+                    return Err(anyhow!("--receipts-dir or --state-db-url is required"))
+                }
+            };
+            let receipts: Vec<PaymentReceipt> = receipts
+                .into_iter()
+                .filter(|receipt| {
+                    vote_pubkey
+                        .as_ref()
+                        .is_none_or(|vote_pubkey| &receipt.vote_pubkey == vote_pubkey)
+                })
+                .collect();
+            let pending = pending_bonds(bond_states, epoch_progress_file)?;
+            let entries = epoch_entries(
+                &receipts,
+                vote_pubkey.as_deref(),
+                from_epoch,
+                to_epoch,
+                &pending,
+            );
+            if entries.is_empty() {
+                return Err(anyhow!("No receipts found"));
+            }
+            match format {
+                LedgerFormat::Table => print_table(&entries, payments)?,
+                LedgerFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use solana_sdk::signature::Signature;
+
+    use crate::control::PaymentOutcome;
+    use crate::receipts::{sample_receipt, ExcessRewards};
+    use crate::transactions::Payment;
+
+    fn payment(signature: Signature) -> Result<PaymentOutcome, String> {
+        Ok(PaymentOutcome::Paid(Payment {
+            signature,
+            fee: Some(5_000),
+            slot: Some(345_600_123),
+            verification: None,
+        }))
+    }
+
+    fn receipt(
+        epoch: u64,
+        pye_account: &str,
+        payment: Result<PaymentOutcome, String>,
+        total: i64,
+        settled_at: &str,
+    ) -> PaymentReceipt {
+        let mut receipt = sample_receipt(Ok(PaymentOutcome::NotDue));
+        receipt.vote_pubkey = "vote".to_string();
+        receipt.epoch = epoch;
+        receipt.pye_account = pye_account.to_string();
+        receipt.excess_rewards = ExcessRewards {
+            inflation: total,
+            mev: 0,
+            block: 0,
+            total,
+        };
+        receipt.with_payment(payment, settled_at.parse::<DateTime<Utc>>().unwrap())
+    }
+
+    #[test]
+    fn test_epoch_entries() {
+        let (early, late) = ("2025-01-01T00:00:00+00:00", "2025-01-02T00:00:00+00:00");
+        let (sig1, sig2) = (Signature::from([1; 64]), Signature::from([2; 64]));
+        let failed = || Err("blockhash expired".to_string());
+        let receipts = vec![
+            receipt(800, "bond1", payment(sig1), 100, early),
+            receipt(800, "bond2", Ok(PaymentOutcome::NotDue), 0, early),
+            // Epoch 801 was skipped, bond2's epoch 802 payment failed and bond1's was retried.
+            receipt(802, "bond1", failed(), 300, early),
+            receipt(802, "bond1", payment(sig2), 300, late),
+            receipt(802, "bond2", failed(), 200, early),
+        ];
+        let pending = BTreeMap::from([(
+            803,
+            BTreeMap::from([("bond1".to_string(), "sent".to_string())]),
+        )]);
+        let entries = epoch_entries(&receipts, Some("vote"), None, None, &pending);
+        let statuses: Vec<(u64, EpochStatus)> = entries
+            .iter()
+            .map(|entry| (entry.epoch, entry.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (800, EpochStatus::Processed),
+                (801, EpochStatus::Skipped),
+                (802, EpochStatus::Partial),
+                (803, EpochStatus::Partial),
+            ]
+        );
+        assert_eq!(
+            (entries[0].bonds, entries[0].paid, entries[0].fees),
+            (2, 100, 5_000)
+        );
+        assert_eq!(entries[2].paid, 300);
+        assert_eq!(
+            entries[2].payments,
+            vec![LedgerPayment {
+                pye_account: "bond1".to_string(),
+                lamports: 300,
+                signature: sig2.to_string(),
+                fee: Some(5_000),
+                slot: Some(345_600_123),
+            }]
+        );
+        assert_eq!(
+            entries[2].outstanding,
+            BTreeMap::from([("bond2".to_string(), "failed".to_string())])
+        );
+        assert_eq!(entries[3].outstanding["bond1"], "sent");
+
+        // Other vote accounts don't get the manager's pending bonds.
+        let entries = epoch_entries(&receipts, None, Some(802), Some(802), &pending);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].outstanding.len(), 1);
+    }
+
+    #[test]
+    fn test_unverified_payment_is_outstanding() {
+        let payment = payment(Signature::default());
+        let mut paid = receipt(800, "bond1", payment, 100, "2025-01-01T00:00:00+00:00");
+        assert_eq!(outstanding_reason(&paid), None);
+        paid.payment_verification = Some("verified".to_string());
+        assert_eq!(outstanding_reason(&paid), None);
+        paid.payment_verification = Some("not_finalized".to_string());
+        assert_eq!(
+            outstanding_reason(&paid).as_deref(),
+            Some("paid_not_finalized")
+        );
+    }
+}
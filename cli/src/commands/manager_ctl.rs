@@ -0,0 +1,157 @@
+use crate::control::connect;
+use crate::control::proto::{
+    EpochRewardBreakdowns, GetRewardBreakdownsRequest, GetStatusRequest, PausePaymentsRequest,
+    PaymentOutcome, RecomputeEpochRequest, ResumePaymentsRequest,
+};
+use crate::sol_format::format_sol;
+use crate::table::{Align, Table};
+use anyhow::Result;
+use chrono::DateTime;
+use clap::{Parser, Subcommand};
+
+#[derive(Clone, Debug, Parser)]
+pub struct ManagerCtlArgs {
+    /// gRPC control API endpoint of the manager, as passed to its --grpc-listen.
+    #[arg(long, env, default_value = "http://127.0.0.1:50051")]
+    endpoint: String,
+    /// Token of the control API, as passed to the manager's --grpc-token.
+    #[arg(long, env = "GRPC_TOKEN")]
+    token: Option<String>,
+    #[command(subcommand)]
+    command: ManagerCtlCommand,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum ManagerCtlCommand {
+    /// Show what the manager is currently doing.
+    Status,
+    /// Show the excess rewards computed for an epoch.
+    Breakdowns {
+        /// Epoch to show [default: the latest computed epoch]
+        #[arg(long)]
+        epoch: Option<u64>,
+    },
+    /// Stop paying excess rewards until resumed.
+    Pause,
+    /// Resume paying excess rewards.
+    Resume,
+    /// Recompute the excess rewards of a completed epoch.
+    Recompute {
+        #[arg(long)]
+        epoch: u64,
+        /// Also pay the recomputed rewards. Bonds whose payment was already sent for the epoch
+        /// aren't paid again. Needs the manager to run with --grpc-token.
+        #[arg(long)]
+        pay: bool,
+    },
+}
+
+pub async fn handle_manager_ctl(args: ManagerCtlArgs) -> Result<()> {
+    let mut client = connect(&args.endpoint, args.token.as_deref()).await?;
+    match args.command {
+        ManagerCtlCommand::Status => {
+            let status = client.get_status(GetStatusRequest {}).await?.into_inner();
+            println!("Vote account:         {}", status.vote_pubkey);
+            println!("Current epoch:        {}", status.current_epoch);
+            println!(
+                "Last processed epoch: {}",
+                status
+                    .last_processed_epoch
+                    .map(|epoch| epoch.to_string())
+                    .unwrap_or_else(|| "-".to_string())
+            );
+            println!("Monitored bonds:      {}", status.monitored_bonds);
+            println!("Payments paused:      {}", status.payments_paused);
+            println!("Dry run:              {}", status.dry_run);
+            println!("Status:               {}", status.phase);
+            if !status.unfinished_bonds.is_empty() {
+                let mut table = Table::new(&[
+                    ("EPOCH", Align::Left),
+                    ("BOND", Align::Left),
+                    ("STAGE", Align::Left),
+                    ("SINCE", Align::Left),
+                    ("DETAIL", Align::Left),
+                ]);
+                for bond in status.unfinished_bonds.iter() {
+                    table.push(vec![
+                        bond.epoch.to_string(),
+                        bond.pye_account.clone(),
+                        match bond.pass.as_str() {
+                            "full" | "" => bond.stage.clone(),
+                            pass => format!("{} ({})", bond.stage, pass),
+                        },
+                        DateTime::from_timestamp(bond.updated_at, 0)
+                            .map(|since| since.to_rfc3339())
+                            .unwrap_or_default(),
+                        bond.detail.clone().unwrap_or_else(|| "-".to_string()),
+                    ]);
+                }
+                println!();
+                table.print()?;
+            }
+        }
+        ManagerCtlCommand::Breakdowns { epoch } => {
+            let breakdowns = client
+                .get_reward_breakdowns(GetRewardBreakdownsRequest { epoch })
+                .await?
+                .into_inner();
+            print_breakdowns(&breakdowns)?;
+        }
+        ManagerCtlCommand::Pause => {
+            client.pause_payments(PausePaymentsRequest {}).await?;
+            println!("Payments paused");
+        }
+        ManagerCtlCommand::Resume => {
+            client.resume_payments(ResumePaymentsRequest {}).await?;
+            println!("Payments resumed");
+        }
+        ManagerCtlCommand::Recompute { epoch, pay } => {
+            let breakdowns = client
+                .recompute_epoch(RecomputeEpochRequest { epoch, pay })
+                .await?
+                .into_inner();
+            print_breakdowns(&breakdowns)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_breakdowns(breakdowns: &EpochRewardBreakdowns) -> Result<()> {
+    println!(
+        "Excess rewards for epoch {} ({} bonds)\n",
+        breakdowns.epoch,
+        breakdowns.bonds.len()
+    );
+    let mut table = Table::new(&[
+        ("BOND", Align::Left),
+        ("INFLATION", Align::Right),
+        ("MEV", Align::Right),
+        ("BLOCK", Align::Right),
+        ("TOTAL (SOL)", Align::Right),
+        ("PAYMENT", Align::Left),
+    ]);
+    for bond in breakdowns.bonds.iter() {
+        let payment = match bond.payment() {
+            PaymentOutcome::Paid => bond.signature.clone().unwrap_or_default(),
+            PaymentOutcome::NotDue => "not due".to_string(),
+            PaymentOutcome::DryRun => "dry run".to_string(),
+            PaymentOutcome::Paused => "paused".to_string(),
+            PaymentOutcome::NotRequested => "not requested".to_string(),
+            PaymentOutcome::OverCap => "over cap".to_string(),
+            PaymentOutcome::Interrupted => "interrupted".to_string(),
+            PaymentOutcome::BondChanged => "bond changed".to_string(),
+            PaymentOutcome::OverLimit => "pending approval".to_string(),
+            PaymentOutcome::Declined => "declined".to_string(),
+            PaymentOutcome::Unspecified => "-".to_string(),
+        };
+        table.push(vec![
+            bond.pye_account.clone(),
+            format_sol(bond.excess_inflation_rewards),
+            format_sol(bond.excess_mev_rewards),
+            format_sol(bond.excess_block_rewards),
+            format_sol(bond.total_excess_rewards),
+            payment,
+        ]);
+    }
+    table.print()
+}
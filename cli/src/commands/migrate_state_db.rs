@@ -0,0 +1,22 @@
+use anyhow::Result;
+use clap::Parser;
+
+use crate::state_db::StateDb;
+
+#[derive(Clone, Debug, Parser)]
+pub struct MigrateStateDbArgs {
+    /// PostgreSQL URL of the state database, as passed to validator-pye-account-manager.
+    #[arg(long, env)]
+    state_db_url: String,
+}
+
+pub async fn handle_migrate_state_db(args: MigrateStateDbArgs) -> Result<()> {
+    let applied = StateDb::new(&args.state_db_url).migrate().await?;
+    if applied.is_empty() {
+        println!("State database is up to date");
+    } else {
+        let versions: Vec<String> = applied.iter().map(ToString::to_string).collect();
+        println!("Applied migrations {}", versions.join(", "));
+    }
+    Ok(())
+}
@@ -1,2 +1,16 @@
+pub mod analytics;
+pub mod archive_urls;
+pub mod create_lookup_table;
+pub mod diff_report;
+pub mod discover_issuers;
+pub mod doctor;
+pub mod fleet_manager;
+pub mod ledger;
+pub mod manager_ctl;
+pub mod migrate_state_db;
+pub mod resend;
+pub mod retry_failed;
 pub mod transfer_excess_rewards;
 pub mod validator_pye_account_manager;
+pub mod verify_attestation;
+pub mod watch;
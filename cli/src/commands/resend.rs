@@ -0,0 +1,273 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use clap::Parser;
+use dialoguer::Confirm;
+use log::{error, info};
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    cluster::ClusterPreset,
+    commands::retry_failed::check_receipt,
+    control::{connect, proto::RecomputeEpochRequest, PaymentOutcome},
+    exit_code::{ConfigError, PaymentError},
+    keypairs::load_signers,
+    labels::labelled,
+    receipts::{latest_receipts, load_receipts, PaymentReceipt, ReceiptWriter},
+    rewards::source::RewardCategory,
+    rpc_utils::new_rpc_client,
+    sol_format::format_amount,
+    transactions::{fingerprint_memo, transfer_excess_rewards, PaymentVerification, SendOptions},
+};
+
+#[derive(Clone, Debug, Parser)]
+pub struct ResendArgs {
+    /// Cluster preset selecting the default RPC endpoint.
+    #[arg(long, env, value_enum, default_value_t = ClusterPreset::Mainnet)]
+    cluster: ClusterPreset,
+    /// RPC Endpoint [default: the cluster preset's endpoint]
+    #[arg(short, long, env)]
+    rpc: Option<String>,
+    /// Receipts directory written by validator-pye-account-manager --receipts-dir. The payment
+    /// is recorded there too.
+    #[arg(long, env)]
+    receipts_dir: PathBuf,
+    /// Bond to pay.
+    #[arg(long, env)]
+    pye_account: Pubkey,
+    /// Epoch whose excess rewards are paid.
+    #[arg(long, env)]
+    epoch: u64,
+    /// Have the manager serving its control API at this endpoint recompute the epoch, and pay
+    /// the recomputed amount instead of the receipt's. The manager must write its receipts to
+    /// --receipts-dir.
+    #[arg(long, env)]
+    recompute_endpoint: Option<String>,
+    /// Token of the manager's control API, as passed to its --grpc-token.
+    #[arg(long, env, requires = "recompute_endpoint")]
+    grpc_token: Option<String>,
+    /// Path to payer keypair, or `-` to read it from stdin [default: the PAYER_KEYPAIR_JSON
+    /// environment variable]
+    #[arg(short, long, env)]
+    payer: Option<String>,
+    /// Path to a keypair that pays transaction fees instead of the payer. `-` reads it from stdin.
+    #[arg(long, env)]
+    fee_payer: Option<String>,
+    /// Run the checks and show the payment without sending it.
+    #[arg(long, env)]
+    dry_run: bool,
+    /// Pay without asking for confirmation.
+    #[arg(short, long, env)]
+    yes: bool,
+    #[command(flatten)]
+    send: SendOptions,
+}
+
+/// Asks the manager to recompute `epoch` without paying, returning the bond's excess rewards
+/// in the categories the receipt's payment covers, i.e. those not `skipped`.
+async fn recompute_total(
+    endpoint: &str,
+    token: Option<&str>,
+    epoch: u64,
+    pye_account: &Pubkey,
+    skipped: &[String],
+) -> Result<i64> {
+    let mut client = connect(endpoint, token).await?;
+    let breakdowns = client
+        .recompute_epoch(RecomputeEpochRequest { epoch, pay: false })
+        .await?
+        .into_inner();
+    breakdowns
+        .bonds
+        .iter()
+        .find(|bond| bond.pye_account == pye_account.to_string())
+        .map(|bond| {
+            [
+                (RewardCategory::Inflation, bond.excess_inflation_rewards),
+                (RewardCategory::Mev, bond.excess_mev_rewards),
+                (RewardCategory::Block, bond.excess_block_rewards),
+            ]
+            .into_iter()
+            .filter(|(category, _)| !skipped.contains(&category.to_string()))
+            .map(|(_, lamports)| lamports)
+            .sum()
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "The manager didn't compute pye_account {} for epoch {}",
+                pye_account,
+                epoch
+            )
+        })
+}
+
+/// A receipt of a payment that landed. Payments that didn't verify at finalized commitment
+/// don't count; the bond's transactions show whether they landed.
+fn paid_receipt<'a>(
+    receipts: impl IntoIterator<Item = &'a PaymentReceipt>,
+) -> Option<&'a PaymentReceipt> {
+    receipts.into_iter().find(|receipt| {
+        receipt.payment == "paid"
+            && receipt
+                .payment_verification
+                .as_deref()
+                .is_none_or(|verification| verification == "verified")
+    })
+}
+
+pub async fn handle_resend(args: ResendArgs) -> Result<()> {
+    let receipts: Vec<PaymentReceipt> = load_receipts(&args.receipts_dir)
+        .context(ConfigError)?
+        .into_iter()
+        .filter(|receipt| {
+            receipt.epoch == args.epoch && receipt.pye_account == args.pye_account.to_string()
+        })
+        .collect();
+    // With --defer-mev, the bond's MEV rewards are a payment of their own, so the one not paid
+    // yet is resent.
+    let unpaid = latest_receipts(receipts.clone())
+        .into_iter()
+        .find(|latest| {
+            paid_receipt(
+                receipts
+                    .iter()
+                    .filter(|receipt| receipt.payment_key() == latest.payment_key()),
+            )
+            .is_none()
+        });
+    let receipt = match (unpaid, paid_receipt(&receipts)) {
+        (Some(receipt), _) => receipt,
+        (None, Some(paid)) => {
+            return Err(anyhow!(
+                "pye_account {} was already paid for epoch {}: {}",
+                args.pye_account,
+                args.epoch,
+                paid.transaction_signature.as_deref().unwrap_or("-")
+            ))
+        }
+        (None, None) => {
+            return Err(anyhow!(
+                "No receipt of pye_account {} for epoch {} in {}",
+                args.pye_account,
+                args.epoch,
+                args.receipts_dir.display()
+            )
+            .context(ConfigError))
+        }
+    };
+    if let Some(endpoint) = &args.recompute_endpoint {
+        let total = recompute_total(
+            endpoint,
+            args.grpc_token.as_deref(),
+            args.epoch,
+            &args.pye_account,
+            &receipt.epoch_inputs.skipped_categories,
+        )
+        .await?;
+        info!(
+            "Recomputed excess rewards of pye_account {} for epoch {}: {}",
+            args.pye_account,
+            args.epoch,
+            format_amount(total)
+        );
+        if total != receipt.excess_rewards.total {
+            return Err(anyhow!(
+                "The latest receipt holds {} rather than the recomputed {}; is --receipts-dir the manager's?",
+                format_amount(receipt.excess_rewards.total),
+                format_amount(total)
+            )
+            .context(ConfigError));
+        }
+    }
+
+    let rpc = args
+        .rpc
+        .clone()
+        .unwrap_or_else(|| args.cluster.rpc_url().to_string());
+    let client = new_rpc_client(rpc.clone(), CommitmentConfig::confirmed(), None, None);
+    let retry = check_receipt(&client, receipt, &receipts)
+        .await
+        .with_context(|| format!("Not paying pye_account {}", args.pye_account))?;
+
+    println!(
+        "Epoch {}: {} to pye_account {} (latest receipt: {})",
+        args.epoch,
+        format_amount(retry.lamports),
+        labelled(&args.pye_account),
+        retry.receipt.payment
+    );
+    if args.dry_run {
+        info!("Dry run: the payment would be sent");
+        return Ok(());
+    }
+
+    let (payer, fee_payer) = load_signers(args.payer.as_deref(), args.fee_payer.as_deref())
+        .await
+        .context(ConfigError)?;
+    if !args.yes
+        && !Confirm::new()
+            .with_prompt(format!(
+                "Pay {} to pye_account {} for epoch {}?",
+                format_amount(retry.lamports),
+                labelled(&args.pye_account),
+                args.epoch
+            ))
+            .interact()?
+    {
+        info!("Aborted: user declined to send the payment.");
+        return Ok(());
+    }
+
+    // Receipts written before fingerprints were recorded have none to put in the memo.
+    let memo = Some(&retry.receipt.fingerprint)
+        .filter(|fingerprint| !fingerprint.is_empty())
+        .map(|fingerprint| fingerprint_memo(args.epoch, fingerprint));
+    let result = transfer_excess_rewards(
+        payer,
+        fee_payer,
+        args.cluster.anchor_cluster(&rpc),
+        &retry.program_id,
+        &retry.pye_account_pubkey,
+        &retry.pye_account,
+        retry.lamports,
+        memo.as_deref(),
+        &args.send,
+    )
+    .await;
+    let receipts = ReceiptWriter::new(args.receipts_dir.clone());
+    match result {
+        Ok(payment) => {
+            receipts.write(
+                &retry
+                    .receipt
+                    .with_payment(Ok(PaymentOutcome::Paid(payment)), Utc::now()),
+            );
+            match payment.verification {
+                Some(verification) if verification != PaymentVerification::Verified => {
+                    Err(anyhow!("Payment {} is {}", payment.signature, verification)
+                        .context(PaymentError))
+                }
+                _ => {
+                    info!(
+                        "Paid {} to pye_account {} for epoch {}: {}",
+                        format_amount(retry.lamports),
+                        args.pye_account,
+                        args.epoch,
+                        payment.signature
+                    );
+                    Ok(())
+                }
+            }
+        }
+        Err(e) => {
+            error!(
+                "Payment to pye_account {} for epoch {} failed: {}",
+                args.pye_account, args.epoch, e
+            );
+            receipts.write(&retry.receipt.with_payment(Err(e.to_string()), Utc::now()));
+            Err(e.context(PaymentError))
+        }
+    }
+}
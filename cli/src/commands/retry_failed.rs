@@ -0,0 +1,411 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use clap::Parser;
+use dialoguer::Confirm;
+use log::{error, info, warn};
+use pye_core_cpi::pye_core::accounts::SoloValidatorBond as SoloValidatorPyeAccount;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+
+use crate::{
+    accounts::fetch_solo_validator_pye_account,
+    cluster::ClusterPreset,
+    control::PaymentOutcome,
+    exit_code::{ConfigError, PaymentError},
+    keypairs::load_signers,
+    labels::labelled,
+    receipts::{failed_payments, load_receipts, pending_payments, PaymentReceipt, ReceiptWriter},
+    rpc_utils::new_rpc_client,
+    sol_format::format_amount,
+    transactions::{fingerprint_memo, transfer_excess_rewards, PaymentVerification, SendOptions},
+};
+
+/// Pages of the bond's transactions searched for an earlier payment.
+const MAX_SIGNATURE_PAGES: usize = 10;
+const SIGNATURES_PAGE_LIMIT: usize = 1000;
+
+#[derive(Clone, Debug, Parser)]
+pub struct RetryFailedArgs {
+    /// Cluster preset selecting the default RPC endpoint.
+    #[arg(long, env, value_enum, default_value_t = ClusterPreset::Mainnet)]
+    cluster: ClusterPreset,
+    /// RPC Endpoint [default: the cluster preset's endpoint]
+    #[arg(short, long, env)]
+    rpc: Option<String>,
+    /// Receipts directory written by validator-pye-account-manager --receipts-dir. Retries are
+    /// recorded there too.
+    #[arg(long, env)]
+    receipts_dir: PathBuf,
+    /// Only retry the failed payments of this epoch.
+    #[arg(long, env)]
+    epoch: Option<u64>,
+    /// Only retry the failed payments of this bond.
+    #[arg(long, env)]
+    pye_account: Option<Pubkey>,
+    /// Path to payer keypair, or `-` to read it from stdin [default: the PAYER_KEYPAIR_JSON
+    /// environment variable]
+    #[arg(short, long, env)]
+    payer: Option<String>,
+    /// Path to a keypair that pays transaction fees instead of the payer. `-` reads it from stdin.
+    #[arg(long, env)]
+    fee_payer: Option<String>,
+    /// Also pay the payments withheld by the manager's spending limits, approving them.
+    #[arg(long, env)]
+    approve_pending: bool,
+    /// List the payments that would be retried without sending them.
+    #[arg(long, env)]
+    dry_run: bool,
+    /// Retry without asking for confirmation.
+    #[arg(short, long, env)]
+    yes: bool,
+    #[command(flatten)]
+    send: SendOptions,
+}
+
+/// A failed payment checked against the bond's current on-chain state.
+pub struct Retry {
+    pub receipt: PaymentReceipt,
+    pub program_id: Pubkey,
+    pub pye_account_pubkey: Pubkey,
+    pub pye_account: SoloValidatorPyeAccount,
+    pub lamports: u64,
+}
+
+/// Why the bond no longer matches the receipt, if it doesn't. The amount can't be recomputed
+/// for a past epoch, so it is only paid if the inputs it was computed from still hold.
+fn receipt_mismatch(
+    receipt: &PaymentReceipt,
+    program_id: &Pubkey,
+    pye_account: &SoloValidatorPyeAccount,
+) -> Option<String> {
+    let inputs = &receipt.bond_inputs;
+    let rewards = &receipt.excess_rewards;
+    let commissions = &pye_account.reward_commissions;
+    if program_id.to_string() != receipt.program_id {
+        Some(format!("now owned by {}", program_id))
+    } else if pye_account.validator_vote_account.to_string() != receipt.vote_pubkey {
+        Some(format!(
+            "now delegates to {}",
+            pye_account.validator_vote_account
+        ))
+    } else if pye_account.issuer.to_string() != inputs.issuer
+        || pye_account.stake_account.to_string() != inputs.stake_account
+        || pye_account.transient_stake_account.to_string() != inputs.transient_stake_account
+    {
+        Some("issuer or stake accounts changed".to_string())
+    } else if (
+        commissions.inflation_bps,
+        commissions.mev_tips_bps,
+        commissions.block_rewards_bps,
+    ) != (
+        inputs.inflation_bps,
+        inputs.mev_tips_bps,
+        inputs.block_rewards_bps,
+    ) {
+        Some("commission terms changed".to_string())
+    } else if inputs.maturity_ts <= receipt.epoch_inputs.epoch_end_time {
+        Some("matured before the epoch ended".to_string())
+    } else if rewards.inflation + rewards.mev + rewards.block != rewards.total {
+        Some(format!("invalid amount of {} lamports", rewards.total))
+    } else if receipt.amount_due() <= 0 {
+        // Adjustments can make a non-positive excess owed, or cancel a positive one.
+        Some(format!("nothing due, {} lamports", receipt.amount_due()))
+    } else {
+        None
+    }
+}
+
+/// Whether a memo, as the RPC reports it with a transaction signature, marks a payment for
+/// `epoch`. The RPC prefixes each memo with its length and joins several with `; `.
+fn is_payment_memo(memo: &str, epoch: u64) -> bool {
+    let prefix = fingerprint_memo(epoch, "");
+    memo.split("; ").any(|memo| {
+        memo.split_once("] ")
+            .map_or(memo, |(_, memo)| memo)
+            .starts_with(&prefix)
+    })
+}
+
+/// Searches the bond's transactions since `since_slot` for a successful payment carrying the
+/// memo of `epoch`, e.g. one that landed after its receipt recorded a failure. The `recorded`
+/// signatures, of installments the receipt accounts for, are passed over.
+pub async fn find_landed_payment(
+    client: &RpcClient,
+    pye_account_pubkey: &Pubkey,
+    epoch: u64,
+    since_slot: u64,
+    recorded: &[&str],
+) -> Result<Option<Signature>> {
+    let mut before = None;
+    for _ in 0..MAX_SIGNATURE_PAGES {
+        let signatures = client
+            .get_signatures_for_address_with_config(
+                pye_account_pubkey,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until: None,
+                    limit: Some(SIGNATURES_PAGE_LIMIT),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                },
+            )
+            .await?;
+        let Some(last) = signatures.last() else {
+            return Ok(None);
+        };
+        before = Some(Signature::from_str(&last.signature)?);
+        for status in signatures.iter() {
+            if status.slot < since_slot {
+                return Ok(None);
+            }
+            let is_payment = status
+                .memo
+                .as_deref()
+                .is_some_and(|memo| is_payment_memo(memo, epoch));
+            if status.err.is_none() && is_payment && !recorded.contains(&status.signature.as_str())
+            {
+                return Ok(Some(Signature::from_str(&status.signature)?));
+            }
+        }
+    }
+    Err(anyhow!(
+        "Gave up searching the transactions of pye_account {} for an earlier payment after {} pages",
+        pye_account_pubkey,
+        MAX_SIGNATURE_PAGES
+    ))
+}
+
+/// Fetches the receipt's bond and checks that its amount can still be paid and wasn't paid by a
+/// transaction the receipts don't record. `receipts` holds the bond's other receipts.
+pub async fn check_receipt(
+    client: &RpcClient,
+    receipt: PaymentReceipt,
+    receipts: &[PaymentReceipt],
+) -> Result<Retry> {
+    let pye_account_pubkey = Pubkey::from_str(&receipt.pye_account)
+        .map_err(|e| anyhow!("Invalid pye_account {}: {}", receipt.pye_account, e))?;
+    let receipt_program_id = Pubkey::from_str(&receipt.program_id)
+        .map_err(|e| anyhow!("Invalid program_id {}: {}", receipt.program_id, e))?;
+    let (program_id, pye_account) =
+        fetch_solo_validator_pye_account(client, &[receipt_program_id], &pye_account_pubkey)
+            .await?;
+    if let Some(mismatch) = receipt_mismatch(&receipt, &program_id, &pye_account) {
+        return Err(anyhow!("Bond no longer matches its receipt: {}", mismatch));
+    }
+    // Payments for an epoch are only made once it has ended.
+    let since_slot = client
+        .get_epoch_schedule()
+        .await?
+        .get_first_slot_in_epoch(receipt.epoch + 1);
+    // The bond's other payment for the epoch, if its MEV rewards were deferred, carries the
+    // same memo.
+    let other_payments = receipts.iter().filter(|other| {
+        other.payment == "paid"
+            && other.pye_account == receipt.pye_account
+            && other.epoch == receipt.epoch
+            && other.payment_key() != receipt.payment_key()
+    });
+    let recorded: Vec<&str> = receipt
+        .installments
+        .iter()
+        .map(|installment| installment.signature.as_str())
+        .chain(other_payments.filter_map(|other| other.transaction_signature.as_deref()))
+        .collect();
+    if let Some(signature) = find_landed_payment(
+        client,
+        &pye_account_pubkey,
+        receipt.epoch,
+        since_slot,
+        &recorded,
+    )
+    .await?
+    {
+        return Err(anyhow!(
+            "Already paid by transaction {}, which isn't recorded in the receipts",
+            signature
+        ));
+    }
+    // Only the rest of a split payment is owed once some of its installments landed.
+    let lamports = (receipt.amount_due().max(0) as u64).saturating_sub(receipt.installments_paid());
+    Ok(Retry {
+        lamports,
+        receipt,
+        program_id,
+        pye_account_pubkey,
+        pye_account,
+    })
+}
+
+pub async fn handle_retry_failed(args: RetryFailedArgs) -> Result<()> {
+    let receipts = load_receipts(&args.receipts_dir).context(ConfigError)?;
+    let mut failed = failed_payments(receipts.clone());
+    if args.approve_pending {
+        failed.extend(pending_payments(receipts.clone()));
+        failed.sort_by_key(|receipt| receipt.epoch);
+    }
+    let failed: Vec<PaymentReceipt> = failed
+        .into_iter()
+        .filter(|receipt| args.epoch.is_none_or(|epoch| receipt.epoch == epoch))
+        .filter(|receipt| {
+            args.pye_account
+                .is_none_or(|pye_account| receipt.pye_account == pye_account.to_string())
+        })
+        .collect();
+    if failed.is_empty() {
+        info!("No failed payments to retry");
+        return Ok(());
+    }
+
+    let rpc = args
+        .rpc
+        .clone()
+        .unwrap_or_else(|| args.cluster.rpc_url().to_string());
+    let client = new_rpc_client(rpc.clone(), CommitmentConfig::confirmed(), None, None);
+    let mut retries = Vec::new();
+    let mut skipped = 0;
+    for receipt in failed {
+        let (epoch, pye_account) = (receipt.epoch, receipt.pye_account.clone());
+        match check_receipt(&client, receipt, &receipts).await {
+            Ok(retry) => {
+                let reason = match retry.receipt.payment.as_str() {
+                    "failed" => format!(
+                        "failed: {}",
+                        retry.receipt.error.as_deref().unwrap_or("unknown error")
+                    ),
+                    payment => payment.to_string(),
+                };
+                println!(
+                    "Epoch {}: {} to pye_account {} ({})",
+                    epoch,
+                    format_amount(retry.lamports),
+                    labelled(&retry.pye_account_pubkey),
+                    reason
+                );
+                retries.push(retry);
+            }
+            Err(e) => {
+                warn!(
+                    "Not retrying the payment of epoch {} to pye_account {}: {:#}",
+                    epoch, pye_account, e
+                );
+                skipped += 1;
+            }
+        }
+    }
+    if retries.is_empty() {
+        return Err(anyhow!(
+            "None of the {} failed payments can be retried",
+            skipped
+        ));
+    }
+    let total: u64 = retries.iter().map(|retry| retry.lamports).sum();
+    if args.dry_run {
+        info!(
+            "Dry run: {} payments totalling {} would be retried",
+            retries.len(),
+            format_amount(total)
+        );
+        return Ok(());
+    }
+
+    let (payer, fee_payer) = load_signers(args.payer.as_deref(), args.fee_payer.as_deref())
+        .await
+        .context(ConfigError)?;
+    if !args.yes
+        && !Confirm::new()
+            .with_prompt(format!(
+                "Retry {} payments totalling {}?",
+                retries.len(),
+                format_amount(total)
+            ))
+            .interact()?
+    {
+        info!("Aborted: user declined to retry the payments.");
+        return Ok(());
+    }
+
+    let receipts = ReceiptWriter::new(args.receipts_dir.clone());
+    let cluster = args.cluster.anchor_cluster(&rpc);
+    let mut failures = 0;
+    for retry in &retries {
+        // Receipts written before fingerprints were recorded have none to put in the memo.
+        let memo = Some(&retry.receipt.fingerprint)
+            .filter(|fingerprint| !fingerprint.is_empty())
+            .map(|fingerprint| fingerprint_memo(retry.receipt.epoch, fingerprint));
+        let result = transfer_excess_rewards(
+            payer.clone(),
+            fee_payer.clone(),
+            cluster.clone(),
+            &retry.program_id,
+            &retry.pye_account_pubkey,
+            &retry.pye_account,
+            retry.lamports,
+            memo.as_deref(),
+            &args.send,
+        )
+        .await;
+        let payment = match result {
+            Ok(payment) => {
+                info!(
+                    "Paid {} to pye_account {} for epoch {}: {}",
+                    format_amount(retry.lamports),
+                    retry.pye_account_pubkey,
+                    retry.receipt.epoch,
+                    payment.signature
+                );
+                if let Some(verification) = payment
+                    .verification
+                    .filter(|verification| *verification != PaymentVerification::Verified)
+                {
+                    error!(
+                        "Payment {} to pye_account {} for epoch {} is {}",
+                        payment.signature,
+                        retry.pye_account_pubkey,
+                        retry.receipt.epoch,
+                        verification
+                    );
+                    failures += 1;
+                }
+                Ok(PaymentOutcome::Paid(payment))
+            }
+            Err(e) => {
+                error!(
+                    "Retry of the payment to pye_account {} for epoch {} failed: {}",
+                    retry.pye_account_pubkey, retry.receipt.epoch, e
+                );
+                failures += 1;
+                Err(e.to_string())
+            }
+        };
+        receipts.write(&retry.receipt.with_payment(payment, Utc::now()));
+    }
+    if failures > 0 {
+        return Err(anyhow!(
+            "{} of {} retried payments failed",
+            failures,
+            retries.len()
+        ))
+        .context(PaymentError);
+    }
+    info!("Retried {} payments", retries.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_payment_memo() {
+        assert!(is_payment_memo("[20] pye:800:3vQB7B6MrGQZ", 800));
+        assert!(is_payment_memo("[5] hello; [20] pye:800:3vQB7B6MrGQZ", 800));
+        assert!(!is_payment_memo("[20] pye:801:3vQB7B6MrGQZ", 800));
+        assert!(!is_payment_memo("[20] pye:8000:3vQB7B6MrGQ", 800));
+        assert!(!is_payment_memo("[8] pye:800", 800));
+    }
+}
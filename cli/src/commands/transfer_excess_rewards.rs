@@ -1,88 +1,588 @@
-use crate::accounts::fetch_solo_validator_pye_account;
-use crate::active_stake::fetch_pye_account_active_stake;
+use crate::accounts::{bond_unpayable_reason, fetch_solo_validator_pye_account, EpochSysvars};
+use crate::active_stake::{check_delegations, fetch_pye_account_active_stake};
+use crate::cluster::ClusterPreset;
+use crate::control::{BondRewardBreakdown, PaymentOutcome};
+use crate::dashboard::RewardBreakdown;
+use crate::exit_code::{ConfigError, ExitCode, PaymentError};
+use crate::keypairs::{load_signers, SharedSigner};
+use crate::labels::labelled;
+use crate::metrics::{datapoint_info, datapoint_warn, flush};
 use crate::metrics_helpers::*;
-use crate::rewards::block_rewards::calculate_excess_block_reward;
-use crate::rewards::inflation_rewards::calculate_excess_inflation_reward;
-use crate::rewards::mev_rewards::{calculate_excess_mev_reward, fetch_and_filter_mev_data};
-use crate::transactions::transfer_excess_rewards;
-use anchor_client::Cluster;
-use anyhow::{anyhow, Result};
+use crate::notifications::{Notification, NotificationArgs, Notifier, Severity};
+use crate::receipts::{calculation_fingerprint, value_name, EpochInputs};
+use crate::rewards::block_archive::BlockArchiveArgs;
+use crate::rewards::block_rewards::{
+    calculate_excess_block_reward, BlockRewardComponents, BlockScanOptions, SlotExportArgs,
+    VerifyRpcArgs,
+};
+use crate::rewards::commission_history::{
+    fetch_vote_commission, log_epoch_commission, track_epoch_commission,
+};
+use crate::rewards::inflation_model::{
+    bond_stake_accounts, verify_inflation_rewards, InflationVerifyArgs, LocalInflationModel,
+};
+use crate::rewards::inflation_rewards::{
+    calculate_excess_inflation_reward, InflationFallback, InflationRewardCache,
+};
+use crate::rewards::mev_rewards::{
+    calculate_excess_mev_reward, load_mev_data, use_epoch_mev_commission,
+};
+use crate::rewards::{Rounding, RoundingMode};
+use crate::rpc_fixtures::{FixtureArgs, FixtureStore};
+use crate::rpc_usage::RpcUsage;
+use crate::rpc_utils::{check_for_next_epoch, new_rpc_client, BoundaryCheck};
+use crate::snapshot::{epoch_info_at, new_snapshot_rpc_client};
+use crate::sol_format::format_amount;
+use crate::stake_lineage::fetch_linked_stake_accounts;
+use crate::transactions::{
+    fingerprint_memo, transfer_excess_rewards, Payment, PaymentVerification, SendOptions,
+};
+use anyhow::{anyhow, Context, Result};
 use dialoguer::Confirm;
-use log::info;
+use log::{info, warn};
+use pye_core_cpi::pye_core::accounts::SoloValidatorBond as SoloValidatorPyeAccount;
 use solana_client::nonblocking::rpc_client::RpcClient;
-use solana_metrics::{datapoint_info, flush};
 use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub struct TransferExcessRewardsArgs {
-    pub rpc: String,
-    pub payer_file_path: String,
+    pub cluster: ClusterPreset,
+    pub rpc: Option<String>,
+    pub program_id: Option<Pubkey>,
+    pub payer: Option<String>,
+    pub fee_payer: Option<String>,
     pub pye_account: String,
     pub concurrency: usize,
     pub dry_run: bool,
     pub block_retry_delay: u64,
+    pub block_retry_deadline: u64,
+    pub block_reward_components: BlockRewardComponents,
+    pub jito_api_url: Option<String>,
+    pub rounding: RoundingMode,
+    pub assumed_inflation_commission: Option<u8>,
+    pub track_commission_changes: bool,
+    pub track_mev_commission: bool,
+    pub track_stake_accounts: bool,
+    pub as_of_slot: Option<u64>,
+    pub verify: VerifyRpcArgs,
+    pub slot_export: SlotExportArgs,
+    pub block_archive: BlockArchiveArgs,
+    pub verify_inflation: InflationVerifyArgs,
+    pub send: SendOptions,
+    pub fixtures: FixtureArgs,
+    pub daemon: bool,
+    pub cycle_secs: u64,
+    pub notifications: NotificationArgs,
 }
 
-pub async fn handle_transfer_excess_rewards(args: TransferExcessRewardsArgs) -> Result<()> {
-    let client = RpcClient::new_with_commitment(args.rpc.clone(), CommitmentConfig::confirmed());
-    let pye_account_pubkey = Pubkey::from_str(&args.pye_account).map_err(|e| anyhow!("Invalid pye_account: {}", e))?;
+/// The payer and optional fee payer of a transfer.
+type Signers = (SharedSigner, Option<SharedSigner>);
+
+/// What became of the bond's excess rewards for the epoch before the current one.
+#[derive(Clone, Copy, Debug)]
+enum EpochTransfer {
+    NothingOwed {
+        epoch: u64,
+    },
+    DryRun {
+        epoch: u64,
+        excess_rewards: i64,
+    },
+    Declined {
+        epoch: u64,
+        excess_rewards: i64,
+    },
+    Unpayable {
+        epoch: u64,
+        excess_rewards: i64,
+        reason: String,
+    },
+    Paid {
+        epoch: u64,
+        excess_rewards: i64,
+        payment: Payment,
+    },
+}
+
+pub async fn handle_transfer_excess_rewards(args: TransferExcessRewardsArgs) -> Result<ExitCode> {
+    if args.daemon {
+        return run_daemon(&args).await;
+    }
+    Ok(match transfer_epoch(&args, None).await? {
+        EpochTransfer::NothingOwed { .. } => ExitCode::NothingOwed,
+        EpochTransfer::DryRun { .. } => ExitCode::DryRun,
+        EpochTransfer::Declined { .. }
+        | EpochTransfer::Unpayable { .. }
+        | EpochTransfer::Paid { .. } => ExitCode::Success,
+    })
+}
+
+/// Processes the bond at every epoch boundary until stopped, notifying what became of each
+/// epoch's excess rewards. Only boundaries crossed while it runs are processed, so a restart
+/// never pays an epoch twice. Transfers are sent without a prompt, so bonds that matured or
+/// changed are skipped rather than paid.
+async fn run_daemon(args: &TransferExcessRewardsArgs) -> Result<ExitCode> {
+    let notifier = Notifier::from_args(&args.notifications).context(ConfigError)?;
+    // Load the keypairs once, so a bad one fails at startup and stdin is read only once.
+    let signers = if args.dry_run {
+        None
+    } else {
+        Some(
+            load_signers(args.payer.as_deref(), args.fee_payer.as_deref())
+                .await
+                .context(ConfigError)?,
+        )
+    };
+    let rpc = args
+        .rpc
+        .clone()
+        .unwrap_or_else(|| args.cluster.rpc_url().to_string());
+    let client = new_rpc_client(rpc, CommitmentConfig::confirmed(), None, None);
+    let mut current_epoch = client.get_epoch_info().await?.epoch;
+    info!(
+        "Monitoring pye_account {}, waiting for the end of epoch {}",
+        args.pye_account, current_epoch
+    );
+    loop {
+        let epoch_info = loop {
+            match check_for_next_epoch(&client, current_epoch, Duration::from_secs(args.cycle_secs))
+                .await
+            {
+                BoundaryCheck::NewEpoch(epoch_info) => break epoch_info,
+                BoundaryCheck::NotYet(delay) => tokio::time::sleep(delay).await,
+            }
+        };
+        current_epoch = epoch_info.epoch;
+        // We wait 30 seconds to avoid "Epoch rewards period still active at slot" RPC errors
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        let transfer = transfer_epoch(args, signers.clone()).await;
+        notifier
+            .notify(epoch_notification(
+                &args.pye_account,
+                current_epoch.saturating_sub(1),
+                &transfer,
+            ))
+            .await;
+        if let Err(e) = transfer {
+            if e.downcast_ref::<ConfigError>().is_some() {
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// The notification of what became of an epoch's excess rewards for the daemon.
+fn epoch_notification(
+    pye_account: &str,
+    target_epoch: u64,
+    transfer: &Result<EpochTransfer>,
+) -> Notification {
+    let bond =
+        Pubkey::from_str(pye_account).map_or(pye_account.to_string(), |pubkey| labelled(&pubkey));
+    match transfer {
+        Ok(EpochTransfer::NothingOwed { epoch }) => Notification::new(
+            Severity::Info,
+            format!("Nothing owed to {} for epoch {}", bond, epoch),
+            "The bond's excess rewards were computed and none are owed.",
+        ),
+        Ok(EpochTransfer::DryRun {
+            epoch,
+            excess_rewards,
+        }) => Notification::new(
+            Severity::Info,
+            format!(
+                "{} owed to {} for epoch {}",
+                format_amount(*excess_rewards),
+                bond,
+                epoch
+            ),
+            "Computed with --dry-run, nothing was paid.",
+        ),
+        Ok(EpochTransfer::Declined {
+            epoch,
+            excess_rewards,
+        }) => Notification::new(
+            Severity::Warning,
+            format!(
+                "{} owed to {} for epoch {}",
+                format_amount(*excess_rewards),
+                bond,
+                epoch
+            ),
+            "The transfer was declined, nothing was paid.",
+        ),
+        Ok(EpochTransfer::Unpayable {
+            epoch,
+            excess_rewards,
+            reason,
+        }) => Notification::new(
+            Severity::Warning,
+            format!("Payment to {} skipped", bond),
+            format!(
+                "Excess rewards of {} for epoch {} weren't transferred because {}. Review the \
+                 bond and pay it manually if it's still owed.",
+                format_amount(*excess_rewards),
+                epoch,
+                reason
+            ),
+        ),
+        Ok(EpochTransfer::Paid {
+            epoch,
+            excess_rewards,
+            payment,
+        }) => Notification::new(
+            Severity::Info,
+            format!(
+                "Paid {} to {} for epoch {}",
+                format_amount(*excess_rewards),
+                bond,
+                epoch
+            ),
+            format!("Transaction: {}", payment.signature),
+        ),
+        Err(e) => Notification::new(
+            Severity::Critical,
+            format!("Processing {} for epoch {} failed", bond, target_epoch),
+            format!(
+                "{:#}\nThe daemon continues with the next epoch. Run transfer-excess-rewards \
+                 once by hand before epoch {} ends to pay this one, since it only computes the \
+                 epoch before the current one.",
+                e,
+                target_epoch + 1
+            ),
+        ),
+    }
+}
+
+/// Computes the bond's excess rewards for the epoch before the current one and pays them, after
+/// asking unless `signers` were loaded up front by the daemon.
+async fn transfer_epoch(
+    args: &TransferExcessRewardsArgs,
+    signers: Option<Signers>,
+) -> Result<EpochTransfer> {
+    let fixtures = FixtureStore::from_args(&args.fixtures)?;
+    if fixtures.as_ref().is_some_and(|store| store.is_replay()) && !args.dry_run {
+        return Err(
+            anyhow!("--replay can only be used together with --dry-run").context(ConfigError)
+        );
+    }
+    let rpc = args
+        .rpc
+        .clone()
+        .unwrap_or_else(|| args.cluster.rpc_url().to_string());
+    let jito_api_url = args
+        .jito_api_url
+        .clone()
+        .or_else(|| args.cluster.jito_api_url().map(str::to_string));
+    let rpc_usage = Arc::new(RpcUsage::default());
+    let client = match args.as_of_slot {
+        Some(slot) => new_snapshot_rpc_client(
+            rpc.clone(),
+            CommitmentConfig::confirmed(),
+            fixtures.clone(),
+            Some(Arc::clone(&rpc_usage)),
+            slot,
+        ),
+        None => new_rpc_client(
+            rpc.clone(),
+            CommitmentConfig::confirmed(),
+            fixtures.clone(),
+            Some(Arc::clone(&rpc_usage)),
+        ),
+    };
+    let pye_account_pubkey =
+        Pubkey::from_str(&args.pye_account).map_err(|e| anyhow!("Invalid pye_account: {}", e))?;
 
     // Fetch RewardCommissions configured on SoloValidatorPyeAccount.
-    let pye_account = fetch_solo_validator_pye_account(&client, &pye_account_pubkey).await?;
+    let expected_program_id = args.program_id.unwrap_or_else(|| args.cluster.program_id());
+    let (program_id, pye_account) =
+        fetch_solo_validator_pye_account(&client, &[expected_program_id], &pye_account_pubkey)
+            .await?;
     let reward_commissions = pye_account.reward_commissions.clone();
-    info!("Current: {:?}", reward_commissions);
+    info!("Current: {:?} (program {})", reward_commissions, program_id);
 
-    // Fetch the current Solana Network epoch.
-    let epoch_info = client.get_epoch_info().await?;
+    // Fetch the current Solana Network epoch, or the epoch of the slot the reads are pinned to.
+    let epoch_info = match args.as_of_slot {
+        Some(slot) => epoch_info_at(&client, slot).await?,
+        None => client.get_epoch_info().await?,
+    };
     let current_epoch = epoch_info.epoch;
-    let target_epoch = current_epoch - 1;
+    let target_epoch = current_epoch
+        .checked_sub(1)
+        .ok_or_else(|| anyhow!("No epoch has ended yet"))?;
     println!("Current epoch: {}\n", current_epoch);
-    log_reward_commissions(target_epoch, &pye_account_pubkey, &reward_commissions);
+    log_reward_commissions(
+        target_epoch,
+        &program_id,
+        &pye_account_pubkey,
+        &reward_commissions,
+    );
+
+    // Sysvars shared by the stake activation, inflation and block reward calculations.
+    let sysvars = EpochSysvars::default();
 
     // Fetch info about MEV rewards for target epoch from Jito's API.
-    let mev_data = fetch_and_filter_mev_data(&pye_account.validator_vote_account, target_epoch).await?;
+    let mut mev_data = load_mev_data(
+        &client,
+        &sysvars,
+        jito_api_url.as_deref(),
+        &pye_account.validator_vote_account,
+        target_epoch,
+        fixtures.as_deref(),
+    )
+    .await?;
+    if args.track_mev_commission {
+        use_epoch_mev_commission(
+            &client,
+            &mut mev_data,
+            &pye_account.validator_vote_account,
+            target_epoch,
+        )
+        .await;
+    }
     log_validator_mev_data(target_epoch, &mev_data);
 
+    // Fetch the inflation rewards of the bond's stake accounts and the vote account at once.
+    let mut addresses = bond_stake_accounts(
+        &pye_account.stake_account,
+        &pye_account.transient_stake_account,
+    );
+    addresses.push(pye_account.validator_vote_account);
+    let inflation_rewards = InflationRewardCache::fetch(&client, &addresses, target_epoch)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("{}, fetching each account's inflation reward on its own", e);
+            InflationRewardCache::empty(target_epoch)
+        });
+
+    // Follow the stake that left the bond's stake accounts since the target epoch.
+    let linked_stake_accounts = if args.track_stake_accounts {
+        let epoch_schedule = client.get_epoch_schedule().await?;
+        fetch_linked_stake_accounts(
+            &client,
+            &bond_stake_accounts(
+                &pye_account.stake_account,
+                &pye_account.transient_stake_account,
+            ),
+            epoch_schedule.get_first_slot_in_epoch(target_epoch),
+        )
+        .await?
+    } else {
+        Vec::new()
+    };
+
+    // Stake redelegated to another validator isn't the validator's to pay for.
+    let mut stake_accounts = bond_stake_accounts(
+        &pye_account.stake_account,
+        &pye_account.transient_stake_account,
+    );
+    stake_accounts.extend(linked_stake_accounts.iter().copied());
+    let redelegated = check_delegations(
+        &client,
+        &sysvars,
+        &stake_accounts,
+        &pye_account.validator_vote_account,
+        target_epoch,
+    )
+    .await?;
+
     // Fetch the SoloValidatorPyeAccount's active stake during target epoch.
     let pye_account_active_stake = fetch_pye_account_active_stake(
         &client,
+        &sysvars,
+        &inflation_rewards,
         &pye_account.stake_account,
         &pye_account.transient_stake_account,
+        &linked_stake_accounts,
+        &redelegated,
         target_epoch,
         current_epoch,
     )
     .await?;
 
+    // Reconstruct the validator's commission over the target epoch to catch mid-epoch changes.
+    let (epoch_start_commission, epoch_end_commission, vote_account_commission) =
+        if args.track_commission_changes {
+            let epoch_schedule = client.get_epoch_schedule().await?;
+            let epoch_commission = track_epoch_commission(
+                &client,
+                &pye_account.validator_vote_account,
+                &epoch_schedule,
+                target_epoch,
+            )
+            .await?;
+            log_epoch_commission(
+                &pye_account.validator_vote_account,
+                target_epoch,
+                &epoch_commission,
+            );
+            (
+                epoch_commission.start,
+                epoch_commission.end,
+                epoch_commission.end,
+            )
+        } else {
+            // Without history the current commission stands in for the target epoch's.
+            let current = fetch_vote_commission(&client, &pye_account.validator_vote_account)
+                .await
+                .ok();
+            (None, None, current)
+        };
+
+    // Recompute the stake accounts' inflation rewards locally to catch incomplete RPC history.
+    if args.verify_inflation.verify_inflation {
+        match LocalInflationModel::load(
+            &client,
+            &sysvars,
+            &pye_account.validator_vote_account,
+            target_epoch,
+            current_epoch,
+            vote_account_commission,
+        )
+        .await
+        {
+            Ok(model) => {
+                verify_inflation_rewards(
+                    &client,
+                    &model,
+                    &bond_stake_accounts(
+                        &pye_account.stake_account,
+                        &pye_account.transient_stake_account,
+                    ),
+                    args.verify_inflation.verify_inflation_tolerance_bps,
+                )
+                .await
+            }
+            Err(e) => warn!("Skipping local inflation reward verification: {}", e),
+        }
+    }
+
+    let mut rounding = Rounding::new(args.rounding);
+    // The inflation and block rewards are fetched concurrently, each rounding on its own.
+    let mut inflation_rounding = Rounding::new(args.rounding);
+    let mut mev_rounding = Rounding::new(args.rounding);
+    let mut block_rounding = Rounding::new(args.rounding);
+
     // Calculate the excess inflation reward to be refunded by validator to SoloValidatorPyeAccount.
     let excess_inflation_reward = calculate_excess_inflation_reward(
         &client,
+        &inflation_rewards,
         &pye_account.stake_account,
         &pye_account.transient_stake_account,
+        &linked_stake_accounts,
+        &redelegated,
         target_epoch,
         &reward_commissions,
-    )
-    .await;
+        &InflationFallback {
+            vote_pubkey: &pye_account.validator_vote_account,
+            validator_active_stake: mev_data.active_stake,
+            assumed_commission: args.assumed_inflation_commission,
+            epoch_end_commission,
+            vote_account_commission,
+            sysvars: &sysvars,
+        },
+        &mut inflation_rounding,
+    );
 
     // Calculate the excess MEV reward to be refunded by validator to SoloValidatorPyeAccount.
-    let excess_mev_commission =
-        calculate_excess_mev_reward(&mev_data, pye_account_active_stake, &reward_commissions);
+    let excess_mev_commission = calculate_excess_mev_reward(
+        &mev_data,
+        pye_account_active_stake,
+        &reward_commissions,
+        &mut mev_rounding,
+    )?;
 
     // Calculate the excess block reward to be refunded by validator to SoloValidatorPyeAccount.
     let excess_block_commission = calculate_excess_block_reward(
         &client,
+        &sysvars,
         &pye_account.validator_vote_account,
         &epoch_info,
         pye_account_active_stake,
         mev_data.active_stake,
         &reward_commissions,
-        args.concurrency,
-        args.block_retry_delay,
-    )
-    .await?;
+        &BlockScanOptions {
+            concurrency: args.concurrency,
+            block_retry_delay: args.block_retry_delay,
+            block_retry_deadline: args.block_retry_deadline,
+            components: args.block_reward_components,
+            verify: args.verify.clone(),
+            slot_export: args.slot_export.clone(),
+            archive: args.block_archive.clone(),
+        },
+        &mut block_rounding,
+    );
+    let (excess_inflation_reward, excess_block_commission) =
+        tokio::join!(excess_inflation_reward, excess_block_commission);
+    let (excess_block_commission, validator_block_rewards) = excess_block_commission?;
+    for part in [&inflation_rounding, &mev_rounding, &block_rounding] {
+        rounding.merge(part);
+    }
 
     let excess_rewards = excess_inflation_reward + excess_block_commission + excess_mev_commission;
-    println!("Total Excess Rewards: {}\n", excess_rewards);
+    println!(
+        "Rounding drift ({:?}): {:.4} lamports",
+        rounding.mode,
+        rounding.drift()
+    );
+    println!("Total Excess Rewards: {}\n", format_amount(excess_rewards));
+    let usage = rpc_usage.snapshot();
+    println!("RPC usage: {}\n", usage);
+    usage.report(&pye_account.validator_vote_account, target_epoch);
+
+    // The same inputs and fingerprint the manager records, so the payment's memo marks it as
+    // the epoch's payment to retry-failed, resend and the manager's duplicate checks.
+    let epoch_end_time = epoch_end_time(&client, target_epoch).await;
+    let epoch_inputs = EpochInputs {
+        epoch_end_time,
+        validator_active_stake: mev_data.active_stake,
+        validator_mev_rewards: mev_data.mev_rewards,
+        validator_mev_commission_bps: mev_data.mev_commission_bps,
+        validator_block_rewards,
+        block_reward_components: value_name(args.block_reward_components),
+        inflation_commission_start: epoch_start_commission,
+        inflation_commission_end: epoch_end_commission,
+        rounding: value_name(args.rounding),
+        skipped_categories: Vec::new(),
+    };
+    let fingerprint = calculation_fingerprint(
+        &pye_account.validator_vote_account,
+        &epoch_inputs,
+        &pye_account,
+        &BondRewardBreakdown {
+            program_id,
+            pye_account: pye_account_pubkey,
+            active_stake: pye_account_active_stake,
+            rewards: RewardBreakdown {
+                epoch: target_epoch,
+                inflation: excess_inflation_reward,
+                mev: excess_mev_commission,
+                block: excess_block_commission,
+            },
+            payment: PaymentOutcome::NotDue,
+        },
+    );
 
+    let transfer = settle_transfer(
+        args,
+        &client,
+        &rpc,
+        signers,
+        &program_id,
+        &pye_account_pubkey,
+        &pye_account,
+        target_epoch,
+        excess_rewards,
+        epoch_end_time,
+        &fingerprint_memo(target_epoch, &fingerprint),
+    )
+    .await;
+    let payment = match &transfer {
+        Ok(EpochTransfer::Paid { payment, .. }) => Some(payment),
+        _ => None,
+    };
     datapoint_info!(
         "excess_reward",
         (
@@ -91,47 +591,223 @@ pub async fn handle_transfer_excess_rewards(args: TransferExcessRewardsArgs) ->
             String
         ),
         ("epoch", target_epoch.to_string(), String),
+        ("program_id", program_id.to_string(), String),
         ("pye_account", pye_account_pubkey.to_string(), String),
-        ("pye_account_active_stake", pye_account_active_stake as i64, i64),
+        (
+            "pye_account_active_stake",
+            pye_account_active_stake as i64,
+            i64
+        ),
         ("excess_inflation_rewards", excess_inflation_reward, i64),
         ("excess_mev_rewards", excess_mev_commission, i64),
         ("excess_block_rewards", excess_block_commission, i64),
         ("total_excess_rewards", excess_rewards, i64),
+        ("rounding_drift", rounding.drift(), f64),
+        ("fingerprint", fingerprint, String),
+        ("payment", transfer_outcome(&transfer).to_string(), String),
+        (
+            "signature",
+            payment
+                .map(|payment| payment.signature.to_string())
+                .unwrap_or_default(),
+            String
+        ),
+        (
+            "fee",
+            payment.and_then(|payment| payment.fee).unwrap_or_default() as i64,
+            i64
+        ),
+        (
+            "slot",
+            payment.and_then(|payment| payment.slot).unwrap_or_default() as i64,
+            i64
+        ),
     );
     flush();
+    transfer
+}
 
+/// Pays the excess rewards computed for the bond, unless none are owed, it's a dry run, the
+/// daemon finds the bond unpayable or the transfer is declined.
+async fn settle_transfer(
+    args: &TransferExcessRewardsArgs,
+    client: &RpcClient,
+    rpc: &str,
+    signers: Option<Signers>,
+    program_id: &Pubkey,
+    pye_account_pubkey: &Pubkey,
+    pye_account: &SoloValidatorPyeAccount,
+    target_epoch: u64,
+    excess_rewards: i64,
+    epoch_end_time: i64,
+    memo: &str,
+) -> Result<EpochTransfer> {
     if excess_rewards <= 0 {
         info!(
             "No excess rewards to transfer to SoloValidatorPyeAccount for epoch {}\n",
             target_epoch
         );
-        return Ok(());
+        return Ok(EpochTransfer::NothingOwed {
+            epoch: target_epoch,
+        });
     }
 
     if args.dry_run {
         info!("Dry run complete");
-        return Ok(());
+        return Ok(EpochTransfer::DryRun {
+            epoch: target_epoch,
+            excess_rewards,
+        });
     }
 
-    if Confirm::new()
-        .with_prompt(format!(
-            "Transfer {} lamports in excess rewards to SoloValidatorPyeAccount at {}?",
-            excess_rewards, pye_account_pubkey
-        ))
-        .interact()?
-    {
-        let cluster = Cluster::Custom(args.rpc.clone(), args.rpc.replace("http", "ws"));
+    // Nobody confirms the daemon's transfers, so bonds that matured before the epoch ended or
+    // changed since are skipped, as the manager does.
+    if signers.is_some() {
+        if let Some(reason) = unpayable_reason(
+            client,
+            program_id,
+            pye_account_pubkey,
+            pye_account,
+            epoch_end_time,
+        )
+        .await
+        {
+            warn!(
+                "Not transferring {} to pye_account {} for epoch {}: {}",
+                format_amount(excess_rewards),
+                pye_account_pubkey,
+                target_epoch,
+                reason
+            );
+            datapoint_warn!(
+                "payment_bond_changed",
+                (
+                    "vote_pubkey",
+                    pye_account.validator_vote_account.to_string(),
+                    String
+                ),
+                ("epoch", target_epoch.to_string(), String),
+                ("pye_account", pye_account_pubkey.to_string(), String),
+                ("lamports", excess_rewards, i64),
+                ("reason", reason.clone(), String),
+            );
+            return Ok(EpochTransfer::Unpayable {
+                epoch: target_epoch,
+                excess_rewards,
+                reason,
+            });
+        }
+    }
+
+    let ((payer, fee_payer), confirmed) = match signers {
+        Some(signers) => (signers, true),
+        None => {
+            let signers = load_signers(args.payer.as_deref(), args.fee_payer.as_deref())
+                .await
+                .context(ConfigError)?;
+            let confirmed = Confirm::new()
+                .with_prompt(format!(
+                    "Transfer {} in excess rewards to SoloValidatorPyeAccount at {}?",
+                    format_amount(excess_rewards),
+                    labelled(pye_account_pubkey)
+                ))
+                .interact()?;
+            (signers, confirmed)
+        }
+    };
+    if confirmed {
+        let cluster = args.cluster.anchor_cluster(rpc);
         transfer_excess_rewards(
-            args.payer_file_path,
+            payer,
+            fee_payer,
             cluster,
-            &pye_account_pubkey,
-            &pye_account,
+            program_id,
+            pye_account_pubkey,
+            pye_account,
             u64::try_from(excess_rewards)?,
+            Some(memo),
+            &args.send,
         )
         .await
-        .map_err(|e| anyhow!("Failed to transfer excess rewards: {}", e))
+        .map_err(|e| {
+            e.context("Failed to transfer excess rewards")
+                .context(PaymentError)
+        })
+        .and_then(|payment| {
+            if let Some(fee) = payment.fee {
+                info!("Transaction fee: {}", format_amount(fee));
+            }
+            if let Some(slot) = payment.slot {
+                info!("Confirmed in slot {}", slot);
+            }
+            match payment.verification {
+                Some(verification) if verification != PaymentVerification::Verified => {
+                    Err(anyhow!("Payment {} is {}", payment.signature, verification)
+                        .context(PaymentError))
+                }
+                _ => Ok(EpochTransfer::Paid {
+                    epoch: target_epoch,
+                    excess_rewards,
+                    payment,
+                }),
+            }
+        })
     } else {
         info!("Aborted: user declined to transfer excess rewards.");
-        Ok(())
+        Ok(EpochTransfer::Declined {
+            epoch: target_epoch,
+            excess_rewards,
+        })
+    }
+}
+
+/// What became of the excess rewards, named like the manager's payment outcomes.
+fn transfer_outcome(transfer: &Result<EpochTransfer>) -> &'static str {
+    match transfer {
+        Ok(EpochTransfer::NothingOwed { .. }) => "not_due",
+        Ok(EpochTransfer::DryRun { .. }) => "dry_run",
+        Ok(EpochTransfer::Declined { .. }) => "declined",
+        Ok(EpochTransfer::Unpayable { .. }) => "bond_changed",
+        Ok(EpochTransfer::Paid { .. }) => "paid",
+        Err(_) => "failed",
+    }
+}
+
+/// Block time of the last slot of `target_epoch`, or the current time when the RPC can't tell,
+/// as the manager compares bond maturities against.
+async fn epoch_end_time(client: &RpcClient, target_epoch: u64) -> i64 {
+    match client.get_epoch_schedule().await {
+        Ok(epoch_schedule) => client
+            .get_block_time(epoch_schedule.get_last_slot_in_epoch(target_epoch))
+            .await
+            .ok(),
+        Err(_) => None,
+    }
+    .unwrap_or_else(|| chrono::Utc::now().timestamp())
+}
+
+/// Why the bond must not be paid: it matured before the epoch ended at `epoch_end_time`, or its
+/// account, fetched again, was closed or changed since it was read.
+async fn unpayable_reason(
+    client: &RpcClient,
+    program_id: &Pubkey,
+    pye_account_pubkey: &Pubkey,
+    pye_account: &SoloValidatorPyeAccount,
+    epoch_end_time: i64,
+) -> Option<String> {
+    if pye_account.maturity_ts <= epoch_end_time {
+        return Some("the bond matured before the epoch ended".to_string());
+    }
+    match client
+        .get_account_with_commitment(pye_account_pubkey, CommitmentConfig::confirmed())
+        .await
+    {
+        Ok(response) => bond_unpayable_reason(
+            program_id,
+            &pye_account.validator_vote_account,
+            pye_account,
+            response.value.as_ref(),
+        ),
+        Err(e) => Some(format!("the bond account couldn't be re-fetched: {}", e)),
     }
 }
@@ -1,74 +1,542 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashSet, future::Future, net::SocketAddr, path::PathBuf, str::FromStr, sync::Arc,
+    time::Duration,
+};
 
-use anchor_client::Cluster;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use clap::Parser;
-use futures::stream::{self, StreamExt};
-use log::info;
+use cron::Schedule;
+use futures::future::try_join_all;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use log::{error, info, warn};
 use pye_core_cpi::pye_core::accounts::SoloValidatorBond as SoloValidatorPyeAccount;
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_commitment_config::CommitmentConfig;
-use solana_metrics::{datapoint_error, datapoint_info, flush};
-use solana_sdk::pubkey::Pubkey;
+use solana_sdk::{
+    epoch_info::EpochInfo, epoch_schedule::EpochSchedule, pubkey::Pubkey, signature::Signature,
+    signer::Signer,
+};
+use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle, time::Instant};
 
+use crate::metrics::{self, datapoint_error, datapoint_info, datapoint_warn, flush};
 use crate::{
-    accounts::fetch_active_solo_validator_pye_accounts_by_vote_key_and_issuer,
-    active_stake::fetch_pye_account_active_stake,
+    accounts::{
+        bond_unpayable_reason, fetch_solo_validator_pye_account,
+        fetch_solo_validator_pye_accounts_by_vote_key, EpochSysvars, GpaChunking,
+        PyeAccountFetchOptions,
+    },
+    active_stake::{
+        check_delegations, fetch_pye_account_active_stake, fetch_validator_active_stake,
+    },
+    adjustments::{adjustments_total, Adjustment, Adjustments},
+    alert_thresholds::AlertThresholdArgs,
+    archive::{Archive, ArchiveArgs},
+    attestations::{AttestationArgs, AttestationWriter},
+    bond_scheduling::{round_robin, BondKey, BondSchedulingArgs},
+    bond_state::{BondPass, BondStage, BondState, BondStates},
+    bond_watch::BondWatcher,
+    cluster::ClusterPreset,
+    commands::retry_failed::find_landed_payment,
+    commission_guard::CommissionGuard,
+    control::{BondRewardBreakdown, ManagerControl, PaymentOutcome, RecomputeRequest},
+    dashboard::{Dashboard, RewardBreakdown},
+    email::epoch_report_html,
+    epoch_boundary::{default_ws_url, wait_for_rollover, watch_slots, BoundaryDetection},
+    epoch_progress::EpochProgress,
+    events::{Event, EventArgs, EventEmitter},
+    exit_code::{rpc_error, ConfigError, PaymentError},
+    heartbeat::{Heartbeat, HeartbeatArgs},
+    installments::{Installment, InstallmentArgs},
+    keypairs::{load_signer, load_signers, SharedSigner},
+    labels::labelled,
     metrics_helpers::{log_reward_commissions, log_validator_mev_data},
+    notifications::{Notification, NotificationArgs, Notifier, Severity},
+    operating_cost::{EpochCost, OperatingCostArgs},
+    payer_balance::{projected_liability, PayerBalanceArgs, PayerBalanceGuard},
+    payment_review::{review_payments, ProposedTransfer},
+    progress,
+    receipts::{
+        calculation_fingerprint, load_receipts, value_name, EpochInputs, ExcessRewards,
+        PaymentReceipt, ReceiptWriter,
+    },
     rewards::{
-        block_rewards::{calculate_block_rewards, compute_excess_block_commission},
-        inflation_rewards::calculate_excess_inflation_reward,
-        mev_rewards::{calculate_excess_mev_reward, fetch_and_filter_mev_data},
+        block_archive::BlockArchiveArgs,
+        block_rewards::{
+            BlockRewardComponents, BlockScanOptions, LeaderSlots, SlotExportArgs, VerifyRpcArgs,
+        },
+        commission_history::{
+            fetch_vote_commission, log_epoch_commission, track_epoch_commission, EpochCommission,
+        },
+        external_check::{check_external_totals, ExternalCheckArgs, LocalTotals},
+        inflation_model::{bond_stake_accounts, InflationVerifyArgs},
+        inflation_rewards::InflationRewardCache,
+        mev_rewards::{
+            fetch_and_filter_mev_data, load_mev_data, use_epoch_mev_commission,
+            use_onchain_active_stake, without_mev, ValidatorInfo,
+        },
+        source::{
+            fetch_sources, leader_slots, skipped_categories, validator_rewards, BlockSource,
+            BondContext, EpochContext, FetchedSource, InflationSource, MevSource, RewardCategory,
+            RewardSource,
+        },
+        CommissionOverrides, Rounding, RoundingMode,
+    },
+    rpc_fixtures::{FixtureArgs, FixtureStore},
+    rpc_usage::{RpcUsage, RpcUsageCounts},
+    rpc_utils::{check_for_next_epoch, new_rpc_client, BoundaryCheck},
+    schedule::{parse_schedule, wait_for_next_run},
+    sol_format::format_amount,
+    spending_limits::{SpendingLimitArgs, SpendingLimits},
+    stake_lineage::fetch_linked_stake_accounts,
+    state_db::{StateDb, StateDbWriter},
+    transactions::{
+        fingerprint_memo, transfer_excess_rewards, OutcomeUnknown, Payment, PaymentVerification,
+        SendOptions,
     },
-    rpc_utils::wait_for_next_epoch,
-    transactions::transfer_excess_rewards,
 };
 
 #[derive(Clone, Debug, Parser)]
 pub struct ValidatorPyeAccountManagerArgs {
-    /// RPC Endpoint
-    #[arg(
-        short,
-        long,
-        env,
-        default_value = "https://api.mainnet-beta.solana.com"
-    )]
-    rpc: String,
-    /// The Pye program ID
-    #[arg(
-        long,
-        env,
-        default_value = "PYEQZ2qYHPQapnw8Ms8MSPMNzoq59NHHfNwAtuV26wx"
-    )]
-    program_id: Pubkey,
+    /// Cluster preset selecting the default RPC endpoint, program ID and MEV source.
+    #[arg(long, env, value_enum, default_value_t = ClusterPreset::Mainnet)]
+    cluster: ClusterPreset,
+    /// RPC Endpoint [default: the cluster preset's endpoint]
+    #[arg(short, long, env)]
+    rpc: Option<String>,
+    /// The Pye program ID [default: the cluster preset's program ID]. Repeat to monitor bonds
+    /// under several programs, e.g. during a program migration.
+    #[arg(long = "program-id", env = "PROGRAM_ID", value_delimiter = ',')]
+    program_ids: Vec<Pubkey>,
     /// Validator's vote accoutn
     #[arg(short, long, env)]
     vote_pubkey: Pubkey,
     /// Restricts pye_account payments to only pye_accounts issued by pubkeys in this list.
     #[arg(short, long, env, value_delimiter = ',')]
     issuers: Vec<Pubkey>,
-    /// Path to payer keypair
+    /// Bonds to leave alone, e.g. ones under dispute: they are neither computed nor paid.
+    #[arg(long, env, value_delimiter = ',')]
+    exclude_pye_accounts: Vec<Pubkey>,
+    /// Only compute and pay these bonds, e.g. ones handled by a separate manager.
+    #[arg(long, env, value_delimiter = ',')]
+    only_pye_accounts: Vec<Pubkey>,
+    /// Path to payer keypair, or `-` to read it from stdin [default: the PAYER_KEYPAIR_JSON
+    /// environment variable]
     #[arg(short, long, env)]
-    payer: String,
+    payer: Option<String>,
+    /// Path to a keypair that pays transaction fees instead of the payer, which then only funds
+    /// the transferred rewards. `-` reads it from stdin.
+    #[arg(long, env)]
+    fee_payer: Option<String>,
     /// Maximum RPC requests to send concurrently.
     #[arg(long, env, default_value = "50")]
     concurrency: usize,
     /// Dry mode to calculate excess rewards without transferring.
     #[arg(long, env)]
     dry_run: bool,
-    /// The wait time (in secs) between epoch change checks
+    /// Abort instead of skipping pye_accounts that can't be fetched or deserialized.
+    #[arg(long, env)]
+    strict: bool,
+    /// How getProgramAccounts queries are split up. Large fleets may time out with a single query.
+    #[arg(long, env, value_enum, default_value_t = GpaChunking::Auto)]
+    gpa_chunking: GpaChunking,
+    /// The wait time (in secs) between cycles. The epoch boundary is checked for every cycle once
+    /// it's estimated to be close, and less often before.
     #[arg(long, env, default_value = "60")]
     cycle_secs: u64,
-    /// The wait time (in secs) between get_block RPC call retries.
+    /// Process the most recent completed epoch, along with any epochs left unfinished by earlier
+    /// runs, and exit instead of waiting for the next boundaries. For systemd timers and cron
+    /// jobs. Paying runs need --bond-state-file or --state-db-url, so a repeated run doesn't pay
+    /// an epoch twice.
+    #[arg(long, env, conflicts_with_all = ["tui", "schedule", "grpc_listen", "defer_mev"])]
+    once: bool,
+    /// Process epoch `current - N` at each boundary instead of the epoch that just ended, for
+    /// archive RPCs that take longer than an epoch to index its rewards and blocks.
+    #[arg(long, env, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    epoch_lag: u64,
+    /// The wait time (in secs) after an epoch boundary before the epoch is processed, so the
+    /// RPC has indexed its rewards and blocks.
+    #[arg(long, env, default_value = "30")]
+    epoch_settle_secs: u64,
+    /// How the epoch boundary is detected.
+    #[arg(long, env, value_enum, default_value_t = BoundaryDetection::Poll)]
+    boundary_detection: BoundaryDetection,
+    /// Websocket endpoint used by --boundary-detection ws [default: derived from the RPC URL]
+    #[arg(long, env)]
+    ws_url: Option<String>,
+    /// Longest wait (in secs) between get_block RPC call retries, which back off exponentially
+    /// from 2s with jitter. Network errors are first retried immediately.
     #[arg(long, env, default_value = "1800")]
     block_retry_delay: u64,
+    /// Time (in secs) after which a block's fetch stops being retried and the scan fails.
+    #[arg(long, env, default_value = "9000")]
+    block_retry_deadline: u64,
+    /// Maximum time (in secs) spent computing and paying the excess rewards of an epoch. Bonds
+    /// left unpaid at the deadline are retried in the background every cycle.
+    #[arg(long, env)]
+    epoch_processing_deadline_secs: Option<u64>,
+    /// File recording the bonds left unpaid at --epoch-processing-deadline-secs, so a restart
+    /// still retries them.
+    #[arg(long, env, requires = "epoch_processing_deadline_secs")]
+    epoch_progress_file: Option<PathBuf>,
+    /// File recording the stage each bond of the recent epochs reached, so an epoch processed
+    /// again resumes every bond where it stopped and never pays it twice.
+    #[arg(long, env)]
+    bond_state_file: Option<PathBuf>,
+    /// PostgreSQL URL of a state database shared by several managers, e.g.
+    /// `postgres://pye:<password>@db.internal/pye?sslmode=require`. It holds the bond states
+    /// instead of --bond-state-file, and every receipt alongside --receipts-dir.
+    #[arg(long, env, conflicts_with = "bond_state_file")]
+    state_db_url: Option<String>,
+    /// Which parts of the leader's block fee reward count toward the bond obligation.
+    #[arg(long, env, value_enum, default_value_t = BlockRewardComponents::All)]
+    block_reward_components: BlockRewardComponents,
+    /// Jito API endpoint used to look up the validator's MEV rewards [default: the cluster
+    /// preset's Jito API, MEV is treated as zero on clusters without one]
+    #[arg(long, env)]
+    jito_api_url: Option<String>,
+    /// Reward categories computed and paid. The others are skipped, which is logged and recorded
+    /// in receipts and metrics, e.g. `--categories inflation` for bonds only covering inflation.
+    #[arg(
+        long,
+        env,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [RewardCategory::Inflation, RewardCategory::Mev, RewardCategory::Block]
+    )]
+    categories: Vec<RewardCategory>,
+    /// Rounding applied to integer divisions in the reward calculations.
+    #[arg(long, env, value_enum, default_value_t = RoundingMode::Floor)]
+    rounding: RoundingMode,
+    /// Inflation commission (0-100) assumed when the RPC reports a reward without commission
+    /// data. When unset, such rewards are skipped with a warning.
+    #[arg(long, env, value_parser = clap::value_parser!(u8).range(0..=100))]
+    assumed_inflation_commission: Option<u8>,
+    /// Reconstruct the validator's commission over the target epoch from the vote account's
+    /// history, using the commission in effect at epoch end. Commissions observed at each epoch
+    /// boundary are always used to flag mid-epoch changes.
+    #[arg(long, env)]
+    track_commission_changes: bool,
+    /// Use the MEV commission the target epoch's tip distribution account was created with,
+    /// read on-chain or from its creation transaction, instead of the Jito API's.
+    #[arg(long, env)]
+    track_mev_commission: bool,
+    /// Compute and pay the other reward categories of a new epoch without waiting for the Jito
+    /// API to publish its MEV rewards, which can take hours, and pay the MEV in a follow-up pass
+    /// once it has.
+    #[arg(long, env)]
+    defer_mev: bool,
+    /// Follow the history of each bond's stake accounts since the target epoch and count the
+    /// stake split, merged or moved out of them toward the bond.
+    #[arg(long, env)]
+    track_stake_accounts: bool,
+    /// Cron expression (UTC) at which to send a report of the manager's state, e.g. "0 2 * * *"
+    /// for a daily report at 02:00 UTC. Seconds and years may be added as extra fields.
+    #[arg(long, env, value_parser = parse_schedule)]
+    schedule: Option<Schedule>,
+    /// Notify whenever a new bond targeting the vote account appears, from any issuer.
+    #[arg(long, env)]
+    watch_new_bonds: bool,
+    /// File recording the bonds already seen by --watch-new-bonds, so bonds created while the
+    /// manager was down are still reported.
+    #[arg(long, env, requires = "watch_new_bonds")]
+    known_bonds_file: Option<PathBuf>,
+    /// Stop checking the validator's inflation and MEV commissions every cycle for increases.
+    #[arg(long, env)]
+    disable_commission_guard: bool,
+    /// Warn when more than this percentage of the validator's leader slots in an epoch were
+    /// skipped, as found by the block rewards scan.
+    #[arg(long, env)]
+    alert_skip_rate_percent: Option<f64>,
+    /// Show a live terminal dashboard of the epoch, bonds, accruing rewards, payments and RPC
+    /// health. Logs still go to stderr, redirect them to a file to keep the dashboard readable.
+    #[arg(long, env)]
+    tui: bool,
+    /// Before paying an epoch, show a table of its proposed transfers and ask whether to pay all
+    /// of them, a selection or none. Transfers left out are recorded as `declined`. Needs a
+    /// terminal.
+    #[arg(long, env, conflicts_with = "tui")]
+    review_payments: bool,
+    /// CSV file of manual corrections to the amounts paid, one
+    /// `pye_account,epoch,delta_lamports,reason` row each. They are added to the computed excess
+    /// rewards before payment and recorded in the receipts. Read again for every epoch.
+    #[arg(long, env)]
+    adjustments_file: Option<PathBuf>,
+    /// Address to serve the gRPC control API on, e.g. 127.0.0.1:50051. The API can pause and
+    /// trigger payments, so only bind it to a private interface.
+    #[arg(long, env)]
+    grpc_listen: Option<SocketAddr>,
+    /// Token control API clients must send as `authorization: Bearer <TOKEN>`. Without it the
+    /// API is unauthenticated and refuses recomputes that pay.
+    #[arg(long, env, requires = "grpc_listen")]
+    grpc_token: Option<String>,
+    /// Directory to write a JSON receipt per bond and epoch to, with the calculation inputs, the
+    /// excess rewards, the payment outcome and its timestamps.
+    #[arg(long, env)]
+    receipts_dir: Option<PathBuf>,
+    /// Largest payment (in lamports) sent to a bond for an epoch. Larger excess rewards are
+    /// withheld and reported for review instead.
+    #[arg(long, env)]
+    max_payment_lamports: Option<u64>,
+    #[command(flatten)]
+    installments: InstallmentArgs,
+    #[command(flatten)]
+    spending_limits: SpendingLimitArgs,
+    #[command(flatten)]
+    alert_thresholds: AlertThresholdArgs,
+    #[command(flatten)]
+    payer_balance: PayerBalanceArgs,
+    #[command(flatten)]
+    scheduling: BondSchedulingArgs,
+    #[command(flatten)]
+    notifications: NotificationArgs,
+    /// Mail an HTML report of each processed epoch's payouts to the --email-to recipients.
+    #[arg(long, env, requires = "smtp_url")]
+    email_epoch_report: bool,
+    #[command(flatten)]
+    events: EventArgs,
+    #[command(flatten)]
+    attestations: AttestationArgs,
+    #[command(flatten)]
+    archive: ArchiveArgs,
+    #[command(flatten)]
+    heartbeat: HeartbeatArgs,
+    #[command(flatten)]
+    operating_cost: OperatingCostArgs,
+    #[command(flatten)]
+    verify: VerifyRpcArgs,
+    #[command(flatten)]
+    slot_export: SlotExportArgs,
+    #[command(flatten)]
+    block_archive: BlockArchiveArgs,
+    #[command(flatten)]
+    external_check: ExternalCheckArgs,
+    #[command(flatten)]
+    commission_overrides: CommissionOverrides,
+    #[command(flatten)]
+    verify_inflation: InflationVerifyArgs,
+    #[command(flatten)]
+    send: SendOptions,
+    #[command(flatten)]
+    fixtures: FixtureArgs,
+}
+
+impl ValidatorPyeAccountManagerArgs {
+    pub fn vote_pubkey(&self) -> Pubkey {
+        self.vote_pubkey
+    }
+
+    pub fn tui(&self) -> bool {
+        self.tui
+    }
+
+    pub fn review_payments(&self) -> bool {
+        self.review_payments
+    }
+
+    pub fn once(&self) -> bool {
+        self.once
+    }
+
+    /// Whether --only-pye-accounts and --exclude-pye-accounts let the bond be processed.
+    fn processes_bond(&self, pye_account_pubkey: &Pubkey) -> bool {
+        (self.only_pye_accounts.is_empty() || self.only_pye_accounts.contains(pye_account_pubkey))
+            && !self.exclude_pye_accounts.contains(pye_account_pubkey)
+    }
+
+    /// Whether a keypair is read from stdin, which only one manager per process can do.
+    pub fn reads_stdin(&self) -> bool {
+        [&self.payer, &self.fee_payer, &self.events.event_signer]
+            .into_iter()
+            .any(|source| source.as_deref() == Some("-"))
+    }
+
+    /// Files and addresses the manager owns, which managers sharing a process must not share.
+    pub fn exclusive_resources(&self) -> Vec<(&'static str, String)> {
+        let paths = [
+            ("--receipts-dir", &self.receipts_dir),
+            ("--epoch-progress-file", &self.epoch_progress_file),
+            ("--bond-state-file", &self.bond_state_file),
+            ("--known-bonds-file", &self.known_bonds_file),
+            ("--attestation-dir", &self.attestations.attestation_dir),
+            ("--slot-export-dir", &self.slot_export.slot_export_dir),
+        ];
+        let mut resources: Vec<(&'static str, String)> = paths
+            .into_iter()
+            .filter_map(|(flag, path)| path.as_ref().map(|path| (flag, path.display().to_string())))
+            .collect();
+        if let Some(addr) = self.grpc_listen {
+            resources.push(("--grpc-listen", addr.to_string()));
+        }
+        resources
+    }
+}
+
+pub async fn handle_validator_pye_account_manager(
+    args: ValidatorPyeAccountManagerArgs,
+) -> Result<()> {
+    if !args.tui {
+        return run_validator_pye_account_manager(args, None).await;
+    }
+    // Progress bars would draw over the dashboard.
+    progress::disable();
+    let dashboard = Dashboard::new(args.vote_pubkey);
+    let mut ui = tokio::task::spawn_blocking({
+        let dashboard = dashboard.clone();
+        move || dashboard.run_ui()
+    });
+    let result = tokio::select! {
+        result = run_validator_pye_account_manager(args, Some(dashboard.clone())) => result,
+        // The operator quit the dashboard.
+        result = &mut ui => return result?,
+    };
+    // Restore the terminal before the error, if any, is printed.
+    dashboard.shutdown();
+    ui.await??;
+    result
+}
+
+async fn run_validator_pye_account_manager(
+    args: ValidatorPyeAccountManagerArgs,
+    dashboard: Option<Dashboard>,
+) -> Result<()> {
+    let state_db = match &args.state_db_url {
+        Some(url) => Some(Arc::new(StateDb::connect(url).await.context(ConfigError)?)),
+        None => None,
+    };
+    let (state_db_writer, state_db_task) = state_db.clone().map(StateDbWriter::spawn).unzip();
+    let result = manage_validator_pye_accounts(args, dashboard, state_db, state_db_writer).await;
+    // The writer stops once the manager has dropped its senders, after applying the writes still
+    // queued, which a `--once` run or a failing manager would otherwise lose on exit.
+    if let Some(task) = state_db_task {
+        let _ = task.await;
+    }
+    result
 }
 
-pub async fn handle_validator_pye_account_manager(args: ValidatorPyeAccountManagerArgs) -> Result<()> {
-    let rpc_client = Arc::new(RpcClient::new_with_commitment(
-        args.rpc.clone(),
+async fn manage_validator_pye_accounts(
+    args: ValidatorPyeAccountManagerArgs,
+    dashboard: Option<Dashboard>,
+    state_db: Option<Arc<StateDb>>,
+    state_db_writer: Option<StateDbWriter>,
+) -> Result<()> {
+    let fixtures = FixtureStore::from_args(&args.fixtures)?;
+    if fixtures.as_ref().is_some_and(|store| store.is_replay()) && !args.dry_run {
+        return Err(
+            anyhow!("--replay can only be used together with --dry-run").context(ConfigError)
+        );
+    }
+    let rpc = args
+        .rpc
+        .clone()
+        .unwrap_or_else(|| args.cluster.rpc_url().to_string());
+    let program_ids = if args.program_ids.is_empty() {
+        vec![args.cluster.program_id()]
+    } else {
+        args.program_ids.clone()
+    };
+    let jito_api_url = args
+        .jito_api_url
+        .clone()
+        .or_else(|| args.cluster.jito_api_url().map(str::to_string));
+    let rpc_usage = Arc::new(RpcUsage::default());
+    let rpc_client = Arc::new(new_rpc_client(
+        rpc.clone(),
         CommitmentConfig::confirmed(),
+        fixtures.clone(),
+        Some(Arc::clone(&rpc_usage)),
     ));
+    if let Some(dashboard) = &dashboard {
+        tokio::spawn(
+            dashboard
+                .clone()
+                .poll_rpc(Arc::clone(&rpc_client), Duration::from_secs(5)),
+        );
+    }
+    let (control, mut recompute_rx, _control_task) = match args.grpc_listen {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .map_err(|e| anyhow!("Failed to bind the gRPC control API to {}: {}", addr, e))?;
+            let (control, recompute_rx) =
+                ManagerControl::new(args.vote_pubkey, args.dry_run, args.grpc_token.clone());
+            let task = AbortOnDrop(tokio::spawn(control.clone().serve(listener)));
+            (Some(control), Some(recompute_rx), Some(task))
+        }
+        None => (None, None, None),
+    };
+
+    // Load the keypairs once up front: stdin and the environment can't be re-read per transfer.
+    let signers = if args.dry_run {
+        None
+    } else {
+        Some(
+            load_signers(args.payer.as_deref(), args.fee_payer.as_deref())
+                .await
+                .context(ConfigError)?,
+        )
+    };
+    if args.once
+        && signers.is_some()
+        && args.bond_state_file.is_none()
+        && args.state_db_url.is_none()
+    {
+        return Err(anyhow!(
+            "--once needs --bond-state-file or --state-db-url to remember the bonds already paid"
+        )
+        .context(ConfigError));
+    }
+    let archive = Archive::from_args(&args.archive).context(ConfigError)?;
+    let attestations = match &signers {
+        Some((payer, _)) => AttestationWriter::from_args(
+            &args.attestations,
+            archive.as_ref(),
+            Arc::clone(payer),
+            args.vote_pubkey,
+        )?,
+        None => None,
+    };
+    let receipts = ReceiptWriter::open(args.receipts_dir.clone(), state_db_writer.clone());
+    // Payments recorded before a restart still count toward the spending limits.
+    let spending_receipts = match &args.receipts_dir {
+        Some(dir) if args.spending_limits.is_enabled() && dir.exists() => {
+            load_receipts(dir).context(ConfigError)?
+        }
+        _ => Vec::new(),
+    };
+    let spending_limits = SpendingLimits::new(
+        &args.spending_limits,
+        &spending_receipts,
+        chrono::Utc::now().timestamp(),
+    );
+    let events = if args.events.event_webhooks.is_empty() {
+        None
+    } else {
+        let signer = match (&args.events.event_signer, &signers) {
+            (Some(source), _) => load_signer(source, "event signer").await?,
+            (None, Some((payer, _))) => Arc::clone(payer),
+            (None, None) => {
+                return Err(anyhow!(
+                    "--event-webhook requires --event-signer together with --dry-run"
+                ))
+            }
+        };
+        Some(EventEmitter::new(
+            args.events.event_webhooks.clone(),
+            signer,
+            args.vote_pubkey,
+        ))
+    };
+
+    let mut slots = match args.boundary_detection {
+        BoundaryDetection::Poll => None,
+        BoundaryDetection::Ws => {
+            let ws_url = match &args.ws_url {
+                Some(ws_url) => ws_url.clone(),
+                None => default_ws_url(&rpc)?,
+            };
+            Some(watch_slots(ws_url))
+        }
+    };
 
     let epoch_schedule = rpc_client.get_epoch_schedule().await?;
     let mut current_epoch_info = match rpc_client.get_epoch_info().await {
@@ -81,28 +549,110 @@ pub async fn handle_validator_pye_account_manager(args: ValidatorPyeAccountManag
             return Err(anyhow!("Error getting epoch info: {:?}", err));
         }
     };
+    let fetch_options = PyeAccountFetchOptions {
+        strict: args.strict,
+        chunking: args.gpa_chunking,
+        concurrency: args.concurrency,
+    };
+    let notifier = Notifier::from_args(&args.notifications)?;
+    let heartbeat = Heartbeat::new(&args.heartbeat, args.vote_pubkey);
+    let _heartbeat_task = AbortOnDrop(metrics::spawn_tagged(
+        heartbeat.clone().run(notifier.clone()),
+    ));
+    let mut bond_watcher = BondWatcher::load(args.known_bonds_file.clone())?;
+    let mut epoch_progress = EpochProgress::load(args.epoch_progress_file.clone())?;
+    let bond_states = match (&state_db, state_db_writer) {
+        (Some(db), Some(writer)) => BondStates::load_from_db(db, writer, &args.vote_pubkey).await?,
+        _ => BondStates::load(args.bond_state_file.clone())?,
+    };
+    let mut last_breakdowns = Vec::new();
+    let mut last_cost = None;
+    let mut last_leader_slots = None;
+    let mut commission_guard = (!args.disable_commission_guard)
+        .then(|| CommissionGuard::new(args.vote_pubkey, jito_api_url.is_some()));
+    let mut payer_balance_guard = signers
+        .as_ref()
+        .and_then(|(payer, _)| PayerBalanceGuard::new(payer.pubkey(), &args.payer_balance));
+    // Commission observed at the previous epoch boundary, i.e. at the start of the target epoch.
+    let mut boundary_commission = fetch_vote_commission(&rpc_client, &args.vote_pubkey)
+        .await
+        .ok();
+    let skipped = skipped_category_names(&args.categories);
+    if !skipped.is_empty() {
+        warn!(
+            "Skipping {} rewards: bonds are neither computed nor paid for them",
+            skipped.join(", ")
+        );
+    }
+    if !args.commission_overrides.is_empty() {
+        warn!(
+            "What-if dry run: bonds are computed with overridden commissions {:?}",
+            args.commission_overrides
+        );
+    }
+    let processor = EpochProcessor {
+        args: &args,
+        program_ids: &program_ids,
+        rpc: &rpc,
+        rpc_client: &rpc_client,
+        rpc_usage: &rpc_usage,
+        jito_api_url: jito_api_url.as_deref(),
+        fixtures: fixtures.as_deref(),
+        epoch_schedule: &epoch_schedule,
+        signers: signers.as_ref(),
+        notifier: &notifier,
+        heartbeat: &heartbeat,
+        events: events.as_ref(),
+        attestations: attestations.as_ref(),
+        archive: archive.as_ref(),
+        receipts: receipts.as_ref(),
+        dashboard: dashboard.as_ref(),
+        control: control.as_ref(),
+        bond_states: &bond_states,
+        spending_limits: &spending_limits,
+        sources: &reward_sources(&args),
+    };
+    processor.publish_bond_states();
+    processor
+        .resume_interrupted(current_epoch_info.epoch, &mut epoch_progress)
+        .await?;
     loop {
         // Fetch pye_accounts that are still active prior to waiting for the next epoch, to make sure we
-        // don't miss any.
-        let results: Vec<_> = stream::iter(args.issuers.to_owned())
-            .map(|issuer_pubkey| {
+        // don't miss any. A lagged epoch is still owed by bonds that have matured since, so those are
+        // fetched too and filtered by maturity at the end of the processed epoch.
+        let queries: Vec<(Pubkey, Pubkey)> = program_ids
+            .iter()
+            .flat_map(|program_id| {
+                args.issuers
+                    .iter()
+                    .map(move |issuer_pubkey| (*program_id, *issuer_pubkey))
+            })
+            .collect();
+        let results: Vec<_> = stream::iter(queries)
+            .map(|(program_id, issuer_pubkey)| {
                 let cloned_client = rpc_client.clone();
                 async move {
-                    match fetch_active_solo_validator_pye_accounts_by_vote_key_and_issuer(
+                    match fetch_solo_validator_pye_accounts_by_vote_key(
                         &cloned_client,
-                        &args.program_id,
+                        &program_id,
                         &args.vote_pubkey,
-                        &issuer_pubkey.clone(),
+                        Some(&issuer_pubkey),
+                        args.epoch_lag == 1,
+                        &fetch_options,
                     )
                     .await
                     {
-                        Ok(pye_accounts) => Ok(pye_accounts),
+                        // Tag each bond with its owning program for reports and metrics.
+                        Ok(pye_accounts) => Ok(pye_accounts
+                            .into_iter()
+                            .map(|(pubkey, pye_account)| (program_id, pubkey, pye_account))
+                            .collect::<Vec<_>>()),
                         Err(err) => {
                             datapoint_error!(
                                 "handle_validator_pye_account_manager",
                                 ("error", err.to_string(), String),
                             );
-                            return Err(anyhow!("Error fetching active pye_accounts: {:?}", err));
+                            return Err(rpc_error("Error fetching active pye_accounts", err));
                         }
                     }
                 }
@@ -110,30 +660,540 @@ pub async fn handle_validator_pye_account_manager(args: ValidatorPyeAccountManag
             .buffer_unordered(args.concurrency)
             .collect::<Vec<_>>()
             .await;
-        let active_pye_accounts: Vec<(Pubkey, SoloValidatorPyeAccount)> = results
+        if args.strict {
+            if let Some(err) = results.iter().find_map(|result| result.as_ref().err()) {
+                return Err(anyhow!("{}", err));
+            }
+        }
+        let active_pye_accounts: Vec<(Pubkey, Pubkey, SoloValidatorPyeAccount)> = results
             .into_iter()
             .filter_map(Result::ok)
             .flatten()
             .collect();
 
+        if args.watch_new_bonds {
+            notify_new_bonds(
+                &rpc_client,
+                &program_ids,
+                &args,
+                &fetch_options,
+                &mut bond_watcher,
+                &notifier,
+            )
+            .await;
+        }
+
         info!(
             "Monitoring {} pye_accounts for epoch {}",
             active_pye_accounts.len(),
             current_epoch_info.epoch
         );
-        // We block the flow until the next epoch
-        current_epoch_info =
-            wait_for_next_epoch(&rpc_client, current_epoch_info.epoch, args.cycle_secs).await;
-        // We wait 30 seconds to avoid "Epoch rewards period still active at slot" RPC errors
-        tokio::time::sleep(Duration::from_secs(30)).await;
-        info!(
-            "Epoch boundary detected. New epoch: {}",
-            current_epoch_info.epoch
+        if let Some(dashboard) = &dashboard {
+            dashboard.set_bonds(&active_pye_accounts);
+        }
+        if let Some(control) = &control {
+            control.update_status(|status| {
+                status.current_epoch = current_epoch_info.epoch;
+                status.monitored_bonds = active_pye_accounts.len();
+            });
+        }
+        if args.once {
+            // Epochs earlier runs left unfinished are retried before the latest one.
+            if epoch_progress.oldest().is_some() {
+                processor.retry_unfinished(&mut epoch_progress).await?;
+            }
+        } else {
+            let monitoring_phase = format!(
+                "Monitoring {} bonds until the end of epoch {}",
+                active_pye_accounts.len(),
+                current_epoch_info.epoch
+            );
+            processor.set_phase(monitoring_phase.clone());
+            // We block the flow until the next epoch, checking the validator's commissions every cycle
+            // and recomputing past epochs on request of the control API in between. The boundary is
+            // only checked for when it's estimated to be close.
+            let mut next_boundary_check = Instant::now();
+            current_epoch_info = loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(args.cycle_secs)) => {
+                        if epoch_progress.oldest().is_some() {
+                            processor.retry_unfinished(&mut epoch_progress).await?;
+                            processor.set_phase(monitoring_phase.clone());
+                        }
+                    }
+                    Some(request) = next_recompute(&mut recompute_rx) => {
+                        processor
+                            .recompute_epoch(&program_ids, &fetch_options, request)
+                            .await;
+                        processor.set_phase(monitoring_phase.clone());
+                        continue;
+                    }
+                    epoch_info = wait_for_rollover(
+                        &mut slots,
+                        &rpc_client,
+                        &epoch_schedule,
+                        current_epoch_info.epoch,
+                    ) => {
+                        info!("Epoch rollover seen on the slot subscription");
+                        break epoch_info;
+                    }
+                    run_at = wait_for_next_run(args.schedule.as_ref()) => {
+                        info!("Sending the report scheduled at {}", run_at);
+                        notifier
+                            .notify(scheduled_report(
+                                &args.vote_pubkey,
+                                current_epoch_info.epoch,
+                                active_pye_accounts.len(),
+                                &last_breakdowns,
+                                last_cost.as_ref(),
+                                last_leader_slots.as_ref(),
+                                &epoch_progress.epochs(),
+                                control.as_ref().is_some_and(ManagerControl::payments_paused),
+                            ))
+                            .await;
+                        continue;
+                    }
+                }
+                if let Some(guard) = commission_guard.as_mut() {
+                    guard
+                        .check(&rpc_client, current_epoch_info.epoch, &notifier)
+                        .await;
+                }
+                if let Some(guard) = payer_balance_guard.as_mut() {
+                    guard
+                        .check(
+                            &rpc_client,
+                            &args.vote_pubkey,
+                            projected_liability(&last_breakdowns),
+                            &notifier,
+                        )
+                        .await;
+                }
+                if Instant::now() < next_boundary_check {
+                    continue;
+                }
+                match check_for_next_epoch(
+                    &rpc_client,
+                    current_epoch_info.epoch,
+                    Duration::from_secs(args.cycle_secs),
+                )
+                .await
+                {
+                    BoundaryCheck::NewEpoch(epoch_info) => break epoch_info,
+                    BoundaryCheck::NotYet(delay) => next_boundary_check = Instant::now() + delay,
+                }
+            };
+            // We wait for the epoch to settle, which also avoids "Epoch rewards period still active
+            // at slot" RPC errors
+            tokio::time::sleep(Duration::from_secs(args.epoch_settle_secs)).await;
+            info!(
+                "Epoch boundary detected. New epoch: {}",
+                current_epoch_info.epoch
+            );
+        }
+        let Some(target_epoch) = current_epoch_info.epoch.checked_sub(args.epoch_lag) else {
+            warn!(
+                "Epoch {} is earlier than the epoch lag of {}, so there's no epoch to process yet",
+                current_epoch_info.epoch, args.epoch_lag
+            );
+            if args.once {
+                return Ok(());
+            }
+            continue;
+        };
+        if args.once {
+            info!(
+                "Processing the most recent completed epoch {}",
+                target_epoch
+            );
+        }
+        if let Some(events) = &events {
+            events
+                .emit(Event::EpochStarted {
+                    epoch: current_epoch_info.epoch,
+                    target_epoch,
+                })
+                .await;
+        }
+        let observed_commission = fetch_vote_commission(&rpc_client, &args.vote_pubkey)
+            .await
+            .ok();
+        // The commissions observed at the last boundaries are those of a later epoch when lagging.
+        let (epoch_info, observed) = if args.epoch_lag == 1 {
+            (
+                current_epoch_info.clone(),
+                (boundary_commission, observed_commission),
+            )
+        } else {
+            (
+                epoch_info_after(&epoch_schedule, target_epoch),
+                (None, None),
+            )
+        };
+        let processed = processor
+            .process_epoch_passes(&epoch_info, active_pye_accounts, observed, true, false)
+            .await?;
+        if !processed.unfinished.is_empty() {
+            epoch_progress.update(
+                target_epoch,
+                processed.pass,
+                &processed.unfinished,
+                observed,
+            );
+        }
+        if !processed.unfinished_mev.is_empty() {
+            epoch_progress.update(
+                target_epoch,
+                BondPass::MevOnly,
+                &processed.unfinished_mev,
+                observed,
+            );
+        }
+        if args.email_epoch_report {
+            mail_epoch_report(
+                &notifier,
+                &args.vote_pubkey,
+                target_epoch,
+                &processed.breakdowns,
+                &processed.cost,
+                processed.leader_slots.as_ref(),
+            )
+            .await;
+        }
+        last_breakdowns = processed.breakdowns;
+        last_cost = Some(processed.cost);
+        last_leader_slots = processed.leader_slots;
+        boundary_commission = observed_commission;
+        if args.once {
+            return Ok(());
+        }
+    }
+}
+
+/// Aborts a background task once the manager returns, so a fleet restarting the manager doesn't
+/// keep the previous run's heartbeat or control API listener around.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Epoch-wide inputs of the bonds' excess reward calculations.
+struct BondInputs<'a> {
+    epoch: EpochContext<'a>,
+    /// The epoch data of every reward source.
+    sources: &'a [FetchedSource],
+    /// Inputs recorded in receipts and hashed into the bonds' fingerprints.
+    epoch_inputs: &'a EpochInputs,
+    /// Where the bonds' stages are recorded, unless the run doesn't pay.
+    bond_states: Option<&'a BondStates>,
+    /// The pass whose stages the bonds move through.
+    pass: BondPass,
+}
+
+/// A bond's excess rewards for the epoch, computed but not paid yet.
+struct ComputedBond {
+    program_id: Pubkey,
+    pye_account_pubkey: Pubkey,
+    pye_account: SoloValidatorPyeAccount,
+    active_stake: u64,
+    rewards: RewardBreakdown,
+    excess_rewards: i64,
+    rounding: Rounding,
+    computed_at: chrono::DateTime<chrono::Utc>,
+    /// The stage the bond had reached in an earlier run of the epoch.
+    resumed: Option<BondState>,
+    /// Hash of the calculation, see [`calculation_fingerprint`].
+    fingerprint: String,
+    /// Manual adjustments from --adjustments-file, included in `excess_rewards`.
+    adjustments: Vec<Adjustment>,
+}
+
+impl ComputedBond {
+    fn breakdown(&self, payment: PaymentOutcome) -> BondRewardBreakdown {
+        BondRewardBreakdown {
+            program_id: self.program_id,
+            pye_account: self.pye_account_pubkey,
+            active_stake: self.active_stake,
+            rewards: self.rewards,
+            payment,
+        }
+    }
+
+    /// Sets the fingerprint of the bond's calculation from the epoch's inputs.
+    fn fingerprinted(mut self, vote_pubkey: &Pubkey, epoch_inputs: &EpochInputs) -> Self {
+        self.fingerprint = calculation_fingerprint(
+            vote_pubkey,
+            epoch_inputs,
+            &self.pye_account,
+            &self.breakdown(PaymentOutcome::NotDue),
         );
-        let target_epoch = current_epoch_info.epoch - 1;
-        let last_slot_of_target = epoch_schedule.get_last_slot_in_epoch(target_epoch);
+        self
+    }
+
+    fn key(&self) -> BondKey {
+        BondKey {
+            issuer: self.pye_account.issuer,
+            active_stake: self.active_stake,
+            maturity_ts: self.pye_account.maturity_ts,
+        }
+    }
+}
+
+/// The excess rewards computed for an epoch.
+struct ProcessedEpoch {
+    breakdowns: Vec<BondRewardBreakdown>,
+    cost: EpochCost,
+    /// Bonds that weren't processed before --epoch-processing-deadline-secs ran out.
+    unfinished: Vec<Pubkey>,
+    /// The pass `unfinished` are retried in.
+    pass: BondPass,
+    /// With --defer-mev, bonds whose MEV wasn't paid yet, retried in a MEV-only pass.
+    unfinished_mev: Vec<Pubkey>,
+    /// The validator's leader slots over the epoch, when the block rewards were scanned.
+    leader_slots: Option<LeaderSlots>,
+}
+
+/// The reward categories a run over an epoch computes and pays, out of `--categories`.
+#[derive(Clone, Debug)]
+enum EpochPass {
+    /// Every category, with MEV waited for first.
+    Full,
+    /// Every category but MEV, without waiting for the Jito API.
+    WithoutMev,
+    /// Only MEV, from the Jito API's data for the epoch, after the others were paid.
+    MevOnly(ValidatorInfo),
+}
+
+impl EpochPass {
+    fn includes(&self, category: RewardCategory) -> bool {
+        match self {
+            EpochPass::Full => true,
+            EpochPass::WithoutMev => category != RewardCategory::Mev,
+            EpochPass::MevOnly(_) => category == RewardCategory::Mev,
+        }
+    }
 
-        let block_time = match rpc_client.get_block_time(last_slot_of_target).await {
+    /// The pass bond states are recorded under.
+    fn bond_pass(&self) -> BondPass {
+        match self {
+            EpochPass::Full => BondPass::Full,
+            EpochPass::WithoutMev => BondPass::WithoutMev,
+            EpochPass::MevOnly(_) => BondPass::MevOnly,
+        }
+    }
+}
+
+/// Everything needed to compute, and optionally pay, the excess rewards of an epoch.
+struct EpochProcessor<'a> {
+    args: &'a ValidatorPyeAccountManagerArgs,
+    /// The pye programs bonds must be owned by.
+    program_ids: &'a [Pubkey],
+    rpc: &'a str,
+    rpc_client: &'a RpcClient,
+    /// Requests sent by `rpc_client`, by method.
+    rpc_usage: &'a RpcUsage,
+    jito_api_url: Option<&'a str>,
+    fixtures: Option<&'a FixtureStore>,
+    epoch_schedule: &'a EpochSchedule,
+    signers: Option<&'a (SharedSigner, Option<SharedSigner>)>,
+    notifier: &'a Notifier,
+    heartbeat: &'a Heartbeat,
+    events: Option<&'a EventEmitter>,
+    attestations: Option<&'a AttestationWriter>,
+    archive: Option<&'a Archive>,
+    receipts: Option<&'a ReceiptWriter>,
+    dashboard: Option<&'a Dashboard>,
+    control: Option<&'a ManagerControl>,
+    bond_states: &'a BondStates,
+    spending_limits: &'a SpendingLimits,
+    /// The reward sources bonds are owed excess rewards from, see [`reward_sources`].
+    sources: &'a [Box<dyn RewardSource>],
+}
+
+/// The provider list of reward sources, in the order their epoch data is fetched. A new reward
+/// stream only needs a [`RewardSource`] registered here. Sources outside `--categories` are
+/// left out.
+fn reward_sources(args: &ValidatorPyeAccountManagerArgs) -> Vec<Box<dyn RewardSource>> {
+    let sources: Vec<Box<dyn RewardSource>> = vec![
+        Box::new(InflationSource {
+            assumed_commission: args.assumed_inflation_commission,
+            track_commission_changes: args.track_commission_changes,
+            verify: args.verify_inflation.clone(),
+        }),
+        Box::new(MevSource),
+        Box::new(BlockSource {
+            options: BlockScanOptions {
+                concurrency: args.concurrency,
+                block_retry_delay: args.block_retry_delay,
+                block_retry_deadline: args.block_retry_deadline,
+                components: args.block_reward_components,
+                verify: args.verify.clone(),
+                slot_export: args.slot_export.clone(),
+                archive: args.block_archive.clone(),
+            },
+        }),
+    ];
+    sources
+        .into_iter()
+        .filter(|source| args.categories.contains(&source.category()))
+        .collect()
+}
+
+/// The skipped reward categories as recorded in receipts and metrics, e.g. `mev,block`.
+fn skipped_category_names(categories: &[RewardCategory]) -> Vec<String> {
+    skipped_categories(categories)
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+impl EpochProcessor<'_> {
+    /// Processes the epoch before `epoch_info` in the passes asked for. With `--defer-mev` every
+    /// category but MEV is paid right away while the Jito API is waited for, then the MEV in a
+    /// second pass with its own payments, receipts and bond stages. The first pass records every
+    /// bond's MEV as owed before paying anything, so MEV left unpaid by an error or a crash is
+    /// paid when the epoch is resumed.
+    async fn process_epoch_passes(
+        &self,
+        epoch_info: &EpochInfo,
+        pye_accounts: Vec<(Pubkey, Pubkey, SoloValidatorPyeAccount)>,
+        observed_commission: (Option<u8>, Option<u8>),
+        pay: bool,
+        start_over: bool,
+    ) -> Result<ProcessedEpoch> {
+        let jito_api_url = self
+            .jito_api_url
+            .filter(|_| self.args.defer_mev && self.args.categories.contains(&RewardCategory::Mev));
+        let Some(jito_api_url) = jito_api_url else {
+            return self
+                .process_epoch(
+                    epoch_info,
+                    pye_accounts,
+                    observed_commission,
+                    pay,
+                    start_over,
+                    EpochPass::Full,
+                )
+                .await;
+        };
+        let target_epoch = epoch_info.epoch - 1;
+        let pubkeys: Vec<Pubkey> = pye_accounts.iter().map(|(_, pubkey, _)| *pubkey).collect();
+        let (processed, jito) = tokio::join!(
+            self.process_epoch(
+                epoch_info,
+                pye_accounts.clone(),
+                observed_commission,
+                pay,
+                start_over,
+                EpochPass::WithoutMev,
+            ),
+            fetch_and_filter_mev_data(
+                jito_api_url,
+                &self.args.vote_pubkey,
+                target_epoch,
+                self.fixtures,
+            )
+        );
+        let mut processed = processed.inspect_err(|_| {
+            if pay {
+                warn!(
+                    "The MEV of epoch {} is still owed, it is paid when the epoch is resumed",
+                    target_epoch
+                )
+            }
+        })?;
+        let jito = match jito {
+            Ok(jito) => jito,
+            Err(e) => {
+                self.notifier
+                    .notify(Notification::new(
+                        Severity::Critical,
+                        format!("MEV of epoch {} was not paid", target_epoch),
+                        format!(
+                            "The other reward categories were processed, but the Jito API's \
+                             data couldn't be fetched, so the MEV is retried later: {:#}",
+                            e
+                        ),
+                    ))
+                    .await;
+                processed.unfinished_mev = pubkeys;
+                return Ok(processed);
+            }
+        };
+        info!("Processing the MEV rewards of epoch {}", target_epoch);
+        let mev = self
+            .process_epoch(
+                epoch_info,
+                pye_accounts,
+                observed_commission,
+                pay,
+                start_over,
+                EpochPass::MevOnly(jito),
+            )
+            .await?;
+        processed.breakdowns.extend(mev.breakdowns);
+        processed.cost.merge(&mev.cost);
+        processed.unfinished_mev = mev.unfinished;
+        Ok(processed)
+    }
+
+    /// Computes the excess rewards owed to `pye_accounts` for the epoch before `epoch_info` and,
+    /// with `pay`, transfers them. `observed_commission` holds the vote account's commission as
+    /// observed live at the start and end of that epoch. Transfers already sent are never cut
+    /// short by the processing deadline, only the bonds not started yet are left unfinished.
+    /// Bonds processed before resume at the stage they reached in `pass`, unless `start_over`.
+    /// Only the categories of `pass` are computed.
+    async fn process_epoch(
+        &self,
+        epoch_info: &EpochInfo,
+        pye_accounts: Vec<(Pubkey, Pubkey, SoloValidatorPyeAccount)>,
+        observed_commission: (Option<u8>, Option<u8>),
+        pay: bool,
+        start_over: bool,
+        pass: EpochPass,
+    ) -> Result<ProcessedEpoch> {
+        let target_epoch = epoch_info.epoch - 1;
+        let (pye_accounts, skipped): (Vec<_>, Vec<_>) = pye_accounts
+            .into_iter()
+            .partition(|(_, pye_account_pubkey, _)| self.args.processes_bond(pye_account_pubkey));
+        for (_, pye_account_pubkey, _) in skipped {
+            info!(
+                "Skipping pye_account {} for epoch {}: excluded by --only-pye-accounts or --exclude-pye-accounts",
+                pye_account_pubkey, target_epoch
+            );
+        }
+        let categories: Vec<RewardCategory> = self
+            .args
+            .categories
+            .iter()
+            .copied()
+            .filter(|category| pass.includes(*category))
+            .collect();
+        // Read every epoch, so edits apply without a restart. The MEV-only pass pays bonds the
+        // first pass already adjusted.
+        let adjustments = match &self.args.adjustments_file {
+            Some(path) if !matches!(pass, EpochPass::MevOnly(_)) => {
+                Adjustments::load(path).context(ConfigError)?
+            }
+            _ => Adjustments::default(),
+        };
+        let deadline = self
+            .args
+            .epoch_processing_deadline_secs
+            .map(|secs| Instant::now() + Duration::from_secs(secs));
+        let _watch = self.heartbeat.begin_epoch(target_epoch);
+        let rpc_requests = self.rpc_client.get_transport_stats().request_count;
+        let rpc_usage = self.rpc_usage.snapshot();
+        self.set_phase(format!(
+            "Computing excess rewards for epoch {}",
+            target_epoch
+        ));
+        let last_slot_of_target = self.epoch_schedule.get_last_slot_in_epoch(target_epoch);
+
+        let block_time = match self.rpc_client.get_block_time(last_slot_of_target).await {
             Err(_) => {
                 // TODO: Get a more accurate time of the end of the epoch to determine if payment
                 // should be made. One idea is catch RpcError::ForUser and check for next block
@@ -145,104 +1205,1893 @@ pub async fn handle_validator_pye_account_manager(args: ValidatorPyeAccountManag
         };
 
         // For all active pye_accounts, log their commission structures and filter by maturity
-        let active_pye_accounts: Vec<(Pubkey, SoloValidatorPyeAccount)> = active_pye_accounts
+        let pye_accounts: Vec<(Pubkey, Pubkey, SoloValidatorPyeAccount)> = pye_accounts
             .into_iter()
-            .filter(|(pye_account_pubkey, pye_account)| {
-                log_reward_commissions(target_epoch, &pye_account_pubkey, &pye_account.reward_commissions);
+            .filter(|(program_id, pye_account_pubkey, pye_account)| {
+                log_reward_commissions(
+                    target_epoch,
+                    program_id,
+                    &pye_account_pubkey,
+                    &pye_account.reward_commissions,
+                );
                 pye_account.maturity_ts > block_time
             })
             .collect();
+        // Runs that can't pay don't move bonds along, so they can't hide a bond still owed.
+        let bond_states = (pay && self.signers.is_some()).then_some(self.bond_states);
+        let bond_pass = pass.bond_pass();
+        if let Some(bond_states) = bond_states {
+            for (_, pye_account_pubkey, _) in &pye_accounts {
+                bond_states.discover(target_epoch, pye_account_pubkey, bond_pass, start_over);
+                // The deferred MEV is owed from now on, even if the MEV pass never starts.
+                if bond_pass == BondPass::WithoutMev {
+                    bond_states.discover(
+                        target_epoch,
+                        pye_account_pubkey,
+                        BondPass::MevOnly,
+                        start_over,
+                    );
+                }
+            }
+            self.publish_bond_states();
+        }
 
-        // Load MEV data
-        let mev_data = fetch_and_filter_mev_data(&args.vote_pubkey, target_epoch).await?;
-        log_validator_mev_data(target_epoch, &mev_data);
+        let epoch_commission = if self.args.track_commission_changes {
+            track_epoch_commission(
+                self.rpc_client,
+                &self.args.vote_pubkey,
+                self.epoch_schedule,
+                target_epoch,
+            )
+            .await
+            .unwrap_or_else(|err| {
+                warn!("Failed to track commission changes: {}", err);
+                EpochCommission::default()
+            })
+        } else {
+            EpochCommission::default()
+        }
+        .with_observed(observed_commission.0, observed_commission.1);
+        log_epoch_commission(&self.args.vote_pubkey, target_epoch, &epoch_commission);
+        if epoch_commission.changed_mid_epoch() {
+            self.notifier
+                .notify(Notification::new(
+                    Severity::Warning,
+                    format!(
+                        "Commission of {} changed during epoch {}",
+                        self.args.vote_pubkey, target_epoch
+                    ),
+                    format!(
+                        "Start: {:?}\nEnd: {:?}\nUpdates: {:?}",
+                        epoch_commission.start, epoch_commission.end, epoch_commission.changes
+                    ),
+                ))
+                .await;
+        }
 
-        let validators_total_block_rewards = calculate_block_rewards(
-            &rpc_client,
-            &args.vote_pubkey,
-            &current_epoch_info,
-            args.concurrency,
-            args.block_retry_delay,
+        // The StakeHistory and SlotHistory sysvars are fetched once for every bond and source.
+        let sysvars = EpochSysvars::default();
+        // Load the validator's stake and MEV data, then every reward source's data for the epoch.
+        // Scanning the epoch's blocks may retry missing blocks for hours.
+        let fetched = before_deadline(deadline, async {
+            let mut mev_data = match &pass {
+                EpochPass::Full => {
+                    load_mev_data(
+                        self.rpc_client,
+                        &sysvars,
+                        self.jito_api_url,
+                        &self.args.vote_pubkey,
+                        target_epoch,
+                        self.fixtures,
+                    )
+                    .await?
+                }
+                EpochPass::WithoutMev => without_mev(
+                    &self.args.vote_pubkey,
+                    fetch_validator_active_stake(
+                        self.rpc_client,
+                        &sysvars,
+                        &self.args.vote_pubkey,
+                        target_epoch,
+                    )
+                    .await?,
+                ),
+                EpochPass::MevOnly(jito) => {
+                    let mut mev_data = jito.clone();
+                    let active_stake = fetch_validator_active_stake(
+                        self.rpc_client,
+                        &sysvars,
+                        &self.args.vote_pubkey,
+                        target_epoch,
+                    )
+                    .await?;
+                    use_onchain_active_stake(&mut mev_data, active_stake, target_epoch);
+                    mev_data
+                }
+            };
+            if self.args.track_mev_commission && pass.includes(RewardCategory::Mev) {
+                use_epoch_mev_commission(
+                    self.rpc_client,
+                    &mut mev_data,
+                    &self.args.vote_pubkey,
+                    target_epoch,
+                )
+                .await;
+            }
+            log_validator_mev_data(target_epoch, &mev_data);
+
+            // Every bond's stake accounts, and the vote account for 100% commission fallbacks,
+            // share a few batched getInflationReward requests.
+            let addresses: Vec<Pubkey> = std::iter::once(self.args.vote_pubkey)
+                .chain(pye_accounts.iter().flat_map(|(_, _, pye_account)| {
+                    bond_stake_accounts(
+                        &pye_account.stake_account,
+                        &pye_account.transient_stake_account,
+                    )
+                }))
+                .collect();
+            let inflation_rewards =
+                InflationRewardCache::fetch(self.rpc_client, &addresses, target_epoch)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!("{}, fetching each account's inflation reward on its own", e);
+                        InflationRewardCache::empty(target_epoch)
+                    });
+
+            let sources = fetch_sources(
+                self.sources
+                    .iter()
+                    .map(Box::as_ref)
+                    .filter(|source| pass.includes(source.category())),
+                &EpochContext {
+                    rpc_client: self.rpc_client,
+                    vote_pubkey: &self.args.vote_pubkey,
+                    epoch_info,
+                    validator: &mev_data,
+                    epoch_commission: &epoch_commission,
+                    inflation_rewards: &inflation_rewards,
+                    sysvars: &sysvars,
+                },
+            )
+            .await?;
+            Ok::<_, anyhow::Error>((mev_data, inflation_rewards, sources))
+        })
+        .await;
+        let Some(fetched) = fetched else {
+            let unfinished: Vec<Pubkey> =
+                pye_accounts.iter().map(|(_, pubkey, _)| *pubkey).collect();
+            self.report_deadline_exceeded(target_epoch, &unfinished)
+                .await;
+            return Ok(ProcessedEpoch {
+                breakdowns: Vec::new(),
+                cost: self.epoch_cost(target_epoch, &[], rpc_requests, &rpc_usage),
+                unfinished,
+                pass: bond_pass,
+                unfinished_mev: Vec::new(),
+                leader_slots: None,
+            });
+        };
+        let (mev_data, inflation_rewards, sources) = fetched?;
+        let leader_slots = leader_slots(&sources);
+        if let Some(slots) = &leader_slots {
+            self.check_skip_rate(target_epoch, slots).await;
+        }
+        let validators_total_block_rewards = validator_rewards(&sources, RewardCategory::Block);
+
+        let epoch_inputs = EpochInputs {
+            epoch_end_time: block_time,
+            validator_active_stake: mev_data.active_stake,
+            validator_mev_rewards: mev_data.mev_rewards,
+            validator_mev_commission_bps: mev_data.mev_commission_bps,
+            validator_block_rewards: validators_total_block_rewards,
+            block_reward_components: value_name(self.args.block_reward_components),
+            inflation_commission_start: epoch_commission.start,
+            inflation_commission_end: epoch_commission.end,
+            rounding: value_name(self.args.rounding),
+            skipped_categories: skipped_category_names(&categories),
+        };
+        check_external_totals(
+            &self.args.external_check,
+            &self.args.vote_pubkey,
+            target_epoch,
+            &LocalTotals {
+                block_rewards: categories
+                    .contains(&RewardCategory::Block)
+                    .then_some(validators_total_block_rewards),
+                mev_rewards: categories
+                    .contains(&RewardCategory::Mev)
+                    .then_some(mev_data.mev_rewards),
+                commission: epoch_commission.end,
+                mev_commission_bps: mev_data.mev_commission_bps,
+            },
         )
         .await?;
 
-        // Note: could add concurrency in this loop
-        // For each pye_account calculate the additional rewards required for each category
-        for (pye_account_pubkey, pye_account) in active_pye_accounts.into_iter() {
-            // Fetch the SoloValidatorPyeAccount's active stake during target epoch.
-            let pye_account_active_stake = fetch_pye_account_active_stake(
-                &rpc_client,
-                &pye_account.stake_account,
-                &pye_account.transient_stake_account,
-                target_epoch,
-                current_epoch_info.epoch,
-            )
+        let mut epoch_rounding = Rounding::new(self.args.rounding);
+        let mut breakdowns = Vec::new();
+        let mut epoch_receipts = Vec::new();
+        let mut unfinished = Vec::new();
+
+        // Compute every bond's excess rewards first, alternating between issuers so that
+        // --issuer-concurrency doesn't leave the other issuers waiting behind a large one.
+        let scheduling = &self.args.scheduling;
+        let issuer_limits = scheduling.issuer_limits();
+        let inputs = BondInputs {
+            epoch: EpochContext {
+                rpc_client: self.rpc_client,
+                vote_pubkey: &self.args.vote_pubkey,
+                epoch_info,
+                validator: &mev_data,
+                epoch_commission: &epoch_commission,
+                inflation_rewards: &inflation_rewards,
+                sysvars: &sysvars,
+            },
+            sources: &sources,
+            epoch_inputs: &epoch_inputs,
+            bond_states,
+            pass: bond_pass,
+        };
+        let pye_accounts = round_robin(
+            pye_accounts.into_iter().enumerate().collect(),
+            |(_, (_, _, pye_account))| pye_account.issuer,
+        );
+        // Bonds not started before the deadline come back as `Err` with their pubkey.
+        let mut computed: Vec<(usize, Result<ComputedBond, Pubkey>)> = stream::iter(pye_accounts)
+            .map(|(index, (program_id, pye_account_pubkey, pye_account))| {
+                let issuer_limits = &issuer_limits;
+                let inputs = &inputs;
+                async move {
+                    let _permit = issuer_limits.acquire(&pye_account.issuer).await;
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Ok((index, Err(pye_account_pubkey)));
+                    }
+                    self.compute_bond(inputs, program_id, pye_account_pubkey, pye_account)
+                        .await
+                        .map(|bond| (index, Ok(bond)))
+                }
+            })
+            .buffered(scheduling.bond_concurrency())
+            .try_collect()
             .await?;
-            // Calculate the excess inflation reward to be refunded by validator to SoloValidatorPyeAccount.
-            let excess_inflation_reward = calculate_excess_inflation_reward(
-                &rpc_client,
-                &pye_account.stake_account,
-                &pye_account.transient_stake_account,
-                target_epoch,
-                &pye_account.reward_commissions,
-            )
+        // Back to the order the bonds were fetched in.
+        computed.sort_by_key(|(index, _)| *index);
+        let mut bonds = Vec::with_capacity(computed.len());
+        for (_, bond) in computed {
+            match bond {
+                Ok(mut bond) => {
+                    epoch_rounding.merge(&bond.rounding);
+                    self.adjust_bond(
+                        target_epoch,
+                        &mut bond,
+                        adjustments.get(&bond.pye_account_pubkey, target_epoch),
+                    );
+                    bonds.push(bond);
+                }
+                Err(pye_account_pubkey) => unfinished.push(pye_account_pubkey),
+            }
+        }
+        self.check_alert_thresholds(target_epoch, &bonds, pay && !start_over)
             .await;
 
-            // Calculate the excess MEV reward to be refunded by validator to SoloValidatorPyeAccount.
-            let excess_mev_commission =
-                calculate_excess_mev_reward(&mev_data, pye_account_active_stake, &pye_account.reward_commissions);
+        // Then pay them one at a time in priority order, so the bonds paid first are the ones
+        // prioritized when the deadline or the payer's balance runs out.
+        let bonds = scheduling.payment_priority.order(bonds, ComputedBond::key);
+        let payer = self.signers.map(|(payer, _)| payer.pubkey());
+        let approved = match payer.filter(|_| pay && self.args.review_payments) {
+            Some(payer) => match self
+                .review_epoch_payments(target_epoch, &bonds, &payer)
+                .await?
+            {
+                Some(approved) => Some(approved),
+                None => {
+                    return Err(anyhow!(
+                        "Payments for epoch {} were aborted in review",
+                        target_epoch
+                    ))
+                }
+            },
+            None => None,
+        };
+        let mut bonds = bonds.into_iter();
+        while let Some(bond) = bonds.next() {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                unfinished.push(bond.pye_account_pubkey);
+                unfinished.extend(bonds.map(|bond| bond.pye_account_pubkey));
+                break;
+            }
+            let approved = approved
+                .as_ref()
+                .is_none_or(|approved| approved.contains(&bond.pye_account_pubkey));
+            let payment = self
+                .pay_bond(
+                    &bond,
+                    target_epoch,
+                    bond_pass,
+                    pay,
+                    start_over,
+                    approved,
+                    bond_states,
+                )
+                .await;
+            self.report_excess_reward(target_epoch, &epoch_inputs, &bond, &payment);
+            let mut breakdown = bond.breakdown(PaymentOutcome::NotDue);
+            // The receipt records `payment` itself, so failed transfers get one too before the
+            // manager stops.
+            if self.receipts.is_some() || self.archive.is_some() {
+                let mut receipt = PaymentReceipt::new(
+                    &self.args.vote_pubkey,
+                    &epoch_inputs,
+                    &bond.pye_account,
+                    &breakdown,
+                    payment.as_ref().copied().map_err(ToString::to_string),
+                    bond.computed_at,
+                    chrono::Utc::now(),
+                );
+                if let Some(state) = bond_states.and_then(|bond_states| {
+                    bond_states.get(target_epoch, &bond.pye_account_pubkey, bond_pass)
+                }) {
+                    receipt.installments = state.installments;
+                }
+                receipt.adjustments = bond.adjustments.clone();
+                receipt.deferred_mev = matches!(pass, EpochPass::MevOnly(_));
+                if let Some(receipts) = self.receipts {
+                    receipts.write(&receipt);
+                }
+                epoch_receipts.push(receipt);
+            }
+            breakdown.payment = match payment {
+                Ok(payment) => payment,
+                Err(e) => {
+                    self.archive_epoch(target_epoch, &epoch_receipts).await;
+                    return Err(
+                        anyhow!("Failed to transfer excess rewards: {}", e).context(PaymentError)
+                    );
+                }
+            };
+            breakdowns.push(breakdown);
+        }
+        self.archive_epoch(target_epoch, &epoch_receipts).await;
+        info!(
+            "Cumulative rounding drift ({:?}) for epoch {}: {:.4} lamports",
+            epoch_rounding.mode,
+            target_epoch,
+            epoch_rounding.drift()
+        );
+        flush();
+        if !unfinished.is_empty() {
+            self.report_deadline_exceeded(target_epoch, &unfinished)
+                .await;
+        }
+        // Only payment runs are attested, a recompute without payment must not look like a
+        // receipt.
+        if let Some(attestations) = self.attestations.filter(|_| pay) {
+            attestations.write_epoch(target_epoch, &breakdowns).await;
+        }
+        if let Some(control) = self.control {
+            match pass {
+                EpochPass::MevOnly(_) => {
+                    control.extend_breakdowns(target_epoch, breakdowns.clone())
+                }
+                _ => control.record_breakdowns(target_epoch, breakdowns.clone()),
+            }
+            control.update_status(|status| {
+                status.last_processed_epoch = status.last_processed_epoch.max(Some(target_epoch));
+            });
+        }
+        Ok(ProcessedEpoch {
+            cost: self.epoch_cost(target_epoch, &breakdowns, rpc_requests, &rpc_usage),
+            breakdowns,
+            unfinished,
+            pass: bond_pass,
+            unfinished_mev: Vec::new(),
+            leader_slots,
+        })
+    }
+
+    /// Computes the excess rewards owed to a bond from every reward source for the epoch
+    /// before `inputs.epoch.epoch_info`, at the bond's commission rates unless overridden for a
+    /// what-if dry run.
+    async fn compute_bond(
+        &self,
+        inputs: &BondInputs<'_>,
+        program_id: Pubkey,
+        pye_account_pubkey: Pubkey,
+        mut pye_account: SoloValidatorPyeAccount,
+    ) -> Result<ComputedBond> {
+        self.args
+            .commission_overrides
+            .apply(&mut pye_account.reward_commissions);
+        let target_epoch = inputs.epoch.target_epoch();
+        let resumed = inputs.bond_states.and_then(|bond_states| {
+            bond_states.get(target_epoch, &pye_account_pubkey, inputs.pass)
+        });
+        if let Some(state) = resumed
+            .as_ref()
+            .filter(|state| state.stage >= BondStage::RewardsComputed)
+        {
+            if let (Some(active_stake), Some(rewards)) =
+                (state.active_stake, state.excess_rewards.clone())
+            {
+                info!(
+                    "Resuming pye_account {} for epoch {} at stage {}",
+                    pye_account_pubkey, target_epoch, state.stage
+                );
+                return Ok(ComputedBond {
+                    program_id,
+                    pye_account_pubkey,
+                    pye_account,
+                    active_stake,
+                    rewards: RewardBreakdown {
+                        epoch: target_epoch,
+                        inflation: rewards.inflation,
+                        mev: rewards.mev,
+                        block: rewards.block,
+                    },
+                    excess_rewards: rewards.total,
+                    rounding: Rounding::new(self.args.rounding),
+                    computed_at: chrono::Utc::now(),
+                    resumed,
+                    fingerprint: String::new(),
+                    adjustments: Vec::new(),
+                }
+                .fingerprinted(&self.args.vote_pubkey, inputs.epoch_inputs));
+            }
+        }
+        let linked_stake_accounts = if self.args.track_stake_accounts {
+            fetch_linked_stake_accounts(
+                self.rpc_client,
+                &bond_stake_accounts(
+                    &pye_account.stake_account,
+                    &pye_account.transient_stake_account,
+                ),
+                self.epoch_schedule.get_first_slot_in_epoch(target_epoch),
+            )
+            .await
+            .unwrap_or_else(|e| {
+                warn!(
+                    "Failed to follow the stake accounts of {}, counting only its current ones: {}",
+                    pye_account_pubkey, e
+                );
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+        let stake_accounts: Vec<Pubkey> = bond_stake_accounts(
+            &pye_account.stake_account,
+            &pye_account.transient_stake_account,
+        )
+        .into_iter()
+        .chain(linked_stake_accounts.iter().copied())
+        .collect();
+        let redelegated = check_delegations(
+            self.rpc_client,
+            inputs.epoch.sysvars,
+            &stake_accounts,
+            &self.args.vote_pubkey,
+            target_epoch,
+        )
+        .await?;
+        if !redelegated.is_empty() {
+            self.notifier
+                .notify(Notification::new(
+                    Severity::Warning,
+                    format!("Bond stake redelegated away from {}", self.args.vote_pubkey),
+                    format!(
+                        "Stake accounts of bond {} are delegated to another validator and were excluded from epoch {}: {:?}",
+                        pye_account_pubkey, target_epoch, redelegated
+                    ),
+                ))
+                .await;
+        }
+        // Fetch the SoloValidatorPyeAccount's active stake during target epoch.
+        let pye_account_active_stake = match resumed.as_ref().and_then(|state| state.active_stake) {
+            Some(active_stake) => active_stake,
+            None => {
+                let active_stake = fetch_pye_account_active_stake(
+                    self.rpc_client,
+                    inputs.epoch.sysvars,
+                    inputs.epoch.inflation_rewards,
+                    &pye_account.stake_account,
+                    &pye_account.transient_stake_account,
+                    &linked_stake_accounts,
+                    &redelegated,
+                    target_epoch,
+                    inputs.epoch.epoch_info.epoch,
+                )
+                .await?;
+                self.advance_bond(
+                    inputs.bond_states,
+                    target_epoch,
+                    inputs.pass,
+                    &pye_account_pubkey,
+                    BondStage::StakeComputed,
+                    |state| state.active_stake = Some(active_stake),
+                );
+                active_stake
+            }
+        };
+        let mut rounding = Rounding::new(self.args.rounding);
+        let mut rewards = RewardBreakdown {
+            epoch: target_epoch,
+            ..Default::default()
+        };
+        let bond_context = BondContext {
+            pye_account: &pye_account,
+            active_stake: pye_account_active_stake,
+            linked_stake_accounts: &linked_stake_accounts,
+            redelegated: &redelegated,
+        };
+        // The sources don't depend on each other, so a bond waits on the slowest one only. Each
+        // rounds on its own and their drift is merged in provider order.
+        let excesses = try_join_all(inputs.sources.iter().map(|(category, source)| {
+            let epoch = &inputs.epoch;
+            let bond_context = &bond_context;
+            let mode = rounding.mode;
+            async move {
+                let mut source_rounding = Rounding::new(mode);
+                let excess = source
+                    .excess(epoch, bond_context, &mut source_rounding)
+                    .await?;
+                Ok::<_, anyhow::Error>((*category, excess, source_rounding))
+            }
+        }))
+        .await?;
+        for (category, excess, source_rounding) in excesses {
+            rewards.add(category, excess);
+            rounding.merge(&source_rounding);
+        }
+        let excess_rewards = rewards.total();
+
+        info!(
+            "pye_account: {} (program {})\nExcess rewards to transfer: {}\n\n",
+            labelled(&pye_account_pubkey),
+            program_id,
+            format_amount(excess_rewards)
+        );
 
-            // Calculate the excess block reward to be funded by validator to SoloValidatorPyeAccount.
-            let excess_block_commission = compute_excess_block_commission(
-                validators_total_block_rewards,
-                pye_account_active_stake,
-                mev_data.active_stake,
-                pye_account.reward_commissions.block_rewards_bps,
-            );
+        let computed_at = chrono::Utc::now();
+        self.advance_bond(
+            inputs.bond_states,
+            target_epoch,
+            inputs.pass,
+            &pye_account_pubkey,
+            BondStage::RewardsComputed,
+            |state| {
+                state.excess_rewards = Some(ExcessRewards {
+                    inflation: rewards.inflation,
+                    mev: rewards.mev,
+                    block: rewards.block,
+                    total: excess_rewards,
+                })
+            },
+        );
+        if let Some(dashboard) = self.dashboard {
+            dashboard.record_breakdown(&pye_account_pubkey, pye_account_active_stake, rewards);
+        }
+        if let Some(events) = self.events {
+            events
+                .emit(Event::BreakdownComputed {
+                    epoch: target_epoch,
+                    program_id: program_id.to_string(),
+                    pye_account: pye_account_pubkey.to_string(),
+                    active_stake: pye_account_active_stake,
+                    excess_inflation_rewards: rewards.inflation,
+                    excess_mev_rewards: rewards.mev,
+                    excess_block_rewards: rewards.block,
+                    total_excess_rewards: excess_rewards,
+                })
+                .await;
+        }
+
+        let bond = ComputedBond {
+            program_id,
+            pye_account_pubkey,
+            pye_account,
+            active_stake: pye_account_active_stake,
+            rewards,
+            excess_rewards,
+            rounding,
+            computed_at,
+            resumed,
+            fingerprint: String::new(),
+            adjustments: Vec::new(),
+        }
+        .fingerprinted(&self.args.vote_pubkey, inputs.epoch_inputs);
+        Ok(bond)
+    }
 
-            let excess_rewards =
-                excess_inflation_reward + excess_block_commission + excess_mev_commission;
+    /// Emits the bond's excess rewards for the epoch once its payment is settled, with the
+    /// transaction's signature, fee and slot when it was paid so the datapoint links to it.
+    fn report_excess_reward(
+        &self,
+        target_epoch: u64,
+        epoch_inputs: &EpochInputs,
+        bond: &ComputedBond,
+        payment: &Result<PaymentOutcome>,
+    ) {
+        let outcome = payment.as_ref().ok();
+        datapoint_info!(
+            "excess_reward",
+            ("vote_pubkey", self.args.vote_pubkey.to_string(), String),
+            ("epoch", target_epoch.to_string(), String),
+            ("program_id", bond.program_id.to_string(), String),
+            ("pye_account", bond.pye_account_pubkey.to_string(), String),
+            ("pye_account_active_stake", bond.active_stake as i64, i64),
+            ("excess_inflation_rewards", bond.rewards.inflation, i64),
+            ("excess_mev_rewards", bond.rewards.mev, i64),
+            ("excess_block_rewards", bond.rewards.block, i64),
+            ("total_excess_rewards", bond.rewards.total(), i64),
+            ("rounding_drift", bond.rounding.drift(), f64),
+            ("fingerprint", bond.fingerprint.clone(), String),
+            (
+                "skipped_categories",
+                epoch_inputs.skipped_categories.join(","),
+                String
+            ),
+            (
+                "payment",
+                outcome.map_or("failed".to_string(), ToString::to_string),
+                String
+            ),
+            (
+                "signature",
+                outcome
+                    .and_then(PaymentOutcome::signature)
+                    .map(|signature| signature.to_string())
+                    .unwrap_or_default(),
+                String
+            ),
+            (
+                "fee",
+                outcome.and_then(PaymentOutcome::fee).unwrap_or_default() as i64,
+                i64
+            ),
+            (
+                "slot",
+                outcome.and_then(PaymentOutcome::slot).unwrap_or_default() as i64,
+                i64
+            ),
+        );
+    }
 
+    /// Transfers the excess rewards computed for `bond`, unless there are none, payments
+    /// weren't requested, the transfer is held back or an earlier run already sent it. Runs that
+    /// `start_over` look for the earlier payment on chain too.
+    async fn pay_bond(
+        &self,
+        bond: &ComputedBond,
+        target_epoch: u64,
+        pass: BondPass,
+        pay: bool,
+        start_over: bool,
+        approved: bool,
+        bond_states: Option<&BondStates>,
+    ) -> Result<PaymentOutcome> {
+        let pye_account_pubkey = bond.pye_account_pubkey;
+        let excess_rewards = bond.excess_rewards;
+        let advance = |stage, update: &dyn Fn(&mut BondState)| {
+            self.advance_bond(
+                bond_states,
+                target_epoch,
+                pass,
+                &pye_account_pubkey,
+                stage,
+                update,
+            )
+        };
+        match &bond.resumed {
+            Some(state) if state.stage == BondStage::Confirmed => {
+                let signature = state.signature.as_deref().unwrap_or_default();
+                let signature = Signature::from_str(signature)
+                    .map_err(|e| anyhow!("Invalid signature {} in bond state: {}", signature, e))?;
+                info!(
+                    "pye_account {} was already paid for epoch {}: {}",
+                    pye_account_pubkey, target_epoch, signature
+                );
+                return Ok(PaymentOutcome::Paid(Payment {
+                    signature,
+                    fee: state.fee,
+                    slot: state.slot,
+                    verification: None,
+                }));
+            }
+            Some(state) if state.stage == BondStage::Sent => {
+                warn!(
+                    "The payment to pye_account {} for epoch {} was interrupted while sending, not sending it again",
+                    pye_account_pubkey, target_epoch
+                );
+                advance(BondStage::Sent, &|state| {
+                    state.detail = Some("interrupted while sending".to_string())
+                });
+                self.notifier
+                    .notify(Notification::new(
+                        Severity::Critical,
+                        format!("Payment to {} may not have landed", labelled(&pye_account_pubkey)),
+                        format!(
+                            "A previous run stopped while sending the payment for epoch {}. Check the payer's transactions and pay it manually if it didn't land.",
+                            target_epoch
+                        ),
+                    ))
+                    .await;
+                return Ok(PaymentOutcome::Interrupted);
+            }
+            _ => {}
+        }
+        if excess_rewards <= 0 {
+            info!(
+                "No excess rewards to transfer to pye_account {} for epoch {}\n",
+                pye_account_pubkey, target_epoch
+            );
+            advance(BondStage::NotDue, &|_| {});
+            Ok(PaymentOutcome::NotDue)
+        } else if !pay {
+            Ok(PaymentOutcome::NotRequested)
+        } else if !approved {
             info!(
-                "pye_account: {}\nSOL to transfer: {}\n\n",
-                pye_account_pubkey, excess_rewards
+                "Not transferring {} to pye_account {} for epoch {}: declined in review",
+                format_amount(excess_rewards),
+                pye_account_pubkey,
+                target_epoch
+            );
+            advance(BondStage::RewardsComputed, &|state| {
+                state.detail = Some("declined in review".to_string())
+            });
+            Ok(PaymentOutcome::Declined)
+        } else if let Some((payer, fee_payer)) = self.signers {
+            if self.control.is_some_and(ManagerControl::payments_paused) {
+                let amount = format_amount(excess_rewards);
+                warn!(
+                    "Payments are paused, not transferring {} to pye_account {} for epoch {}",
+                    amount, pye_account_pubkey, target_epoch
+                );
+                advance(BondStage::RewardsComputed, &|state| {
+                    state.detail = Some("payments paused".to_string())
+                });
+                Ok(PaymentOutcome::Paused)
+            } else if let Some(cap) = self
+                .args
+                .max_payment_lamports
+                .filter(|cap| excess_rewards as u64 > *cap)
+            {
+                self.report_over_cap(target_epoch, &pye_account_pubkey, excess_rewards, cap)
+                    .await;
+                advance(BondStage::RewardsComputed, &|state| {
+                    state.detail = Some("over --max-payment-lamports".to_string())
+                });
+                Ok(PaymentOutcome::OverCap)
+            } else if let Some(reason) = self.recheck_bond(bond).await {
+                self.report_bond_changed(
+                    target_epoch,
+                    &pye_account_pubkey,
+                    excess_rewards,
+                    &reason,
+                )
+                .await;
+                advance(BondStage::RewardsComputed, &|state| {
+                    state.detail = Some(format!("not paid: {}", reason))
+                });
+                Ok(PaymentOutcome::BondChanged)
+            } else if let Some(signature) = self
+                .landed_payment(bond, target_epoch, pass, start_over, bond_states)
+                .await?
+            {
+                warn!(
+                    "pye_account {} was already paid for epoch {} by transaction {}, not paying it again",
+                    pye_account_pubkey, target_epoch, signature
+                );
+                advance(BondStage::Confirmed, &|state| {
+                    state.signature = Some(signature.to_string());
+                    state.detail = Some("paid by an earlier run".to_string());
+                });
+                Ok(PaymentOutcome::Paid(Payment {
+                    signature,
+                    fee: None,
+                    slot: None,
+                    verification: None,
+                }))
+            } else if let Some(reason) = self.spending_limits.check(
+                target_epoch,
+                excess_rewards as u64,
+                chrono::Utc::now().timestamp(),
+            ) {
+                self.report_over_limit(target_epoch, &pye_account_pubkey, excess_rewards, &reason)
+                    .await;
+                advance(BondStage::RewardsComputed, &|state| {
+                    state.detail = Some("over spending limit".to_string())
+                });
+                Ok(PaymentOutcome::OverLimit)
+            } else {
+                // Make the actual SOL transfer if not a dry run and rewards are greater than 0
+                let cluster = self.args.cluster.anchor_cluster(self.rpc);
+                let lamports = u64::try_from(excess_rewards)?;
+                advance(BondStage::PlanApproved, &|_| {});
+                if let Some(events) = self.events {
+                    events
+                        .emit(Event::TransferSent {
+                            epoch: target_epoch,
+                            pye_account: pye_account_pubkey.to_string(),
+                            lamports,
+                        })
+                        .await;
+                }
+                let memo = fingerprint_memo(target_epoch, &bond.fingerprint);
+                let amounts = self.args.installments.split(lamports);
+                let result = if amounts.len() > 1 {
+                    self.pay_installments(
+                        bond,
+                        target_epoch,
+                        &amounts,
+                        &memo,
+                        bond_states,
+                        &advance,
+                    )
+                    .await
+                } else {
+                    advance(BondStage::Sent, &|_| {});
+                    match self.persist_bond_states(bond_states).await {
+                        Ok(()) => {
+                            transfer_excess_rewards(
+                                Arc::clone(payer),
+                                fee_payer.clone(),
+                                cluster,
+                                &bond.program_id,
+                                &pye_account_pubkey,
+                                &bond.pye_account,
+                                lamports,
+                                Some(&memo),
+                                &self.args.send,
+                            )
+                            .await
+                        }
+                        Err(e) => Err(e),
+                    }
+                };
+                if result.is_ok() {
+                    let now = chrono::Utc::now().timestamp();
+                    self.spending_limits
+                        .record(target_epoch, lamports, now, now);
+                }
+                match &result {
+                    Ok(payment) => advance(BondStage::Confirmed, &|state| {
+                        state.signature = Some(payment.signature.to_string());
+                        state.fee = payment.fee;
+                        state.slot = payment.slot;
+                        if let Some(verification) = payment
+                            .verification
+                            .filter(|verification| *verification != PaymentVerification::Verified)
+                        {
+                            state.detail = Some(format!("payment {}", verification));
+                        }
+                    }),
+                    // The transfer may still have landed, so it stays sent and isn't paid again.
+                    Err(err) if err.downcast_ref::<OutcomeUnknown>().is_some() => {
+                        advance(BondStage::Sent, &|state| {
+                            state.detail = Some(err.to_string())
+                        })
+                    }
+                    Err(err) => advance(BondStage::Failed, &|state| {
+                        state.detail = Some(err.to_string())
+                    }),
+                }
+                // A landed payment whose stage isn't stored would be paid again after a restart.
+                let result = match result {
+                    Ok(payment) => self
+                        .persist_bond_states(bond_states)
+                        .await
+                        .map(|()| payment)
+                        .map_err(|e| anyhow!("Payment {} landed, but {}", payment.signature, e)),
+                    Err(e) => Err(e),
+                };
+                if let Some(dashboard) = self.dashboard {
+                    let outcome = match &result {
+                        Ok(payment) => payment.signature.to_string(),
+                        Err(err) => format!("Failed: {}", err),
+                    };
+                    dashboard.record_payment(target_epoch, &pye_account_pubkey, lamports, outcome);
+                }
+                if let Some(events) = self.events {
+                    let pye_account = pye_account_pubkey.to_string();
+                    let event = match &result {
+                        Ok(payment) => Event::TransferConfirmed {
+                            epoch: target_epoch,
+                            pye_account,
+                            lamports,
+                            signature: payment.signature.to_string(),
+                        },
+                        Err(err) => Event::TransferFailed {
+                            epoch: target_epoch,
+                            pye_account,
+                            lamports,
+                            error: err.to_string(),
+                        },
+                    };
+                    events.emit(event).await;
+                }
+                if let Ok(payment) = &result {
+                    if let Some(verification) = payment
+                        .verification
+                        .filter(|verification| *verification != PaymentVerification::Verified)
+                    {
+                        self.report_unverified_payment(
+                            target_epoch,
+                            &pye_account_pubkey,
+                            lamports,
+                            payment,
+                            verification,
+                        )
+                        .await;
+                    }
+                }
+                result.map(PaymentOutcome::Paid)
+            }
+        } else {
+            Ok(PaymentOutcome::DryRun)
+        }
+    }
+
+    /// Uploads the epoch's report and receipts to the --archive-url bucket, if one is used.
+    async fn archive_epoch(&self, target_epoch: u64, receipts: &[PaymentReceipt]) {
+        if let Some(archive) = self.archive {
+            archive
+                .archive_epoch(&self.args.vote_pubkey.to_string(), target_epoch, receipts)
+                .await;
+        }
+    }
+
+    /// Logs and reports the cost of servicing the bonds for `target_epoch`, counting the RPC
+    /// requests made since the count was `rpc_requests_before` and the usage `rpc_usage_before`.
+    fn epoch_cost(
+        &self,
+        target_epoch: u64,
+        breakdowns: &[BondRewardBreakdown],
+        rpc_requests_before: usize,
+        rpc_usage_before: &RpcUsageCounts,
+    ) -> EpochCost {
+        let rpc_usage = self.rpc_usage.snapshot().since(rpc_usage_before);
+        info!("RPC usage for epoch {}: {}", target_epoch, rpc_usage);
+        rpc_usage.report(&self.args.vote_pubkey, target_epoch);
+        let rpc_requests = self
+            .rpc_client
+            .get_transport_stats()
+            .request_count
+            .saturating_sub(rpc_requests_before);
+        let cost = EpochCost::new(
+            target_epoch,
+            breakdowns,
+            rpc_requests as u64,
+            &self.args.operating_cost,
+        );
+        info!("{}", cost);
+        datapoint_info!(
+            "epoch_operating_cost",
+            ("vote_pubkey", self.args.vote_pubkey.to_string(), String),
+            ("epoch", target_epoch.to_string(), String),
+            ("transactions", cost.transactions as i64, i64),
+            ("fees", cost.fees as i64, i64),
+            ("unknown_fees", cost.unknown_fees as i64, i64),
+            ("rpc_requests", cost.rpc_requests as i64, i64),
+            ("rpc_credits", cost.rpc_credits as i64, i64),
+        );
+        cost
+    }
+
+    /// Warns about the alert thresholds crossed by the excess rewards computed for the epoch and,
+    /// with --pause-on-alert and `pause`, pauses payments before any is sent. Recomputes
+    /// requested through the control API are the approval, so they don't pause.
+    async fn check_alert_thresholds(&self, target_epoch: u64, bonds: &[ComputedBond], pause: bool) {
+        let thresholds = &self.args.alert_thresholds;
+        let breaches = thresholds.breaches(
+            bonds
+                .iter()
+                .map(|bond| (&bond.pye_account_pubkey, &bond.rewards)),
+        );
+        if breaches.is_empty() {
+            return;
+        }
+        for breach in &breaches {
+            datapoint_warn!(
+                "alert_threshold",
+                ("vote_pubkey", self.args.vote_pubkey.to_string(), String),
+                ("epoch", target_epoch.to_string(), String),
+                ("threshold", breach.name().to_string(), String),
+                ("detail", breach.to_string(), String),
             );
+        }
+        let mut body: Vec<String> = breaches.iter().map(ToString::to_string).collect();
+        if let Some(control) = self.control.filter(|_| pause && thresholds.pause_on_alert) {
+            control.pause_for_review();
+            body.push(format!(
+                "Payments are paused pending approval. Review the amounts, then resume payments with `manager-ctl resume` and pay the epoch with `manager-ctl recompute --epoch {} --pay`.",
+                target_epoch
+            ));
+        }
+        self.notifier
+            .notify(Notification::new(
+                Severity::Warning,
+                format!(
+                    "Excess rewards for epoch {} crossed alert thresholds",
+                    target_epoch
+                ),
+                body.join("\n"),
+            ))
+            .await;
+    }
 
+    /// Applies the bond-epoch's manual adjustments to the amount paid.
+    fn adjust_bond(&self, target_epoch: u64, bond: &mut ComputedBond, adjustments: &[Adjustment]) {
+        for adjustment in adjustments {
+            info!(
+                "Adjusting the excess rewards of pye_account {} for epoch {} by {}: {}",
+                bond.pye_account_pubkey,
+                target_epoch,
+                format_amount(adjustment.delta_lamports),
+                adjustment.reason
+            );
             datapoint_info!(
-                "excess_reward",
-                ("vote_pubkey", args.vote_pubkey.to_string(), String),
+                "excess_reward_adjustment",
+                ("vote_pubkey", self.args.vote_pubkey.to_string(), String),
                 ("epoch", target_epoch.to_string(), String),
-                ("pye_account", pye_account_pubkey.to_string(), String),
-                ("pye_account_active_stake", pye_account_active_stake as i64, i64),
-                ("excess_inflation_rewards", excess_inflation_reward, i64),
-                ("excess_mev_rewards", excess_mev_commission, i64),
-                ("excess_block_rewards", excess_block_commission, i64),
-                ("total_excess_rewards", excess_rewards, i64),
+                ("pye_account", bond.pye_account_pubkey.to_string(), String),
+                ("delta_lamports", adjustment.delta_lamports, i64),
+                ("reason", adjustment.reason.clone(), String),
             );
+        }
+        bond.excess_rewards += adjustments_total(adjustments);
+        bond.adjustments = adjustments.to_vec();
+    }
 
-            if excess_rewards <= 0 {
-                info!(
-                    "No excess rewards to transfer to pye_account {} for epoch {}\n",
-                    pye_account_pubkey, target_epoch
-                );
+    /// Asks the operator which of the epoch's due transfers to pay, in payment order. Returns the
+    /// bonds approved, or None when the operator aborts. Transfers a previous run already sent
+    /// aren't proposed again.
+    async fn review_epoch_payments(
+        &self,
+        target_epoch: u64,
+        bonds: &[ComputedBond],
+        payer: &Pubkey,
+    ) -> Result<Option<HashSet<Pubkey>>> {
+        let transfers: Vec<ProposedTransfer> = bonds
+            .iter()
+            .filter(|bond| bond.excess_rewards > 0)
+            .filter(|bond| {
+                !bond.resumed.as_ref().is_some_and(|state| {
+                    matches!(state.stage, BondStage::Sent | BondStage::Confirmed)
+                })
+            })
+            .map(|bond| ProposedTransfer {
+                pye_account: bond.pye_account_pubkey,
+                issuer: bond.pye_account.issuer,
+                rewards: bond.rewards,
+                lamports: bond.excess_rewards as u64,
+            })
+            .collect();
+        if transfers.is_empty() {
+            return Ok(Some(HashSet::new()));
+        }
+        let payer_balance = self.rpc_client.get_balance(payer).await?;
+        let approved = review_payments(target_epoch, &transfers, payer_balance)?;
+        Ok(approved.map(|approved| approved.into_iter().collect()))
+    }
+
+    /// Warns when the validator skipped more of its leader slots over the epoch than
+    /// --alert-skip-rate-percent allows.
+    async fn check_skip_rate(&self, target_epoch: u64, slots: &LeaderSlots) {
+        let Some(threshold) = self
+            .args
+            .alert_skip_rate_percent
+            .filter(|threshold| slots.skip_rate_percent() > *threshold)
+        else {
+            return;
+        };
+        datapoint_warn!(
+            "skip_rate_alert",
+            ("vote_pubkey", self.args.vote_pubkey.to_string(), String),
+            ("epoch", target_epoch.to_string(), String),
+            ("skipped", slots.skipped as i64, i64),
+            ("skip_rate_percent", slots.skip_rate_percent(), f64),
+            ("threshold_percent", threshold, f64),
+        );
+        self.notifier
+            .notify(Notification::new(
+                Severity::Warning,
+                format!(
+                    "{} skipped {:.2}% of its leader slots in epoch {}",
+                    labelled(&self.args.vote_pubkey),
+                    slots.skip_rate_percent(),
+                    target_epoch
+                ),
+                format!(
+                    "{}\nAlert threshold (--alert-skip-rate-percent): {}%",
+                    slots, threshold
+                ),
+            ))
+            .await;
+    }
+
+    /// Re-fetches the bond right before its transfer, since it may have been closed or matured in
+    /// the hours since discovery. Returns why it must not be paid, if so.
+    async fn recheck_bond(&self, bond: &ComputedBond) -> Option<String> {
+        match self
+            .rpc_client
+            .get_account_with_commitment(&bond.pye_account_pubkey, CommitmentConfig::confirmed())
+            .await
+        {
+            Ok(response) => bond_unpayable_reason(
+                &bond.program_id,
+                &self.args.vote_pubkey,
+                &bond.pye_account,
+                response.value.as_ref(),
+            ),
+            Err(e) => Some(format!("the bond account couldn't be re-fetched: {}", e)),
+        }
+    }
+
+    /// The transaction carrying the epoch's payment memo that already paid the bond, searched
+    /// for when the run started over. Bond states can't tell on their own then: they are only
+    /// kept in memory without --bond-state-file or --state-db-url, and pruned after 16 epochs.
+    /// Payments recorded for the bond's other passes of the epoch are passed over.
+    async fn landed_payment(
+        &self,
+        bond: &ComputedBond,
+        target_epoch: u64,
+        pass: BondPass,
+        start_over: bool,
+        bond_states: Option<&BondStates>,
+    ) -> Result<Option<Signature>> {
+        if !start_over {
+            return Ok(None);
+        }
+        let recorded: Vec<String> = [BondPass::Full, BondPass::WithoutMev, BondPass::MevOnly]
+            .into_iter()
+            .filter(|other| *other != pass)
+            .filter_map(|other| {
+                bond_states?
+                    .get(target_epoch, &bond.pye_account_pubkey, other)?
+                    .signature
+            })
+            .collect();
+        let recorded: Vec<&str> = recorded.iter().map(String::as_str).collect();
+        find_landed_payment(
+            self.rpc_client,
+            &bond.pye_account_pubkey,
+            target_epoch,
+            self.epoch_schedule
+                .get_first_slot_in_epoch(target_epoch + 1),
+            &recorded,
+        )
+        .await
+        .context("Failed to search for an earlier payment")
+    }
+
+    async fn report_bond_changed(
+        &self,
+        target_epoch: u64,
+        pye_account_pubkey: &Pubkey,
+        excess_rewards: i64,
+        reason: &str,
+    ) {
+        warn!(
+            "Not transferring {} to pye_account {} for epoch {}: {}",
+            format_amount(excess_rewards),
+            pye_account_pubkey,
+            target_epoch,
+            reason
+        );
+        datapoint_warn!(
+            "payment_bond_changed",
+            ("vote_pubkey", self.args.vote_pubkey.to_string(), String),
+            ("epoch", target_epoch.to_string(), String),
+            ("pye_account", pye_account_pubkey.to_string(), String),
+            ("lamports", excess_rewards, i64),
+            ("reason", reason.to_string(), String),
+        );
+        self.notifier
+            .notify(Notification::new(
+                Severity::Warning,
+                format!("Payment to {} skipped", labelled(&pye_account_pubkey)),
+                format!(
+                    "Excess rewards of {} for epoch {} weren't transferred because {} since the bond was discovered. Review the bond and pay it manually if it's still owed.",
+                    format_amount(excess_rewards),
+                    target_epoch,
+                    reason
+                ),
+            ))
+            .await;
+    }
+
+    /// Pays `amounts` one transfer at a time, --installment-interval-secs apart, recording each
+    /// landed installment in the bond's state so a restart carries on with the next one. The
+    /// installments make up one payment: the last one's signature, with the fees summed.
+    async fn pay_installments(
+        &self,
+        bond: &ComputedBond,
+        target_epoch: u64,
+        amounts: &[u64],
+        memo: &str,
+        bond_states: Option<&BondStates>,
+        advance: &dyn Fn(BondStage, &dyn Fn(&mut BondState)),
+    ) -> Result<Payment> {
+        let (payer, fee_payer) = self
+            .signers
+            .ok_or_else(|| anyhow!("Installments can't be paid without a payer"))?;
+        let pye_account_pubkey = bond.pye_account_pubkey;
+        let mut installments = bond
+            .resumed
+            .as_ref()
+            .map(|state| state.installments.clone())
+            .unwrap_or_default();
+        if !installments.is_empty() {
+            info!(
+                "Resuming the payment to pye_account {} for epoch {} after {} of {} installments",
+                pye_account_pubkey,
+                target_epoch,
+                installments.len(),
+                amounts.len()
+            );
+        }
+        let interval = self.args.installments.installment_interval_secs as i64;
+        let mut verification = None;
+        while let Some(&lamports) = amounts.get(installments.len()) {
+            let number = installments.len() + 1;
+            if let Some(previous) = installments.last() {
+                let wait = previous.paid_at + interval - chrono::Utc::now().timestamp();
+                if wait > 0 {
+                    info!(
+                        "Waiting {}s before installment {} of {} to pye_account {} for epoch {}",
+                        wait,
+                        number,
+                        amounts.len(),
+                        pye_account_pubkey,
+                        target_epoch
+                    );
+                    tokio::time::sleep(Duration::from_secs(wait as u64)).await;
+                }
+            }
+            advance(BondStage::Sent, &|_| {});
+            self.persist_bond_states(bond_states).await?;
+            let payment = transfer_excess_rewards(
+                Arc::clone(payer),
+                fee_payer.clone(),
+                self.args.cluster.anchor_cluster(self.rpc),
+                &bond.program_id,
+                &pye_account_pubkey,
+                &bond.pye_account,
+                lamports,
+                Some(memo),
+                &self.args.send,
+            )
+            .await
+            .map_err(|e| {
+                let message = format!("Installment {} of {} failed: {}", number, amounts.len(), e);
+                e.context(message)
+            })?;
+            info!(
+                "Paid installment {} of {} ({}) to pye_account {} for epoch {}: {}",
+                number,
+                amounts.len(),
+                format_amount(lamports),
+                pye_account_pubkey,
+                target_epoch,
+                payment.signature
+            );
+            // An installment that didn't verify taints the whole payment.
+            if verification.is_none_or(|verification| verification == PaymentVerification::Verified)
+            {
+                verification = payment.verification;
+            }
+            installments.push(Installment {
+                lamports,
+                signature: payment.signature.to_string(),
+                fee: payment.fee,
+                slot: payment.slot,
+                paid_at: chrono::Utc::now().timestamp(),
+            });
+            advance(BondStage::PlanApproved, &|state| {
+                state.installments = installments.clone()
+            });
+        }
+        let last = installments
+            .last()
+            .ok_or_else(|| anyhow!("A payment in installments needs at least one"))?;
+        Ok(Payment {
+            signature: Signature::from_str(&last.signature).map_err(|e| {
+                anyhow!("Invalid signature {} in bond state: {}", last.signature, e)
+            })?,
+            fee: installments.iter().map(|installment| installment.fee).sum(),
+            slot: last.slot,
+            verification,
+        })
+    }
+
+    async fn report_unverified_payment(
+        &self,
+        target_epoch: u64,
+        pye_account_pubkey: &Pubkey,
+        lamports: u64,
+        payment: &Payment,
+        verification: PaymentVerification,
+    ) {
+        error!(
+            "Payment {} of {} to pye_account {} for epoch {} is {}",
+            payment.signature,
+            format_amount(lamports),
+            pye_account_pubkey,
+            target_epoch,
+            verification
+        );
+        datapoint_error!(
+            "payment_unverified",
+            ("vote_pubkey", self.args.vote_pubkey.to_string(), String),
+            ("epoch", target_epoch.to_string(), String),
+            ("pye_account", pye_account_pubkey.to_string(), String),
+            ("lamports", lamports as i64, i64),
+            ("signature", payment.signature.to_string(), String),
+            ("slot", payment.slot.unwrap_or_default() as i64, i64),
+            ("verification", verification.name().to_string(), String),
+        );
+        self.notifier
+            .notify(Notification::new(
+                Severity::Critical,
+                format!("Payment to {} unverified", labelled(&pye_account_pubkey)),
+                format!(
+                    "The payment of {} for epoch {} in transaction {} was confirmed, but is {} at finalized commitment. Check the bond's balance and pay it manually if the payment didn't land.",
+                    format_amount(lamports),
+                    target_epoch,
+                    payment.signature,
+                    verification
+                ),
+            ))
+            .await;
+    }
+
+    async fn report_over_limit(
+        &self,
+        target_epoch: u64,
+        pye_account_pubkey: &Pubkey,
+        excess_rewards: i64,
+        reason: &str,
+    ) {
+        warn!(
+            "Withholding {} for pye_account {} in epoch {}: {}",
+            format_amount(excess_rewards),
+            pye_account_pubkey,
+            target_epoch,
+            reason
+        );
+        datapoint_error!(
+            "payment_over_limit",
+            ("vote_pubkey", self.args.vote_pubkey.to_string(), String),
+            ("epoch", target_epoch.to_string(), String),
+            ("pye_account", pye_account_pubkey.to_string(), String),
+            ("lamports", excess_rewards, i64),
+            ("reason", reason.to_string(), String),
+        );
+        self.notifier
+            .notify(Notification::new(
+                Severity::Critical,
+                format!("Payment to {} pending approval", labelled(&pye_account_pubkey)),
+                format!(
+                    "Excess rewards of {} for epoch {} were withheld because {}. Review the epoch's payments and approve them with `retry-failed --approve-pending`.",
+                    format_amount(excess_rewards),
+                    target_epoch,
+                    reason
+                ),
+            ))
+            .await;
+    }
+
+    async fn report_over_cap(
+        &self,
+        target_epoch: u64,
+        pye_account_pubkey: &Pubkey,
+        excess_rewards: i64,
+        cap: u64,
+    ) {
+        warn!(
+            "Excess rewards of {} for pye_account {} in epoch {} exceed the {} cap, not transferring",
+            format_amount(excess_rewards),
+            pye_account_pubkey,
+            target_epoch,
+            format_amount(cap)
+        );
+        datapoint_error!(
+            "payment_over_cap",
+            ("vote_pubkey", self.args.vote_pubkey.to_string(), String),
+            ("epoch", target_epoch.to_string(), String),
+            ("pye_account", pye_account_pubkey.to_string(), String),
+            ("lamports", excess_rewards, i64),
+            ("cap", cap as i64, i64),
+        );
+        self.notifier
+            .notify(Notification::new(
+                Severity::Critical,
+                format!("Payment to {} withheld over the cap", labelled(&pye_account_pubkey)),
+                format!(
+                    "Excess rewards of {} for epoch {} exceed --max-payment-lamports of {}. Review the calculation and pay it manually or raise the cap.",
+                    format_amount(excess_rewards),
+                    target_epoch,
+                    format_amount(cap)
+                ),
+            ))
+            .await;
+    }
+
+    async fn report_deadline_exceeded(&self, target_epoch: u64, unfinished: &[Pubkey]) {
+        datapoint_error!(
+            "epoch_deadline_exceeded",
+            ("vote_pubkey", self.args.vote_pubkey.to_string(), String),
+            ("epoch", target_epoch.to_string(), String),
+            ("unfinished_bonds", unfinished.len() as i64, i64),
+        );
+        self.notifier
+            .notify(Notification::new(
+                Severity::Critical,
+                format!("Processing of epoch {} ran past its deadline", target_epoch),
+                format!(
+                    "{} bonds weren't paid within {}s and will be retried in the background: {}",
+                    unfinished.len(),
+                    self.args.epoch_processing_deadline_secs.unwrap_or_default(),
+                    unfinished
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ))
+            .await;
+    }
+
+    /// Retries the oldest epoch left unfinished at its deadline, with a fresh deadline. Bonds that
+    /// can't be fetched right now are retried on the next cycle.
+    async fn retry_unfinished(&self, progress: &mut EpochProgress) -> Result<()> {
+        let Some(unfinished) = progress.oldest().cloned() else {
+            return Ok(());
+        };
+        info!(
+            "Retrying {} unfinished bonds of epoch {} ({} pass)",
+            unfinished.pye_accounts.len(),
+            unfinished.epoch,
+            unfinished.pass
+        );
+        let Some(pass) = self.resumed_pass(unfinished.pass, unfinished.epoch).await else {
+            return Ok(());
+        };
+        let mut pye_accounts = Vec::new();
+        for pubkey in unfinished.pye_accounts()? {
+            match fetch_solo_validator_pye_account(self.rpc_client, self.program_ids, &pubkey).await
+            {
+                Ok((program_id, pye_account)) => {
+                    pye_accounts.push((program_id, pubkey, pye_account))
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to fetch pye_account {} to retry epoch {}: {}",
+                        pubkey, unfinished.epoch, err
+                    );
+                    return Ok(());
+                }
+            }
+        }
+        let processed = self
+            .process_epoch(
+                &epoch_info_after(self.epoch_schedule, unfinished.epoch),
+                pye_accounts,
+                unfinished.observed_commission,
+                true,
+                false,
+                pass,
+            )
+            .await?;
+        progress.update(
+            unfinished.epoch,
+            unfinished.pass,
+            &processed.unfinished,
+            unfinished.observed_commission,
+        );
+        Ok(())
+    }
+
+    /// The pass that carries on with bonds left unfinished in `pass`, with the Jito API's data
+    /// for a MEV-only pass. `None` while that data can't be fetched.
+    async fn resumed_pass(&self, pass: BondPass, target_epoch: u64) -> Option<EpochPass> {
+        match pass {
+            BondPass::Full => Some(EpochPass::Full),
+            BondPass::WithoutMev => Some(EpochPass::WithoutMev),
+            BondPass::MevOnly => {
+                let Some(jito_api_url) = self.jito_api_url else {
+                    warn!(
+                        "No Jito API to pay the deferred MEV of epoch {} from",
+                        target_epoch
+                    );
+                    return None;
+                };
+                match fetch_and_filter_mev_data(
+                    jito_api_url,
+                    &self.args.vote_pubkey,
+                    target_epoch,
+                    self.fixtures,
+                )
+                .await
+                {
+                    Ok(jito) => Some(EpochPass::MevOnly(jito)),
+                    Err(e) => {
+                        warn!(
+                            "Failed to fetch the Jito API's data to pay the deferred MEV of epoch {}: {:#}",
+                            target_epoch, e
+                        );
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finishes the completed epochs a previous run stopped processing, e.g. by crashing between
+    /// computing and paying the rewards, instead of waiting for the next epoch. Bonds resume at
+    /// the stage they reached in the pass they were in; those stopped before their rewards were
+    /// computed are computed again. Deferred MEV whose Jito data can't be fetched yet is retried
+    /// with the unfinished epochs.
+    async fn resume_interrupted(
+        &self,
+        current_epoch: u64,
+        progress: &mut EpochProgress,
+    ) -> Result<()> {
+        if self.signers.is_none() {
+            return Ok(());
+        }
+        for ((epoch, pass), states) in self.bond_states.interrupted(current_epoch) {
+            info!(
+                "Resuming {} bonds of epoch {} ({} pass) left unfinished by the previous run",
+                states.len(),
+                epoch,
+                pass
+            );
+            self.set_phase(format!("Resuming epoch {}", epoch));
+            let mut pye_accounts = Vec::new();
+            for state in states {
+                let pubkey = state.pye_account()?;
+                match fetch_solo_validator_pye_account(self.rpc_client, self.program_ids, &pubkey)
+                    .await
+                {
+                    Ok((program_id, pye_account)) => {
+                        pye_accounts.push((program_id, pubkey, pye_account))
+                    }
+                    Err(err) => warn!(
+                        "Failed to fetch pye_account {} to resume epoch {}: {}",
+                        pubkey, epoch, err
+                    ),
+                }
+            }
+            if pye_accounts.is_empty() {
                 continue;
             }
+            let Some(epoch_pass) = self.resumed_pass(pass, epoch).await else {
+                let pubkeys: Vec<Pubkey> =
+                    pye_accounts.iter().map(|(_, pubkey, _)| *pubkey).collect();
+                progress.update(epoch, pass, &pubkeys, (None, None));
+                continue;
+            };
+            // Commissions observed live at the time are unknown after a restart.
+            let processed = self
+                .process_epoch(
+                    &epoch_info_after(self.epoch_schedule, epoch),
+                    pye_accounts,
+                    (None, None),
+                    true,
+                    false,
+                    epoch_pass,
+                )
+                .await?;
+            if !processed.unfinished.is_empty() {
+                progress.update(epoch, pass, &processed.unfinished, (None, None));
+            }
+        }
+        Ok(())
+    }
 
-            // Make the actual SOL transfer if not a dry run and rewards are greater than 0
-            if !args.dry_run {
-                // transfer_excess_rewards_with_delegate_tips
-                let cluster = Cluster::Custom(args.rpc.clone(), args.rpc.replace("http", "ws"));
-                transfer_excess_rewards(
-                    args.payer.clone(),
-                    cluster,
-                    &pye_account_pubkey,
-                    &pye_account,
-                    u64::try_from(excess_rewards)?,
+    /// Recomputes a completed epoch for the control API and answers with the breakdowns or the
+    /// error. Bonds are fetched again, matured ones included, and filtered by their maturity at
+    /// the end of the epoch.
+    async fn recompute_epoch(
+        &self,
+        program_ids: &[Pubkey],
+        fetch_options: &PyeAccountFetchOptions,
+        request: RecomputeRequest,
+    ) {
+        info!(
+            "Recomputing excess rewards for epoch {} (pay: {})",
+            request.epoch, request.pay
+        );
+        let result = async {
+            let mut pye_accounts = Vec::new();
+            for program_id in program_ids {
+                let found = fetch_solo_validator_pye_accounts_by_vote_key(
+                    self.rpc_client,
+                    program_id,
+                    &self.args.vote_pubkey,
+                    None,
+                    false,
+                    fetch_options,
                 )
+                .await?;
+                pye_accounts.extend(
+                    found
+                        .into_iter()
+                        .filter(|(_, pye_account)| self.args.issuers.contains(&pye_account.issuer))
+                        .map(|(pubkey, pye_account)| (*program_id, pubkey, pye_account)),
+                );
+            }
+            let epoch_info = epoch_info_after(self.epoch_schedule, request.epoch);
+            // Commissions observed live at the time are unknown for past epochs.
+            let processed = self
+                .process_epoch_passes(&epoch_info, pye_accounts, (None, None), request.pay, true)
+                .await?;
+            let unfinished = processed.unfinished.len() + processed.unfinished_mev.len();
+            if unfinished > 0 {
+                warn!(
+                    "Recompute of epoch {} left {} bonds unfinished at the deadline",
+                    request.epoch, unfinished
+                );
+            }
+            Ok(processed.breakdowns)
+        }
+        .await;
+        if let Err(err) = &result {
+            warn!("Failed to recompute epoch {}: {}", request.epoch, err);
+        }
+        let _ = request.respond_to.send(result.map_err(|e| e.to_string()));
+    }
+
+    /// Moves a bond's `pass` to `stage` if the run records bond states.
+    fn advance_bond(
+        &self,
+        bond_states: Option<&BondStates>,
+        target_epoch: u64,
+        pass: BondPass,
+        pye_account_pubkey: &Pubkey,
+        stage: BondStage,
+        update: impl FnOnce(&mut BondState),
+    ) {
+        if let Some(bond_states) = bond_states {
+            bond_states.advance(target_epoch, pye_account_pubkey, pass, stage, update);
+            self.publish_bond_states();
+        }
+    }
+
+    /// Waits until the bond stages recorded so far are stored, see [`BondStates::persist`].
+    async fn persist_bond_states(&self, bond_states: Option<&BondStates>) -> Result<()> {
+        match bond_states {
+            Some(bond_states) => bond_states
+                .persist()
                 .await
-                .map_err(|e| anyhow!("Failed to transfer excess rewards: {}", e))?
+                .map_err(|e| anyhow!("recording its stage failed: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Reports the bonds not done with yet on the control API.
+    fn publish_bond_states(&self) {
+        if let Some(control) = self.control {
+            let unfinished = self.bond_states.unfinished();
+            control.update_status(|status| status.unfinished_bonds = unfinished);
+        }
+    }
+
+    /// Describes what the manager is doing on the dashboard, the control API and heartbeats.
+    fn set_phase(&self, phase: String) {
+        self.heartbeat.set_phase(phase.clone());
+        if let Some(dashboard) = self.dashboard {
+            dashboard.set_phase(phase.clone());
+        }
+        if let Some(control) = self.control {
+            control.update_status(|status| status.phase = phase);
+        }
+    }
+}
+
+/// The epoch info as of the first slot of the epoch after `target_epoch`, for recomputing a
+/// completed epoch after the fact.
+fn epoch_info_after(epoch_schedule: &EpochSchedule, target_epoch: u64) -> EpochInfo {
+    let epoch = target_epoch + 1;
+    EpochInfo {
+        epoch,
+        slot_index: 0,
+        slots_in_epoch: epoch_schedule.get_slots_in_epoch(epoch),
+        absolute_slot: epoch_schedule.get_first_slot_in_epoch(epoch),
+        block_height: 0,
+        transaction_count: None,
+    }
+}
+
+/// Mails the HTML payout report of `epoch`. Failures are logged, like notifications.
+async fn mail_epoch_report(
+    notifier: &Notifier,
+    vote_pubkey: &Pubkey,
+    epoch: u64,
+    breakdowns: &[BondRewardBreakdown],
+    cost: &EpochCost,
+    leader_slots: Option<&LeaderSlots>,
+) {
+    let Some(mailer) = notifier.mailer() else {
+        return;
+    };
+    let subject = format!("Excess rewards report for {}, epoch {}", vote_pubkey, epoch);
+    let html = epoch_report_html(vote_pubkey, epoch, breakdowns, cost, leader_slots);
+    match mailer.send(&subject, html, true).await {
+        Ok(()) => info!("Mailed the report of epoch {}", epoch),
+        Err(e) => warn!("Failed to mail the report of epoch {}: {}", epoch, e),
+    }
+}
+
+/// Summarizes the manager's state for the --schedule report.
+fn scheduled_report(
+    vote_pubkey: &Pubkey,
+    current_epoch: u64,
+    monitored_bonds: usize,
+    last_breakdowns: &[BondRewardBreakdown],
+    last_cost: Option<&EpochCost>,
+    last_leader_slots: Option<&LeaderSlots>,
+    unfinished_epochs: &[u64],
+    payments_paused: bool,
+) -> Notification {
+    let owed: i64 = last_breakdowns
+        .iter()
+        .map(|breakdown| breakdown.rewards.total().max(0))
+        .sum();
+    let paid: i64 = last_breakdowns
+        .iter()
+        .filter(|breakdown| breakdown.payment.signature().is_some())
+        .map(|breakdown| breakdown.rewards.total())
+        .sum();
+    let last_epoch = last_breakdowns
+        .first()
+        .map(|breakdown| breakdown.rewards.epoch.to_string())
+        .unwrap_or_else(|| "-".to_string());
+    datapoint_info!(
+        "scheduled_report",
+        ("vote_pubkey", vote_pubkey.to_string(), String),
+        ("current_epoch", current_epoch as i64, i64),
+        ("monitored_bonds", monitored_bonds as i64, i64),
+        ("last_epoch_owed", owed, i64),
+        ("last_epoch_paid", paid, i64),
+        ("unfinished_epochs", unfinished_epochs.len() as i64, i64),
+    );
+    let mut body = format!(
+        "Current epoch: {}\nMonitored bonds: {}\nLast processed epoch: {}\nOwed: {} to {} bonds\nPaid: {}",
+        current_epoch,
+        monitored_bonds,
+        last_epoch,
+        format_amount(owed),
+        last_breakdowns.len(),
+        format_amount(paid)
+    );
+    if let Some(cost) = last_cost {
+        body.push_str(&format!("\n{}", cost));
+    }
+    if let Some(slots) = last_leader_slots {
+        body.push_str(&format!("\n{}", slots));
+    }
+    if !unfinished_epochs.is_empty() {
+        body.push_str(&format!(
+            "\nEpochs with unpaid bonds: {:?}",
+            unfinished_epochs
+        ));
+    }
+    if payments_paused {
+        body.push_str("\nPayments are paused");
+    }
+    let severity = if owed > paid || !unfinished_epochs.is_empty() {
+        Severity::Warning
+    } else {
+        Severity::Info
+    };
+    Notification::new(
+        severity,
+        format!("Excess rewards report for {}", vote_pubkey),
+        body,
+    )
+}
+
+/// Runs `future` to completion, or until `deadline` passes when there is one.
+async fn before_deadline<F: Future>(deadline: Option<Instant>, future: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// Waits for the next recompute request, forever when the control API is disabled.
+async fn next_recompute(
+    receiver: &mut Option<mpsc::Receiver<RecomputeRequest>>,
+) -> Option<RecomputeRequest> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Scans every bond targeting the vote account, regardless of issuer, and notifies about the
+/// ones that weren't seen before.
+async fn notify_new_bonds(
+    rpc_client: &RpcClient,
+    program_ids: &[Pubkey],
+    args: &ValidatorPyeAccountManagerArgs,
+    fetch_options: &PyeAccountFetchOptions,
+    bond_watcher: &mut BondWatcher,
+    notifier: &Notifier,
+) {
+    let mut bonds = Vec::new();
+    for program_id in program_ids {
+        match fetch_solo_validator_pye_accounts_by_vote_key(
+            rpc_client,
+            program_id,
+            &args.vote_pubkey,
+            None,
+            true,
+            fetch_options,
+        )
+        .await
+        {
+            Ok(found) => bonds.extend(
+                found
+                    .into_iter()
+                    .map(|(pubkey, pye_account)| (*program_id, pubkey, pye_account)),
+            ),
+            Err(err) => {
+                warn!("Failed to scan for new pye_accounts: {}", err);
+                return;
             }
         }
-        flush();
+    }
+
+    let new_bonds = bond_watcher.observe(bonds.iter().map(|(_, pubkey, _)| *pubkey));
+    for (program_id, pye_account_pubkey, pye_account) in bonds
+        .iter()
+        .filter(|(_, pubkey, _)| new_bonds.contains(pubkey))
+    {
+        let allowlisted = args.issuers.contains(&pye_account.issuer);
+        let commissions = &pye_account.reward_commissions;
+        datapoint_info!(
+            "new_pye_account",
+            ("vote_pubkey", args.vote_pubkey.to_string(), String),
+            ("program_id", program_id.to_string(), String),
+            ("pye_account", pye_account_pubkey.to_string(), String),
+            ("issuer", pye_account.issuer.to_string(), String),
+            ("issuer_allowlisted", allowlisted, bool),
+        );
+        let maturity = chrono::DateTime::from_timestamp(pye_account.maturity_ts, 0)
+            .map(|ts| ts.to_rfc3339())
+            .unwrap_or_else(|| pye_account.maturity_ts.to_string());
+        let (severity, issuer_note) = if allowlisted {
+            (Severity::Info, "")
+        } else {
+            (
+                Severity::Warning,
+                " (not in --issuers, excess rewards will not be paid)",
+            )
+        };
+        let body = [
+            format!("Program: {}", program_id),
+            format!("Issuer: {}{}", labelled(&pye_account.issuer), issuer_note),
+            format!("Inflation commission: {} bps", commissions.inflation_bps),
+            format!("MEV commission: {} bps", commissions.mev_tips_bps),
+            format!(
+                "Block rewards commission: {} bps",
+                commissions.block_rewards_bps
+            ),
+            format!("Maturity: {}", maturity),
+        ]
+        .join("\n");
+        notifier
+            .notify(Notification::new(
+                severity,
+                format!(
+                    "New pye_account {} targeting {}",
+                    labelled(&pye_account_pubkey),
+                    labelled(&args.vote_pubkey)
+                ),
+                body,
+            ))
+            .await;
     }
 }
@@ -0,0 +1,41 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::attestations::SignedAttestation;
+
+#[derive(Clone, Debug, Parser)]
+pub struct VerifyAttestationArgs {
+    /// Attestation file written by validator-pye-account-manager --attestation-dir.
+    #[arg(long, env)]
+    file: PathBuf,
+    /// The operator's payer pubkey the attestation must be signed by.
+    #[arg(long, env)]
+    signer: Option<Pubkey>,
+}
+
+pub async fn handle_verify_attestation(args: VerifyAttestationArgs) -> Result<()> {
+    let contents = fs::read(&args.file)
+        .map_err(|e| anyhow!("Failed to read {}: {}", args.file.display(), e))?;
+    let signed: SignedAttestation = serde_json::from_slice(&contents)
+        .map_err(|e| anyhow!("Invalid attestation file {}: {}", args.file.display(), e))?;
+    let attestation = signed.verify()?;
+    if let Some(signer) = args.signer {
+        if signed.signer != signer.to_string() {
+            return Err(anyhow!(
+                "Attestation is signed by {}, expected {}",
+                signed.signer,
+                signer
+            ));
+        }
+    }
+    println!("Valid attestation signed by {}", signed.signer);
+    println!("{}", serde_json::to_string_pretty(&attestation)?);
+    if args.signer.is_none() {
+        println!("\nPass --signer to also check the signer is the operator's payer.");
+    }
+    Ok(())
+}
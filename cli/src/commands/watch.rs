@@ -0,0 +1,628 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use futures::StreamExt;
+use log::{info, warn};
+use pye_core_cpi::pye_core::accounts::SoloValidatorBond as SoloValidatorPyeAccount;
+use serde::Serialize;
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient};
+use solana_client::rpc_config::{
+    RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcTransactionConfig, RpcTransactionLogsConfig,
+    RpcTransactionLogsFilter,
+};
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status_client_types::option_serializer::OptionSerializer;
+use solana_transaction_status_client_types::UiTransactionEncoding;
+use tokio::sync::mpsc;
+
+use crate::{
+    accounts::{
+        fetch_solo_validator_pye_accounts_by_vote_key, pye_accounts_filters, GpaChunking,
+        PyeAccountFetchOptions,
+    },
+    bond_schema::{decode_solo_validator_bond, known_discriminators},
+    cluster::ClusterPreset,
+    epoch_boundary::default_ws_url,
+    metrics::datapoint_info,
+    notifications::{Notification, NotificationArgs, Notifier, Severity},
+    rpc_utils::new_rpc_client,
+    sol_format::format_sol,
+};
+
+/// Wait before reconnecting a dropped subscription.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Instructions logged by Anchor when SOL or stake is deposited into a bond.
+const DEPOSIT_INSTRUCTIONS: [&str; 2] = [
+    "Instruction: SoloValidatorDepositSol",
+    "Instruction: SoloValidatorDepositStake",
+];
+
+#[derive(Clone, Debug, Parser)]
+pub struct WatchArgs {
+    /// Cluster preset selecting the default RPC endpoint and program ID.
+    #[arg(long, env, value_enum, default_value_t = ClusterPreset::Mainnet)]
+    cluster: ClusterPreset,
+    /// RPC Endpoint [default: the cluster preset's endpoint]
+    #[arg(short, long, env)]
+    rpc: Option<String>,
+    /// Websocket endpoint of the RPC [default: derived from --rpc]
+    #[arg(long, env)]
+    ws_url: Option<String>,
+    /// The Pye program ID [default: the cluster preset's program ID]. Repeat to watch several
+    /// programs.
+    #[arg(long = "program-id", env = "PROGRAM_ID", value_delimiter = ',')]
+    program_ids: Vec<Pubkey>,
+    /// Validator's vote account
+    #[arg(short, long, env)]
+    vote_pubkey: Pubkey,
+    /// Print each event as a JSON line instead of text.
+    #[arg(long, env)]
+    json: bool,
+    #[command(flatten)]
+    notifications: NotificationArgs,
+}
+
+/// A change in the lifecycle of a bond targeting the watched vote account.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BondEvent {
+    /// A bond was initialized.
+    Created {
+        pye_account: String,
+        issuer: String,
+        maturity_ts: i64,
+    },
+    /// SOL or stake was deposited into the bond's stake accounts.
+    Deposit {
+        pye_account: String,
+        lamports: u64,
+        signature: String,
+    },
+    /// The bond's maturity was handled, so its tokens can be redeemed.
+    Matured { pye_account: String },
+    /// The bond's stake was withdrawn or its account closed.
+    Closed { pye_account: String },
+}
+
+impl BondEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            BondEvent::Created { .. } => "created",
+            BondEvent::Deposit { .. } => "deposit",
+            BondEvent::Matured { .. } => "matured",
+            BondEvent::Closed { .. } => "closed",
+        }
+    }
+
+    fn pye_account(&self) -> &str {
+        match self {
+            BondEvent::Created { pye_account, .. }
+            | BondEvent::Deposit { pye_account, .. }
+            | BondEvent::Matured { pye_account }
+            | BondEvent::Closed { pye_account } => pye_account,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            BondEvent::Created {
+                pye_account,
+                issuer,
+                maturity_ts,
+            } => {
+                let maturity = DateTime::from_timestamp(*maturity_ts, 0)
+                    .map(|ts| ts.to_rfc3339())
+                    .unwrap_or_else(|| maturity_ts.to_string());
+                format!(
+                    "New bond {} from issuer {}, maturing {}",
+                    pye_account, issuer, maturity
+                )
+            }
+            BondEvent::Deposit {
+                pye_account,
+                lamports,
+                signature,
+            } => format!(
+                "{} deposited into bond {} ({})",
+                format_sol(*lamports),
+                pye_account,
+                signature
+            ),
+            BondEvent::Matured { pye_account } => format!("Bond {} matured", pye_account),
+            BondEvent::Closed { pye_account } => format!("Bond {} closed", pye_account),
+        }
+    }
+}
+
+/// Bonds targeting the watched vote account as last seen, diffed against each update to derive
+/// events.
+#[derive(Debug, Default)]
+struct WatchState {
+    bonds: HashMap<Pubkey, (Pubkey, SoloValidatorPyeAccount)>,
+}
+
+impl WatchState {
+    /// Records a bond's latest contents, or its closure when None.
+    fn update_bond(
+        &mut self,
+        program_id: &Pubkey,
+        pubkey: Pubkey,
+        bond: Option<SoloValidatorPyeAccount>,
+    ) -> Vec<BondEvent> {
+        let pye_account = pubkey.to_string();
+        let Some(bond) = bond else {
+            return match self.bonds.remove(&pubkey) {
+                Some((_, previous)) if !previous.stake_withdrawn => {
+                    vec![BondEvent::Closed { pye_account }]
+                }
+                _ => vec![],
+            };
+        };
+        let mut events = Vec::new();
+        let previous = self.bonds.get(&pubkey).map(|(_, previous)| previous);
+        if previous.is_none() {
+            events.push(BondEvent::Created {
+                pye_account: pye_account.clone(),
+                issuer: bond.issuer.to_string(),
+                maturity_ts: bond.maturity_ts,
+            });
+        }
+        if bond.maturity_handled && previous.is_none_or(|previous| !previous.maturity_handled) {
+            events.push(BondEvent::Matured {
+                pye_account: pye_account.clone(),
+            });
+        }
+        if bond.stake_withdrawn && previous.is_none_or(|previous| !previous.stake_withdrawn) {
+            events.push(BondEvent::Closed { pye_account });
+        }
+        self.bonds.insert(pubkey, (*program_id, bond));
+        events
+    }
+
+    /// Diffs a full fetch of `program_id`'s bonds, catching up on anything missed while a
+    /// subscription was down.
+    fn resync(
+        &mut self,
+        program_id: &Pubkey,
+        bonds: Vec<(Pubkey, SoloValidatorPyeAccount)>,
+    ) -> Vec<BondEvent> {
+        let mut closed: Vec<Pubkey> = self
+            .bonds
+            .iter()
+            .filter(|(pubkey, (program, _))| {
+                program == program_id && !bonds.iter().any(|(fetched, _)| fetched == *pubkey)
+            })
+            .map(|(pubkey, _)| *pubkey)
+            .collect();
+        closed.sort();
+        let mut events: Vec<BondEvent> = closed
+            .into_iter()
+            .flat_map(|pubkey| self.update_bond(program_id, pubkey, None))
+            .collect();
+        for (pubkey, bond) in bonds {
+            events.extend(self.update_bond(program_id, pubkey, Some(bond)));
+        }
+        events
+    }
+
+    /// Deposits made into known bonds by a transaction, from the balance changes of their stake
+    /// accounts. `account_keys` are in the order of the balances.
+    fn deposits(
+        &self,
+        signature: &Signature,
+        account_keys: &[Pubkey],
+        pre_balances: &[u64],
+        post_balances: &[u64],
+    ) -> Vec<BondEvent> {
+        let balance_change = |account: &Pubkey| {
+            let index = account_keys.iter().position(|key| key == account)?;
+            Some(
+                post_balances
+                    .get(index)?
+                    .saturating_sub(*pre_balances.get(index)?),
+            )
+        };
+        let mut events = Vec::new();
+        for key in account_keys {
+            let Some((_, bond)) = self.bonds.get(key) else {
+                continue;
+            };
+            let lamports: u64 = [bond.stake_account, bond.transient_stake_account]
+                .iter()
+                .filter_map(balance_change)
+                .sum();
+            if lamports > 0 {
+                events.push(BondEvent::Deposit {
+                    pye_account: key.to_string(),
+                    lamports,
+                    signature: signature.to_string(),
+                });
+            }
+        }
+        events
+    }
+}
+
+/// Something seen on a subscription.
+enum Update {
+    /// A bond account changed; None when it was closed.
+    Bond(Pubkey, Pubkey, Option<SoloValidatorPyeAccount>),
+    /// A transaction deposited into one of the program's bonds.
+    Deposit(Signature),
+    /// A subscription to the program was (re)established and may have missed updates.
+    Resync(Pubkey),
+}
+
+pub async fn handle_watch(args: WatchArgs) -> Result<()> {
+    let rpc = args
+        .rpc
+        .clone()
+        .unwrap_or_else(|| args.cluster.rpc_url().to_string());
+    let ws_url = match &args.ws_url {
+        Some(ws_url) => ws_url.clone(),
+        None => default_ws_url(&rpc)?,
+    };
+    let program_ids = if args.program_ids.is_empty() {
+        vec![args.cluster.program_id()]
+    } else {
+        args.program_ids.clone()
+    };
+    let client = new_rpc_client(rpc, CommitmentConfig::confirmed(), None, None);
+    let notifier = Notifier::from_args(&args.notifications)?;
+
+    let mut state = WatchState::default();
+    for program_id in program_ids.iter() {
+        // The first snapshot only seeds the state, existing bonds aren't events.
+        state.resync(
+            program_id,
+            fetch_bonds(&client, program_id, &args.vote_pubkey).await?,
+        );
+    }
+    info!(
+        "Watching {} bonds targeting {} on {}",
+        state.bonds.len(),
+        args.vote_pubkey,
+        ws_url
+    );
+
+    let (sender, mut updates) = mpsc::unbounded_channel();
+    for program_id in program_ids.iter() {
+        let (ws_url, program_id, vote_pubkey, sender) = (
+            ws_url.clone(),
+            *program_id,
+            args.vote_pubkey,
+            sender.clone(),
+        );
+        tokio::spawn(async move {
+            while !sender.is_closed() {
+                if let Err(e) = forward_bonds(&ws_url, &program_id, &vote_pubkey, &sender).await {
+                    warn!("Bond subscription to {} dropped: {}", ws_url, e);
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        });
+        let (ws_url, sender) = (ws_url.clone(), sender.clone());
+        tokio::spawn(async move {
+            while !sender.is_closed() {
+                if let Err(e) = forward_deposits(&ws_url, &program_id, &sender).await {
+                    warn!("Log subscription to {} dropped: {}", ws_url, e);
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        });
+    }
+    drop(sender);
+
+    while let Some(update) = updates.recv().await {
+        let events = match update {
+            Update::Bond(program_id, pubkey, bond) => state.update_bond(&program_id, pubkey, bond),
+            Update::Resync(program_id) => {
+                match fetch_bonds(&client, &program_id, &args.vote_pubkey).await {
+                    Ok(bonds) => state.resync(&program_id, bonds),
+                    Err(e) => {
+                        warn!("Failed to resync the bonds of {}: {}", program_id, e);
+                        continue;
+                    }
+                }
+            }
+            Update::Deposit(signature) => match fetch_deposits(&client, &state, &signature).await {
+                Ok(events) => events,
+                Err(e) => {
+                    warn!("Failed to fetch deposit transaction {}: {}", signature, e);
+                    continue;
+                }
+            },
+        };
+        for event in events {
+            report(&args, &notifier, &event).await;
+        }
+    }
+    Err(anyhow!("All subscriptions stopped"))
+}
+
+async fn fetch_bonds(
+    client: &RpcClient,
+    program_id: &Pubkey,
+    vote_pubkey: &Pubkey,
+) -> Result<Vec<(Pubkey, SoloValidatorPyeAccount)>> {
+    fetch_solo_validator_pye_accounts_by_vote_key(
+        client,
+        program_id,
+        vote_pubkey,
+        None,
+        false,
+        &PyeAccountFetchOptions {
+            strict: false,
+            chunking: GpaChunking::Auto,
+            concurrency: 1,
+        },
+    )
+    .await
+}
+
+/// Forwards changes to `program_id`'s bonds targeting `vote_pubkey`, one subscription per known
+/// layout, until a subscription drops.
+async fn forward_bonds(
+    ws_url: &str,
+    program_id: &Pubkey,
+    vote_pubkey: &Pubkey,
+    sender: &mpsc::UnboundedSender<Update>,
+) -> Result<()> {
+    let client = PubsubClient::new(ws_url).await?;
+    let mut streams = Vec::new();
+    for discriminator in known_discriminators() {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(pye_accounts_filters(
+                discriminator,
+                vote_pubkey,
+                None,
+                false,
+            )),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice: None,
+                commitment: Some(CommitmentConfig::confirmed()),
+                min_context_slot: None,
+            },
+            with_context: None,
+            sort_results: None,
+        };
+        let (stream, _unsubscribe) = client.program_subscribe(program_id, Some(config)).await?;
+        streams.push(stream);
+    }
+    info!("Subscribed to the bonds of {} on {}", program_id, ws_url);
+    if sender.send(Update::Resync(*program_id)).is_err() {
+        return Ok(());
+    }
+    let mut accounts = futures::stream::select_all(streams);
+    while let Some(response) = accounts.next().await {
+        let keyed = response.value;
+        let pubkey = Pubkey::from_str(&keyed.pubkey)?;
+        let Some(account) = keyed.account.decode::<Account>() else {
+            warn!("Failed to decode the account data of bond {}", pubkey);
+            continue;
+        };
+        let bond = if account.lamports == 0 {
+            None
+        } else {
+            match decode_solo_validator_bond(&account.data) {
+                Ok((_, bond)) => Some(bond),
+                Err(e) => {
+                    warn!("Skipping bond {} that failed to deserialize: {}", pubkey, e);
+                    continue;
+                }
+            }
+        };
+        if sender
+            .send(Update::Bond(*program_id, pubkey, bond))
+            .is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Forwards the signatures of successful `program_id` transactions depositing into a bond, until
+/// the subscription drops. Logs don't say which bond, that's read from the transaction.
+async fn forward_deposits(
+    ws_url: &str,
+    program_id: &Pubkey,
+    sender: &mpsc::UnboundedSender<Update>,
+) -> Result<()> {
+    let client = PubsubClient::new(ws_url).await?;
+    let (mut logs, unsubscribe) = client
+        .logs_subscribe(
+            RpcTransactionLogsFilter::Mentions(vec![program_id.to_string()]),
+            RpcTransactionLogsConfig {
+                commitment: Some(CommitmentConfig::confirmed()),
+            },
+        )
+        .await?;
+    info!("Subscribed to the logs of {} on {}", program_id, ws_url);
+    while let Some(response) = logs.next().await {
+        let logs = response.value;
+        let deposit = logs.logs.iter().any(|line| {
+            DEPOSIT_INSTRUCTIONS
+                .iter()
+                .any(|instruction| line.ends_with(instruction))
+        });
+        if logs.err.is_some() || !deposit {
+            continue;
+        }
+        let signature = Signature::from_str(&logs.signature)?;
+        if sender.send(Update::Deposit(signature)).is_err() {
+            break;
+        }
+    }
+    unsubscribe().await;
+    Ok(())
+}
+
+async fn fetch_deposits(
+    client: &RpcClient,
+    state: &WatchState,
+    signature: &Signature,
+) -> Result<Vec<BondEvent>> {
+    let transaction = client
+        .get_transaction_with_config(
+            signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await?;
+    let meta = transaction
+        .transaction
+        .meta
+        .ok_or_else(|| anyhow!("The RPC served no transaction status"))?;
+    let decoded = transaction
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| anyhow!("Failed to decode the transaction"))?;
+    // Balances list the static keys, then the writable and readonly keys loaded from lookup
+    // tables.
+    let mut account_keys = decoded.message.static_account_keys().to_vec();
+    if let OptionSerializer::Some(loaded) = &meta.loaded_addresses {
+        for key in loaded.writable.iter().chain(loaded.readonly.iter()) {
+            account_keys.push(Pubkey::from_str(key)?);
+        }
+    }
+    Ok(state.deposits(
+        signature,
+        &account_keys,
+        &meta.pre_balances,
+        &meta.post_balances,
+    ))
+}
+
+async fn report(args: &WatchArgs, notifier: &Notifier, event: &BondEvent) {
+    if args.json {
+        let line = serde_json::json!({
+            "vote_pubkey": args.vote_pubkey.to_string(),
+            "timestamp": Utc::now().timestamp(),
+            "event": event,
+        });
+        println!("{}", line);
+    } else {
+        println!("{} {}", Utc::now().to_rfc3339(), event.describe());
+    }
+    datapoint_info!(
+        "bond_event",
+        ("vote_pubkey", args.vote_pubkey.to_string(), String),
+        ("event", event.name().to_string(), String),
+        ("pye_account", event.pye_account().to_string(), String),
+    );
+    notifier
+        .notify(Notification::new(
+            Severity::Info,
+            event.describe(),
+            format!("Vote account {}", args.vote_pubkey),
+        ))
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bond_schema::zeroed_bond;
+
+    fn bond() -> SoloValidatorPyeAccount {
+        let mut bond = zeroed_bond();
+        bond.stake_account = Pubkey::new_unique();
+        bond.transient_stake_account = Pubkey::new_unique();
+        bond
+    }
+
+    #[test]
+    fn test_bond_lifecycle_events() {
+        let program_id = Pubkey::new_unique();
+        let pubkey = Pubkey::new_unique();
+        let pye_account = pubkey.to_string();
+        let mut state = WatchState::default();
+        let mut bond = bond();
+        assert!(matches!(
+            state
+                .update_bond(&program_id, pubkey, Some(bond.clone()))
+                .as_slice(),
+            [BondEvent::Created { .. }]
+        ));
+        assert!(state
+            .update_bond(&program_id, pubkey, Some(bond.clone()))
+            .is_empty());
+
+        bond.maturity_handled = true;
+        assert_eq!(
+            state.update_bond(&program_id, pubkey, Some(bond.clone())),
+            vec![BondEvent::Matured {
+                pye_account: pye_account.clone()
+            }]
+        );
+        bond.stake_withdrawn = true;
+        assert_eq!(
+            state.update_bond(&program_id, pubkey, Some(bond)),
+            vec![BondEvent::Closed { pye_account }]
+        );
+        // A withdrawn bond whose account is then closed was already reported.
+        assert!(state.update_bond(&program_id, pubkey, None).is_empty());
+    }
+
+    #[test]
+    fn test_resync_reports_missing_bonds_as_closed() {
+        let program_id = Pubkey::new_unique();
+        let (kept, closed) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut state = WatchState::default();
+        state.resync(&program_id, vec![(kept, bond()), (closed, bond())]);
+        let other_program = Pubkey::new_unique();
+        state.resync(&other_program, vec![]);
+
+        let kept_bond = state.bonds[&kept].1.clone();
+        assert_eq!(
+            state.resync(&program_id, vec![(kept, kept_bond)]),
+            vec![BondEvent::Closed {
+                pye_account: closed.to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_deposits_from_stake_balances() {
+        let program_id = Pubkey::new_unique();
+        let pubkey = Pubkey::new_unique();
+        let bond = bond();
+        let mut state = WatchState::default();
+        state.update_bond(&program_id, pubkey, Some(bond.clone()));
+
+        let depositor = Pubkey::new_unique();
+        let account_keys = [depositor, pubkey, bond.stake_account];
+        let signature = Signature::default();
+        assert_eq!(
+            state.deposits(
+                &signature,
+                &account_keys,
+                &[10_000_000_000, 1_000_000, 5_000_000_000],
+                &[7_999_995_000, 1_000_000, 7_000_000_000],
+            ),
+            vec![BondEvent::Deposit {
+                pye_account: pubkey.to_string(),
+                lamports: 2_000_000_000,
+                signature: signature.to_string(),
+            }]
+        );
+        // Transactions not touching a known bond are ignored.
+        assert!(state
+            .deposits(&signature, &[depositor], &[1], &[2])
+            .is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+use log::{info, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::metrics::datapoint_error;
+use crate::notifications::{Notification, Notifier, Severity};
+use crate::rewards::commission_history::fetch_vote_commission;
+
+/// Jito's tip distribution program, which holds the validator's MEV commission for each epoch.
+pub const TIP_DISTRIBUTION_PROGRAM_ID: Pubkey =
+    pubkey!("4R3gSG8BpU4t19KYj8CfnbtRpnT8gtk4dvTHxVRwc2r7");
+/// Offset of the `Option<MerkleRoot>` tag, after the discriminator, vote account and merkle root
+/// upload authority.
+const MERKLE_ROOT_TAG_OFFSET: usize = 72;
+/// Serialized size of a `MerkleRoot`: root, max_total_claim, max_num_nodes, total_funds_claimed
+/// and num_nodes_claimed.
+const MERKLE_ROOT_LEN: usize = 64;
+
+/// The tip distribution account the validator creates for `epoch`.
+pub fn tip_distribution_address(vote_pubkey: &Pubkey, epoch: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"TIP_DISTRIBUTION_ACCOUNT",
+            vote_pubkey.as_ref(),
+            &epoch.to_le_bytes(),
+        ],
+        &TIP_DISTRIBUTION_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Reads `validator_commission_bps` from a tip distribution account, which follows the optional
+/// merkle root and `epoch_created_at`.
+pub fn parse_tip_distribution_commission_bps(data: &[u8]) -> Option<u16> {
+    let offset = match data.get(MERKLE_ROOT_TAG_OFFSET)? {
+        0 => MERKLE_ROOT_TAG_OFFSET + 1,
+        1 => MERKLE_ROOT_TAG_OFFSET + 1 + MERKLE_ROOT_LEN,
+        _ => return None,
+    } + 8;
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+/// The validator's commissions as last observed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommissionSnapshot {
+    /// Inflation commission (0-100) from the vote account.
+    pub inflation: Option<u8>,
+    /// MEV commission (0-10_000) from the current epoch's tip distribution account.
+    pub mev_bps: Option<u16>,
+}
+
+impl CommissionSnapshot {
+    /// Keeps previously observed values for commissions that couldn't be read this time.
+    fn or(self, previous: CommissionSnapshot) -> Self {
+        Self {
+            inflation: self.inflation.or(previous.inflation),
+            mev_bps: self.mev_bps.or(previous.mev_bps),
+        }
+    }
+}
+
+/// Describes every commission that went up between two observations.
+pub fn commission_increases(
+    previous: &CommissionSnapshot,
+    current: &CommissionSnapshot,
+) -> Vec<String> {
+    let mut increases = Vec::new();
+    if let (Some(before), Some(after)) = (previous.inflation, current.inflation) {
+        if after > before {
+            increases.push(format!("Inflation commission {}% -> {}%", before, after));
+        }
+    }
+    if let (Some(before), Some(after)) = (previous.mev_bps, current.mev_bps) {
+        if after > before {
+            increases.push(format!("MEV commission {} bps -> {} bps", before, after));
+        }
+    }
+    increases
+}
+
+/// Watches the validator's commissions every cycle and pages when one jumps, since every bond
+/// holder is owed the difference.
+pub struct CommissionGuard {
+    vote_pubkey: Pubkey,
+    check_mev: bool,
+    last: Option<CommissionSnapshot>,
+}
+
+impl CommissionGuard {
+    pub fn new(vote_pubkey: Pubkey, check_mev: bool) -> Self {
+        Self {
+            vote_pubkey,
+            check_mev,
+            last: None,
+        }
+    }
+
+    async fn snapshot(&self, client: &RpcClient, epoch: u64) -> CommissionSnapshot {
+        let inflation = match fetch_vote_commission(client, &self.vote_pubkey).await {
+            Ok(commission) => Some(commission),
+            Err(e) => {
+                warn!("Commission guard: {}", e);
+                None
+            }
+        };
+        let mev_bps = if self.check_mev {
+            let address = tip_distribution_address(&self.vote_pubkey, epoch);
+            match client.get_account(&address).await {
+                Ok(account) => parse_tip_distribution_commission_bps(&account.data),
+                // The account only exists once the validator has been leader this epoch.
+                Err(_) => None,
+            }
+        } else {
+            None
+        };
+        CommissionSnapshot { inflation, mev_bps }
+    }
+
+    pub async fn check(&mut self, client: &RpcClient, epoch: u64, notifier: &Notifier) {
+        let current = self.snapshot(client, epoch).await;
+        let Some(previous) = self.last else {
+            info!(
+                "Commission guard watching {}: {:?}",
+                self.vote_pubkey, current
+            );
+            self.last = Some(current);
+            return;
+        };
+        let current = current.or(previous);
+        self.last = Some(current);
+
+        let increases = commission_increases(&previous, &current);
+        if increases.is_empty() {
+            return;
+        }
+        datapoint_error!(
+            "commission_increase",
+            ("vote_pubkey", self.vote_pubkey.to_string(), String),
+            ("epoch", epoch.to_string(), String),
+            (
+                "inflation_commission",
+                current.inflation.map_or(-1, i64::from),
+                i64
+            ),
+            (
+                "mev_commission_bps",
+                current.mev_bps.map_or(-1, i64::from),
+                i64
+            ),
+        );
+        notifier
+            .notify(Notification::new(
+                Severity::Critical,
+                format!("Commission increase on {}", self.vote_pubkey),
+                format!(
+                    "{}\nEvery bond holder is owed the excess above the bond's commission terms.",
+                    increases.join("\n")
+                ),
+            ))
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tip_distribution_data(merkle_root: bool, commission_bps: u16) -> Vec<u8> {
+        let mut data = vec![0u8; MERKLE_ROOT_TAG_OFFSET];
+        if merkle_root {
+            data.push(1);
+            data.extend([0u8; MERKLE_ROOT_LEN]);
+        } else {
+            data.push(0);
+        }
+        data.extend(42u64.to_le_bytes());
+        data.extend(commission_bps.to_le_bytes());
+        data.extend([0u8; 9]);
+        data
+    }
+
+    #[test]
+    fn test_parse_commission_without_merkle_root() {
+        let data = tip_distribution_data(false, 800);
+        assert_eq!(parse_tip_distribution_commission_bps(&data), Some(800));
+    }
+
+    #[test]
+    fn test_parse_commission_with_merkle_root() {
+        let data = tip_distribution_data(true, 10_000);
+        assert_eq!(parse_tip_distribution_commission_bps(&data), Some(10_000));
+    }
+
+    #[test]
+    fn test_parse_commission_truncated() {
+        assert_eq!(parse_tip_distribution_commission_bps(&[0u8; 40]), None);
+    }
+
+    #[test]
+    fn test_commission_increases() {
+        let previous = CommissionSnapshot {
+            inflation: Some(5),
+            mev_bps: Some(800),
+        };
+        let current = CommissionSnapshot {
+            inflation: Some(100),
+            mev_bps: Some(800),
+        };
+        assert_eq!(
+            commission_increases(&previous, &current),
+            vec!["Inflation commission 5% -> 100%".to_string()]
+        );
+        assert!(commission_increases(&current, &previous).is_empty());
+    }
+
+    #[test]
+    fn test_missing_observation_keeps_previous() {
+        let previous = CommissionSnapshot {
+            inflation: Some(5),
+            mev_bps: Some(800),
+        };
+        let current = CommissionSnapshot {
+            inflation: Some(5),
+            mev_bps: None,
+        }
+        .or(previous);
+        assert_eq!(current, previous);
+    }
+}
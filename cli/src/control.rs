@@ -0,0 +1,547 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use log::{error, info, warn};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Response};
+
+use crate::bond_state::BondState;
+use crate::dashboard::RewardBreakdown;
+use crate::transactions::{Payment, PaymentVerification};
+
+pub mod proto {
+    tonic::include_proto!("pye.manager.v1");
+}
+
+use proto::manager_client::ManagerClient;
+use proto::manager_server::{Manager, ManagerServer};
+
+/// Epochs whose reward breakdowns are kept for GetRewardBreakdowns.
+const MAX_BREAKDOWN_EPOCHS: usize = 32;
+
+/// What happened to the excess rewards computed for a bond.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaymentOutcome {
+    NotDue,
+    Paid(Payment),
+    DryRun,
+    Paused,
+    NotRequested,
+    /// The excess rewards exceeded --max-payment-lamports and were withheld.
+    OverCap,
+    /// A previous run stopped while sending the payment, which wasn't sent again.
+    Interrupted,
+    /// The bond was closed, matured or otherwise changed since discovery and wasn't paid.
+    BondChanged,
+    /// Paying the excess rewards would have exceeded a spending limit, so they are pending
+    /// approval.
+    OverLimit,
+    /// The operator left the payment out when reviewing the epoch's transfers.
+    Declined,
+}
+
+impl fmt::Display for PaymentOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentOutcome::NotDue => write!(f, "not_due"),
+            PaymentOutcome::Paid(_) => write!(f, "paid"),
+            PaymentOutcome::DryRun => write!(f, "dry_run"),
+            PaymentOutcome::Paused => write!(f, "paused"),
+            PaymentOutcome::NotRequested => write!(f, "not_requested"),
+            PaymentOutcome::OverCap => write!(f, "over_cap"),
+            PaymentOutcome::Interrupted => write!(f, "interrupted"),
+            PaymentOutcome::BondChanged => write!(f, "bond_changed"),
+            PaymentOutcome::OverLimit => write!(f, "over_limit"),
+            PaymentOutcome::Declined => write!(f, "declined"),
+        }
+    }
+}
+
+impl PaymentOutcome {
+    pub fn signature(&self) -> Option<Signature> {
+        match self {
+            PaymentOutcome::Paid(payment) => Some(payment.signature),
+            _ => None,
+        }
+    }
+
+    /// Fee charged for the payment transaction, if paid and the fee is known.
+    pub fn fee(&self) -> Option<u64> {
+        match self {
+            PaymentOutcome::Paid(payment) => payment.fee,
+            _ => None,
+        }
+    }
+
+    /// Slot the payment transaction was confirmed in, if paid and the slot is known.
+    pub fn slot(&self) -> Option<u64> {
+        match self {
+            PaymentOutcome::Paid(payment) => payment.slot,
+            _ => None,
+        }
+    }
+
+    /// The check of the payment against the finalized ledger, if paid and it wasn't skipped.
+    pub fn verification(&self) -> Option<PaymentVerification> {
+        match self {
+            PaymentOutcome::Paid(payment) => payment.verification,
+            _ => None,
+        }
+    }
+}
+
+/// Excess rewards computed for a bond over one epoch and what was done with them.
+#[derive(Clone, Debug)]
+pub struct BondRewardBreakdown {
+    pub program_id: Pubkey,
+    pub pye_account: Pubkey,
+    pub active_stake: u64,
+    pub rewards: RewardBreakdown,
+    pub payment: PaymentOutcome,
+}
+
+impl From<&BondRewardBreakdown> for proto::BondRewardBreakdown {
+    fn from(breakdown: &BondRewardBreakdown) -> Self {
+        let (payment, signature) = match breakdown.payment {
+            PaymentOutcome::NotDue => (proto::PaymentOutcome::NotDue, None),
+            PaymentOutcome::Paid(payment) => (
+                proto::PaymentOutcome::Paid,
+                Some(payment.signature.to_string()),
+            ),
+            PaymentOutcome::DryRun => (proto::PaymentOutcome::DryRun, None),
+            PaymentOutcome::Paused => (proto::PaymentOutcome::Paused, None),
+            PaymentOutcome::NotRequested => (proto::PaymentOutcome::NotRequested, None),
+            PaymentOutcome::OverCap => (proto::PaymentOutcome::OverCap, None),
+            PaymentOutcome::Interrupted => (proto::PaymentOutcome::Interrupted, None),
+            PaymentOutcome::BondChanged => (proto::PaymentOutcome::BondChanged, None),
+            PaymentOutcome::OverLimit => (proto::PaymentOutcome::OverLimit, None),
+            PaymentOutcome::Declined => (proto::PaymentOutcome::Declined, None),
+        };
+        Self {
+            program_id: breakdown.program_id.to_string(),
+            pye_account: breakdown.pye_account.to_string(),
+            active_stake: breakdown.active_stake,
+            excess_inflation_rewards: breakdown.rewards.inflation,
+            excess_mev_rewards: breakdown.rewards.mev,
+            excess_block_rewards: breakdown.rewards.block,
+            total_excess_rewards: breakdown.rewards.total(),
+            payment: payment.into(),
+            signature,
+        }
+    }
+}
+
+fn epoch_breakdowns(epoch: u64, bonds: &[BondRewardBreakdown]) -> proto::EpochRewardBreakdowns {
+    proto::EpochRewardBreakdowns {
+        epoch,
+        bonds: bonds.iter().map(Into::into).collect(),
+    }
+}
+
+/// The manager's progress as reported by GetStatus.
+#[derive(Clone, Debug, Default)]
+pub struct ManagerStatus {
+    pub current_epoch: u64,
+    pub last_processed_epoch: Option<u64>,
+    pub monitored_bonds: usize,
+    pub phase: String,
+    pub unfinished_bonds: Vec<BondState>,
+}
+
+/// A request to recompute, and optionally pay, a completed epoch. The manager loop answers it
+/// between cycles. Bonds whose payment memo for the epoch is already on chain aren't paid again.
+#[derive(Debug)]
+pub struct RecomputeRequest {
+    pub epoch: u64,
+    pub pay: bool,
+    pub respond_to: oneshot::Sender<Result<Vec<BondRewardBreakdown>, String>>,
+}
+
+#[derive(Debug, Default)]
+struct ControlState {
+    status: ManagerStatus,
+    breakdowns: BTreeMap<u64, Vec<BondRewardBreakdown>>,
+}
+
+/// State shared between the manager loop and the gRPC control API.
+#[derive(Clone, Debug)]
+pub struct ManagerControl {
+    vote_pubkey: Pubkey,
+    dry_run: bool,
+    /// Bearer token every request must carry. Recomputes that pay are refused without one.
+    token: Option<String>,
+    state: Arc<Mutex<ControlState>>,
+    paused: Arc<AtomicBool>,
+    recompute_tx: mpsc::Sender<RecomputeRequest>,
+}
+
+impl ManagerControl {
+    /// Returns the control handle and the receiver of recompute requests for the manager loop.
+    pub fn new(
+        vote_pubkey: Pubkey,
+        dry_run: bool,
+        token: Option<String>,
+    ) -> (Self, mpsc::Receiver<RecomputeRequest>) {
+        let (recompute_tx, recompute_rx) = mpsc::channel(8);
+        let control = Self {
+            vote_pubkey,
+            dry_run,
+            token,
+            state: Arc::new(Mutex::new(ControlState::default())),
+            paused: Arc::new(AtomicBool::new(false)),
+            recompute_tx,
+        };
+        (control, recompute_rx)
+    }
+
+    /// Checks the request's `authorization` metadata against the token, if one is configured.
+    fn authorize<T>(&self, request: &Request<T>) -> Result<(), tonic::Status> {
+        let Some(token) = &self.token else {
+            return Ok(());
+        };
+        let expected = format!("Bearer {}", token);
+        match request.metadata().get("authorization") {
+            Some(value) if constant_time_eq(value.as_bytes(), expected.as_bytes()) => Ok(()),
+            _ => Err(tonic::Status::unauthenticated(
+                "Invalid or missing control API token",
+            )),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, ControlState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn update_status(&self, update: impl FnOnce(&mut ManagerStatus)) {
+        update(&mut self.state().status);
+    }
+
+    /// Stores the breakdowns computed for `epoch`, replacing earlier ones for the same epoch.
+    pub fn record_breakdowns(&self, epoch: u64, breakdowns: Vec<BondRewardBreakdown>) {
+        let mut state = self.state();
+        state.breakdowns.insert(epoch, breakdowns);
+        while state.breakdowns.len() > MAX_BREAKDOWN_EPOCHS {
+            state.breakdowns.pop_first();
+        }
+    }
+
+    /// Adds breakdowns computed for `epoch` to the ones already stored, for a follow-up pass.
+    pub fn extend_breakdowns(&self, epoch: u64, breakdowns: Vec<BondRewardBreakdown>) {
+        let mut state = self.state();
+        state
+            .breakdowns
+            .entry(epoch)
+            .or_default()
+            .extend(breakdowns);
+        while state.breakdowns.len() > MAX_BREAKDOWN_EPOCHS {
+            state.breakdowns.pop_first();
+        }
+    }
+
+    pub fn payments_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Pauses payments until they are resumed through the API, for a review of the amounts.
+    pub fn pause_for_review(&self) {
+        if !self.paused.swap(true, Ordering::Relaxed) {
+            warn!("Payments paused pending approval");
+        }
+    }
+
+    fn set_payments_paused(&self, paused: bool) -> proto::PaymentsState {
+        if self.paused.swap(paused, Ordering::Relaxed) != paused {
+            warn!(
+                "Payments {} via the control API",
+                if paused { "paused" } else { "resumed" }
+            );
+        }
+        proto::PaymentsState {
+            payments_paused: paused,
+        }
+    }
+
+    /// Serves the gRPC control API on `listener` until the process exits.
+    pub async fn serve(self, listener: TcpListener) {
+        if let Ok(addr) = listener.local_addr() {
+            info!("Serving the gRPC control API on {}", addr);
+        }
+        if let Err(err) = tonic::transport::Server::builder()
+            .add_service(ManagerServer::new(self))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+        {
+            error!("gRPC control API stopped: {}", err);
+        }
+    }
+}
+
+/// Adds the control API token, if any, to a client's requests.
+#[derive(Clone, Debug)]
+pub struct TokenInterceptor(Option<MetadataValue<Ascii>>);
+
+impl Interceptor for TokenInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, tonic::Status> {
+        if let Some(authorization) = &self.0 {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.clone());
+        }
+        Ok(request)
+    }
+}
+
+pub type ControlClient = ManagerClient<InterceptedService<Channel, TokenInterceptor>>;
+
+/// Connects to the control API at `endpoint`, sending `token` with every request.
+pub async fn connect(endpoint: &str, token: Option<&str>) -> anyhow::Result<ControlClient> {
+    let authorization = token
+        .map(|token| format!("Bearer {}", token).parse())
+        .transpose()
+        .map_err(|_| anyhow::anyhow!("The control API token must be printable ASCII"))?;
+    let channel = Endpoint::from_shared(endpoint.to_string())
+        .map_err(|e| anyhow::anyhow!("Invalid endpoint {}: {}", endpoint, e))?
+        .connect()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to connect to {}: {}", endpoint, e))?;
+    Ok(ManagerClient::with_interceptor(
+        channel,
+        TokenInterceptor(authorization),
+    ))
+}
+
+#[tonic::async_trait]
+impl Manager for ManagerControl {
+    async fn get_status(
+        &self,
+        request: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<proto::Status>, tonic::Status> {
+        self.authorize(&request)?;
+        let state = self.state();
+        Ok(Response::new(proto::Status {
+            vote_pubkey: self.vote_pubkey.to_string(),
+            current_epoch: state.status.current_epoch,
+            last_processed_epoch: state.status.last_processed_epoch,
+            monitored_bonds: state.status.monitored_bonds as u32,
+            payments_paused: self.payments_paused(),
+            dry_run: self.dry_run,
+            phase: state.status.phase.clone(),
+            unfinished_bonds: state
+                .status
+                .unfinished_bonds
+                .iter()
+                .map(|bond| proto::BondState {
+                    epoch: bond.epoch,
+                    pye_account: bond.pye_account.clone(),
+                    stage: bond.stage.to_string(),
+                    updated_at: bond.updated_at,
+                    detail: bond.detail.clone(),
+                    pass: bond.pass.to_string(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_reward_breakdowns(
+        &self,
+        request: Request<proto::GetRewardBreakdownsRequest>,
+    ) -> Result<Response<proto::EpochRewardBreakdowns>, tonic::Status> {
+        self.authorize(&request)?;
+        let state = self.state();
+        let found = match request.into_inner().epoch {
+            Some(epoch) => state.breakdowns.get_key_value(&epoch),
+            None => state.breakdowns.last_key_value(),
+        };
+        let (epoch, bonds) =
+            found.ok_or_else(|| tonic::Status::not_found("No reward breakdowns for that epoch"))?;
+        Ok(Response::new(epoch_breakdowns(*epoch, bonds)))
+    }
+
+    async fn pause_payments(
+        &self,
+        request: Request<proto::PausePaymentsRequest>,
+    ) -> Result<Response<proto::PaymentsState>, tonic::Status> {
+        self.authorize(&request)?;
+        Ok(Response::new(self.set_payments_paused(true)))
+    }
+
+    async fn resume_payments(
+        &self,
+        request: Request<proto::ResumePaymentsRequest>,
+    ) -> Result<Response<proto::PaymentsState>, tonic::Status> {
+        self.authorize(&request)?;
+        Ok(Response::new(self.set_payments_paused(false)))
+    }
+
+    async fn recompute_epoch(
+        &self,
+        request: Request<proto::RecomputeEpochRequest>,
+    ) -> Result<Response<proto::EpochRewardBreakdowns>, tonic::Status> {
+        self.authorize(&request)?;
+        let request = request.into_inner();
+        if request.pay && self.token.is_none() {
+            return Err(tonic::Status::permission_denied(
+                "Paying through the control API needs the manager to run with --grpc-token",
+            ));
+        }
+        let current_epoch = self.state().status.current_epoch;
+        if request.epoch >= current_epoch {
+            return Err(tonic::Status::invalid_argument(format!(
+                "Epoch {} hasn't completed yet, the current epoch is {}",
+                request.epoch, current_epoch
+            )));
+        }
+        let (respond_to, response) = oneshot::channel();
+        self.recompute_tx
+            .send(RecomputeRequest {
+                epoch: request.epoch,
+                pay: request.pay,
+                respond_to,
+            })
+            .await
+            .map_err(|_| tonic::Status::unavailable("The manager is shutting down"))?;
+        let bonds = response
+            .await
+            .map_err(|_| tonic::Status::aborted("The manager dropped the recompute"))?
+            .map_err(tonic::Status::internal)?;
+        Ok(Response::new(epoch_breakdowns(request.epoch, &bonds)))
+    }
+}
+
+/// Compares `a` and `b` in time that depends only on their lengths, so the token can't be
+/// guessed byte by byte from response times.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breakdown(epoch: u64) -> BondRewardBreakdown {
+        BondRewardBreakdown {
+            program_id: Pubkey::new_unique(),
+            pye_account: Pubkey::new_unique(),
+            active_stake: 1_000,
+            rewards: RewardBreakdown {
+                epoch,
+                inflation: 10,
+                mev: 5,
+                block: -3,
+            },
+            payment: PaymentOutcome::Paid(Payment {
+                signature: Signature::default(),
+                fee: Some(5_000),
+                slot: Some(345_600_123),
+                verification: None,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_reward_breakdowns_defaults_to_latest_epoch() {
+        let (control, _rx) = ManagerControl::new(Pubkey::new_unique(), false, None);
+        for epoch in 0..(MAX_BREAKDOWN_EPOCHS as u64 + 2) {
+            control.record_breakdowns(epoch, vec![breakdown(epoch)]);
+        }
+        let latest = control
+            .get_reward_breakdowns(Request::new(proto::GetRewardBreakdownsRequest {
+                epoch: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(latest.epoch, MAX_BREAKDOWN_EPOCHS as u64 + 1);
+        assert_eq!(latest.bonds[0].total_excess_rewards, 12);
+        assert_eq!(latest.bonds[0].payment, proto::PaymentOutcome::Paid as i32);
+        // The oldest epochs were evicted.
+        let evicted = control
+            .get_reward_breakdowns(Request::new(proto::GetRewardBreakdownsRequest {
+                epoch: Some(0),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(evicted.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume_payments() {
+        let (control, _rx) = ManagerControl::new(Pubkey::new_unique(), false, None);
+        control
+            .pause_payments(Request::new(proto::PausePaymentsRequest {}))
+            .await
+            .unwrap();
+        assert!(control.payments_paused());
+        control
+            .resume_payments(Request::new(proto::ResumePaymentsRequest {}))
+            .await
+            .unwrap();
+        assert!(!control.payments_paused());
+    }
+
+    #[tokio::test]
+    async fn test_recompute_rejects_incomplete_epoch() {
+        let (control, _rx) = ManagerControl::new(Pubkey::new_unique(), false, None);
+        control.update_status(|status| status.current_epoch = 800);
+        let err = control
+            .recompute_epoch(Request::new(proto::RecomputeEpochRequest {
+                epoch: 800,
+                pay: false,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_token_is_required() {
+        let (control, _rx) =
+            ManagerControl::new(Pubkey::new_unique(), false, Some("secret".to_string()));
+        let err = control
+            .pause_payments(Request::new(proto::PausePaymentsRequest {}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::Unauthenticated);
+        assert!(!control.payments_paused());
+        let mut request = Request::new(proto::PausePaymentsRequest {});
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer secret".parse().unwrap());
+        control.pause_payments(request).await.unwrap();
+        assert!(control.payments_paused());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"Bearer secret", b"Bearer secret"));
+        assert!(!constant_time_eq(b"Bearer secreT", b"Bearer secret"));
+        assert!(!constant_time_eq(b"Bearer secret", b"Bearer secret2"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[tokio::test]
+    async fn test_paying_recompute_needs_token() {
+        let (control, _rx) = ManagerControl::new(Pubkey::new_unique(), false, None);
+        control.update_status(|status| status.current_epoch = 800);
+        let err = control
+            .recompute_epoch(Request::new(proto::RecomputeEpochRequest {
+                epoch: 799,
+                pay: true,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    }
+}
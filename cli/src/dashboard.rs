@@ -0,0 +1,490 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use pye_core_cpi::pye_core::accounts::SoloValidatorBond as SoloValidatorPyeAccount;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Table};
+use ratatui::Frame;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::epoch_info::EpochInfo;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::rewards::source::RewardCategory;
+use crate::sol_format::format_sol_rounded;
+
+/// Target slot time used to estimate the time left until the epoch boundary.
+const SLOT_DURATION: Duration = Duration::from_millis(400);
+/// Payments kept in the dashboard's history.
+const MAX_PAYMENTS: usize = 10;
+/// How often the terminal is redrawn and checked for key presses.
+const FRAME_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Excess rewards computed for a bond over one epoch, in lamports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RewardBreakdown {
+    pub epoch: u64,
+    pub inflation: i64,
+    pub mev: i64,
+    pub block: i64,
+}
+
+impl RewardBreakdown {
+    pub fn total(&self) -> i64 {
+        self.inflation + self.mev + self.block
+    }
+
+    /// Adds `lamports` to the excess rewards of `category`.
+    pub fn add(&mut self, category: RewardCategory, lamports: i64) {
+        match category {
+            RewardCategory::Inflation => self.inflation += lamports,
+            RewardCategory::Mev => self.mev += lamports,
+            RewardCategory::Block => self.block += lamports,
+        }
+    }
+
+    /// Scales the breakdown by the elapsed fraction of the current epoch, assuming the bond
+    /// accrues at the same rate as in the last computed epoch.
+    pub fn accrued(&self, progress: f64) -> Self {
+        let scale = |lamports: i64| (lamports as f64 * progress.clamp(0.0, 1.0)) as i64;
+        Self {
+            epoch: self.epoch,
+            inflation: scale(self.inflation),
+            mev: scale(self.mev),
+            block: scale(self.block),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct BondRow {
+    program_id: Pubkey,
+    issuer: Pubkey,
+    active_stake: Option<u64>,
+    last_breakdown: Option<RewardBreakdown>,
+}
+
+#[derive(Clone, Debug)]
+struct PaymentRow {
+    at: chrono::DateTime<chrono::Utc>,
+    epoch: u64,
+    pye_account: Pubkey,
+    lamports: u64,
+    outcome: String,
+}
+
+#[derive(Clone, Debug, Default)]
+struct RpcHealth {
+    last_ok: Option<Instant>,
+    latency: Option<Duration>,
+    last_error: Option<String>,
+    consecutive_failures: u32,
+}
+
+#[derive(Debug)]
+struct DashboardState {
+    vote_pubkey: Pubkey,
+    epoch_info: Option<(EpochInfo, Instant)>,
+    phase: String,
+    bonds: BTreeMap<Pubkey, BondRow>,
+    payments: VecDeque<PaymentRow>,
+    rpc: RpcHealth,
+}
+
+/// Live manager state rendered by the `--tui` dashboard. Clones share the same state, so the
+/// manager loop records progress while the UI thread draws it.
+#[derive(Clone, Debug)]
+pub struct Dashboard {
+    state: Arc<Mutex<DashboardState>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Dashboard {
+    pub fn new(vote_pubkey: Pubkey) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(DashboardState {
+                vote_pubkey,
+                epoch_info: None,
+                phase: "Starting".to_string(),
+                bonds: BTreeMap::new(),
+                payments: VecDeque::new(),
+                rpc: RpcHealth::default(),
+            })),
+            shutdown: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, DashboardState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Describes what the manager is currently doing.
+    pub fn set_phase(&self, phase: impl Into<String>) {
+        self.state().phase = phase.into();
+    }
+
+    /// Replaces the monitored bonds, keeping the stake and rewards already known for each.
+    pub fn set_bonds(&self, bonds: &[(Pubkey, Pubkey, SoloValidatorPyeAccount)]) {
+        let mut state = self.state();
+        let previous = std::mem::take(&mut state.bonds);
+        state.bonds = bonds
+            .iter()
+            .map(|(program_id, pubkey, pye_account)| {
+                let known = previous.get(pubkey);
+                let row = BondRow {
+                    program_id: *program_id,
+                    issuer: pye_account.issuer,
+                    active_stake: known.and_then(|row| row.active_stake),
+                    last_breakdown: known.and_then(|row| row.last_breakdown),
+                };
+                (*pubkey, row)
+            })
+            .collect();
+    }
+
+    /// Records a bond's active stake and excess rewards computed for an epoch.
+    pub fn record_breakdown(
+        &self,
+        pye_account: &Pubkey,
+        active_stake: u64,
+        breakdown: RewardBreakdown,
+    ) {
+        if let Some(row) = self.state().bonds.get_mut(pye_account) {
+            row.active_stake = Some(active_stake);
+            row.last_breakdown = Some(breakdown);
+        }
+    }
+
+    /// Adds a payment to the history, `outcome` being its signature or the error it failed with.
+    pub fn record_payment(&self, epoch: u64, pye_account: &Pubkey, lamports: u64, outcome: String) {
+        let mut state = self.state();
+        state.payments.push_front(PaymentRow {
+            at: chrono::Utc::now(),
+            epoch,
+            pye_account: *pye_account,
+            lamports,
+            outcome,
+        });
+        state.payments.truncate(MAX_PAYMENTS);
+    }
+
+    /// Polls the RPC for the current epoch every `interval`, tracking its latency and failures.
+    pub async fn poll_rpc(self, rpc_client: Arc<RpcClient>, interval: Duration) {
+        loop {
+            let started = Instant::now();
+            let result = rpc_client.get_epoch_info().await;
+            {
+                let mut state = self.state();
+                match result {
+                    Ok(epoch_info) => {
+                        state.epoch_info = Some((epoch_info, Instant::now()));
+                        state.rpc.last_ok = Some(Instant::now());
+                        state.rpc.latency = Some(started.elapsed());
+                        state.rpc.consecutive_failures = 0;
+                    }
+                    Err(err) => {
+                        state.rpc.last_error = Some(err.to_string());
+                        state.rpc.consecutive_failures += 1;
+                    }
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Asks the UI thread to restore the terminal and return.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+
+    /// Draws the dashboard until `q`, `Esc` or `Ctrl-C` is pressed or [`Dashboard::shutdown`] is
+    /// called. Blocks the calling thread, run it with `spawn_blocking`.
+    pub fn run_ui(self) -> Result<()> {
+        let mut terminal = ratatui::init();
+        let result = (|| -> Result<()> {
+            while !self.shutdown.load(Ordering::Relaxed) {
+                terminal.draw(|frame| render(frame, &self.state()))?;
+                if event::poll(FRAME_INTERVAL)? {
+                    if let Event::Key(key) = event::read()? {
+                        let ctrl_c = key.code == KeyCode::Char('c')
+                            && key.modifiers.contains(KeyModifiers::CONTROL);
+                        if key.kind == KeyEventKind::Press
+                            && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc))
+                        {
+                            break;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })();
+        ratatui::restore();
+        result
+    }
+}
+
+/// Estimates the time left in the epoch from the slots remaining when `epoch_info` was fetched.
+pub fn time_to_boundary(epoch_info: &EpochInfo, fetched_ago: Duration) -> Duration {
+    let remaining_slots = epoch_info
+        .slots_in_epoch
+        .saturating_sub(epoch_info.slot_index);
+    (SLOT_DURATION * remaining_slots as u32).saturating_sub(fetched_ago)
+}
+
+pub fn format_countdown(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!(
+        "{}h {:02}m {:02}s",
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
+
+fn epoch_progress(epoch_info: &EpochInfo) -> f64 {
+    if epoch_info.slots_in_epoch == 0 {
+        return 0.0;
+    }
+    (epoch_info.slot_index as f64 / epoch_info.slots_in_epoch as f64).clamp(0.0, 1.0)
+}
+
+fn sol(lamports: i64) -> String {
+    format_sol_rounded(lamports, 4)
+}
+
+fn render(frame: &mut Frame, state: &DashboardState) {
+    let [header, bonds, payments] = Layout::vertical([
+        Constraint::Length(6),
+        Constraint::Min(6),
+        Constraint::Length(MAX_PAYMENTS as u16 + 3),
+    ])
+    .areas(frame.area());
+    let [epoch_area, rpc_area] =
+        Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(header);
+
+    let progress = state
+        .epoch_info
+        .as_ref()
+        .map(|(epoch_info, _)| epoch_progress(epoch_info))
+        .unwrap_or_default();
+    let epoch_block = Block::bordered().title(format!(" Validator {} ", state.vote_pubkey));
+    match &state.epoch_info {
+        Some((epoch_info, fetched_at)) => {
+            let countdown = time_to_boundary(epoch_info, fetched_at.elapsed());
+            let [text_area, gauge_area] =
+                Layout::vertical([Constraint::Length(2), Constraint::Length(1)])
+                    .areas(epoch_block.inner(epoch_area));
+            frame.render_widget(epoch_block, epoch_area);
+            frame.render_widget(
+                Paragraph::new(vec![
+                    Line::from(format!(
+                        "Epoch {}  slot {}  boundary in ~{}",
+                        epoch_info.epoch,
+                        epoch_info.absolute_slot,
+                        format_countdown(countdown)
+                    )),
+                    Line::from(format!("Status: {}", state.phase)),
+                ]),
+                text_area,
+            );
+            frame.render_widget(
+                Gauge::default()
+                    .gauge_style(Style::new().fg(Color::Cyan))
+                    .ratio(progress)
+                    .label(format!(
+                        "{}/{} slots",
+                        epoch_info.slot_index, epoch_info.slots_in_epoch
+                    )),
+                gauge_area,
+            );
+        }
+        None => frame.render_widget(
+            Paragraph::new(vec![
+                Line::from("Waiting for epoch info"),
+                Line::from(format!("Status: {}", state.phase)),
+            ])
+            .block(epoch_block),
+            epoch_area,
+        ),
+    }
+
+    let rpc = &state.rpc;
+    let (rpc_status, rpc_style) = match (rpc.consecutive_failures, rpc.last_ok) {
+        (0, Some(_)) => ("healthy", Style::new().fg(Color::Green)),
+        (0, None) => ("connecting", Style::new().fg(Color::Yellow)),
+        _ => ("failing", Style::new().fg(Color::Red)),
+    };
+    let mut rpc_lines = vec![Line::styled(format!("Status: {}", rpc_status), rpc_style)];
+    if let Some(latency) = rpc.latency {
+        rpc_lines.push(Line::from(format!("Latency: {} ms", latency.as_millis())));
+    }
+    if let Some(last_ok) = rpc.last_ok {
+        rpc_lines.push(Line::from(format!(
+            "Last success: {}s ago",
+            last_ok.elapsed().as_secs()
+        )));
+    }
+    if rpc.consecutive_failures > 0 {
+        if let Some(error) = &rpc.last_error {
+            rpc_lines.push(Line::styled(
+                format!("{} failures: {}", rpc.consecutive_failures, error),
+                Style::new().fg(Color::Red),
+            ));
+        }
+    }
+    frame.render_widget(
+        Paragraph::new(rpc_lines).block(Block::bordered().title(" RPC ")),
+        rpc_area,
+    );
+
+    let bond_rows = state.bonds.iter().map(|(pubkey, row)| {
+        let stake = row
+            .active_stake
+            .map(|stake| format_sol_rounded(stake, 2))
+            .unwrap_or_else(|| "-".to_string());
+        let mut cells = vec![
+            pubkey.to_string(),
+            row.issuer.to_string(),
+            row.program_id.to_string(),
+            stake,
+        ];
+        match row.last_breakdown {
+            Some(breakdown) => {
+                let accrued = breakdown.accrued(progress);
+                cells.extend([
+                    sol(accrued.inflation),
+                    sol(accrued.mev),
+                    sol(accrued.block),
+                    sol(accrued.total()),
+                    format!("{} ({})", sol(breakdown.total()), breakdown.epoch),
+                ]);
+            }
+            None => cells.extend(vec!["-".to_string(); 5]),
+        }
+        Row::new(cells)
+    });
+    frame.render_widget(
+        Table::new(
+            bond_rows,
+            [
+                Constraint::Length(44),
+                Constraint::Length(44),
+                Constraint::Length(44),
+                Constraint::Length(14),
+                Constraint::Length(10),
+                Constraint::Length(10),
+                Constraint::Length(10),
+                Constraint::Length(10),
+                Constraint::Min(16),
+            ],
+        )
+        .header(
+            Row::new([
+                "Bond",
+                "Issuer",
+                "Program",
+                "Stake (SOL)",
+                "Inflation",
+                "MEV",
+                "Block",
+                "Accrued",
+                "Last epoch",
+            ])
+            .style(Style::new().bold()),
+        )
+        .block(Block::bordered().title(format!(
+            " {} bonds, excess rewards accrued this epoch (est., SOL) ",
+            state.bonds.len()
+        ))),
+        bonds,
+    );
+
+    let payment_rows = state.payments.iter().map(|payment| {
+        Row::new([
+            payment.at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            payment.epoch.to_string(),
+            payment.pye_account.to_string(),
+            format_sol_rounded(payment.lamports, 4),
+            payment.outcome.clone(),
+        ])
+    });
+    frame.render_widget(
+        Table::new(
+            payment_rows,
+            [
+                Constraint::Length(19),
+                Constraint::Length(6),
+                Constraint::Length(44),
+                Constraint::Length(12),
+                Constraint::Min(20),
+            ],
+        )
+        .header(
+            Row::new(["Time (UTC)", "Epoch", "Bond", "SOL", "Outcome"]).style(Style::new().bold()),
+        )
+        .block(Block::bordered().title(" Recent payments (q to quit) ")),
+        payments,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn epoch_info(slot_index: u64, slots_in_epoch: u64) -> EpochInfo {
+        EpochInfo {
+            epoch: 800,
+            slot_index,
+            slots_in_epoch,
+            absolute_slot: 800 * slots_in_epoch + slot_index,
+            block_height: 0,
+            transaction_count: None,
+        }
+    }
+
+    #[test]
+    fn test_time_to_boundary() {
+        let info = epoch_info(431_000, 432_000);
+        assert_eq!(
+            time_to_boundary(&info, Duration::ZERO),
+            Duration::from_secs(400)
+        );
+        assert_eq!(
+            time_to_boundary(&info, Duration::from_secs(100)),
+            Duration::from_secs(300)
+        );
+        assert_eq!(
+            time_to_boundary(&info, Duration::from_secs(1_000)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_format_countdown() {
+        assert_eq!(format_countdown(Duration::from_secs(3_723)), "1h 02m 03s");
+        assert_eq!(format_countdown(Duration::ZERO), "0h 00m 00s");
+    }
+
+    #[test]
+    fn test_accrued_scales_by_progress() {
+        let breakdown = RewardBreakdown {
+            epoch: 799,
+            inflation: 1_000,
+            mev: 400,
+            block: -200,
+        };
+        let accrued = breakdown.accrued(epoch_progress(&epoch_info(108_000, 432_000)));
+        assert_eq!(
+            (accrued.inflation, accrued.mev, accrued.block),
+            (250, 100, -50)
+        );
+        assert_eq!(breakdown.accrued(2.0), breakdown);
+    }
+}
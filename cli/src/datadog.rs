@@ -0,0 +1,163 @@
+use std::net::UdpSocket;
+
+use anyhow::{anyhow, Result};
+use log::debug;
+use solana_metrics::datapoint::DataPoint;
+
+/// String fields sent as tags. Others, such as error messages and signatures, would give every
+/// datapoint its own series and are left out.
+const TAG_FIELDS: &[&str] = &[
+    "epoch",
+    "vote_pubkey",
+    "vote_account",
+    "pye_account",
+    "program_id",
+    "issuer",
+    "check",
+    "phase",
+    "reason",
+    "method",
+];
+
+/// A datapoint field as stored by solana_metrics: quoted strings, integers suffixed with `i`,
+/// and bare floats and booleans.
+#[derive(Debug, PartialEq)]
+enum FieldValue {
+    /// The number as written, keeping integers exact.
+    Number(String),
+    Text(String),
+}
+
+fn parse_field(value: &str) -> FieldValue {
+    if let Some(text) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        return FieldValue::Text(text.replace("\\\"", "\""));
+    }
+    match value {
+        "true" => FieldValue::Number("1".to_string()),
+        "false" => FieldValue::Number("0".to_string()),
+        _ => {
+            let number = value.strip_suffix('i').unwrap_or(value);
+            if number.parse::<f64>().is_ok() {
+                FieldValue::Number(number.to_string())
+            } else {
+                FieldValue::Text(value.to_string())
+            }
+        }
+    }
+}
+
+/// Replaces the characters DogStatsD uses as separators.
+fn sanitize_tag(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if matches!(c, ',' | '|' | '#' | '\n') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Sends datapoints to a Datadog agent over DogStatsD: a `<prefix>.<datapoint>.count` counter
+/// for every datapoint and a `<prefix>.<datapoint>.<field>` gauge for every numeric field, tagged
+/// with the static tags and the vote account, bond, issuer and epoch fields.
+pub struct DogStatsd {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<String>,
+}
+
+impl DogStatsd {
+    pub fn connect(address: &str, prefix: String, host_id: &str) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .and_then(|socket| socket.connect(address).map(|_| socket))
+            .map_err(|e| anyhow!("Failed to connect to DogStatsD at {}: {}", address, e))?;
+        socket
+            .set_nonblocking(true)
+            .map_err(|e| anyhow!("Failed to configure the DogStatsD socket: {}", e))?;
+        Ok(Self {
+            socket,
+            prefix,
+            tags: vec![format!("host_id:{}", sanitize_tag(host_id))],
+        })
+    }
+
+    fn lines(&self, point: &DataPoint) -> Vec<String> {
+        let mut tags = self.tags.clone();
+        tags.extend(
+            point
+                .tags
+                .iter()
+                .map(|(name, value)| format!("{}:{}", name, sanitize_tag(value))),
+        );
+        let mut gauges = vec![];
+        for (name, value) in &point.fields {
+            match parse_field(value) {
+                FieldValue::Number(number) => gauges.push((name, number)),
+                FieldValue::Text(text) if TAG_FIELDS.contains(name) => {
+                    tags.push(format!("{}:{}", name, sanitize_tag(&text)))
+                }
+                FieldValue::Text(_) => {}
+            }
+        }
+        let tags = tags.join(",");
+        let mut lines = vec![format!(
+            "{}.{}.count:1|c|#{}",
+            self.prefix, point.name, tags
+        )];
+        lines.extend(gauges.into_iter().map(|(name, number)| {
+            format!(
+                "{}.{}.{}:{}|g|#{}",
+                self.prefix, point.name, name, number, tags
+            )
+        }));
+        lines
+    }
+
+    pub fn send(&self, point: &DataPoint) {
+        let packet = self.lines(point).join("\n");
+        if let Err(e) = self.socket.send(packet.as_bytes()) {
+            debug!("Failed to send {} to DogStatsD: {}", point.name, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_field() {
+        assert_eq!(parse_field("42i"), FieldValue::Number("42".to_string()));
+        assert_eq!(parse_field("0.5"), FieldValue::Number("0.5".to_string()));
+        assert_eq!(parse_field("true"), FieldValue::Number("1".to_string()));
+        assert_eq!(
+            parse_field("\"say \\\"hi\\\"\""),
+            FieldValue::Text("say \"hi\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lines() {
+        let statsd =
+            DogStatsd::connect("127.0.0.1:8125", "pye_cli".to_string(), "manager").unwrap();
+        let mut point = DataPoint::new("excess_reward");
+        point
+            .add_tag("environment", "mainnet")
+            .add_field_str("epoch", "800")
+            .add_field_str("signature", "5abc")
+            .add_field_i64("lamports", 1_000);
+        assert_eq!(
+            statsd.lines(&point),
+            vec![
+                "pye_cli.excess_reward.count:1|c|#host_id:manager,environment:mainnet,epoch:800",
+                "pye_cli.excess_reward.lamports:1000|g|#host_id:manager,environment:mainnet,epoch:800",
+            ]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use futures::StreamExt;
+use log::{info, warn};
+use solana_client::nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient};
+use solana_sdk::{epoch_info::EpochInfo, epoch_schedule::EpochSchedule};
+use tokio::sync::watch;
+use url::Url;
+
+/// Wait before reconnecting a dropped slot subscription.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Interval between getEpochInfo calls confirming a rollover seen on the subscription.
+const ROLLOVER_CONFIRMATION_POLL: Duration = Duration::from_secs(1);
+
+/// How the manager notices that an epoch ended.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum BoundaryDetection {
+    /// Poll getEpochInfo, spaced out by the estimated time left in the epoch.
+    #[default]
+    Poll,
+    /// Subscribe to slots over the RPC's websocket and react to the rollover within seconds.
+    /// Polling keeps running as a fallback.
+    Ws,
+}
+
+/// The pubsub endpoint Solana RPC nodes serve next to `rpc_url`: `ws(s)://` on the next port
+/// when a port is given.
+pub fn default_ws_url(rpc_url: &str) -> Result<String> {
+    let mut url = Url::parse(rpc_url).map_err(|e| anyhow!("Invalid RPC URL {}: {}", rpc_url, e))?;
+    let scheme = match url.scheme() {
+        "https" => "wss",
+        "http" => "ws",
+        scheme => return Err(anyhow!("Unsupported RPC URL scheme {}", scheme)),
+    };
+    url.set_scheme(scheme)
+        .map_err(|_| anyhow!("Failed to derive a websocket URL from {}", rpc_url))?;
+    if let Some(port) = url.port() {
+        url.set_port(Some(port + 1))
+            .map_err(|_| anyhow!("Failed to derive a websocket URL from {}", rpc_url))?;
+    }
+    Ok(url.to_string())
+}
+
+/// Subscribes to slot updates on `ws_url`, reconnecting whenever the subscription drops, and
+/// publishes the latest slot on the returned channel.
+pub fn watch_slots(ws_url: String) -> watch::Receiver<u64> {
+    let (sender, receiver) = watch::channel(0);
+    tokio::spawn(async move {
+        while !sender.is_closed() {
+            if let Err(e) = forward_slots(&ws_url, &sender).await {
+                warn!("Slot subscription to {} dropped: {}", ws_url, e);
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    });
+    receiver
+}
+
+async fn forward_slots(ws_url: &str, sender: &watch::Sender<u64>) -> Result<()> {
+    let client = PubsubClient::new(ws_url).await?;
+    let (mut slots, unsubscribe) = client.slot_subscribe().await?;
+    info!("Subscribed to slots on {}", ws_url);
+    while let Some(slot_info) = slots.next().await {
+        if sender.send(slot_info.slot).is_err() {
+            break;
+        }
+    }
+    unsubscribe().await;
+    Ok(())
+}
+
+/// Waits until the slot subscription reaches an epoch after `current_epoch`, then until the RPC
+/// confirms the rollover, and returns the new epoch info. Never resolves without a
+/// subscription, leaving boundary detection to polling.
+pub async fn wait_for_rollover(
+    slots: &mut Option<watch::Receiver<u64>>,
+    rpc_client: &RpcClient,
+    epoch_schedule: &EpochSchedule,
+    current_epoch: u64,
+) -> EpochInfo {
+    let Some(slots) = slots else {
+        return std::future::pending().await;
+    };
+    if slots
+        .wait_for(|slot| epoch_schedule.get_epoch(*slot) > current_epoch)
+        .await
+        .is_err()
+    {
+        return std::future::pending().await;
+    }
+    loop {
+        // Subscriptions report processed slots, which run slightly ahead of the RPC's commitment.
+        match rpc_client.get_epoch_info().await {
+            Ok(epoch_info) if epoch_info.epoch > current_epoch => return epoch_info,
+            Ok(_) => {}
+            Err(e) => warn!("Error confirming the epoch rollover: {:?}", e),
+        }
+        tokio::time::sleep(ROLLOVER_CONFIRMATION_POLL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_ws_url() {
+        assert_eq!(
+            default_ws_url("https://api.mainnet-beta.solana.com").unwrap(),
+            "wss://api.mainnet-beta.solana.com/"
+        );
+        assert_eq!(
+            default_ws_url("http://127.0.0.1:8899").unwrap(),
+            "ws://127.0.0.1:8900/"
+        );
+        assert!(default_ws_url("ftp://example.com").is_err());
+    }
+}
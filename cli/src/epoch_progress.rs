@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::bond_state::BondPass;
+
+/// An epoch whose processing ran past its deadline, with the bonds that weren't paid yet.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnfinishedEpoch {
+    pub epoch: u64,
+    /// The pass the bonds are retried in. With --defer-mev, an epoch can have bonds left in both passes.
+    #[serde(default)]
+    pub pass: BondPass,
+    pub pye_accounts: Vec<String>,
+    /// The vote account's commission observed live at the start and end of the epoch.
+    pub observed_commission: (Option<u8>, Option<u8>),
+}
+
+impl UnfinishedEpoch {
+    pub fn pye_accounts(&self) -> Result<Vec<Pubkey>> {
+        self.pye_accounts
+            .iter()
+            .map(|pubkey| {
+                Pubkey::from_str(pubkey)
+                    .map_err(|e| anyhow!("Invalid pubkey {} in epoch progress: {}", pubkey, e))
+            })
+            .collect()
+    }
+}
+
+/// Epochs left unfinished at their processing deadline, retried in the background. Optionally
+/// persisted to a file so a restart picks up where the previous run stopped.
+#[derive(Debug, Default)]
+pub struct EpochProgress {
+    path: Option<PathBuf>,
+    unfinished: Vec<UnfinishedEpoch>,
+}
+
+impl EpochProgress {
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let mut progress = Self {
+            path,
+            ..Self::default()
+        };
+        if let Some(path) = progress.path.as_ref().filter(|path| path.exists()) {
+            let contents = fs::read_to_string(path)
+                .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+            progress.unfinished = serde_json::from_str(&contents)
+                .map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))?;
+        }
+        Ok(progress)
+    }
+
+    /// The oldest unfinished epoch, retried first.
+    pub fn oldest(&self) -> Option<&UnfinishedEpoch> {
+        self.unfinished
+            .iter()
+            .min_by_key(|unfinished| (unfinished.epoch, unfinished.pass))
+    }
+
+    /// Epochs with bonds still to be paid.
+    pub fn unfinished(&self) -> &[UnfinishedEpoch] {
+        &self.unfinished
+    }
+
+    /// Epochs with bonds still to be paid, oldest first.
+    pub fn epochs(&self) -> Vec<u64> {
+        let mut epochs: Vec<u64> = self
+            .unfinished
+            .iter()
+            .map(|unfinished| unfinished.epoch)
+            .collect();
+        epochs.sort_unstable();
+        epochs.dedup();
+        epochs
+    }
+
+    /// Records the bonds of `epoch` still to be paid in `pass`, forgetting them once none are
+    /// left.
+    pub fn update(
+        &mut self,
+        epoch: u64,
+        pass: BondPass,
+        pye_accounts: &[Pubkey],
+        observed_commission: (Option<u8>, Option<u8>),
+    ) {
+        self.unfinished
+            .retain(|unfinished| (unfinished.epoch, unfinished.pass) != (epoch, pass));
+        if !pye_accounts.is_empty() {
+            self.unfinished.push(UnfinishedEpoch {
+                epoch,
+                pass,
+                pye_accounts: pye_accounts.iter().map(ToString::to_string).collect(),
+                observed_commission,
+            });
+        }
+        self.save();
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let write = serde_json::to_vec_pretty(&self.unfinished)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| fs::write(path, bytes).map_err(|e| e.to_string()));
+        if let Err(e) = write {
+            warn!(
+                "Failed to write epoch progress to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unfinished_epochs_persist_until_done() {
+        let path =
+            std::env::temp_dir().join(format!("pye-epoch-progress-{}.json", std::process::id()));
+        let bonds = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut progress = EpochProgress::load(Some(path.clone())).unwrap();
+        progress.update(801, BondPass::Full, &bonds[..1], (None, None));
+        progress.update(800, BondPass::MevOnly, &bonds[..1], (None, None));
+        progress.update(800, BondPass::WithoutMev, &bonds, (Some(5), Some(5)));
+        assert_eq!(progress.epochs(), [800, 801]);
+
+        let mut progress = EpochProgress::load(Some(path.clone())).unwrap();
+        let oldest = progress.oldest().unwrap().clone();
+        assert_eq!(oldest.epoch, 800);
+        // The first pass is retried before the MEV deferred after it.
+        assert_eq!(oldest.pass, BondPass::WithoutMev);
+        assert_eq!(oldest.pye_accounts().unwrap(), bonds);
+        assert_eq!(oldest.observed_commission, (Some(5), Some(5)));
+
+        progress.update(
+            800,
+            BondPass::WithoutMev,
+            &bonds[1..],
+            oldest.observed_commission,
+        );
+        progress.update(801, BondPass::Full, &[], (None, None));
+        let mut progress = EpochProgress::load(Some(path.clone())).unwrap();
+        assert_eq!(
+            progress.oldest().unwrap().pye_accounts().unwrap(),
+            &bonds[1..]
+        );
+        progress.update(800, BondPass::WithoutMev, &[], (None, None));
+        assert_eq!(progress.oldest().unwrap().pass, BondPass::MevOnly);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::Result;
+use clap::Args;
+use futures::future::join_all;
+use log::error;
+use reqwest::Client;
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::signer::Signer;
+
+use crate::http::http_client;
+use crate::keypairs::SharedSigner;
+
+/// Header carrying the pubkey that signed an event.
+pub const SIGNER_HEADER: &str = "X-Pye-Signer";
+/// Header carrying the base58 ed25519 signature of the raw request body.
+pub const SIGNATURE_HEADER: &str = "X-Pye-Signature";
+
+/// Options controlling where signed lifecycle events are delivered.
+#[derive(Clone, Debug, Default, Args)]
+pub struct EventArgs {
+    /// Webhook URL that receives signed JSON lifecycle events. Repeat for several receivers.
+    #[arg(long = "event-webhook", env = "EVENT_WEBHOOK", value_delimiter = ',')]
+    pub event_webhooks: Vec<String>,
+    /// Keypair signing the events, with the same sources as --payer [default: the payer]
+    #[arg(long, env)]
+    pub event_signer: Option<String>,
+}
+
+/// A step of the manager's epoch lifecycle.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A new epoch started and the previous one is about to be processed.
+    EpochStarted { epoch: u64, target_epoch: u64 },
+    /// The excess rewards owed to a bond for `epoch` were computed.
+    BreakdownComputed {
+        epoch: u64,
+        program_id: String,
+        pye_account: String,
+        active_stake: u64,
+        excess_inflation_rewards: i64,
+        excess_mev_rewards: i64,
+        excess_block_rewards: i64,
+        total_excess_rewards: i64,
+    },
+    /// A transfer of excess rewards is being sent.
+    TransferSent {
+        epoch: u64,
+        pye_account: String,
+        lamports: u64,
+    },
+    /// A transfer of excess rewards landed.
+    TransferConfirmed {
+        epoch: u64,
+        pye_account: String,
+        lamports: u64,
+        signature: String,
+    },
+    /// A transfer of excess rewards failed and the manager is stopping.
+    TransferFailed {
+        epoch: u64,
+        pye_account: String,
+        lamports: u64,
+        error: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    vote_pubkey: String,
+    timestamp: i64,
+    event: &'a Event,
+}
+
+/// Returns true if `signature` is `signer`'s signature of the raw webhook `body`.
+pub fn verify_event(body: &[u8], signer: &Pubkey, signature: &Signature) -> bool {
+    signature.verify(signer.as_ref(), body)
+}
+
+/// Posts lifecycle events signed by `signer` to the configured webhooks. Like notifications,
+/// delivery is best effort and failures are only logged.
+#[derive(Clone)]
+pub struct EventEmitter {
+    http: Client,
+    webhooks: Vec<String>,
+    signer: SharedSigner,
+    vote_pubkey: Pubkey,
+}
+
+impl EventEmitter {
+    pub fn new(webhooks: Vec<String>, signer: SharedSigner, vote_pubkey: Pubkey) -> Self {
+        Self {
+            http: http_client(),
+            webhooks,
+            signer,
+            vote_pubkey,
+        }
+    }
+
+    /// Serializes and signs `event`, returning the body and its signature.
+    pub fn sign(&self, event: &Event) -> Result<(Vec<u8>, Signature)> {
+        let body = serde_json::to_vec(&Envelope {
+            vote_pubkey: self.vote_pubkey.to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+            event,
+        })?;
+        let signature = self.signer.try_sign_message(&body)?;
+        Ok((body, signature))
+    }
+
+    pub async fn emit(&self, event: Event) {
+        let (body, signature) = match self.sign(&event) {
+            Ok(signed) => signed,
+            Err(e) => {
+                error!("Failed to sign event {:?}: {}", event, e);
+                return;
+            }
+        };
+        // Delivered to every webhook at once, so a slow one only holds the payments up until
+        // the request timeout.
+        join_all(self.webhooks.iter().map(|url| async {
+            let result = self
+                .http
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNER_HEADER, self.signer.pubkey().to_string())
+                .header(SIGNATURE_HEADER, signature.to_string())
+                .body(body.clone())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                error!("Failed to deliver event to {}: {}", url, e);
+            }
+        }))
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use solana_sdk::signer::keypair::Keypair;
+
+    use super::*;
+
+    fn emitter(keypair: Keypair) -> EventEmitter {
+        EventEmitter::new(vec![], Arc::new(keypair), Pubkey::new_unique())
+    }
+
+    #[test]
+    fn test_signed_event_verifies() {
+        let keypair = Keypair::new();
+        let signer = keypair.pubkey();
+        let (body, signature) = emitter(keypair)
+            .sign(&Event::EpochStarted {
+                epoch: 801,
+                target_epoch: 800,
+            })
+            .unwrap();
+        assert!(verify_event(&body, &signer, &signature));
+
+        let mut tampered = body.clone();
+        tampered[body.len() - 2] ^= 1;
+        assert!(!verify_event(&tampered, &signer, &signature));
+        assert!(!verify_event(&body, &Pubkey::new_unique(), &signature));
+    }
+
+    #[test]
+    fn test_event_payload_shape() {
+        let (body, _) = emitter(Keypair::new())
+            .sign(&Event::TransferConfirmed {
+                epoch: 800,
+                pye_account: "bond".to_string(),
+                lamports: 42,
+                signature: "sig".to_string(),
+            })
+            .unwrap();
+        let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(payload["event"]["type"], "transfer_confirmed");
+        assert_eq!(payload["event"]["lamports"], 42);
+        assert!(payload["timestamp"].is_i64());
+        assert!(payload["vote_pubkey"].is_string());
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt::Display;
+
+use solana_client::client_error::ClientError;
+use thiserror::Error;
+
+/// Exit status of the CLI, telling wrapper scripts and systemd what happened. Errors map to
+/// distinct codes whatever the flags; `NothingOwed` and `DryRun` exit with `Success` unless
+/// `--detailed-exit-codes` is passed, since scripts commonly treat any non-zero exit as a failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Done, including any payment owed.
+    Success = 0,
+    /// An error not covered by a more specific code.
+    Failure = 1,
+    /// Invalid flags, config files or keypairs. Also used by clap for usage errors.
+    Config = 2,
+    /// An RPC request failed, e.g. the endpoint is down or lacks the history needed.
+    Rpc = 3,
+    /// A payment failed, possibly after other payments of the epoch were sent.
+    PaymentFailed = 4,
+    /// The excess rewards were computed and nothing is owed.
+    NothingOwed = 10,
+    /// The excess rewards were computed with --dry-run, nothing was paid.
+    DryRun = 11,
+}
+
+/// Marks an error as caused by the configuration, see [`ExitCode::Config`].
+#[derive(Debug, Error)]
+#[error("invalid configuration")]
+pub struct ConfigError;
+
+/// Marks an error as a failed payment, see [`ExitCode::PaymentFailed`].
+#[derive(Debug, Error)]
+#[error("payment failed")]
+pub struct PaymentError;
+
+/// Wraps the error of a failed RPC request with `message`, keeping it in the chain so
+/// [`ExitCode::of`] finds the [`ClientError`]. The message still ends with the error, since call
+/// sites commonly log errors with `{}`, which only shows the outermost one.
+pub fn rpc_error(message: impl Display, error: impl Into<anyhow::Error>) -> anyhow::Error {
+    let error = error.into();
+    let message = format!("{}: {}", message, error);
+    error.context(message)
+}
+
+impl ExitCode {
+    /// The exit code of a failed command, from the most specific cause found in the error chain.
+    pub fn of(error: &anyhow::Error) -> Self {
+        if error.downcast_ref::<ConfigError>().is_some() {
+            ExitCode::Config
+        } else if error.downcast_ref::<PaymentError>().is_some() {
+            ExitCode::PaymentFailed
+        } else if error.downcast_ref::<ClientError>().is_some() {
+            ExitCode::Rpc
+        } else {
+            ExitCode::Failure
+        }
+    }
+
+    /// The code to exit with, folding the informational codes into `Success` unless `detailed`.
+    pub fn code(self, detailed: bool) -> u8 {
+        match self {
+            ExitCode::NothingOwed | ExitCode::DryRun if !detailed => ExitCode::Success as u8,
+            code => code as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use solana_client::client_error::ClientErrorKind;
+
+    use super::*;
+
+    #[test]
+    fn test_of() {
+        let config = anyhow!("No payer keypair").context(ConfigError);
+        assert_eq!(ExitCode::of(&config), ExitCode::Config);
+        let payment = anyhow!("blockhash expired").context(PaymentError);
+        assert_eq!(ExitCode::of(&payment), ExitCode::PaymentFailed);
+        let rpc = anyhow::Error::from(ClientError::from(ClientErrorKind::Custom(
+            "connection refused".to_string(),
+        )))
+        .context("Failed to fetch the epoch info");
+        assert_eq!(ExitCode::of(&rpc), ExitCode::Rpc);
+        assert_eq!(ExitCode::of(&anyhow!("boom")), ExitCode::Failure);
+    }
+
+    #[test]
+    fn test_rpc_error() {
+        let error = rpc_error(
+            "Failed to fetch StakeHistory",
+            ClientError::from(ClientErrorKind::Custom("connection refused".to_string())),
+        );
+        assert_eq!(
+            error.to_string(),
+            "Failed to fetch StakeHistory: connection refused"
+        );
+        assert_eq!(ExitCode::of(&error), ExitCode::Rpc);
+        // Wrapping it again, e.g. on a cache miss, keeps the exit code.
+        let error = rpc_error("Failed to fetch inflation reward", error);
+        assert_eq!(ExitCode::of(&error), ExitCode::Rpc);
+    }
+
+    #[test]
+    fn test_code() {
+        assert_eq!(ExitCode::DryRun.code(false), 0);
+        assert_eq!(ExitCode::DryRun.code(true), 11);
+        assert_eq!(ExitCode::Rpc.code(false), 3);
+    }
+}
@@ -0,0 +1,201 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use log::{info, warn};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::http::http_client;
+use crate::metrics::{datapoint_error, datapoint_info};
+use crate::notifications::{Notification, Notifier, Severity};
+
+/// Options controlling the heartbeat and the stall watchdog.
+#[derive(Clone, Debug, Args)]
+pub struct HeartbeatArgs {
+    /// The wait time (in secs) between heartbeat datapoints and pings.
+    #[arg(long, env, default_value = "60")]
+    pub heartbeat_secs: u64,
+    /// URL pinged with a GET on every heartbeat, e.g. a healthchecks.io check. While epoch
+    /// processing is stalled, `<URL>/fail` is pinged instead.
+    #[arg(long, env)]
+    pub heartbeat_url: Option<String>,
+    /// Time (in secs) processing an epoch may take before the watchdog reports it as stalled.
+    #[arg(long, env, default_value = "7200")]
+    pub stall_deadline_secs: u64,
+}
+
+#[derive(Debug, Default)]
+struct HeartbeatState {
+    phase: String,
+    /// The epoch being processed and when processing started.
+    processing: Option<(u64, Instant)>,
+    stall_reported: bool,
+}
+
+/// Reports that the manager is alive every `--heartbeat-secs` and watches epoch processing for
+/// stalls, e.g. a wedged RPC call or block retries stacking up. The heartbeat runs on its own
+/// task so it keeps reporting while the manager loop is stuck.
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    vote_pubkey: Pubkey,
+    interval: Duration,
+    stall_deadline: Duration,
+    url: Option<String>,
+    state: Arc<Mutex<HeartbeatState>>,
+}
+
+/// Marks an epoch as being processed until dropped, so early returns on errors are covered too.
+pub struct EpochWatch {
+    heartbeat: Heartbeat,
+}
+
+impl Drop for EpochWatch {
+    fn drop(&mut self) {
+        let mut state = self.heartbeat.state();
+        if let Some((epoch, started)) = state.processing.take() {
+            if state.stall_reported {
+                info!(
+                    "Processing of epoch {} finished after {}s",
+                    epoch,
+                    started.elapsed().as_secs()
+                );
+            }
+        }
+        state.stall_reported = false;
+    }
+}
+
+impl Heartbeat {
+    pub fn new(args: &HeartbeatArgs, vote_pubkey: Pubkey) -> Self {
+        Self {
+            vote_pubkey,
+            interval: Duration::from_secs(args.heartbeat_secs.max(1)),
+            stall_deadline: Duration::from_secs(args.stall_deadline_secs),
+            url: args.heartbeat_url.clone(),
+            state: Arc::new(Mutex::new(HeartbeatState::default())),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, HeartbeatState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn set_phase(&self, phase: String) {
+        self.state().phase = phase;
+    }
+
+    pub fn begin_epoch(&self, epoch: u64) -> EpochWatch {
+        let mut state = self.state();
+        state.processing = Some((epoch, Instant::now()));
+        state.stall_reported = false;
+        EpochWatch {
+            heartbeat: self.clone(),
+        }
+    }
+
+    /// The epoch being processed and for how long, if past the stall deadline at `now`.
+    pub fn stalled(&self, now: Instant) -> Option<(u64, Duration)> {
+        let (epoch, started) = self.state().processing?;
+        let elapsed = now.saturating_duration_since(started);
+        (elapsed > self.stall_deadline).then_some((epoch, elapsed))
+    }
+
+    /// Emits heartbeats until the process exits or the task is aborted.
+    pub async fn run(self, notifier: Notifier) {
+        let http = http_client();
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            let stalled = self.stalled(Instant::now());
+            let (phase, processing, newly_stalled) = {
+                let mut state = self.state();
+                let newly_stalled = stalled.is_some() && !state.stall_reported;
+                state.stall_reported |= newly_stalled;
+                (state.phase.clone(), state.processing, newly_stalled)
+            };
+            let processing_secs = processing
+                .map(|(_, started)| started.elapsed().as_secs() as i64)
+                .unwrap_or(0);
+            datapoint_info!(
+                "manager_heartbeat",
+                ("vote_pubkey", self.vote_pubkey.to_string(), String),
+                ("phase", phase, String),
+                (
+                    "processing_epoch",
+                    processing.map(|(epoch, _)| epoch as i64).unwrap_or(-1),
+                    i64
+                ),
+                ("processing_secs", processing_secs, i64),
+                ("stalled", stalled.is_some(), bool),
+            );
+            if let (true, Some((epoch, elapsed))) = (newly_stalled, stalled) {
+                datapoint_error!(
+                    "manager_stall",
+                    ("vote_pubkey", self.vote_pubkey.to_string(), String),
+                    ("epoch", epoch.to_string(), String),
+                    ("processing_secs", elapsed.as_secs() as i64, i64),
+                );
+                notifier
+                    .notify(Notification::new(
+                        Severity::Critical,
+                        format!("Processing of epoch {} is stalled", epoch),
+                        format!(
+                            "Excess rewards of {} for epoch {} have been processing for {}s, over the {}s deadline.",
+                            self.vote_pubkey,
+                            epoch,
+                            elapsed.as_secs(),
+                            self.stall_deadline.as_secs()
+                        ),
+                    ))
+                    .await;
+            }
+            if let Some(url) = &self.url {
+                let url = if stalled.is_some() {
+                    format!("{}/fail", url.trim_end_matches('/'))
+                } else {
+                    url.clone()
+                };
+                let result = http
+                    .get(&url)
+                    .timeout(Duration::from_secs(10))
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    warn!("Failed to ping heartbeat URL {}: {}", url, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat(stall_deadline_secs: u64) -> Heartbeat {
+        Heartbeat::new(
+            &HeartbeatArgs {
+                heartbeat_secs: 60,
+                heartbeat_url: None,
+                stall_deadline_secs,
+            },
+            Pubkey::new_unique(),
+        )
+    }
+
+    #[test]
+    fn test_stall_detected_past_deadline() {
+        let heartbeat = heartbeat(60);
+        assert_eq!(heartbeat.stalled(Instant::now()), None);
+        let watch = heartbeat.begin_epoch(800);
+        assert_eq!(heartbeat.stalled(Instant::now()), None);
+        let later = Instant::now() + Duration::from_secs(61);
+        assert_eq!(heartbeat.stalled(later).map(|(epoch, _)| epoch), Some(800));
+        // Finishing the epoch, even on an error path, clears the watchdog.
+        drop(watch);
+        assert_eq!(heartbeat.stalled(later), None);
+    }
+}
@@ -0,0 +1,70 @@
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+/// Options for splitting very large payments into several transfers.
+#[derive(Clone, Debug, Default, Args)]
+pub struct InstallmentArgs {
+    /// Split a bond's payment for an epoch into installments when it exceeds this many lamports,
+    /// e.g. to stay within a treasury's withdrawal limit or a multisig's spending policy.
+    #[arg(long, env)]
+    pub installment_threshold_lamports: Option<u64>,
+    /// Number of transfers a payment above --installment-threshold-lamports is split into.
+    #[arg(long, env, default_value_t = 2, value_parser = clap::value_parser!(u64).range(2..))]
+    pub installments: u64,
+    /// Seconds between the installments of a payment.
+    #[arg(long, env, default_value_t = 3600)]
+    pub installment_interval_secs: u64,
+}
+
+impl InstallmentArgs {
+    /// The transfers a payment of `lamports` is made in: itself, or --installments nearly equal
+    /// parts when it is above the threshold, the first ones taking the remainder.
+    pub fn split(&self, lamports: u64) -> Vec<u64> {
+        if self
+            .installment_threshold_lamports
+            .is_none_or(|threshold| lamports <= threshold)
+        {
+            return vec![lamports];
+        }
+        let count = self.installments.min(lamports);
+        let (part, remainder) = (lamports / count, lamports % count);
+        (0..count)
+            .map(|index| part + u64::from(index < remainder))
+            .collect()
+    }
+}
+
+/// A landed installment of a payment.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Installment {
+    pub lamports: u64,
+    pub signature: String,
+    /// Fee charged for the transaction in lamports, when known.
+    #[serde(default)]
+    pub fee: Option<u64>,
+    /// Slot the transaction was confirmed in, when known.
+    #[serde(default)]
+    pub slot: Option<u64>,
+    /// Unix time the installment landed.
+    pub paid_at: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let args = InstallmentArgs {
+            installment_threshold_lamports: Some(1_000),
+            installments: 3,
+            installment_interval_secs: 60,
+        };
+        assert_eq!(args.split(1_000), vec![1_000]);
+        assert_eq!(args.split(1_001), vec![334, 334, 333]);
+        assert_eq!(args.split(3_000), vec![1_000, 1_000, 1_000]);
+
+        let disabled = InstallmentArgs::default();
+        assert_eq!(disabled.split(u64::MAX), vec![u64::MAX]);
+    }
+}
@@ -0,0 +1,326 @@
+use std::io::Read;
+use std::sync::Arc;
+
+use age::armor::ArmoredReader;
+use age::secrecy::Secret;
+use anyhow::{anyhow, Result};
+use dialoguer::{Confirm, Password};
+use solana_sdk::derivation_path::DerivationPath;
+use solana_sdk::signer::keypair::{
+    generate_seed_from_seed_phrase_and_passphrase, keypair_from_seed_and_derivation_path,
+    keypair_from_seed_phrase_and_passphrase, read_keypair, Keypair,
+};
+use solana_sdk::signer::Signer;
+
+use crate::vault::{parse_vault_source, VaultSigner};
+
+/// Environment variable holding the payer keypair as a JSON byte array, for deployments that
+/// inject secrets at runtime instead of mounting a file.
+pub const PAYER_KEYPAIR_JSON_ENV: &str = "PAYER_KEYPAIR_JSON";
+/// Keypair source that reads the JSON keypair from stdin.
+pub const STDIN_SOURCE: &str = "-";
+/// Environment variable holding the passphrase of age encrypted keypairs. When unset the
+/// passphrase is prompted for.
+pub const KEYPAIR_PASSPHRASE_ENV: &str = "KEYPAIR_PASSPHRASE";
+
+/// A payer or fee payer, backed by a local keypair or a remote key.
+pub type SharedSigner = Arc<dyn Signer + Send + Sync>;
+
+/// Scheme of keypair sources derived from a seed phrase entered at a prompt, as in the Solana CLI.
+const PROMPT_SCHEME: &str = "prompt:";
+
+const AGE_BINARY_HEADER: &[u8] = b"age-encryption.org/v1";
+const AGE_ARMOR_HEADER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Parses a keypair in the JSON byte array format written by `solana-keygen`.
+pub fn parse_keypair_json(json: &str) -> Result<Keypair> {
+    read_keypair(&mut json.trim().as_bytes()).map_err(|e| anyhow!("Invalid keypair JSON: {}", e))
+}
+
+/// Returns true for keypairs encrypted with `age -p`, in the binary or the armored format.
+pub fn is_age_encrypted(data: &[u8]) -> bool {
+    let data = data.trim_ascii_start();
+    data.starts_with(AGE_BINARY_HEADER) || data.starts_with(AGE_ARMOR_HEADER)
+}
+
+/// Decrypts a passphrase encrypted age file holding a JSON keypair. The plaintext only ever
+/// lives in memory.
+pub fn decrypt_keypair(data: &[u8], passphrase: &str) -> Result<Keypair> {
+    let decryptor = match age::Decryptor::new(ArmoredReader::new(data.trim_ascii_start()))
+        .map_err(|e| anyhow!("Invalid encrypted keypair: {}", e))?
+    {
+        age::Decryptor::Passphrase(decryptor) => decryptor,
+        _ => {
+            return Err(anyhow!(
+                "Encrypted keypair must be encrypted with a passphrase (age -p)"
+            ))
+        }
+    };
+    let mut json = String::new();
+    decryptor
+        .decrypt(&Secret::new(passphrase.to_string()), None)
+        .map_err(|e| anyhow!("Failed to decrypt keypair: {}", e))?
+        .read_to_string(&mut json)
+        .map_err(|e| anyhow!("Failed to decrypt keypair: {}", e))?;
+    parse_keypair_json(&json)
+}
+
+/// Parses the derivation path of a `prompt:` keypair source. Accepts the Solana CLI's
+/// `prompt://?key=<account>/<change>` and `prompt://?full-path=m/44/501/...` forms as well as
+/// `prompt://bip44?account=<account>&change=<change>`. A bare `prompt://` derives the keypair
+/// from the seed directly, without a derivation path. Returns None for other sources.
+pub fn parse_prompt_source(source: &str) -> Option<Result<Option<DerivationPath>>> {
+    let uri = source.strip_prefix(PROMPT_SCHEME)?;
+    let uri = uri.strip_prefix("//").unwrap_or(uri);
+    let (host, query) = uri.split_once('?').unwrap_or((uri, ""));
+    Some(prompt_derivation_path(host, query))
+}
+
+fn prompt_derivation_path(host: &str, query: &str) -> Result<Option<DerivationPath>> {
+    if !matches!(host, "" | "bip44") {
+        return Err(anyhow!("Unsupported prompt derivation scheme {}", host));
+    }
+    let parse_index = |name: &str, value: &str| {
+        value
+            .trim_end_matches('\'')
+            .parse::<u32>()
+            .map_err(|e| anyhow!("Invalid {} {}: {}", name, value, e))
+    };
+    let (mut account, mut change) = (None, None);
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        match name {
+            "account" => account = Some(parse_index(name, value)?),
+            "change" => change = Some(parse_index(name, value)?),
+            "key" => {
+                let (key_account, key_change) = match value.split_once('/') {
+                    Some((key_account, key_change)) => (key_account, Some(key_change)),
+                    None => (value, None),
+                };
+                account = Some(parse_index("account", key_account)?);
+                change = key_change
+                    .map(|key_change| parse_index("change", key_change))
+                    .transpose()?;
+            }
+            "full-path" => {
+                return DerivationPath::from_absolute_path_str(value)
+                    .map(Some)
+                    .map_err(|e| anyhow!("Invalid derivation path {}: {}", value, e));
+            }
+            _ => return Err(anyhow!("Unsupported prompt parameter {}", name)),
+        }
+    }
+    if host.is_empty() && account.is_none() && change.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(DerivationPath::new_bip44(account, change)))
+}
+
+/// Derives a keypair from a seed phrase and optional BIP39 passphrase entered at the terminal,
+/// then asks the operator to confirm the resulting pubkey since a mistyped phrase silently
+/// derives a different key.
+fn prompt_keypair(role: &str, derivation_path: Option<DerivationPath>) -> Result<Keypair> {
+    let seed_phrase = Password::new()
+        .with_prompt(format!("Seed phrase for {} keypair", role))
+        .interact()
+        .map_err(|e| anyhow!("Failed to read seed phrase: {}", e))?;
+    let seed_phrase = seed_phrase.split_whitespace().collect::<Vec<_>>().join(" ");
+    let passphrase = Password::new()
+        .with_prompt("BIP39 passphrase (empty for none)")
+        .allow_empty_password(true)
+        .interact()
+        .map_err(|e| anyhow!("Failed to read passphrase: {}", e))?;
+    let keypair = match derivation_path {
+        Some(derivation_path) => {
+            let seed = generate_seed_from_seed_phrase_and_passphrase(&seed_phrase, &passphrase);
+            keypair_from_seed_and_derivation_path(&seed, Some(derivation_path))
+        }
+        None => keypair_from_seed_phrase_and_passphrase(&seed_phrase, &passphrase),
+    }
+    .map_err(|e| anyhow!("Failed to derive {} keypair: {}", role, e))?;
+    let confirmed = Confirm::new()
+        .with_prompt(format!("Use {} as the {}?", keypair.pubkey(), role))
+        .interact()
+        .map_err(|e| anyhow!("Failed to confirm {} keypair: {}", role, e))?;
+    if !confirmed {
+        return Err(anyhow!("{} keypair rejected", role));
+    }
+    Ok(keypair)
+}
+
+fn keypair_passphrase(role: &str, source: &str) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(KEYPAIR_PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    Password::new()
+        .with_prompt(format!("Passphrase for {} keypair {}", role, source))
+        .interact()
+        .map_err(|e| anyhow!("Failed to read passphrase: {}", e))
+}
+
+fn decode_keypair(data: &[u8], role: &str, source: &str) -> Result<Keypair> {
+    if is_age_encrypted(data) {
+        return decrypt_keypair(data, &keypair_passphrase(role, source)?);
+    }
+    let json = std::str::from_utf8(data).map_err(|e| anyhow!("Invalid keypair JSON: {}", e))?;
+    parse_keypair_json(json)
+}
+
+/// Loads a plain or age encrypted keypair from a file path, from stdin when `source` is `-`, or
+/// from a seed phrase prompt for `prompt:` sources.
+pub fn load_keypair(source: &str, role: &str) -> Result<Keypair> {
+    if let Some(derivation_path) = parse_prompt_source(source) {
+        return prompt_keypair(role, derivation_path?);
+    }
+    let data = if source == STDIN_SOURCE {
+        let mut data = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .map_err(|e| anyhow!("Failed to read {} keypair from stdin: {}", role, e))?;
+        data
+    } else {
+        std::fs::read(source)
+            .map_err(|e| anyhow!("Failed to read {} keypair from {}: {}", role, source, e))?
+    };
+    let source = if source == STDIN_SOURCE {
+        "stdin"
+    } else {
+        source
+    };
+    decode_keypair(&data, role, source)
+        .map_err(|e| anyhow!("Failed to read {} keypair from {}: {}", role, source, e))
+}
+
+/// Loads a signer from a keypair source, or connects to Vault for `vault://` sources.
+pub async fn load_signer(source: &str, role: &str) -> Result<SharedSigner> {
+    if let Some(key) = parse_vault_source(source) {
+        let signer = VaultSigner::connect(key?)
+            .await
+            .map_err(|e| anyhow!("Failed to load {} from Vault: {}", role, e))?;
+        return Ok(Arc::new(signer));
+    }
+    Ok(Arc::new(load_keypair(source, role)?))
+}
+
+/// Loads the payer from `--payer`, falling back to the `PAYER_KEYPAIR_JSON` environment variable.
+pub async fn load_payer(payer: Option<&str>) -> Result<SharedSigner> {
+    if let Some(source) = payer {
+        return load_signer(source, "payer").await;
+    }
+    let json = std::env::var(PAYER_KEYPAIR_JSON_ENV).map_err(|_| {
+        anyhow!(
+            "No payer keypair: pass --payer <PATH>, --payer - or set {}",
+            PAYER_KEYPAIR_JSON_ENV
+        )
+    })?;
+    let keypair = parse_keypair_json(&json).map_err(|e| {
+        anyhow!(
+            "Failed to read payer keypair from {}: {}",
+            PAYER_KEYPAIR_JSON_ENV,
+            e
+        )
+    })?;
+    Ok(Arc::new(keypair))
+}
+
+/// Loads the payer and the optional fee payer. Stdin can only be read once, so at most one of
+/// them may come from it.
+pub async fn load_signers(
+    payer: Option<&str>,
+    fee_payer: Option<&str>,
+) -> Result<(SharedSigner, Option<SharedSigner>)> {
+    if payer == Some(STDIN_SOURCE) && fee_payer == Some(STDIN_SOURCE) {
+        return Err(anyhow!(
+            "--payer and --fee-payer can't both be read from stdin"
+        ));
+    }
+    let payer = load_payer(payer).await?;
+    let fee_payer = match fee_payer {
+        Some(source) => Some(load_signer(source, "fee payer").await?),
+        None => None,
+    };
+    Ok((payer, fee_payer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keypair_json_round_trip() {
+        let keypair = Keypair::new();
+        let json = format!("{:?}\n", keypair.to_bytes().to_vec());
+        assert_eq!(
+            parse_keypair_json(&json).unwrap().pubkey(),
+            keypair.pubkey()
+        );
+    }
+
+    #[test]
+    fn test_parse_keypair_json_rejects_garbage() {
+        assert!(parse_keypair_json("not a keypair").is_err());
+        assert!(parse_keypair_json("[1, 2, 3]").is_err());
+    }
+
+    #[test]
+    fn test_decrypt_passphrase_encrypted_keypair() {
+        use std::io::Write;
+
+        let keypair = Keypair::new();
+        let json = format!("{:?}", keypair.to_bytes().to_vec());
+        let mut encrypted = Vec::new();
+        let mut writer = age::Encryptor::with_user_passphrase(Secret::new("hunter2".to_string()))
+            .wrap_output(&mut encrypted)
+            .unwrap();
+        writer.write_all(json.as_bytes()).unwrap();
+        writer.finish().unwrap();
+
+        assert!(is_age_encrypted(&encrypted));
+        assert!(!is_age_encrypted(json.as_bytes()));
+        assert_eq!(
+            decrypt_keypair(&encrypted, "hunter2").unwrap().pubkey(),
+            keypair.pubkey()
+        );
+        assert!(decrypt_keypair(&encrypted, "wrong").is_err());
+    }
+
+    #[test]
+    fn test_parse_prompt_sources() {
+        assert!(parse_prompt_source("/path/to/id.json").is_none());
+        assert_eq!(parse_prompt_source("prompt://").unwrap().unwrap(), None);
+        assert_eq!(parse_prompt_source("prompt:").unwrap().unwrap(), None);
+        assert_eq!(
+            parse_prompt_source("prompt://bip44?account=0")
+                .unwrap()
+                .unwrap(),
+            Some(DerivationPath::new_bip44(Some(0), None))
+        );
+        assert_eq!(
+            parse_prompt_source("prompt://bip44?account=2&change=1")
+                .unwrap()
+                .unwrap(),
+            Some(DerivationPath::new_bip44(Some(2), Some(1)))
+        );
+        assert_eq!(
+            parse_prompt_source("prompt://?key=3/0").unwrap().unwrap(),
+            Some(DerivationPath::new_bip44(Some(3), Some(0)))
+        );
+        assert_eq!(
+            parse_prompt_source("prompt://?full-path=m/44/501/1/0")
+                .unwrap()
+                .unwrap(),
+            Some(DerivationPath::new_bip44(Some(1), Some(0)))
+        );
+        assert!(parse_prompt_source("prompt://ledger").unwrap().is_err());
+        assert!(parse_prompt_source("prompt://bip44?account=x")
+            .unwrap()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_both_signers_from_stdin_rejected() {
+        assert!(load_signers(Some(STDIN_SOURCE), Some(STDIN_SOURCE))
+            .await
+            .is_err());
+    }
+}
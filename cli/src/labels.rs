@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use clap::Args;
+use solana_sdk::pubkey::Pubkey;
+
+static LABELS: OnceLock<HashMap<Pubkey, String>> = OnceLock::new();
+
+/// Options for naming pubkeys in the output.
+#[derive(Clone, Debug, Default, Args)]
+pub struct LabelArgs {
+    /// JSON file mapping pubkeys of bonds, issuers, vote accounts or payers to local labels,
+    /// e.g. `{"<PUBKEY>": "Acme Capital bond #3"}`. Labels are shown with the pubkeys in logs,
+    /// reports, prompts and notifications.
+    #[arg(long, global = true, env)]
+    pub labels_file: Option<PathBuf>,
+}
+
+fn parse_labels(contents: &str) -> Result<HashMap<Pubkey, String>> {
+    let labels: HashMap<String, String> = serde_json::from_str(contents)?;
+    labels
+        .into_iter()
+        .map(|(pubkey, label)| {
+            let pubkey = Pubkey::from_str(&pubkey)
+                .map_err(|e| anyhow!("Invalid pubkey {}: {}", pubkey, e))?;
+            Ok((pubkey, label))
+        })
+        .collect()
+}
+
+/// Reads and installs the labels used by [`labelled`] and [`label_or_pubkey`].
+pub fn init_labels(args: &LabelArgs) -> Result<()> {
+    let Some(path) = &args.labels_file else {
+        return Ok(());
+    };
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let labels = parse_labels(&contents)
+        .map_err(|e| anyhow!("Invalid labels file {}: {}", path.display(), e))?;
+    LABELS
+        .set(labels)
+        .map_err(|_| anyhow!("The labels are already loaded"))
+}
+
+fn with_label(labels: Option<&HashMap<Pubkey, String>>, pubkey: &Pubkey) -> String {
+    match labels.and_then(|labels| labels.get(pubkey)) {
+        Some(label) => format!("{} ({})", label, pubkey),
+        None => pubkey.to_string(),
+    }
+}
+
+/// The pubkey with its label, e.g. `Acme Capital bond #3 (7xKX...)`, or just the pubkey when it
+/// has none.
+pub fn labelled(pubkey: &Pubkey) -> String {
+    with_label(LABELS.get(), pubkey)
+}
+
+/// The label of the pubkey, or the pubkey when it has none, for table columns.
+pub fn label_or_pubkey(pubkey: &Pubkey) -> String {
+    LABELS
+        .get()
+        .and_then(|labels| labels.get(pubkey))
+        .cloned()
+        .unwrap_or_else(|| pubkey.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels() {
+        let bond = Pubkey::new_unique();
+        let labels = parse_labels(&format!(r#"{{"{}": "Acme Capital bond #3"}}"#, bond)).unwrap();
+        assert_eq!(
+            with_label(Some(&labels), &bond),
+            format!("Acme Capital bond #3 ({})", bond)
+        );
+        let other = Pubkey::new_unique();
+        assert_eq!(with_label(Some(&labels), &other), other.to_string());
+        assert!(parse_labels(r#"{"not-a-pubkey": "Acme"}"#).is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use clap::{Args, ValueEnum};
+
+/// When the log file is rotated regardless of its size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogRotation {
+    Never,
+    Hourly,
+    #[default]
+    Daily,
+}
+
+impl LogRotation {
+    /// Identifies the period `time` falls in, rotating whenever it changes.
+    fn period(self, time: DateTime<Utc>) -> String {
+        match self {
+            LogRotation::Never => String::new(),
+            LogRotation::Hourly => time.format("%Y%m%d%H").to_string(),
+            LogRotation::Daily => time.format("%Y%m%d").to_string(),
+        }
+    }
+}
+
+/// Options for writing logs to a file as well as stderr.
+#[derive(Clone, Debug, Args)]
+pub struct LogFileArgs {
+    /// Also append logs to this file, rotating it by size and time. Rotated files are renamed to
+    /// `<PATH>.<UTC timestamp>`.
+    #[arg(long, global = true, env)]
+    pub log_file: Option<PathBuf>,
+    /// Size (in MB) past which the log file is rotated, 0 to only rotate by time.
+    #[arg(long, global = true, env, default_value = "100")]
+    pub log_max_size_mb: u64,
+    /// Time based rotation of the log file.
+    #[arg(long, global = true, env, value_enum, default_value_t = LogRotation::Daily)]
+    pub log_rotation: LogRotation,
+    /// Rotated log files to keep, the oldest are deleted first. 0 keeps all of them.
+    #[arg(long, global = true, env, default_value = "14")]
+    pub log_max_files: usize,
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// A log file rotated by size and time, keeping a bounded number of rotated files.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: Option<u64>,
+    rotation: LogRotation,
+    max_files: usize,
+    file: File,
+    size: u64,
+    period: String,
+}
+
+impl RotatingFile {
+    pub fn open(args: &LogFileArgs, path: PathBuf) -> Result<Self> {
+        let file = open_append(&path)
+            .map_err(|e| anyhow!("Failed to open log file {}: {}", path.display(), e))?;
+        let metadata = file
+            .metadata()
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        // An existing file is rotated on the first write if it was last written in an earlier
+        // period.
+        let modified = metadata
+            .modified()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+        Ok(Self {
+            max_bytes: (args.log_max_size_mb > 0).then(|| args.log_max_size_mb * 1024 * 1024),
+            rotation: args.log_rotation,
+            max_files: args.log_max_files,
+            size: metadata.len(),
+            period: args.log_rotation.period(modified),
+            file,
+            path,
+        })
+    }
+
+    fn should_rotate(&self, len: usize, now: DateTime<Utc>) -> bool {
+        self.size > 0
+            && (self
+                .max_bytes
+                .is_some_and(|max_bytes| self.size + len as u64 > max_bytes)
+                || self.rotation.period(now) != self.period)
+    }
+
+    fn rotated_path(&self, now: DateTime<Utc>) -> PathBuf {
+        let base = format!("{}.{}", self.path.display(), now.format("%Y%m%dT%H%M%S"));
+        let mut rotated = PathBuf::from(&base);
+        let mut n = 1;
+        while rotated.exists() {
+            rotated = PathBuf::from(format!("{}-{}", base, n));
+            n += 1;
+        }
+        rotated
+    }
+
+    fn rotate(&mut self, now: DateTime<Utc>) -> io::Result<()> {
+        self.file.flush()?;
+        fs::rename(&self.path, self.rotated_path(now))?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.prune()
+    }
+
+    /// Deletes the oldest rotated files beyond `max_files`.
+    fn prune(&self) -> io::Result<()> {
+        if self.max_files == 0 {
+            return Ok(());
+        }
+        let Some(name) = self.path.file_name().and_then(|name| name.to_str()) else {
+            return Ok(());
+        };
+        let prefix = format!("{}.", name);
+        let dir = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let mut rotated: Vec<PathBuf> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|file_name| file_name.starts_with(&prefix))
+            })
+            .map(|entry| entry.path())
+            .collect();
+        // Timestamped names sort oldest first.
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.max_files);
+        for path in &rotated[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, buf: &[u8], now: DateTime<Utc>) -> io::Result<usize> {
+        if self.should_rotate(buf.len(), now) {
+            self.rotate(now)?;
+        }
+        self.period = self.rotation.period(now);
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_at(buf, Utc::now())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Writes logs to stderr and a rotating file.
+pub struct TeeWriter {
+    file: RotatingFile,
+}
+
+impl TeeWriter {
+    pub fn new(file: RotatingFile) -> Self {
+        Self { file }
+    }
+}
+
+impl Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A full or unwritable log file shouldn't hide logs from stderr.
+        let _ = io::stderr().write_all(buf);
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let _ = io::stderr().flush();
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn args(log_max_size_mb: u64, log_max_files: usize) -> LogFileArgs {
+        LogFileArgs {
+            log_file: None,
+            log_max_size_mb,
+            log_rotation: LogRotation::Daily,
+            log_max_files,
+        }
+    }
+
+    fn rotated_files(dir: &Path) -> usize {
+        fs::read_dir(dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_str()
+                    .unwrap()
+                    .starts_with("manager.log.")
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_rotates_by_size_and_time_and_prunes() {
+        let dir = std::env::temp_dir().join(format!("pye-log-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("manager.log");
+        let mut file = RotatingFile::open(&args(1, 2), path.clone()).unwrap();
+        let day = Utc.with_ymd_and_hms(2026, 10, 15, 12, 0, 0).unwrap();
+        let line = vec![b'x'; 600 * 1024];
+
+        file.write_at(&line, day).unwrap();
+        assert_eq!(rotated_files(&dir), 0);
+        // Past 1 MB.
+        file.write_at(&line, day).unwrap();
+        assert_eq!(rotated_files(&dir), 1);
+        assert_eq!(fs::metadata(&path).unwrap().len(), line.len() as u64);
+        // The next day.
+        file.write_at(b"next day\n", day + chrono::Duration::days(1))
+            .unwrap();
+        assert_eq!(rotated_files(&dir), 2);
+        assert_eq!(fs::read(&path).unwrap(), b"next day\n");
+        // Only the two newest rotated files are kept.
+        file.write_at(&line, day + chrono::Duration::days(2))
+            .unwrap();
+        assert_eq!(rotated_files(&dir), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
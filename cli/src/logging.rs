@@ -0,0 +1,132 @@
+use anyhow::Result;
+use clap::{ArgAction, Args, ValueEnum};
+use env_logger::Target;
+
+use crate::log_file::{LogFileArgs, RotatingFile, TeeWriter};
+
+/// Log target of per-block logs while scanning an epoch, e.g. skipped blocks and fetch retries.
+pub const BLOCK_FETCH_TARGET: &str = "pye_cli::block_fetch";
+
+/// Per-module filters applied on top of the log level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogPreset {
+    /// Every module logs at the selected level.
+    #[default]
+    All,
+    /// Silences per-block fetch logs, epoch boundary polling and metrics submission, keeping
+    /// reward totals, payments and alerts.
+    Payments,
+    /// Debug logs of the reward calculations and per-block fetches, everything else at the
+    /// selected level.
+    Rewards,
+}
+
+impl LogPreset {
+    fn directives(self) -> &'static [&'static str] {
+        match self {
+            LogPreset::All => &[],
+            LogPreset::Payments => &[
+                "pye_cli::block_fetch=error",
+                "pye_cli::rpc_utils=warn",
+                "pye_cli::epoch_boundary=warn",
+                "solana_metrics=warn",
+            ],
+            LogPreset::Rewards => &["pye_cli::rewards=debug", "pye_cli::block_fetch=debug"],
+        }
+    }
+}
+
+/// Options controlling what gets logged, without crafting RUST_LOG expressions.
+#[derive(Clone, Debug, Args)]
+pub struct LogArgs {
+    /// Log more: `--verbose` adds this tool's debug logs, given twice its trace logs and the debug
+    /// logs of its dependencies. There's no `-v`, which subcommands use for --vote-pubkey.
+    #[arg(long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+    /// Only log warnings and errors, and hide progress bars. Progress bars are also hidden when
+    /// stderr isn't a terminal.
+    #[arg(short, long, global = true, env)]
+    pub quiet: bool,
+    /// Per-module filtering preset.
+    #[arg(long, global = true, env, value_enum, default_value_t = LogPreset::All)]
+    pub log_preset: LogPreset,
+    #[command(flatten)]
+    pub file: LogFileArgs,
+}
+
+impl LogArgs {
+    /// The env_logger filter for these options. `RUST_LOG`, when set, replaces the default info
+    /// level, while `--verbose`/`-q` and the preset take precedence over it.
+    pub fn filters(&self, rust_log: Option<&str>) -> String {
+        let level = match (self.quiet, self.verbose) {
+            (true, _) => "warn",
+            (false, 0) => "info",
+            (false, 1) => "info,pye_cli=debug",
+            (false, _) => "debug,pye_cli=trace",
+        };
+        let mut filters = match rust_log.filter(|rust_log| !rust_log.trim().is_empty()) {
+            Some(rust_log) if !self.quiet && self.verbose == 0 => rust_log.to_string(),
+            _ => level.to_string(),
+        };
+        for directive in self.log_preset.directives() {
+            filters.push(',');
+            filters.push_str(directive);
+        }
+        filters
+    }
+
+    pub fn init(&self) -> Result<()> {
+        let rust_log = std::env::var("RUST_LOG").ok();
+        let mut builder = env_logger::Builder::new();
+        builder.parse_filters(&self.filters(rust_log.as_deref()));
+        if let Some(path) = &self.file.log_file {
+            let file = RotatingFile::open(&self.file, path.clone())?;
+            builder.target(Target::Pipe(Box::new(TeeWriter::new(file))));
+        }
+        builder.init();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_file::LogRotation;
+
+    fn args(verbose: u8, quiet: bool, log_preset: LogPreset) -> LogArgs {
+        LogArgs {
+            verbose,
+            quiet,
+            log_preset,
+            file: LogFileArgs {
+                log_file: None,
+                log_max_size_mb: 100,
+                log_rotation: LogRotation::Daily,
+                log_max_files: 14,
+            },
+        }
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(args(0, false, LogPreset::All).filters(None), "info");
+        assert_eq!(args(0, true, LogPreset::All).filters(None), "warn");
+        assert_eq!(
+            args(2, false, LogPreset::All).filters(None),
+            "debug,pye_cli=trace"
+        );
+        // RUST_LOG replaces the default level, but not an explicit --verbose or -q.
+        assert_eq!(
+            args(0, false, LogPreset::All).filters(Some("info,solana_metrics=warn")),
+            "info,solana_metrics=warn"
+        );
+        assert_eq!(
+            args(1, false, LogPreset::All).filters(Some("error")),
+            "info,pye_cli=debug"
+        );
+        assert_eq!(
+            args(0, true, LogPreset::Payments).filters(Some("")),
+            "warn,pye_cli::block_fetch=error,pye_cli::rpc_utils=warn,pye_cli::epoch_boundary=warn,solana_metrics=warn"
+        );
+    }
+}
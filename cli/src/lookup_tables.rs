@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use log::info;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::address_lookup_table::instruction::{create_lookup_table, extend_lookup_table};
+use solana_sdk::address_lookup_table::state::AddressLookupTable;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::AddressLookupTableAccount;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signer::Signer;
+use tokio::time::sleep;
+
+use crate::exit_code::rpc_error;
+use crate::transactions::{send_versioned, SendOptions, TransactionVersion};
+
+/// Addresses added per extend transaction, keeping it well under the packet size limit.
+const EXTEND_CHUNK_SIZE: usize = 20;
+
+pub async fn fetch_lookup_table(
+    client: &RpcClient,
+    address: &Pubkey,
+) -> Result<AddressLookupTableAccount> {
+    let account = client
+        .get_account(address)
+        .await
+        .map_err(|e| rpc_error(format!("Failed to fetch lookup table {}", address), e))?;
+    let table = AddressLookupTable::deserialize(&account.data)
+        .map_err(|e| anyhow!("Failed to deserialize lookup table {}: {}", address, e))?;
+    Ok(AddressLookupTableAccount {
+        key: *address,
+        addresses: table.addresses.to_vec(),
+    })
+}
+
+/// Accounts of `instructions` that a lookup table can hold: signers and invoked programs must stay
+/// in the static account keys.
+pub fn lookup_candidates(instructions: &[Instruction], signers: &[Pubkey]) -> Vec<Pubkey> {
+    let programs: HashSet<Pubkey> = instructions.iter().map(|ix| ix.program_id).collect();
+    let mut seen = HashSet::new();
+    instructions
+        .iter()
+        .flat_map(|ix| ix.accounts.iter())
+        .filter(|meta| !meta.is_signer && !signers.contains(&meta.pubkey))
+        .map(|meta| meta.pubkey)
+        .filter(|pubkey| !programs.contains(pubkey) && seen.insert(*pubkey))
+        .collect()
+}
+
+/// Lookups only resolve addresses added in an earlier slot, so wait for the slot to advance.
+async fn wait_for_next_slot(client: &RpcClient) -> Result<()> {
+    let slot = client
+        .get_slot_with_commitment(CommitmentConfig::confirmed())
+        .await?;
+    while client
+        .get_slot_with_commitment(CommitmentConfig::confirmed())
+        .await?
+        <= slot
+    {
+        sleep(Duration::from_millis(400)).await;
+    }
+    Ok(())
+}
+
+/// Creates a lookup table owned by `authority`, which also pays for it.
+pub async fn create_table(
+    client: &RpcClient,
+    authority: &dyn Signer,
+    send_options: &SendOptions,
+) -> Result<Pubkey> {
+    let recent_slot = client
+        .get_slot_with_commitment(CommitmentConfig::finalized())
+        .await?;
+    let (instruction, address) =
+        create_lookup_table(authority.pubkey(), authority.pubkey(), recent_slot);
+    // The lookup table's own management transactions are always legacy.
+    send_versioned(
+        client,
+        &[instruction],
+        &[authority],
+        send_options,
+        TransactionVersion::Legacy,
+        &[],
+    )
+    .await?;
+    info!("Created lookup table {}", address);
+    Ok(address)
+}
+
+/// Adds every address missing from `table`, signed and paid for by the table's `authority`, and
+/// returns the table as it will be seen by the next transaction.
+pub async fn extend_table(
+    client: &RpcClient,
+    table: AddressLookupTableAccount,
+    authority: &dyn Signer,
+    addresses: &[Pubkey],
+    send_options: &SendOptions,
+) -> Result<AddressLookupTableAccount> {
+    let missing: Vec<Pubkey> = addresses
+        .iter()
+        .filter(|address| !table.addresses.contains(address))
+        .copied()
+        .collect();
+    if missing.is_empty() {
+        return Ok(table);
+    }
+    for chunk in missing.chunks(EXTEND_CHUNK_SIZE) {
+        let instruction = extend_lookup_table(
+            table.key,
+            authority.pubkey(),
+            Some(authority.pubkey()),
+            chunk.to_vec(),
+        );
+        send_versioned(
+            client,
+            &[instruction],
+            &[authority],
+            send_options,
+            TransactionVersion::Legacy,
+            &[],
+        )
+        .await?;
+    }
+    info!(
+        "Extended lookup table {} with {} addresses",
+        table.key,
+        missing.len()
+    );
+    wait_for_next_slot(client).await?;
+    fetch_lookup_table(client, &table.key).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::instruction::AccountMeta;
+
+    #[test]
+    fn test_lookup_candidates_skip_signers_and_programs() {
+        let signer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let account = Pubkey::new_unique();
+        let instructions = [
+            Instruction::new_with_bytes(
+                program,
+                &[],
+                vec![
+                    AccountMeta::new(signer, true),
+                    AccountMeta::new(account, false),
+                ],
+            ),
+            Instruction::new_with_bytes(
+                Pubkey::new_unique(),
+                &[],
+                vec![
+                    AccountMeta::new_readonly(account, false),
+                    AccountMeta::new_readonly(program, false),
+                ],
+            ),
+        ];
+        assert_eq!(lookup_candidates(&instructions, &[signer]), vec![account]);
+    }
+}
@@ -1,19 +1,105 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use cluster::ClusterPreset;
+use commands::analytics::*;
+use commands::archive_urls::*;
+use commands::create_lookup_table::*;
+use commands::diff_report::*;
+use commands::discover_issuers::*;
+use commands::doctor::*;
+use commands::fleet_manager::*;
+use commands::ledger::*;
+use commands::manager_ctl::*;
+use commands::migrate_state_db::*;
+use commands::resend::*;
+use commands::retry_failed::*;
 use commands::transfer_excess_rewards::*;
 use commands::validator_pye_account_manager::*;
+use commands::verify_attestation::*;
+use commands::watch::*;
+use exit_code::{ConfigError, ExitCode};
+use http::ProxyArgs;
+use labels::LabelArgs;
+use logging::LogArgs;
+use metrics::MetricsArgs;
+use notifications::NotificationArgs;
+use rewards::block_archive::BlockArchiveArgs;
+use rewards::block_rewards::{BlockRewardComponents, SlotExportArgs, VerifyRpcArgs};
+use rewards::inflation_model::InflationVerifyArgs;
+use rewards::RoundingMode;
+use rpc_fixtures::FixtureArgs;
+use solana_sdk::pubkey::Pubkey;
+use table::TableArgs;
+use transactions::SendOptions;
 
 pub mod accounts;
 pub mod active_stake;
+pub mod adjustments;
+pub mod alert_thresholds;
+pub mod archive;
+pub mod attestations;
+pub mod bond_scheduling;
+pub mod bond_schema;
+pub mod bond_state;
+pub mod bond_watch;
+pub mod cluster;
 pub mod commands;
+pub mod commission_guard;
+pub mod control;
+pub mod dashboard;
+pub mod datadog;
+pub mod email;
+pub mod epoch_boundary;
+pub mod epoch_progress;
+pub mod events;
+pub mod exit_code;
+pub mod heartbeat;
+pub mod http;
+pub mod installments;
+pub mod keypairs;
+pub mod labels;
+pub mod log_file;
+pub mod logging;
+pub mod lookup_tables;
+pub mod metrics;
 pub mod metrics_helpers;
+pub mod notifications;
+pub mod operating_cost;
+pub mod payer_balance;
+pub mod payment_review;
+pub mod progress;
+pub mod receipts;
 pub mod rewards;
+pub mod rpc_fixtures;
+pub mod rpc_usage;
 pub mod rpc_utils;
+pub mod schedule;
+pub mod snapshot;
+pub mod sol_format;
+pub mod spending_limits;
+pub mod stake_lineage;
+pub mod state_db;
+pub mod table;
 pub mod transactions;
+pub mod vault;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct Cli {
+    #[command(flatten)]
+    log: LogArgs,
+    #[command(flatten)]
+    metrics: MetricsArgs,
+    #[command(flatten)]
+    proxy: ProxyArgs,
+    #[command(flatten)]
+    labels: LabelArgs,
+    #[command(flatten)]
+    tables: TableArgs,
+    /// Exit with 10 when nothing is owed and 11 after a dry run, instead of 0. See the README for
+    /// every exit code.
+    #[arg(long, global = true, env)]
+    detailed_exit_codes: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -22,19 +108,26 @@ struct Cli {
 enum Commands {
     /// Transfer excess rewards collected for the last completed epoch to SoloValiatorPyeAccount.
     TransferExcessRewards {
-        /// RPC Endpoint
-        #[arg(
-            short,
-            long,
-            env,
-            default_value = "https://api.mainnet-beta.solana.com"
-        )]
-        rpc: String,
-        /// Path to payer keypair
+        /// Cluster preset selecting the default RPC endpoint, program ID and MEV source.
+        #[arg(long, env, value_enum, default_value_t = ClusterPreset::Mainnet)]
+        cluster: ClusterPreset,
+        /// RPC Endpoint [default: the cluster preset's endpoint]
         #[arg(short, long, env)]
-        payer: String,
-        /// SoloValidatorPyeAccount's pubkey
+        rpc: Option<String>,
+        /// The Pye program ID the SoloValidatorPyeAccount must be owned by [default: the cluster
+        /// preset's program ID]
+        #[arg(long, env)]
+        program_id: Option<Pubkey>,
+        /// Path to payer keypair, or `-` to read it from stdin [default: the PAYER_KEYPAIR_JSON
+        /// environment variable]
         #[arg(short, long, env)]
+        payer: Option<String>,
+        /// Path to a keypair that pays transaction fees instead of the payer, which then only
+        /// funds the transferred rewards. `-` reads it from stdin.
+        #[arg(long, env)]
+        fee_payer: Option<String>,
+        /// SoloValidatorPyeAccount's pubkey
+        #[arg(long, env)]
         pye_account: String,
         /// Maximum RPC requests to send concurrently.
         #[arg(long, env, default_value = "50")]
@@ -42,9 +135,66 @@ enum Commands {
         /// Dry mode to calculate excess rewards without transferring.
         #[arg(long, env)]
         dry_run: bool,
-        /// The wait time (in secs) between get_block RPC call retries.
+        /// Longest wait (in secs) between get_block RPC call retries, which back off exponentially
+        /// from 2s with jitter. Network errors are first retried immediately.
         #[arg(long, env, default_value = "1800")]
         block_retry_delay: u64,
+        /// Time (in secs) after which a block's fetch stops being retried and the scan fails.
+        #[arg(long, env, default_value = "9000")]
+        block_retry_deadline: u64,
+        /// Which parts of the leader's block fee reward count toward the bond obligation.
+        #[arg(long, env, value_enum, default_value_t = BlockRewardComponents::All)]
+        block_reward_components: BlockRewardComponents,
+        /// Jito API endpoint used to look up the validator's MEV rewards [default: the cluster
+        /// preset's Jito API, MEV is treated as zero on clusters without one]
+        #[arg(long, env)]
+        jito_api_url: Option<String>,
+        /// Rounding applied to integer divisions in the reward calculations.
+        #[arg(long, env, value_enum, default_value_t = RoundingMode::Floor)]
+        rounding: RoundingMode,
+        /// Inflation commission (0-100) assumed when the RPC reports a reward without commission
+        /// data. When unset, such rewards are skipped with a warning.
+        #[arg(long, env, value_parser = clap::value_parser!(u8).range(0..=100))]
+        assumed_inflation_commission: Option<u8>,
+        /// Reconstruct the validator's commission over the target epoch from the vote account's
+        /// history, flagging mid-epoch changes and using the commission in effect at epoch end.
+        #[arg(long, env)]
+        track_commission_changes: bool,
+        /// Use the MEV commission the target epoch's tip distribution account was created with,
+        /// read on-chain or from its creation transaction, instead of the Jito API's.
+        #[arg(long, env)]
+        track_mev_commission: bool,
+        /// Follow the history of the bond's stake accounts since the target epoch and count the
+        /// stake split, merged or moved out of them toward the bond.
+        #[arg(long, env)]
+        track_stake_accounts: bool,
+        /// Pin the bond and stake account reads to this slot and compute the epoch before the
+        /// one containing it, failing if an account was modified since. Two runs with the same
+        /// slot read identical inputs.
+        #[arg(long, env, requires = "dry_run")]
+        as_of_slot: Option<u64>,
+        #[command(flatten)]
+        verify: VerifyRpcArgs,
+        #[command(flatten)]
+        slot_export: SlotExportArgs,
+        #[command(flatten)]
+        block_archive: BlockArchiveArgs,
+        #[command(flatten)]
+        verify_inflation: InflationVerifyArgs,
+        #[command(flatten)]
+        send: SendOptions,
+        #[command(flatten)]
+        fixtures: FixtureArgs,
+        /// Keep running and process the bond at every epoch boundary, paying without a prompt
+        /// (or only computing with --dry-run) and notifying the outcome.
+        #[arg(long, env, conflicts_with = "as_of_slot")]
+        daemon: bool,
+        /// The wait time (in secs) between --daemon's epoch boundary checks once the boundary is
+        /// estimated to be close.
+        #[arg(long, env, default_value = "60", requires = "daemon")]
+        cycle_secs: u64,
+        #[command(flatten)]
+        notifications: NotificationArgs,
     },
 
     /// Will run the excess rewards stuff for all pye_accounts owned by a validator
@@ -52,36 +202,225 @@ enum Commands {
         #[command(flatten)]
         args: ValidatorPyeAccountManagerArgs,
     },
+
+    /// Run validator-pye-account-manager for every validator of a fleet config in one process.
+    FleetManager {
+        #[command(flatten)]
+        args: FleetManagerArgs,
+    },
+
+    /// List the issuers of bonds delegating to a vote account, with bond counts and total stake.
+    DiscoverIssuers {
+        #[command(flatten)]
+        args: DiscoverIssuersArgs,
+    },
+
+    /// Create an address lookup table for v0 transfer transactions.
+    CreateLookupTable {
+        #[command(flatten)]
+        args: CreateLookupTableArgs,
+    },
+
+    /// Query and control a running validator-pye-account-manager over its gRPC control API.
+    ManagerCtl {
+        #[command(flatten)]
+        args: ManagerCtlArgs,
+    },
+
+    /// Create or upgrade the tables of a PostgreSQL state database shared by managers.
+    MigrateStateDb {
+        #[command(flatten)]
+        args: MigrateStateDbArgs,
+    },
+
+    /// Verify the signature of a payment attestation.
+    VerifyAttestation {
+        #[command(flatten)]
+        args: VerifyAttestationArgs,
+    },
+
+    /// Compare two breakdown reports and list the differences per bond and category.
+    DiffReport {
+        #[command(flatten)]
+        args: DiffReportArgs,
+    },
+
+    /// Aggregate the receipts of a range of epochs per category, issuer and bond.
+    Analytics {
+        #[command(flatten)]
+        args: AnalyticsArgs,
+    },
+
+    /// Print signed URLs of an epoch's archived reports, receipts and attestations, to share
+    /// them with issuers.
+    ArchiveUrls {
+        #[command(flatten)]
+        args: ArchiveUrlsArgs,
+    },
+
+    /// Query the payments recorded in a receipts directory or state database, e.g. which epochs
+    /// were paid.
+    Ledger {
+        #[command(flatten)]
+        args: LedgerArgs,
+    },
+
+    /// Stream the lifecycle events of the bonds targeting a vote account: new bonds, deposits,
+    /// maturities and closures.
+    Watch {
+        #[command(flatten)]
+        args: WatchArgs,
+    },
+
+    /// Retry the payments recorded as failed in a receipts directory.
+    RetryFailed {
+        #[command(flatten)]
+        args: RetryFailedArgs,
+    },
+
+    /// Pay a single bond's excess rewards for an epoch, e.g. for a support case, after checking
+    /// it wasn't paid already.
+    Resend {
+        #[command(flatten)]
+        args: ResendArgs,
+    },
+
+    /// Check the RPC, keypairs, program, vote account and Jito API before an epoch boundary.
+    #[command(alias = "validate-config")]
+    Doctor {
+        #[command(flatten)]
+        args: DoctorArgs,
+    },
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> std::process::ExitCode {
     let cli = Cli::parse();
+    let detailed_exit_codes = cli.detailed_exit_codes;
+    let exit_code = match run(cli).await {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::of(&e)
+        }
+    };
+    std::process::ExitCode::from(exit_code.code(detailed_exit_codes))
+}
 
+async fn run(cli: Cli) -> Result<ExitCode> {
     // Setup logging to InfluxDB with solana_metrics
-    env_logger::init();
-    solana_metrics::set_host_id("pye_cli".to_string());
-    solana_metrics::set_panic_hook("pye_cli", Some(env!("CARGO_PKG_VERSION").to_string()));
+    cli.log.init().context(ConfigError)?;
+    metrics::init(&cli.metrics).context(ConfigError)?;
+    http::init_proxy(&cli.proxy).context(ConfigError)?;
+    labels::init_labels(&cli.labels).context(ConfigError)?;
+    table::init_tables(&cli.tables).context(ConfigError)?;
+    if cli.log.quiet {
+        progress::disable();
+    }
 
     match cli.command {
         Commands::TransferExcessRewards {
+            cluster,
             rpc,
+            program_id,
             payer,
+            fee_payer,
             pye_account,
             concurrency,
             dry_run,
             block_retry_delay,
+            block_retry_deadline,
+            block_reward_components,
+            jito_api_url,
+            rounding,
+            assumed_inflation_commission,
+            track_commission_changes,
+            track_mev_commission,
+            track_stake_accounts,
+            as_of_slot,
+            verify,
+            slot_export,
+            block_archive,
+            verify_inflation,
+            send,
+            fixtures,
+            daemon,
+            cycle_secs,
+            notifications,
         } => {
             handle_transfer_excess_rewards(TransferExcessRewardsArgs {
+                cluster,
                 rpc,
-                payer_file_path: payer,
+                program_id,
+                payer,
+                fee_payer,
                 pye_account,
                 concurrency,
                 dry_run,
                 block_retry_delay,
+                block_retry_deadline,
+                block_reward_components,
+                jito_api_url,
+                rounding,
+                assumed_inflation_commission,
+                track_commission_changes,
+                track_mev_commission,
+                track_stake_accounts,
+                as_of_slot,
+                verify,
+                slot_export,
+                block_archive,
+                verify_inflation,
+                send,
+                fixtures,
+                daemon,
+                cycle_secs,
+                notifications,
             })
             .await
         }
-        Commands::ValidatorPyeAccountManager { args } => handle_validator_pye_account_manager(args).await,
+        Commands::ValidatorPyeAccountManager { args } => handle_validator_pye_account_manager(args)
+            .await
+            .map(|()| ExitCode::Success),
+        Commands::FleetManager { args } => {
+            handle_fleet_manager(args).await.map(|()| ExitCode::Success)
+        }
+        Commands::DiscoverIssuers { args } => handle_discover_issuers(args)
+            .await
+            .map(|()| ExitCode::Success),
+        Commands::CreateLookupTable { args } => handle_create_lookup_table(args)
+            .await
+            .map(|()| ExitCode::Success),
+        Commands::ManagerCtl { args } => handle_manager_ctl(args).await.map(|()| ExitCode::Success),
+        Commands::MigrateStateDb { args } => handle_migrate_state_db(args)
+            .await
+            .map(|()| ExitCode::Success),
+        Commands::VerifyAttestation { args } => handle_verify_attestation(args)
+            .await
+            .map(|()| ExitCode::Success),
+        Commands::DiffReport { args } => handle_diff_report(args).await.map(|()| ExitCode::Success),
+        Commands::Analytics { args } => handle_analytics(args).await.map(|()| ExitCode::Success),
+        Commands::ArchiveUrls { args } => {
+            handle_archive_urls(args).await.map(|()| ExitCode::Success)
+        }
+        Commands::Ledger { args } => handle_ledger(args).await.map(|()| ExitCode::Success),
+        Commands::Watch { args } => handle_watch(args).await.map(|()| ExitCode::Success),
+        Commands::RetryFailed { args } => {
+            handle_retry_failed(args).await.map(|()| ExitCode::Success)
+        }
+        Commands::Resend { args } => handle_resend(args).await.map(|()| ExitCode::Success),
+        Commands::Doctor { args } => handle_doctor(args).await.map(|()| ExitCode::Success),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        // Catches flags clashing across flattened and global arguments, e.g. two `-v`.
+        Cli::command().debug_assert();
     }
 }
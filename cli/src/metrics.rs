@@ -0,0 +1,200 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use clap::Args;
+use log::info;
+use solana_metrics::datapoint::DataPoint;
+use tokio::task::JoinHandle;
+
+use crate::datadog::DogStatsd;
+
+/// Whether datapoints are submitted to InfluxDB, set once the metrics identity is configured.
+static INFLUX: AtomicBool = AtomicBool::new(false);
+/// Datadog agent receiving datapoints as well, if configured.
+static DATADOG: OnceLock<DogStatsd> = OnceLock::new();
+/// Tags added to every datapoint.
+static TAGS: OnceLock<Vec<(&'static str, String)>> = OnceLock::new();
+
+tokio::task_local! {
+    /// Name of the fleet validator whose manager submits the datapoint.
+    static VALIDATOR_NAME: String;
+}
+
+/// Options controlling the identity of the submitted metrics.
+#[derive(Clone, Debug, Args)]
+pub struct MetricsArgs {
+    /// Host ID tagging every datapoint, telling apart several managers reporting to one
+    /// database.
+    #[arg(long, global = true, env, default_value = "pye_cli")]
+    pub metrics_host_id: String,
+    /// Tag added to every datapoint as `KEY=VALUE`, e.g. `validator=my-validator` or
+    /// `environment=mainnet`. Repeat it or comma separate `METRICS_TAGS` for several tags.
+    #[arg(
+        long = "metrics-tag",
+        global = true,
+        env = "METRICS_TAGS",
+        value_delimiter = ',',
+        value_parser = parse_tag
+    )]
+    pub metrics_tags: Vec<(String, String)>,
+    /// Don't submit metrics, even with SOLANA_METRICS_CONFIG or --datadog-statsd set. Metrics
+    /// are also disabled when neither is set.
+    #[arg(long, global = true, env)]
+    pub disable_metrics: bool,
+    /// DogStatsD address of a Datadog agent to send metrics to as well, e.g. `127.0.0.1:8125`.
+    #[arg(long, global = true, env)]
+    pub datadog_statsd: Option<String>,
+    /// Prefix of the metric names sent to Datadog.
+    #[arg(long, global = true, env, default_value = "pye_cli")]
+    pub datadog_prefix: String,
+}
+
+fn parse_tag(tag: &str) -> Result<(String, String), String> {
+    match tag.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() && !value.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("Invalid tag {}, expected KEY=VALUE", tag)),
+    }
+}
+
+/// Sets the host ID and static tags and enables submitting datapoints, unless metrics are
+/// disabled or there's neither SOLANA_METRICS_CONFIG nor a Datadog agent to submit them to.
+pub fn init(args: &MetricsArgs) -> anyhow::Result<()> {
+    let influx = std::env::var("SOLANA_METRICS_CONFIG").is_ok_and(|config| !config.is_empty());
+    if args.disable_metrics {
+        return Ok(());
+    }
+    if !influx && args.datadog_statsd.is_none() {
+        info!("SOLANA_METRICS_CONFIG isn't set, metrics are disabled");
+        return Ok(());
+    }
+    let tags = args
+        .metrics_tags
+        .iter()
+        .map(|(key, value)| (&*Box::leak(key.clone().into_boxed_str()), value.clone()))
+        .collect();
+    let _ = TAGS.set(tags);
+    if let Some(address) = &args.datadog_statsd {
+        let statsd =
+            DogStatsd::connect(address, args.datadog_prefix.clone(), &args.metrics_host_id)?;
+        info!("Sending metrics to DogStatsD at {}", address);
+        let _ = DATADOG.set(statsd);
+    }
+    if influx {
+        solana_metrics::set_host_id(args.metrics_host_id.clone());
+        solana_metrics::set_panic_hook("pye_cli", Some(env!("CARGO_PKG_VERSION").to_string()));
+        INFLUX.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    INFLUX.load(Ordering::Relaxed) || DATADOG.get().is_some()
+}
+
+fn add_tags(point: &mut DataPoint, tags: &[(&'static str, String)]) {
+    for (name, value) in tags {
+        point.add_tag(*name, value);
+    }
+}
+
+/// Submits `point` with the static tags added to every configured sink. Called by the datapoint
+/// macros.
+pub fn submit(mut point: DataPoint, level: log::Level) {
+    add_tags(
+        &mut point,
+        TAGS.get().map(Vec::as_slice).unwrap_or_default(),
+    );
+    let _ = VALIDATOR_NAME.try_with(|name| {
+        point.add_tag("validator_name", name);
+    });
+    if let Some(statsd) = DATADOG.get() {
+        statsd.send(&point);
+    }
+    if INFLUX.load(Ordering::Relaxed) {
+        solana_metrics::submit(point, level);
+    }
+}
+
+/// Runs `future` with the datapoints it submits tagged `validator_name=<name>`, telling apart
+/// the validators of a fleet.
+pub async fn with_validator_name<F: Future>(name: String, future: F) -> F::Output {
+    VALIDATOR_NAME.scope(name, future).await
+}
+
+/// Spawns `future` on a task keeping the current `validator_name` tag, which spawned tasks don't
+/// inherit otherwise.
+pub fn spawn_tagged<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match VALIDATOR_NAME.try_with(String::clone) {
+        Ok(name) => tokio::spawn(VALIDATOR_NAME.scope(name, future)),
+        Err(_) => tokio::spawn(future),
+    }
+}
+
+/// Flushes pending datapoints, if submitting to InfluxDB.
+pub fn flush() {
+    if INFLUX.load(Ordering::Relaxed) {
+        solana_metrics::flush();
+    }
+}
+
+/// Like the solana_metrics macro of the same name, but submitting only when metrics are enabled
+/// and with the static tags added.
+macro_rules! datapoint {
+    ($level:expr, $name:expr, $($fields:tt)+) => {
+        if log::log_enabled!($level) && $crate::metrics::enabled() {
+            $crate::metrics::submit(
+                solana_metrics::create_datapoint!(@point $name, $($fields)+),
+                $level,
+            );
+        }
+    };
+}
+
+macro_rules! datapoint_error {
+    ($name:expr, $($fields:tt)+) => {
+        $crate::metrics::datapoint!(log::Level::Error, $name, $($fields)+)
+    };
+}
+
+macro_rules! datapoint_warn {
+    ($name:expr, $($fields:tt)+) => {
+        $crate::metrics::datapoint!(log::Level::Warn, $name, $($fields)+)
+    };
+}
+
+macro_rules! datapoint_info {
+    ($name:expr, $($fields:tt)+) => {
+        $crate::metrics::datapoint!(log::Level::Info, $name, $($fields)+)
+    };
+}
+
+pub(crate) use {datapoint, datapoint_error, datapoint_info, datapoint_warn};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tag() {
+        assert_eq!(
+            parse_tag("environment=mainnet").unwrap(),
+            ("environment".to_string(), "mainnet".to_string())
+        );
+        assert!(parse_tag("environment").is_err());
+        assert!(parse_tag("=mainnet").is_err());
+    }
+
+    #[test]
+    fn test_static_tags_added() {
+        let mut point = DataPoint::new("excess_reward");
+        add_tags(&mut point, &[("validator", "my-validator".to_string())]);
+        assert_eq!(point.tags, vec![("validator", "my-validator".to_string())]);
+    }
+}
@@ -1,17 +1,19 @@
 use pye_core_cpi::pye_core::types::RewardCommissions;
-use solana_metrics::datapoint_info;
 use solana_sdk::pubkey::Pubkey;
 
+use crate::metrics::datapoint_info;
 use crate::rewards::mev_rewards::ValidatorInfo;
 
 pub fn log_reward_commissions(
     target_epoch: u64,
+    program_id: &Pubkey,
     pye_account_pubkey: &Pubkey,
     reward_commissions: &RewardCommissions,
 ) {
     datapoint_info!(
         "reward_commissions",
         ("epoch", target_epoch.to_string(), String),
+        ("program_id", program_id.to_string(), String),
         ("pye_account", pye_account_pubkey.to_string(), String),
         (
             "inflation_bps",
@@ -0,0 +1,158 @@
+use std::fmt;
+
+use anyhow::Result;
+use clap::Args;
+use log::{error, info, warn};
+use reqwest::Client;
+use serde_json::json;
+
+use crate::email::{EmailArgs, Mailer};
+use crate::http::http_client;
+
+/// Options controlling where operator notifications are delivered.
+#[derive(Clone, Debug, Default, Args)]
+pub struct NotificationArgs {
+    /// Webhook URL that receives notifications as a JSON POST (Slack and Discord compatible).
+    #[arg(long, env)]
+    pub notify_webhook: Option<String>,
+    /// Telegram bot token notifications are sent with, as given by @BotFather.
+    #[arg(long, env, requires = "telegram_chat_id")]
+    pub telegram_bot_token: Option<String>,
+    /// Telegram chat, group or channel ID the bot posts notifications to.
+    #[arg(long, env, requires = "telegram_bot_token")]
+    pub telegram_chat_id: Option<String>,
+    #[command(flatten)]
+    pub email: EmailArgs,
+}
+
+/// Telegram Bot API endpoint, before the bot token.
+const TELEGRAM_API_URL: &str = "https://api.telegram.org/bot";
+/// Longest message Telegram accepts, in characters.
+const TELEGRAM_MAX_MESSAGE_CHARS: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "INFO"),
+            Severity::Warning => write!(f, "WARNING"),
+            Severity::Critical => write!(f, "CRITICAL"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub severity: Severity,
+    pub title: String,
+    pub body: String,
+}
+
+impl Notification {
+    pub fn new(severity: Severity, title: impl Into<String>, body: impl Into<String>) -> Self {
+        Self {
+            severity,
+            title: title.into(),
+            body: body.into(),
+        }
+    }
+
+    fn text(&self) -> String {
+        format!("[{}] {}\n{}", self.severity, self.title, self.body)
+    }
+}
+
+/// Delivers notifications to the log and any configured channels. Delivery failures are logged
+/// but never abort the caller, notifications are best effort.
+#[derive(Clone, Debug)]
+pub struct Notifier {
+    http: Client,
+    webhook_url: Option<String>,
+    /// Bot token and chat ID.
+    telegram: Option<(String, String)>,
+    mailer: Option<Mailer>,
+}
+
+impl Notifier {
+    pub fn from_args(args: &NotificationArgs) -> Result<Self> {
+        Ok(Self {
+            http: http_client(),
+            webhook_url: args.notify_webhook.clone(),
+            telegram: args
+                .telegram_bot_token
+                .clone()
+                .zip(args.telegram_chat_id.clone()),
+            mailer: Mailer::from_args(&args.email)?,
+        })
+    }
+
+    /// The mailer of the email channel, for reports mailed on their own.
+    pub fn mailer(&self) -> Option<&Mailer> {
+        self.mailer.as_ref()
+    }
+
+    pub async fn notify(&self, notification: Notification) {
+        match notification.severity {
+            Severity::Info => info!("{}", notification.text()),
+            Severity::Warning => warn!("{}", notification.text()),
+            Severity::Critical => error!("{}", notification.text()),
+        }
+        if let Some(url) = &self.webhook_url {
+            let text = notification.text();
+            let payload = json!({
+                "text": text,
+                "content": text,
+                "severity": notification.severity.to_string(),
+                "title": notification.title,
+                "body": notification.body,
+            });
+            let result = self
+                .http
+                .post(url)
+                .json(&payload)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                error!("Failed to deliver notification to webhook: {}", e);
+            }
+        }
+        if let Some((bot_token, chat_id)) = &self.telegram {
+            let payload = json!({
+                "chat_id": chat_id,
+                "text": notification
+                    .text()
+                    .chars()
+                    .take(TELEGRAM_MAX_MESSAGE_CHARS)
+                    .collect::<String>(),
+                "disable_web_page_preview": true,
+            });
+            let result = self
+                .http
+                .post(format!("{}{}/sendMessage", TELEGRAM_API_URL, bot_token))
+                .json(&payload)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                // The URL holds the bot token, keep it out of the logs.
+                error!(
+                    "Failed to deliver notification to Telegram: {}",
+                    e.without_url()
+                );
+            }
+        }
+        if let Some(mailer) = &self.mailer {
+            let subject = format!("[{}] {}", notification.severity, notification.title);
+            if let Err(e) = mailer.send(&subject, notification.body, false).await {
+                error!("Failed to deliver notification by email: {}", e);
+            }
+        }
+    }
+}
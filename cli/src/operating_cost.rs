@@ -0,0 +1,144 @@
+use std::fmt;
+
+use clap::Args;
+
+use crate::control::BondRewardBreakdown;
+use crate::sol_format::format_amount;
+
+/// Options for estimating what servicing the bonds costs.
+#[derive(Clone, Debug, Args)]
+pub struct OperatingCostArgs {
+    /// RPC credits the RPC provider charges per request, used to estimate the RPC credits spent
+    /// on an epoch.
+    #[arg(long, env, default_value = "1")]
+    pub rpc_credits_per_request: u64,
+}
+
+/// Overhead of servicing the bonds for an epoch: transaction fees of the payments and RPC usage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EpochCost {
+    pub epoch: u64,
+    /// Payment transactions sent.
+    pub transactions: u64,
+    /// Fees of the payment transactions in lamports, priority fees included.
+    pub fees: u64,
+    /// Payments whose fee couldn't be fetched and isn't included in `fees`.
+    pub unknown_fees: u64,
+    /// RPC requests made while computing the epoch, the payment transactions excluded.
+    pub rpc_requests: u64,
+    pub rpc_credits: u64,
+}
+
+impl EpochCost {
+    pub fn new(
+        epoch: u64,
+        breakdowns: &[BondRewardBreakdown],
+        rpc_requests: u64,
+        args: &OperatingCostArgs,
+    ) -> Self {
+        let mut cost = Self {
+            epoch,
+            rpc_requests,
+            rpc_credits: rpc_requests.saturating_mul(args.rpc_credits_per_request),
+            ..Self::default()
+        };
+        for breakdown in breakdowns {
+            if breakdown.payment.signature().is_none() {
+                continue;
+            }
+            cost.transactions += 1;
+            match breakdown.payment.fee() {
+                Some(fee) => cost.fees += fee,
+                None => cost.unknown_fees += 1,
+            }
+        }
+        cost
+    }
+
+    /// Adds the cost of a follow-up pass over the same epoch.
+    pub fn merge(&mut self, other: &EpochCost) {
+        self.transactions += other.transactions;
+        self.fees += other.fees;
+        self.unknown_fees += other.unknown_fees;
+        self.rpc_requests += other.rpc_requests;
+        self.rpc_credits += other.rpc_credits;
+    }
+}
+
+impl fmt::Display for EpochCost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Cost to operate epoch {}: {} in fees over {} transactions",
+            self.epoch,
+            format_amount(self.fees),
+            self.transactions
+        )?;
+        if self.unknown_fees > 0 {
+            write!(f, " ({} fees unknown)", self.unknown_fees)?;
+        }
+        write!(
+            f,
+            ", {} RPC requests (~{} credits)",
+            self.rpc_requests, self.rpc_credits
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::pubkey::Pubkey;
+    use solana_sdk::signature::Signature;
+
+    use super::*;
+    use crate::control::PaymentOutcome;
+    use crate::dashboard::RewardBreakdown;
+    use crate::transactions::Payment;
+
+    fn breakdown(payment: PaymentOutcome) -> BondRewardBreakdown {
+        BondRewardBreakdown {
+            program_id: Pubkey::new_unique(),
+            pye_account: Pubkey::new_unique(),
+            active_stake: 1_000,
+            rewards: RewardBreakdown {
+                epoch: 800,
+                inflation: 10,
+                mev: 0,
+                block: 0,
+            },
+            payment,
+        }
+    }
+
+    #[test]
+    fn test_epoch_cost() {
+        let paid = |fee| {
+            PaymentOutcome::Paid(Payment {
+                signature: Signature::default(),
+                fee,
+                slot: None,
+                verification: None,
+            })
+        };
+        let cost = EpochCost::new(
+            800,
+            &[
+                breakdown(paid(Some(5_000))),
+                breakdown(paid(Some(10_000))),
+                breakdown(paid(None)),
+                breakdown(PaymentOutcome::DryRun),
+            ],
+            1_200,
+            &OperatingCostArgs {
+                rpc_credits_per_request: 2,
+            },
+        );
+        assert_eq!(cost.transactions, 3);
+        assert_eq!(cost.fees, 15_000);
+        assert_eq!(cost.unknown_fees, 1);
+        assert_eq!(
+            cost.to_string(),
+            "Cost to operate epoch 800: 0.000015000 SOL (15,000 lamports) in fees over 3 transactions (1 fees unknown), 1200 RPC requests (~2400 credits)"
+        );
+    }
+}
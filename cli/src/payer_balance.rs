@@ -0,0 +1,148 @@
+use clap::Args;
+use log::{info, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::control::BondRewardBreakdown;
+use crate::metrics::{datapoint_info, datapoint_warn};
+use crate::notifications::{Notification, Notifier, Severity};
+use crate::sol_format::format_amount;
+
+/// Options of the payer balance check run every cycle.
+#[derive(Clone, Debug, Args)]
+pub struct PayerBalanceArgs {
+    /// Alert when the payer's balance drops below this multiple of the excess rewards projected
+    /// for the next epoch, checked every cycle. 0 disables the check.
+    #[arg(long, env, default_value = "2.0")]
+    pub payer_balance_alert_multiple: f64,
+}
+
+/// The excess rewards expected to be owed for the next epoch: those owed for the last processed
+/// one, assuming the bonds keep accruing at the same rate.
+pub fn projected_liability(last_breakdowns: &[BondRewardBreakdown]) -> u64 {
+    last_breakdowns
+        .iter()
+        .map(|breakdown| breakdown.rewards.total().max(0) as u64)
+        .sum()
+}
+
+/// Watches the payer's balance between epochs, so it can be funded before the boundary instead
+/// of payments failing at it.
+pub struct PayerBalanceGuard {
+    payer: Pubkey,
+    multiple: f64,
+    /// Whether the balance was below the threshold at the last check, to alert once per drop.
+    below: bool,
+}
+
+impl PayerBalanceGuard {
+    /// Returns None when the check is disabled.
+    pub fn new(payer: Pubkey, args: &PayerBalanceArgs) -> Option<Self> {
+        (args.payer_balance_alert_multiple > 0.0).then_some(Self {
+            payer,
+            multiple: args.payer_balance_alert_multiple,
+            below: false,
+        })
+    }
+
+    fn required(&self, projected_liability: u64) -> u64 {
+        (projected_liability as f64 * self.multiple).ceil() as u64
+    }
+
+    /// Records the balance against the threshold, returning true when it just dropped below.
+    fn observe(&mut self, balance: u64, required: u64) -> bool {
+        let was_below = self.below;
+        self.below = balance < required;
+        self.below && !was_below
+    }
+
+    /// Alerts when the payer can no longer cover `projected_liability` with the configured
+    /// margin. Nothing is projected before the first epoch is processed, so nothing is checked.
+    pub async fn check(
+        &mut self,
+        client: &RpcClient,
+        vote_pubkey: &Pubkey,
+        projected_liability: u64,
+        notifier: &Notifier,
+    ) {
+        if projected_liability == 0 {
+            return;
+        }
+        let balance = match client.get_balance(&self.payer).await {
+            Ok(balance) => balance,
+            Err(e) => {
+                warn!("Failed to fetch the payer balance of {}: {}", self.payer, e);
+                return;
+            }
+        };
+        let required = self.required(projected_liability);
+        datapoint_info!(
+            "payer_balance",
+            ("vote_pubkey", vote_pubkey.to_string(), String),
+            ("payer", self.payer.to_string(), String),
+            ("balance", balance as i64, i64),
+            ("required", required as i64, i64),
+        );
+        let was_below = self.below;
+        if !self.observe(balance, required) {
+            if was_below && !self.below {
+                info!(
+                    "Payer {} balance of {} is back above {}",
+                    self.payer,
+                    format_amount(balance),
+                    format_amount(required)
+                );
+            }
+            return;
+        }
+        datapoint_warn!(
+            "payer_balance_low",
+            ("vote_pubkey", vote_pubkey.to_string(), String),
+            ("payer", self.payer.to_string(), String),
+            ("balance", balance as i64, i64),
+            ("required", required as i64, i64),
+        );
+        notifier
+            .notify(Notification::new(
+                Severity::Warning,
+                format!("Payer {} is running low", self.payer),
+                format!(
+                    "Balance: {}\nProjected excess rewards for the next epoch: {}\nAlert threshold ({}x): {}\nFund the payer before the epoch boundary so payments don't fail.",
+                    format_amount(balance),
+                    format_amount(projected_liability),
+                    self.multiple,
+                    format_amount(required)
+                ),
+            ))
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alerts_once_per_drop() {
+        let args = PayerBalanceArgs {
+            payer_balance_alert_multiple: 1.5,
+        };
+        let mut guard = PayerBalanceGuard::new(Pubkey::new_unique(), &args).unwrap();
+        let required = guard.required(1_000_000_000);
+        assert_eq!(required, 1_500_000_000);
+
+        assert!(!guard.observe(2_000_000_000, required));
+        assert!(guard.observe(1_000_000_000, required));
+        assert!(!guard.observe(900_000_000, required));
+        assert!(!guard.observe(1_500_000_000, required));
+        assert!(guard.observe(1_499_999_999, required));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let args = PayerBalanceArgs {
+            payer_balance_alert_multiple: 0.0,
+        };
+        assert!(PayerBalanceGuard::new(Pubkey::new_unique(), &args).is_none());
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use dialoguer::{MultiSelect, Select};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::dashboard::RewardBreakdown;
+use crate::labels::label_or_pubkey;
+use crate::rewards::source::RewardCategory;
+use crate::sol_format::format_sol;
+
+/// A transfer proposed for an epoch, awaiting the operator's review.
+pub struct ProposedTransfer {
+    pub pye_account: Pubkey,
+    pub issuer: Pubkey,
+    pub rewards: RewardBreakdown,
+    /// The amount to pay, which includes any manual adjustments.
+    pub lamports: u64,
+}
+
+impl ProposedTransfer {
+    /// The categories the bond is owed excess rewards in, e.g. `inflation,mev`.
+    fn categories(&self) -> String {
+        [
+            (RewardCategory::Inflation, self.rewards.inflation),
+            (RewardCategory::Mev, self.rewards.mev),
+            (RewardCategory::Block, self.rewards.block),
+        ]
+        .iter()
+        .filter(|(_, lamports)| *lamports > 0)
+        .map(|(category, _)| category.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+}
+
+/// One line per transfer in payment order, with the payer's balance once it and the ones before
+/// it are paid.
+fn review_rows(transfers: &[ProposedTransfer], payer_balance: u64) -> Vec<String> {
+    let mut balance = i128::from(payer_balance);
+    transfers
+        .iter()
+        .map(|transfer| {
+            balance -= i128::from(transfer.lamports);
+            format!(
+                "{:<44}  {:<44}  {:<16}  {:>15}  {:>17}",
+                label_or_pubkey(&transfer.pye_account),
+                label_or_pubkey(&transfer.issuer),
+                transfer.categories(),
+                format_sol(transfer.lamports),
+                format_sol(balance)
+            )
+        })
+        .collect()
+}
+
+/// Shows the transfers proposed for `epoch` and asks the operator which to pay. Returns the bonds
+/// approved, or None when the operator aborts.
+pub fn review_payments(
+    epoch: u64,
+    transfers: &[ProposedTransfer],
+    payer_balance: u64,
+) -> Result<Option<Vec<Pubkey>>> {
+    let rows = review_rows(transfers, payer_balance);
+    println!(
+        "Proposed transfers for epoch {} (payer balance: {} SOL)\n",
+        epoch,
+        format_sol(payer_balance)
+    );
+    println!(
+        "{:<44}  {:<44}  {:<16}  {:>15}  {:>17}",
+        "BOND", "ISSUER", "CATEGORIES", "TOTAL (SOL)", "PAYER AFTER (SOL)"
+    );
+    for row in rows.iter() {
+        println!("{}", row);
+    }
+    println!();
+    let choice = Select::new()
+        .with_prompt("Pay these transfers?")
+        .items(&["Approve all", "Select transfers", "Abort"])
+        .default(0)
+        .interact()?;
+    let selected = match choice {
+        0 => (0..transfers.len()).collect(),
+        1 => MultiSelect::new()
+            .with_prompt("Transfers to pay (space toggles, enter confirms)")
+            .items(&rows)
+            .defaults(&vec![true; rows.len()])
+            .interact()?,
+        _ => return Ok(None),
+    };
+    Ok(Some(
+        selected
+            .into_iter()
+            .map(|index| transfers[index].pye_account)
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOL: i64 = 1_000_000_000;
+
+    #[test]
+    fn test_review_rows() {
+        let transfer = |inflation: i64, mev: i64| ProposedTransfer {
+            pye_account: Pubkey::new_unique(),
+            issuer: Pubkey::new_unique(),
+            rewards: RewardBreakdown {
+                epoch: 800,
+                inflation,
+                mev,
+                block: 0,
+            },
+            lamports: (inflation + mev) as u64,
+        };
+        let transfers = [transfer(2 * SOL, SOL), transfer(0, 3 * SOL)];
+        assert_eq!(transfers[0].categories(), "inflation,mev");
+        assert_eq!(transfers[1].categories(), "mev");
+
+        let rows = review_rows(&transfers, 5 * SOL as u64);
+        assert!(rows[0].ends_with(" 2.000000000"));
+        assert!(rows[1].ends_with(" -1.000000000"));
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Whether progress bars are drawn, turned off by --quiet and by the manager's dashboard.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed) && std::io::stderr().is_terminal()
+}
+
+/// Progress of fetching a leader's blocks for an epoch, drawn on stderr.
+pub struct BlockScanProgress {
+    bar: ProgressBar,
+    fetched: AtomicU64,
+    skipped: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl BlockScanProgress {
+    pub fn new(blocks: u64, epoch: u64, rpc_url: &str) -> Self {
+        let bar = if enabled() {
+            ProgressBar::new(blocks)
+        } else {
+            ProgressBar::hidden()
+        };
+        bar.set_style(
+            ProgressStyle::with_template(
+                "{prefix} [{bar:30}] {pos}/{len} blocks ({per_sec}, ETA {eta}) {msg}",
+            )
+            .expect("valid progress template")
+            .progress_chars("=> "),
+        );
+        bar.set_prefix(format!("Epoch {} from {}", epoch, rpc_url));
+        let progress = Self {
+            bar,
+            fetched: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        };
+        progress.update_message();
+        progress
+    }
+
+    fn update_message(&self) {
+        self.bar.set_message(format!(
+            "fetched {}, skipped {}, failed attempts {}",
+            self.fetched.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed)
+        ));
+    }
+
+    pub fn fetched(&self) {
+        self.fetched.fetch_add(1, Ordering::Relaxed);
+        self.bar.inc(1);
+        self.update_message();
+    }
+
+    pub fn skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+        self.bar.inc(1);
+        self.update_message();
+    }
+
+    /// A fetch attempt failed and the block will be retried, or given up on.
+    pub fn failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        self.update_message();
+    }
+}
+
+impl Drop for BlockScanProgress {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+    }
+}
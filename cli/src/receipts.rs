@@ -0,0 +1,574 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use log::error;
+use pye_core_cpi::pye_core::accounts::SoloValidatorBond as SoloValidatorPyeAccount;
+use serde::{Deserialize, Serialize};
+use solana_sdk::hash::hashv;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::adjustments::{adjustments_total, Adjustment};
+use crate::control::{BondRewardBreakdown, PaymentOutcome};
+use crate::installments::Installment;
+use crate::metrics::datapoint_error;
+use crate::state_db::StateDbWriter;
+
+/// Version of the receipt layout.
+pub const RECEIPT_VERSION: u8 = 1;
+/// Version of the layout hashed into calculation fingerprints.
+pub const FINGERPRINT_VERSION: u8 = 1;
+
+/// Validator-wide inputs of an epoch's reward calculation, shared by every bond's receipt.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EpochInputs {
+    /// Block time of the epoch's last slot, compared against bond maturities.
+    pub epoch_end_time: i64,
+    pub validator_active_stake: u64,
+    pub validator_mev_rewards: u64,
+    pub validator_mev_commission_bps: Option<u64>,
+    pub validator_block_rewards: u64,
+    pub block_reward_components: String,
+    pub inflation_commission_start: Option<u8>,
+    pub inflation_commission_end: Option<u8>,
+    pub rounding: String,
+    /// Reward categories left out by `--categories`, whose excess rewards are zero.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_categories: Vec<String>,
+}
+
+/// Name of a clap value as passed on the command line, e.g. `floor`.
+pub fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// The bond's own inputs to the calculation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BondInputs {
+    pub issuer: String,
+    pub stake_account: String,
+    pub transient_stake_account: String,
+    pub maturity_ts: i64,
+    pub inflation_bps: u16,
+    pub mev_tips_bps: u16,
+    pub block_rewards_bps: u16,
+    pub active_stake: u64,
+}
+
+/// Excess rewards owed to the bond, in lamports.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExcessRewards {
+    pub inflation: i64,
+    pub mev: i64,
+    pub block: i64,
+    pub total: i64,
+}
+
+impl BondInputs {
+    pub fn new(pye_account: &SoloValidatorPyeAccount, active_stake: u64) -> Self {
+        Self {
+            issuer: pye_account.issuer.to_string(),
+            stake_account: pye_account.stake_account.to_string(),
+            transient_stake_account: pye_account.transient_stake_account.to_string(),
+            maturity_ts: pye_account.maturity_ts,
+            inflation_bps: pye_account.reward_commissions.inflation_bps,
+            mev_tips_bps: pye_account.reward_commissions.mev_tips_bps,
+            block_rewards_bps: pye_account.reward_commissions.block_rewards_bps,
+            active_stake,
+        }
+    }
+}
+
+impl From<&BondRewardBreakdown> for ExcessRewards {
+    fn from(breakdown: &BondRewardBreakdown) -> Self {
+        Self {
+            inflation: breakdown.rewards.inflation,
+            mev: breakdown.rewards.mev,
+            block: breakdown.rewards.block,
+            total: breakdown.rewards.total(),
+        }
+    }
+}
+
+/// The hashed contents of a calculation fingerprint.
+#[derive(Serialize)]
+struct Calculation<'a> {
+    version: u8,
+    vote_pubkey: String,
+    epoch: u64,
+    program_id: String,
+    pye_account: String,
+    epoch_inputs: &'a EpochInputs,
+    bond_inputs: BondInputs,
+    excess_rewards: ExcessRewards,
+}
+
+/// Base58 SHA-256 of every input to a bond-epoch's calculation and the excess rewards it
+/// resulted in. The payment outcome isn't part of it, so a recomputation from the same inputs
+/// gives the same fingerprint whether or not it pays, and any differing input changes it.
+pub fn calculation_fingerprint(
+    vote_pubkey: &Pubkey,
+    epoch_inputs: &EpochInputs,
+    pye_account: &SoloValidatorPyeAccount,
+    breakdown: &BondRewardBreakdown,
+) -> String {
+    let calculation = Calculation {
+        version: FINGERPRINT_VERSION,
+        vote_pubkey: vote_pubkey.to_string(),
+        epoch: breakdown.rewards.epoch,
+        program_id: breakdown.program_id.to_string(),
+        pye_account: breakdown.pye_account.to_string(),
+        epoch_inputs,
+        bond_inputs: BondInputs::new(pye_account, breakdown.active_stake),
+        excess_rewards: ExcessRewards::from(breakdown),
+    };
+    // Serializing plain structs of strings and integers can't fail.
+    let bytes = serde_json::to_vec(&calculation).unwrap_or_default();
+    hashv(&[&bytes]).to_string()
+}
+
+/// Everything that went into, and came out of, a bond's payment for an epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentReceipt {
+    pub version: u8,
+    pub vote_pubkey: String,
+    pub epoch: u64,
+    pub program_id: String,
+    pub pye_account: String,
+    pub epoch_inputs: EpochInputs,
+    pub bond_inputs: BondInputs,
+    pub excess_rewards: ExcessRewards,
+    /// `paid`, `failed`, or why nothing was paid, e.g. `not_due`.
+    pub payment: String,
+    pub transaction_signature: Option<String>,
+    /// Fee charged for the payment transaction in lamports, when known.
+    #[serde(default)]
+    pub transaction_fee: Option<u64>,
+    /// Slot the payment transaction was confirmed in, when known.
+    #[serde(default)]
+    pub transaction_slot: Option<u64>,
+    /// How the payment checked out against the finalized ledger, e.g. `verified` or `mismatch`,
+    /// unless the check was skipped.
+    #[serde(default)]
+    pub payment_verification: Option<String>,
+    /// The transfers the payment was split into, see --installment-threshold-lamports. The
+    /// signature and fee above are then the last installment's and the total.
+    #[serde(default)]
+    pub installments: Vec<Installment>,
+    /// Manual adjustments from --adjustments-file, paid on top of the excess rewards.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adjustments: Vec<Adjustment>,
+    /// Whether this is the receipt of the MEV-only pass of --defer-mev, which pays the bond's
+    /// MEV rewards apart from its other categories, as a payment of its own.
+    #[serde(default)]
+    pub deferred_mev: bool,
+    pub error: Option<String>,
+    /// When the excess rewards were computed.
+    pub computed_at: String,
+    /// When the payment outcome was known, i.e. the transfer confirmed or failed.
+    pub settled_at: String,
+    /// Hash of the inputs and excess rewards, see [`calculation_fingerprint`].
+    #[serde(default)]
+    pub fingerprint: String,
+}
+
+impl PaymentReceipt {
+    /// Builds the receipt of `breakdown`, or of its failed transfer when `payment` is an error.
+    pub fn new(
+        vote_pubkey: &Pubkey,
+        epoch_inputs: &EpochInputs,
+        pye_account: &SoloValidatorPyeAccount,
+        breakdown: &BondRewardBreakdown,
+        payment: Result<PaymentOutcome, String>,
+        computed_at: DateTime<Utc>,
+        settled_at: DateTime<Utc>,
+    ) -> Self {
+        let transaction_slot = payment.as_ref().ok().and_then(PaymentOutcome::slot);
+        let (payment, transaction_signature, transaction_fee, payment_verification, error) =
+            payment_fields(payment);
+        Self {
+            version: RECEIPT_VERSION,
+            vote_pubkey: vote_pubkey.to_string(),
+            epoch: breakdown.rewards.epoch,
+            program_id: breakdown.program_id.to_string(),
+            pye_account: breakdown.pye_account.to_string(),
+            epoch_inputs: epoch_inputs.clone(),
+            bond_inputs: BondInputs::new(pye_account, breakdown.active_stake),
+            excess_rewards: ExcessRewards::from(breakdown),
+            fingerprint: calculation_fingerprint(vote_pubkey, epoch_inputs, pye_account, breakdown),
+            payment,
+            transaction_signature,
+            transaction_fee,
+            transaction_slot,
+            payment_verification,
+            installments: Vec::new(),
+            adjustments: Vec::new(),
+            deferred_mev: false,
+            error,
+            computed_at: computed_at.to_rfc3339(),
+            settled_at: settled_at.to_rfc3339(),
+        }
+    }
+
+    /// The receipt of a later attempt at the same payment.
+    pub fn with_payment(
+        &self,
+        payment: Result<PaymentOutcome, String>,
+        settled_at: DateTime<Utc>,
+    ) -> Self {
+        let transaction_slot = payment.as_ref().ok().and_then(PaymentOutcome::slot);
+        let (payment, transaction_signature, transaction_fee, payment_verification, error) =
+            payment_fields(payment);
+        Self {
+            payment,
+            transaction_signature,
+            transaction_fee,
+            transaction_slot,
+            payment_verification,
+            error,
+            settled_at: settled_at.to_rfc3339(),
+            ..self.clone()
+        }
+    }
+
+    /// Identifies the payment the receipt is of: a bond-epoch has one, plus one for its MEV
+    /// rewards when they are deferred.
+    pub fn payment_key(&self) -> (u64, String, bool) {
+        (self.epoch, self.pye_account.clone(), self.deferred_mev)
+    }
+
+    /// Lamports owed for the bond-epoch: the excess rewards with the manual adjustments applied.
+    pub fn amount_due(&self) -> i64 {
+        self.excess_rewards.total + adjustments_total(&self.adjustments)
+    }
+
+    /// Lamports paid by the installments that landed before the payment failed, if it was split.
+    pub fn installments_paid(&self) -> u64 {
+        self.installments
+            .iter()
+            .map(|installment| installment.lamports)
+            .sum()
+    }
+
+    fn settled_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(&self.settled_at)
+            .ok()
+            .map(|settled_at| settled_at.with_timezone(&Utc))
+    }
+}
+
+/// The payment outcome, signature, fee, verification and error fields of a receipt.
+fn payment_fields(
+    payment: Result<PaymentOutcome, String>,
+) -> (
+    String,
+    Option<String>,
+    Option<u64>,
+    Option<String>,
+    Option<String>,
+) {
+    match payment {
+        Ok(outcome) => (
+            outcome.to_string(),
+            outcome.signature().map(|signature| signature.to_string()),
+            outcome.fee(),
+            outcome
+                .verification()
+                .map(|verification| verification.name().to_string()),
+            None,
+        ),
+        Err(err) => ("failed".to_string(), None, None, None, Some(err)),
+    }
+}
+
+/// Reads every receipt under `dir`, as written by [`ReceiptWriter`].
+pub fn load_receipts(dir: &Path) -> Result<Vec<PaymentReceipt>> {
+    let read_dir = |dir: &Path| {
+        fs::read_dir(dir).map_err(|e| anyhow!("Failed to read {}: {}", dir.display(), e))
+    };
+    let mut receipts = Vec::new();
+    for epoch_dir in read_dir(dir)? {
+        let epoch_dir = epoch_dir?.path();
+        let is_epoch_dir = epoch_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("epoch-"));
+        if !is_epoch_dir || !epoch_dir.is_dir() {
+            continue;
+        }
+        for file in read_dir(&epoch_dir)? {
+            let path = file?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+            let contents =
+                fs::read(&path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+            receipts.push(
+                serde_json::from_slice(&contents)
+                    .map_err(|e| anyhow!("Invalid receipt {}: {}", path.display(), e))?,
+            );
+        }
+    }
+    Ok(receipts)
+}
+
+/// The latest receipt of each payment, see [`PaymentReceipt::payment_key`], oldest epoch first.
+/// A bond-epoch whose MEV rewards were deferred has two, so the outcome of its first payment
+/// isn't hidden by the MEV one.
+pub fn latest_receipts(receipts: Vec<PaymentReceipt>) -> Vec<PaymentReceipt> {
+    let mut latest: HashMap<(u64, String, bool), PaymentReceipt> = HashMap::new();
+    for receipt in receipts {
+        let key = receipt.payment_key();
+        match latest.get(&key) {
+            Some(current) if current.settled_at() >= receipt.settled_at() => {}
+            _ => {
+                latest.insert(key, receipt);
+            }
+        }
+    }
+    let mut latest: Vec<PaymentReceipt> = latest.into_values().collect();
+    latest.sort_by_key(PaymentReceipt::payment_key);
+    latest
+}
+
+/// The receipts of payments whose latest attempt failed, oldest epoch first. A bond paid after
+/// a failure, e.g. by a retry, isn't included.
+pub fn failed_payments(receipts: Vec<PaymentReceipt>) -> Vec<PaymentReceipt> {
+    latest_receipts(receipts)
+        .into_iter()
+        .filter(|receipt| receipt.payment == "failed")
+        .collect()
+}
+
+/// The receipts of payments withheld by the spending limits and not paid since, oldest epoch
+/// first.
+pub fn pending_payments(receipts: Vec<PaymentReceipt>) -> Vec<PaymentReceipt> {
+    latest_receipts(receipts)
+        .into_iter()
+        .filter(|receipt| receipt.payment == "over_limit")
+        .collect()
+}
+
+/// Writes one JSON receipt per bond and epoch to `<dir>/epoch-<N>/<bond>.json`, and to the
+/// state database if one is used.
+#[derive(Clone, Debug)]
+pub struct ReceiptWriter {
+    dir: Option<PathBuf>,
+    db: Option<StateDbWriter>,
+}
+
+impl ReceiptWriter {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir: Some(dir),
+            db: None,
+        }
+    }
+
+    /// A writer to `dir`, the state database, or both, unless neither is used.
+    pub fn open(dir: Option<PathBuf>, db: Option<StateDbWriter>) -> Option<Self> {
+        (dir.is_some() || db.is_some()).then_some(Self { dir, db })
+    }
+
+    /// Path of the receipt, if written to a directory. An epoch processed again, e.g.
+    /// recomputed through the control API, gets a timestamped file next to the first receipt
+    /// instead of overwriting it.
+    pub fn receipt_path(&self, receipt: &PaymentReceipt, now: DateTime<Utc>) -> Option<PathBuf> {
+        let epoch_dir = self.dir.as_ref()?.join(format!("epoch-{}", receipt.epoch));
+        let path = epoch_dir.join(format!("{}.json", receipt.pye_account));
+        if path.exists() {
+            Some(epoch_dir.join(format!("{}-{}.json", receipt.pye_account, now.timestamp())))
+        } else {
+            Some(path)
+        }
+    }
+
+    /// Writes the receipt. Failures are logged and reported but don't stop the manager.
+    pub fn write(&self, receipt: &PaymentReceipt) {
+        if let Some(db) = &self.db {
+            db.insert_receipt(receipt);
+        }
+        if let Err(e) = self.try_write(receipt) {
+            error!(
+                "Failed to write receipt for pye_account {} and epoch {}: {}",
+                receipt.pye_account, receipt.epoch, e
+            );
+            datapoint_error!(
+                "receipt_error",
+                ("epoch", receipt.epoch.to_string(), String),
+                ("pye_account", receipt.pye_account.clone(), String),
+                ("error", e.to_string(), String),
+            );
+        }
+    }
+
+    fn try_write(&self, receipt: &PaymentReceipt) -> Result<()> {
+        let Some(path) = self.receipt_path(receipt, Utc::now()) else {
+            return Ok(());
+        };
+        if let Some(epoch_dir) = path.parent() {
+            fs::create_dir_all(epoch_dir)
+                .map_err(|e| anyhow!("Failed to create {}: {}", epoch_dir.display(), e))?;
+        }
+        fs::write(&path, serde_json::to_vec_pretty(receipt)?)
+            .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// A receipt of epoch 800 for a fresh bond, for tests to fill in the fields they use.
+#[cfg(test)]
+pub(crate) fn sample_receipt(payment: Result<PaymentOutcome, String>) -> PaymentReceipt {
+    let mut pye_account = crate::bond_schema::zeroed_bond();
+    pye_account.reward_commissions.mev_tips_bps = 500;
+    let now = Utc::now();
+    PaymentReceipt::new(
+        &Pubkey::new_unique(),
+        &EpochInputs {
+            epoch_end_time: 1_700_000_000,
+            validator_active_stake: 1_000_000,
+            validator_mev_rewards: 2_000,
+            validator_mev_commission_bps: Some(800),
+            validator_block_rewards: 3_000,
+            block_reward_components: "all".to_string(),
+            inflation_commission_start: Some(5),
+            inflation_commission_end: Some(5),
+            rounding: value_name(crate::rewards::RoundingMode::Floor),
+            skipped_categories: Vec::new(),
+        },
+        &pye_account,
+        &BondRewardBreakdown {
+            program_id: Pubkey::new_unique(),
+            pye_account: Pubkey::new_unique(),
+            active_stake: 5_000,
+            rewards: crate::dashboard::RewardBreakdown {
+                epoch: 800,
+                inflation: 30,
+                mev: 20,
+                block: 10,
+            },
+            payment: PaymentOutcome::NotDue,
+        },
+        payment,
+        now,
+        now,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::signature::Signature;
+
+    use super::*;
+    use crate::transactions::{Payment, PaymentVerification};
+    use solana_sdk::hash::Hash;
+
+    #[test]
+    fn test_receipt_records_payment() {
+        let paid = sample_receipt(Ok(PaymentOutcome::Paid(Payment {
+            signature: Signature::default(),
+            fee: Some(5_000),
+            slot: Some(345_600_123),
+            verification: Some(PaymentVerification::Verified),
+        })));
+        assert_eq!(paid.payment, "paid");
+        assert_eq!(
+            paid.transaction_signature,
+            Some(Signature::default().to_string())
+        );
+        assert_eq!(paid.transaction_fee, Some(5_000));
+        assert_eq!(paid.transaction_slot, Some(345_600_123));
+        assert_eq!(paid.payment_verification.as_deref(), Some("verified"));
+        assert_eq!(paid.excess_rewards.total, 60);
+        assert_eq!(paid.bond_inputs.mev_tips_bps, 500);
+        assert_eq!(paid.epoch_inputs.rounding, "floor");
+
+        let failed = sample_receipt(Err("blockhash expired".to_string()));
+        assert_eq!(failed.payment, "failed");
+        assert_eq!(failed.transaction_signature, None);
+        assert_eq!(failed.error.as_deref(), Some("blockhash expired"));
+    }
+
+    #[test]
+    fn test_fingerprint_covers_inputs_not_outcome() {
+        let paid = sample_receipt(Ok(PaymentOutcome::DryRun));
+        let failed = sample_receipt(Err("blockhash expired".to_string()));
+        assert!(paid.fingerprint.parse::<Hash>().is_ok());
+        // Receipts use fresh pubkeys, so their inputs differ.
+        assert_ne!(paid.fingerprint, failed.fingerprint);
+        let retried = failed.with_payment(Ok(PaymentOutcome::DryRun), Utc::now());
+        assert_eq!(retried.fingerprint, failed.fingerprint);
+    }
+
+    #[test]
+    fn test_receipts_are_not_overwritten() {
+        let dir = std::env::temp_dir().join(format!("pye-receipts-{}", Pubkey::new_unique()));
+        let writer = ReceiptWriter::new(dir.clone());
+        let receipt = sample_receipt(Ok(PaymentOutcome::DryRun));
+        let first = writer.receipt_path(&receipt, Utc::now()).unwrap();
+        assert_eq!(
+            first,
+            dir.join("epoch-800")
+                .join(format!("{}.json", receipt.pye_account))
+        );
+        writer.write(&receipt);
+        let second = writer.receipt_path(&receipt, Utc::now()).unwrap();
+        assert_ne!(first, second);
+        let written: PaymentReceipt = serde_json::from_slice(&fs::read(&first).unwrap()).unwrap();
+        assert_eq!(written, receipt);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_payments_keeps_latest_attempt() {
+        let failed = sample_receipt(Err("blockhash expired".to_string()));
+        let start = Utc::now();
+        let retried = failed.with_payment(
+            Ok(PaymentOutcome::Paid(Payment {
+                signature: Signature::default(),
+                fee: None,
+                slot: None,
+                verification: None,
+            })),
+            start + chrono::Duration::minutes(5),
+        );
+        assert_eq!(retried.payment, "paid");
+        assert_eq!(retried.excess_rewards, failed.excess_rewards);
+
+        let mut other = sample_receipt(Err("insufficient funds".to_string()));
+        other.epoch = 799;
+        assert_eq!(
+            failed_payments(vec![failed.clone(), other.clone()]),
+            vec![other.clone(), failed.clone()]
+        );
+        assert_eq!(
+            failed_payments(vec![retried, failed, other.clone()]),
+            vec![other]
+        );
+    }
+
+    #[test]
+    fn test_deferred_mev_payment_keeps_first_outcome() {
+        let failed = sample_receipt(Err("insufficient funds".to_string()));
+        let mut mev = failed.with_payment(
+            Ok(PaymentOutcome::Paid(Payment {
+                signature: Signature::default(),
+                fee: None,
+                slot: None,
+                verification: None,
+            })),
+            Utc::now() + chrono::Duration::minutes(5),
+        );
+        mev.deferred_mev = true;
+        assert_eq!(
+            latest_receipts(vec![failed.clone(), mev.clone()]),
+            vec![failed.clone(), mev.clone()]
+        );
+        assert_eq!(failed_payments(vec![failed.clone(), mev]), vec![failed]);
+    }
+}
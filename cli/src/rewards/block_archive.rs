@@ -0,0 +1,266 @@
+use anyhow::{anyhow, Result};
+use clap::Args;
+use object_store::{path::Path as ObjectPath, ObjectStore};
+use solana_ledger::blockstore::Blockstore;
+use solana_ledger::blockstore_options::{AccessType, BlockstoreOptions};
+use solana_storage_bigtable::{CredentialType, LedgerStorage, LedgerStorageConfig};
+use solana_transaction_status::{BlockEncodingOptions, ConfirmedBlock};
+use solana_transaction_status_client_types::{
+    TransactionDetails, UiConfirmedBlock, UiTransactionEncoding,
+};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// Timeout of Bigtable reads.
+const BIGTABLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Options for reading blocks from the local ledger or, when the RPC no longer serves them, from
+/// an archive.
+#[derive(Clone, Debug, Default, Args)]
+pub struct BlockArchiveArgs {
+    /// Ledger directory of a validator on this machine. Blocks are read from its blockstore
+    /// instead of the RPC, and the rooted slots tell produced and skipped leader slots apart.
+    #[arg(long, env, conflicts_with_all = ["bigtable_instance", "block_archive_url"])]
+    pub blockstore_path: Option<PathBuf>,
+    /// Bigtable instance in the standard Solana warehouse schema, e.g. `solana-ledger`, to read
+    /// the blocks pruned from the RPC's ledger from. Credentials come from --bigtable-credentials
+    /// or GOOGLE_APPLICATION_CREDENTIALS.
+    #[arg(long, env, conflicts_with = "block_archive_url")]
+    pub bigtable_instance: Option<String>,
+    /// Google service account key file for --bigtable-instance.
+    #[arg(long, env, requires = "bigtable_instance")]
+    pub bigtable_credentials: Option<PathBuf>,
+    /// Bigtable app profile for --bigtable-instance.
+    #[arg(long, env, default_value = "default")]
+    pub bigtable_app_profile: String,
+    /// Block archive holding one `<slot>.json` file per block, the result of getBlock, to read
+    /// the blocks pruned from the RPC's ledger from. Accepts `file://`, `s3://`, `gs://` and
+    /// `https://` URLs.
+    #[arg(long, env)]
+    pub block_archive_url: Option<Url>,
+}
+
+/// Where blocks are read from besides the RPC.
+pub enum BlockArchive {
+    Blockstore(Arc<Blockstore>),
+    Bigtable(LedgerStorage),
+    Files(Arc<dyn ObjectStore>, ObjectPath),
+}
+
+impl BlockArchive {
+    /// Returns None when no archive is configured.
+    pub async fn connect(args: &BlockArchiveArgs) -> Result<Option<Self>> {
+        if let Some(path) = &args.blockstore_path {
+            // Secondary access reads alongside the running validator, which owns the primary.
+            let options = BlockstoreOptions {
+                access_type: AccessType::Secondary,
+                ..BlockstoreOptions::default()
+            };
+            let blockstore = Blockstore::open_with_options(path, options).map_err(|e| {
+                anyhow!("Failed to open the blockstore at {}: {}", path.display(), e)
+            })?;
+            return Ok(Some(BlockArchive::Blockstore(Arc::new(blockstore))));
+        }
+        if let Some(instance) = &args.bigtable_instance {
+            let config = LedgerStorageConfig {
+                read_only: true,
+                timeout: Some(BIGTABLE_TIMEOUT),
+                credential_type: CredentialType::Filepath(
+                    args.bigtable_credentials
+                        .as_ref()
+                        .map(|path| path.display().to_string()),
+                ),
+                instance_name: instance.clone(),
+                app_profile_id: args.bigtable_app_profile.clone(),
+                ..LedgerStorageConfig::default()
+            };
+            let storage = LedgerStorage::new_with_config(config)
+                .await
+                .map_err(|e| anyhow!("Failed to connect to Bigtable {}: {}", instance, e))?;
+            return Ok(Some(BlockArchive::Bigtable(storage)));
+        }
+        match &args.block_archive_url {
+            Some(url) => {
+                // Unknown keys are ignored, the AWS_* and GOOGLE_* variables configure the client.
+                let options = std::env::vars().map(|(key, value)| (key.to_lowercase(), value));
+                let (store, prefix) = object_store::parse_url_opts(url, options)
+                    .map_err(|e| anyhow!("Invalid --block-archive-url {}: {}", url, e))?;
+                Ok(Some(BlockArchive::Files(Arc::from(store), prefix)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// The archive's name in logs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlockArchive::Blockstore(_) => "blockstore",
+            BlockArchive::Bigtable(_) => "Bigtable",
+            BlockArchive::Files(..) => "block archive",
+        }
+    }
+
+    /// Whether blocks are read from the archive instead of the RPC, not only after it fails.
+    pub fn is_local(&self) -> bool {
+        matches!(self, BlockArchive::Blockstore(_))
+    }
+
+    /// The rooted slots between `first_slot` and `last_slot` (inclusive) of a local ledger, or
+    /// None for archives that only fill in for the RPC. Fails unless the ledger holds the range.
+    pub async fn produced_blocks(
+        &self,
+        first_slot: u64,
+        last_slot: u64,
+    ) -> Result<Option<HashSet<u64>>> {
+        let BlockArchive::Blockstore(blockstore) = self else {
+            return Ok(None);
+        };
+        let blockstore = Arc::clone(blockstore);
+        tokio::task::spawn_blocking(move || {
+            let first_available = blockstore.get_first_available_block()?;
+            let max_root = blockstore.max_root();
+            if first_slot < first_available || last_slot > max_root {
+                return Err(anyhow!(
+                    "The blockstore holds rooted slots {}..={}, not {}..={}",
+                    first_available,
+                    max_root,
+                    first_slot,
+                    last_slot
+                ));
+            }
+            Ok(Some(
+                blockstore
+                    .rooted_slot_iterator(first_slot)?
+                    .take_while(|slot| *slot <= last_slot)
+                    .collect(),
+            ))
+        })
+        .await?
+    }
+
+    /// The block at `slot`, or None when the archive doesn't hold it.
+    pub async fn get_block(
+        &self,
+        slot: u64,
+        transaction_details: TransactionDetails,
+    ) -> Result<Option<UiConfirmedBlock>> {
+        match self {
+            BlockArchive::Blockstore(blockstore) => {
+                let blockstore = Arc::clone(blockstore);
+                let block =
+                    tokio::task::spawn_blocking(move || blockstore.get_rooted_block(slot, false))
+                        .await?
+                        .map_err(|e| {
+                            anyhow!("Failed to read block {} from the blockstore: {}", slot, e)
+                        })?;
+                encode_block(slot, block.into(), transaction_details).map(Some)
+            }
+            BlockArchive::Bigtable(storage) => match storage.get_confirmed_block(slot).await {
+                Ok(block) => encode_block(slot, block, transaction_details).map(Some),
+                Err(solana_storage_bigtable::Error::BlockNotFound(_)) => Ok(None),
+                Err(e) => Err(anyhow!(
+                    "Failed to read block {} from Bigtable: {}",
+                    slot,
+                    e
+                )),
+            },
+            BlockArchive::Files(store, prefix) => {
+                match store.get(&block_path(prefix, slot)).await {
+                    Ok(result) => {
+                        let bytes = result.bytes().await?;
+                        let block: UiConfirmedBlock = serde_json::from_slice(&bytes)
+                            .map_err(|e| anyhow!("Invalid archived block {}: {}", slot, e))?;
+                        // Blocks archived without transactions can't split fees into components.
+                        if !matches!(transaction_details, TransactionDetails::None)
+                            && block.transactions.is_none()
+                        {
+                            return Err(anyhow!(
+                                "Archived block {} has no transactions, which the block reward components need",
+                                slot
+                            ));
+                        }
+                        Ok(Some(block))
+                    }
+                    Err(object_store::Error::NotFound { .. }) => Ok(None),
+                    Err(e) => Err(anyhow!("Failed to read archived block {}: {}", slot, e)),
+                }
+            }
+        }
+    }
+}
+
+/// Encodes a block the way getBlock returns it.
+fn encode_block(
+    slot: u64,
+    block: ConfirmedBlock,
+    transaction_details: TransactionDetails,
+) -> Result<UiConfirmedBlock> {
+    block
+        .encode_with_options(
+            UiTransactionEncoding::Json,
+            BlockEncodingOptions {
+                transaction_details,
+                show_rewards: true,
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .map_err(|e| anyhow!("Failed to encode block {}: {}", slot, e))
+}
+
+/// The archive file of the block at `slot`.
+fn block_path(prefix: &ObjectPath, slot: u64) -> ObjectPath {
+    prefix.child(format!("{}.json", slot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::reward_type::RewardType;
+
+    #[tokio::test]
+    async fn test_files_archive() {
+        let dir = std::env::temp_dir().join(format!("pye-block-archive-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let block = serde_json::json!({
+            "previousBlockhash": "11111111111111111111111111111111",
+            "blockhash": "11111111111111111111111111111111",
+            "parentSlot": 99,
+            "rewards": [{
+                "pubkey": "identity",
+                "lamports": 7500,
+                "postBalance": 1,
+                "rewardType": "Fee",
+                "commission": null,
+            }],
+        });
+        std::fs::write(dir.join("100.json"), block.to_string()).unwrap();
+        let args = BlockArchiveArgs {
+            block_archive_url: Some(Url::from_directory_path(&dir).unwrap()),
+            ..Default::default()
+        };
+        let archive = BlockArchive::connect(&args).await.unwrap().unwrap();
+
+        let block = archive
+            .get_block(100, TransactionDetails::None)
+            .await
+            .unwrap()
+            .unwrap();
+        let rewards = block.rewards.unwrap();
+        assert_eq!(rewards[0].lamports, 7500);
+        assert_eq!(rewards[0].reward_type, Some(RewardType::Fee));
+        // The block was archived without transactions to count signatures from.
+        assert!(archive
+            .get_block(100, TransactionDetails::Accounts)
+            .await
+            .is_err());
+        assert!(archive
+            .get_block(101, TransactionDetails::None)
+            .await
+            .unwrap()
+            .is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,17 +1,383 @@
-use crate::rpc_utils::{self, PriorityFeeKeeperError};
+use crate::accounts::EpochSysvars;
+use crate::exit_code::rpc_error;
+use crate::logging::BLOCK_FETCH_TARGET;
+use crate::metrics::{datapoint_info, datapoint_warn};
+use crate::progress::BlockScanProgress;
+use crate::rewards::block_archive::{BlockArchive, BlockArchiveArgs};
+use crate::rewards::{
+    checked_bps, stake_weighted_share, to_i64, RewardMathError, Rounding, MAX_BPS,
+};
+use crate::rpc_utils::{self, new_rpc_client, Backoff, PriorityFeeKeeperError};
+use crate::sol_format::format_amount;
 use anyhow::{anyhow, Result};
-use futures::stream::{self, StreamExt};
-use log::{info, warn};
+use clap::{Args, ValueEnum};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use log::{debug, error, info, warn};
 use pye_core_cpi::pye_core::types::RewardCommissions;
+use serde::Serialize;
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_client::rpc_config::RpcLeaderScheduleConfig;
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::epoch_info::EpochInfo;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::reward_type::RewardType;
+use solana_sdk::slot_history::{Check as SlotHistoryCheck, SlotHistory};
+use solana_transaction_status_client_types::{
+    EncodedTransaction, TransactionDetails, UiConfirmedBlock,
+};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
+/// Lamports charged per transaction signature as the base fee.
+const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+/// Percent of base fees burned instead of being paid to the leader.
+const BASE_FEE_BURN_PERCENT: u64 = 50;
+
+/// Which parts of the leader's fee reward count toward the bond obligation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum BlockRewardComponents {
+    /// The whole fee reward, base and priority fees.
+    #[default]
+    All,
+    /// Only the leader's share of priority fees.
+    PriorityOnly,
+    /// Only the leader's share of base (signature) fees.
+    BaseOnly,
+}
+
+impl BlockRewardComponents {
+    /// Splitting the reward requires the block's transactions to count signatures.
+    fn transaction_details(&self) -> TransactionDetails {
+        match self {
+            BlockRewardComponents::All => TransactionDetails::None,
+            _ => TransactionDetails::Accounts,
+        }
+    }
+
+    fn select(&self, fee_reward: u64, signature_count: u64) -> u64 {
+        let (base, priority) = split_block_fee_reward(fee_reward, signature_count);
+        match self {
+            BlockRewardComponents::All => fee_reward,
+            BlockRewardComponents::PriorityOnly => priority,
+            BlockRewardComponents::BaseOnly => base,
+        }
+    }
+}
+
+/// Options controlling how the validator's produced blocks are scanned for fee rewards.
+#[derive(Clone, Debug)]
+pub struct BlockScanOptions {
+    /// Maximum RPC requests to send concurrently.
+    pub concurrency: usize,
+    /// Longest wait (in secs) between get_block RPC call retries.
+    pub block_retry_delay: u64,
+    /// Time (in secs) after which a block's fetch stops being retried.
+    pub block_retry_deadline: u64,
+    pub components: BlockRewardComponents,
+    pub verify: VerifyRpcArgs,
+    pub slot_export: SlotExportArgs,
+    pub archive: BlockArchiveArgs,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SlotExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl SlotExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            SlotExportFormat::Csv => "csv",
+            SlotExportFormat::Json => "json",
+        }
+    }
+}
+
+/// Options for exporting the fees of every slot the validator was leader of.
+#[derive(Clone, Debug, Default, Args)]
+pub struct SlotExportArgs {
+    /// Directory to write the fees of every leader slot of each scanned epoch to, as
+    /// `block-rewards-<epoch>.<format>`. The scan of --verify-rpc goes to
+    /// `block-rewards-<epoch>-verify.<format>`.
+    #[arg(long, env)]
+    pub slot_export_dir: Option<PathBuf>,
+    /// Format of the slot export.
+    #[arg(long, env, value_enum, default_value_t = SlotExportFormat::Csv)]
+    pub slot_export_format: SlotExportFormat,
+}
+
+/// The fees of one of the validator's leader slots.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SlotFees {
+    pub slot: u64,
+    /// Whether the validator produced the block, rather than skipping the slot.
+    pub produced: bool,
+    /// The leader's whole fee reward for the block, in lamports.
+    pub fee_reward: u64,
+    /// The part of the fee reward counted under --block-reward-components, in lamports.
+    pub counted: u64,
+}
+
+/// The validator's leader slots over an epoch, as found by the block scan.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LeaderSlots {
+    pub produced: u64,
+    pub skipped: u64,
+}
+
+impl LeaderSlots {
+    fn of(slot_fees: &[SlotFees]) -> Self {
+        let produced = slot_fees.iter().filter(|fees| fees.produced).count() as u64;
+        Self {
+            produced,
+            skipped: slot_fees.len() as u64 - produced,
+        }
+    }
+
+    /// Share of the leader slots skipped, in percent. Zero without leader slots.
+    pub fn skip_rate_percent(&self) -> f64 {
+        match self.produced + self.skipped {
+            0 => 0.0,
+            total => self.skipped as f64 * 100.0 / total as f64,
+        }
+    }
+}
+
+impl fmt::Display for LeaderSlots {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Leader slots: {} produced, {} skipped ({:.2}% skip rate)",
+            self.produced,
+            self.skipped,
+            self.skip_rate_percent()
+        )
+    }
+}
+
+/// Emits the `leader_slots` datapoint of the epoch scanned.
+fn report_leader_slots(vote_pubkey: &Pubkey, epoch: u64, slots: &LeaderSlots) {
+    info!("Epoch {}: {}", epoch, slots);
+    datapoint_info!(
+        "leader_slots",
+        ("vote_pubkey", vote_pubkey.to_string(), String),
+        ("epoch", epoch.to_string(), String),
+        ("produced", slots.produced as i64, i64),
+        ("skipped", slots.skipped as i64, i64),
+        ("skip_rate_percent", slots.skip_rate_percent(), f64),
+    );
+}
+
+/// Renders the slots' fees as a CSV document with a header row.
+fn slot_fees_csv(fees: &[SlotFees]) -> String {
+    let mut csv = String::from("slot,produced,fee_reward,counted\n");
+    for fee in fees {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            fee.slot, fee.produced, fee.fee_reward, fee.counted
+        ));
+    }
+    csv
+}
+
+/// Writes the epoch's slot fees to `--slot-export-dir`, if set. Failures are only logged, the
+/// export is a debugging aid.
+fn export_slot_fees(export: &SlotExportArgs, epoch: u64, suffix: &str, fees: &[SlotFees]) {
+    let Some(dir) = &export.slot_export_dir else {
+        return;
+    };
+    let format = export.slot_export_format;
+    let path = dir.join(format!(
+        "block-rewards-{}{}.{}",
+        epoch,
+        suffix,
+        format.extension()
+    ));
+    let contents = match format {
+        SlotExportFormat::Csv => Ok(slot_fees_csv(fees)),
+        SlotExportFormat::Json => serde_json::to_string_pretty(fees).map_err(|e| e.to_string()),
+    };
+    let write = contents.and_then(|contents| {
+        fs::create_dir_all(dir)
+            .and_then(|()| fs::write(&path, contents))
+            .map_err(|e| e.to_string())
+    });
+    match write {
+        Ok(()) => info!(
+            "Exported the fees of {} slots to {}",
+            fees.len(),
+            path.display()
+        ),
+        Err(e) => error!("Failed to export slot fees to {}: {}", path.display(), e),
+    }
+}
+
+/// Options for cross-checking the epoch's block fees against a second RPC provider.
+#[derive(Clone, Debug, Default, Args)]
+pub struct VerifyRpcArgs {
+    /// Independent RPC endpoint used to recompute the epoch's total block fees. When set, a
+    /// failed block scan aborts instead of counting as no block rewards.
+    #[arg(long, env)]
+    pub verify_rpc: Option<String>,
+    /// Maximum divergence (in bps of the larger total) tolerated between the two providers.
+    #[arg(long, env, default_value = "0")]
+    pub verify_tolerance_bps: u64,
+    /// Only warn, instead of aborting, when the two providers disagree.
+    #[arg(long, env, requires = "verify_rpc")]
+    pub verify_warn_only: bool,
+}
+
+/// Returns true when `primary` and `secondary` differ by more than `tolerance_bps` of the larger
+/// of the two totals.
+pub fn block_rewards_diverge(primary: u64, secondary: u64, tolerance_bps: u64) -> bool {
+    let difference = u128::from(primary.abs_diff(secondary));
+    let allowed = u128::from(primary.max(secondary)) * u128::from(tolerance_bps);
+    difference * u128::from(MAX_BPS) > allowed
+}
+
+/// Recomputes the epoch's block fees from `--verify-rpc` and fails if they diverge from
+/// `primary_total`, unless `--verify-warn-only` is set.
+pub async fn verify_block_rewards(
+    primary_total: u64,
+    vote_pubkey: &Pubkey,
+    epoch_info: &EpochInfo,
+    options: &BlockScanOptions,
+) -> Result<()> {
+    let Some(verify_rpc) = options.verify.verify_rpc.clone() else {
+        return Ok(());
+    };
+    info!("Verifying block rewards against {}", verify_rpc);
+    let verify_client = new_rpc_client(
+        verify_rpc.clone(),
+        CommitmentConfig::confirmed(),
+        None,
+        None,
+    );
+    // The second provider's scan is independent of the local ledger.
+    let secondary_options = BlockScanOptions {
+        verify: VerifyRpcArgs::default(),
+        archive: BlockArchiveArgs {
+            blockstore_path: None,
+            ..options.archive.clone()
+        },
+        ..options.clone()
+    };
+    let (secondary_total, _) = scan_block_rewards(
+        &verify_client,
+        // The second provider's scan reads its own SlotHistory.
+        &EpochSysvars::default(),
+        vote_pubkey,
+        epoch_info,
+        &secondary_options,
+        "-verify",
+    )
+    .await
+    .map_err(|e| {
+        rpc_error(
+            format!("Failed to verify block rewards with {}", verify_rpc),
+            e,
+        )
+    })?;
+
+    if !block_rewards_diverge(
+        primary_total,
+        secondary_total,
+        options.verify.verify_tolerance_bps,
+    ) {
+        info!(
+            "Block rewards verified: primary {}, {} {}",
+            format_amount(primary_total),
+            verify_rpc,
+            format_amount(secondary_total)
+        );
+        return Ok(());
+    }
+
+    datapoint_warn!(
+        "block_reward_divergence",
+        ("vote_pubkey", vote_pubkey.to_string(), String),
+        ("epoch", (epoch_info.epoch - 1).to_string(), String),
+        ("primary_total", primary_total as i64, i64),
+        ("secondary_total", secondary_total as i64, i64),
+    );
+    let message = format!(
+        "Block rewards diverge beyond {} bps: primary RPC reported {}, {} reported {}",
+        options.verify.verify_tolerance_bps,
+        format_amount(primary_total),
+        verify_rpc,
+        format_amount(secondary_total)
+    );
+    if options.verify.verify_warn_only {
+        warn!("{}", message);
+        Ok(())
+    } else {
+        Err(anyhow!(message))
+    }
+}
+
+/// Splits the leader's fee reward for one block into its base fee and priority fee parts.
+///
+/// The base part is derived from the block's signature count and the priority part is the
+/// remainder, so the split holds both before and after SIMD-0096 moved 100% of priority fees to
+/// the leader.
+pub fn split_block_fee_reward(fee_reward: u64, signature_count: u64) -> (u64, u64) {
+    let base = signature_count
+        .saturating_mul(LAMPORTS_PER_SIGNATURE)
+        .saturating_mul(100 - BASE_FEE_BURN_PERCENT)
+        / 100;
+    let base = base.min(fee_reward);
+    (base, fee_reward - base)
+}
+
+fn count_signatures(block: &UiConfirmedBlock) -> u64 {
+    block
+        .transactions
+        .iter()
+        .flatten()
+        .map(|tx| match &tx.transaction {
+            EncodedTransaction::Accounts(accounts) => accounts.signatures.len() as u64,
+            // Archived blocks may hold full transactions.
+            EncodedTransaction::Json(tx) => tx.signatures.len() as u64,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// The fees the validator earned as leader of the block at `slot`.
+fn block_slot_fees(
+    slot: u64,
+    block: &UiConfirmedBlock,
+    node_identity: &str,
+    components: BlockRewardComponents,
+) -> SlotFees {
+    let mut total = 0;
+    if let Some(rewards) = &block.rewards {
+        for r in rewards {
+            if r.pubkey == node_identity {
+                if let Some(RewardType::Fee) = r.reward_type {
+                    total += r.lamports as u64;
+                }
+            }
+        }
+    }
+    let signature_count = match components {
+        BlockRewardComponents::All => 0,
+        _ => count_signatures(block),
+    };
+    SlotFees {
+        slot,
+        produced: true,
+        fee_reward: total,
+        counted: components.select(total, signature_count),
+    }
+}
+
 /// Computes the excess block commission owed to pye_account holders.
 ///
 /// # Arguments
@@ -19,38 +385,79 @@ use std::time::Duration;
 /// - `pye_account_active_stake`: pye_account's active stake at epoch (subset of validator_active_stake)
 /// - `validator_active_stake`: validator's total active stake at epoch
 /// - `block_rewards_bps`: expected commission rate in basis points (0–10000)
+/// - `rounding`: rounding applied to each division
 pub fn compute_excess_block_commission(
     total_block_reward: u64,
     pye_account_active_stake: u64,
     validator_active_stake: u64,
     block_rewards_bps: u16,
-) -> i64 {
+    rounding: &mut Rounding,
+) -> Result<i64, RewardMathError> {
     if validator_active_stake == 0 {
-        return 0;
+        return Ok(0);
     }
 
-    let pye_account_block_reward = ((u128::from(pye_account_active_stake) * u128::from(total_block_reward))
-        / u128::from(validator_active_stake)) as u64;
+    let pye_account_block_reward = stake_weighted_share(
+        total_block_reward,
+        pye_account_active_stake,
+        validator_active_stake,
+        rounding,
+    );
+    let shared_bps = u128::from(MAX_BPS) - checked_bps(u64::from(block_rewards_bps))?;
 
-    let excess_block_commission =
-        (pye_account_block_reward * u64::from(10000 - block_rewards_bps) / 10000) as i64;
+    let excess_block_commission = rounding.div(
+        pye_account_block_reward
+            .checked_mul(shared_bps)
+            .ok_or(RewardMathError::Overflow("excess block commission"))?,
+        u128::from(MAX_BPS),
+    );
 
-    excess_block_commission
+    to_i64(excess_block_commission, "excess block commission")
 }
 
-/// Uses and RPC client to fetch the block rewards for a given validator
+/// Uses and RPC client to fetch the block rewards for a given validator, cross-checking the total
+/// against `--verify-rpc` when set. Also returns the produced and skipped leader slots.
 pub async fn calculate_block_rewards(
     rpc: &RpcClient,
+    sysvars: &EpochSysvars,
     vote_pubkey: &Pubkey,
     epoch_info: &EpochInfo,
-    concurrency: usize,
-    block_retry_delay: u64,
-) -> Result<u64> {
+    options: &BlockScanOptions,
+) -> Result<(u64, LeaderSlots)> {
+    let (total_fees, leader_slots) =
+        scan_block_rewards(rpc, sysvars, vote_pubkey, epoch_info, options, "").await?;
+    report_leader_slots(vote_pubkey, epoch_info.epoch - 1, &leader_slots);
+    verify_block_rewards(total_fees, vote_pubkey, epoch_info, options).await?;
+    Ok((total_fees, leader_slots))
+}
+
+/// Whether the block at a leader slot must be fetched: it was produced according to getBlocks,
+/// SlotHistory disagrees with getBlocks about it, or getBlocks failed (`produced` is `None`).
+fn needs_fetch(slot: u64, produced: Option<&HashSet<u64>>, slot_history: &SlotHistory) -> bool {
+    match produced {
+        Some(produced) => {
+            produced.contains(&slot) || slot_history.check(slot) != SlotHistoryCheck::NotFound
+        }
+        None => true,
+    }
+}
+
+/// Sums the fee rewards of the blocks the validator produced in the epoch before `epoch_info`,
+/// exporting every leader slot's fees with `export_suffix` appended to the file name. Also counts
+/// the leader slots produced and skipped.
+async fn scan_block_rewards(
+    rpc: &RpcClient,
+    sysvars: &EpochSysvars,
+    vote_pubkey: &Pubkey,
+    epoch_info: &EpochInfo,
+    options: &BlockScanOptions,
+    export_suffix: &str,
+) -> Result<(u64, LeaderSlots)> {
     let vote_str = vote_pubkey.to_string();
     let vote_accounts = rpc
         .get_vote_accounts()
         .await
-        .map_err(|e| anyhow!("Failed to fetch vote accounts: {}", e))?;
+        .map_err(|e| rpc_error("Failed to fetch vote accounts", e))?;
 
     let node_identity = vote_accounts
         .current
@@ -75,7 +482,7 @@ pub async fn calculate_block_rewards(
             },
         )
         .await
-        .map_err(|e| anyhow!("Failed to fetch leader schedule: {}", e))?
+        .map_err(|e| rpc_error("Failed to fetch leader schedule", e))?
         .ok_or_else(|| anyhow!("Leader schedule not found for node {}", node_identity))?;
 
     let indices = schedule
@@ -84,58 +491,196 @@ pub async fn calculate_block_rewards(
         .ok_or(anyhow!("Err looking up leader schedule"))?;
     let slots: Vec<u64> = indices.into_iter().map(|i| first + i as u64).collect();
 
-    // 3) Fetch each block that the leader produced to calculate total block rewards earned.
-    let slot_history = Arc::new(crate::accounts::fetch_slot_history(rpc).await?);
+    // 3) Enumerate the blocks produced over the leader slots with getBlocks. A slot missing from
+    // both getBlocks and SlotHistory was skipped, only the slots they disagree on go through the
+    // per-slot skip detection and its retries.
+    // A local ledger's rooted slots settle it without any RPC request.
+    let slot_history = sysvars.slot_history(rpc).await?;
+    let archive = BlockArchive::connect(&options.archive).await?;
+    let local_archive = archive.as_ref().filter(|archive| archive.is_local());
+    let (slots, skipped_slots): (Vec<u64>, Vec<u64>) =
+        match (slots.iter().min(), slots.iter().max(), local_archive) {
+            (Some(first_slot), Some(last_slot), Some(local_archive)) => {
+                let produced = local_archive
+                    .produced_blocks(*first_slot, *last_slot)
+                    .await?
+                    .unwrap_or_default();
+                slots.into_iter().partition(|slot| produced.contains(slot))
+            }
+            (Some(first_slot), Some(last_slot), None) => {
+                let produced = rpc_utils::get_produced_blocks(rpc, *first_slot, *last_slot)
+                    .await
+                    .map_err(|e| {
+                        warn!(
+                            "getBlocks failed, checking every leader slot on its own: {}",
+                            e
+                        )
+                    })
+                    .ok();
+                slots
+                    .into_iter()
+                    .partition(|slot| needs_fetch(*slot, produced.as_ref(), &slot_history))
+            }
+            _ => (slots, Vec::new()),
+        };
+
+    // 4) Fetch each block that the leader produced to calculate total block rewards earned.
 
-    // TODO: Replace with a batched JSON-RPC call to reduce HTTP overhead.
     info!(
-        "Fetching {} Blocks Produced in Epoch {}",
+        "Fetching {} Blocks Produced in Epoch {} ({} leader slots skipped, components: {:?})",
         slots.len(),
         epoch_info.epoch - 1,
+        skipped_slots.len(),
+        options.components,
     );
-    let total_fees: u64 = stream::iter(slots)
+    let components = options.components;
+    let block_retry_delay = Duration::from_secs(options.block_retry_delay);
+    let block_retry_deadline = Duration::from_secs(options.block_retry_deadline);
+    let progress = BlockScanProgress::new(
+        (slots.len() + skipped_slots.len()) as u64,
+        epoch_info.epoch - 1,
+        &rpc.url(),
+    );
+    let progress = &progress;
+    let archive = &archive;
+    for _ in &skipped_slots {
+        progress.skipped();
+    }
+    let mut slot_fees: Vec<SlotFees> = stream::iter(slots)
         .map(|slot| {
             let node_identity = node_identity.clone();
             let slot_history = Arc::clone(&slot_history);
             async move {
-                let mut attempts: u8 = 0;
+                if let Some(local_archive) = local_archive {
+                    let fees = match local_archive
+                        .get_block(slot, components.transaction_details())
+                        .await?
+                    {
+                        Some(block) => block_slot_fees(slot, &block, &node_identity, components),
+                        None => {
+                            return Err(anyhow!(
+                                "Block at slot {} not found in the blockstore",
+                                slot
+                            ));
+                        }
+                    };
+                    debug!(
+                        target: BLOCK_FETCH_TARGET,
+                        "Read block at slot {} from the blockstore: {} lamports of fee rewards",
+                        slot,
+                        fees.fee_reward
+                    );
+                    progress.fetched();
+                    return Ok(fees);
+                }
+                let mut backoff = Backoff::new(block_retry_delay, block_retry_deadline);
+                let mut attempts: u32 = 0;
                 loop {
                     attempts += 1;
-                    match rpc_utils::get_block(rpc, slot, &slot_history).await {
+                    match rpc_utils::get_block(
+                        rpc,
+                        slot,
+                        &slot_history,
+                        components.transaction_details(),
+                    )
+                    .await
+                    {
                         Ok(block) => {
-                            let mut total = 0;
-                            if let Some(rewards) = block.rewards {
-                                for r in rewards {
-                                    if r.pubkey == node_identity {
-                                        if let Some(RewardType::Fee) = r.reward_type {
-                                            total += r.lamports as u64;
-                                        }
-                                    }
-                                }
-                            }
-                            return Ok(Some(total));
+                            let fees = block_slot_fees(slot, &block, &node_identity, components);
+                            debug!(
+                                target: BLOCK_FETCH_TARGET,
+                                "Fetched block at slot {}: {} lamports of fee rewards",
+                                slot,
+                                fees.fee_reward
+                            );
+                            progress.fetched();
+                            return Ok(fees);
                         }
                         Err(e) => {
                             match e {
                                 PriorityFeeKeeperError::SkippedBlock => {
                                     warn!(
+                                        target: BLOCK_FETCH_TARGET,
                                         "PriorityFeeKeeperError::SkippedBlock at slot {}: {}",
                                         slot, e
                                     );
-                                    return Ok(None);
+                                    progress.skipped();
+                                    return Ok(SlotFees {
+                                        slot,
+                                        produced: false,
+                                        fee_reward: 0,
+                                        counted: 0,
+                                    });
+                                }
+                                PriorityFeeKeeperError::InSlotHistoryNotOnRpc(_)
+                                    if archive.is_some() =>
+                                {
+                                    let archive = archive.as_ref().unwrap();
+                                    match archive
+                                        .get_block(slot, components.transaction_details())
+                                        .await
+                                    {
+                                        Ok(Some(block)) => {
+                                            let fees = block_slot_fees(
+                                                slot,
+                                                &block,
+                                                &node_identity,
+                                                components,
+                                            );
+                                            debug!(
+                                                target: BLOCK_FETCH_TARGET,
+                                                "Fetched block at slot {} from the {}: {} lamports of fee rewards",
+                                                slot,
+                                                archive.name(),
+                                                fees.fee_reward
+                                            );
+                                            progress.fetched();
+                                            return Ok(fees);
+                                        }
+                                        Ok(None) => {
+                                            return Err(anyhow!(
+                                                "Block at slot {} is neither on the RPC nor in the {}",
+                                                slot,
+                                                archive.name()
+                                            ));
+                                        }
+                                        Err(archive_err) => {
+                                            progress.failed();
+                                            let Some(delay) = backoff.next_delay(false) else {
+                                                return Err(archive_err);
+                                            };
+                                            warn!(
+                                                target: BLOCK_FETCH_TARGET,
+                                                "Failed to fetch block at slot {} from the {} (attempt {}), retrying in {:?}: {}",
+                                                slot,
+                                                archive.name(),
+                                                attempts,
+                                                delay,
+                                                archive_err
+                                            );
+                                            tokio::time::sleep(delay).await;
+                                        }
+                                    }
                                 }
                                 _ => {
-                                    if attempts >= 5 {
+                                    progress.failed();
+                                    let Some(delay) = backoff.next_delay(e.is_transient()) else {
                                         return Err(anyhow!(
-                                            "Failed to fetch block at slot {}: {}",
+                                            "Failed to fetch block at slot {} after {} attempts: {}",
                                             slot,
+                                            attempts,
                                             e
                                         ));
-                                    } else {
-                                        // sleep for 30min before trying this block again. Max wait time is currently 2.5 hours
-                                        tokio::time::sleep(Duration::from_secs(block_retry_delay))
-                                            .await;
-                                    }
+                                    };
+                                    warn!(
+                                        target: BLOCK_FETCH_TARGET,
+                                        "Failed to fetch block at slot {} (attempt {}), retrying in {:?}: {}",
+                                        slot,
+                                        attempts,
+                                        delay,
+                                        e
+                                    );
+                                    tokio::time::sleep(delay).await;
                                 }
                             }
                         }
@@ -143,62 +688,83 @@ pub async fn calculate_block_rewards(
                 }
             }
         })
-        .buffer_unordered(concurrency)
-        .fold(Ok(0u64), |acc, fee_result| async move {
-            match (acc, fee_result) {
-                (Ok(acc), Ok(fee)) => Ok(acc + fee.unwrap_or(0)),
-                (Err(e), _) | (_, Err(e)) => Err(e),
-            }
-        })
+        .buffer_unordered(options.concurrency)
+        .try_collect()
         .await?;
+    slot_fees.extend(skipped_slots.into_iter().map(|slot| SlotFees {
+        slot,
+        produced: false,
+        fee_reward: 0,
+        counted: 0,
+    }));
+    slot_fees.sort_by_key(|fees| fees.slot);
+    export_slot_fees(
+        &options.slot_export,
+        epoch_info.epoch - 1,
+        export_suffix,
+        &slot_fees,
+    );
 
-    Ok(total_fees)
+    Ok((
+        slot_fees.iter().map(|fees| fees.counted).sum(),
+        LeaderSlots::of(&slot_fees),
+    ))
 }
 
+/// The bond's excess block commission for the epoch before `epoch_info`, along with the
+/// validator's total block rewards it was computed from.
 pub async fn calculate_excess_block_reward(
     client: &RpcClient,
+    sysvars: &EpochSysvars,
     vote_pubkey: &Pubkey,
     epoch_info: &EpochInfo,
     pye_account_active_stake: u64,
     validator_active_stake: u64,
     reward_commissions: &RewardCommissions,
-    concurrency: usize,
-    block_retry_delay: u64,
-) -> Result<i64> {
-    let total_block_reward: std::result::Result<u64, anyhow::Error> = calculate_block_rewards(
-        client,
-        vote_pubkey,
-        epoch_info,
-        concurrency,
-        block_retry_delay,
-    )
-    .await;
+    options: &BlockScanOptions,
+    rounding: &mut Rounding,
+) -> Result<(i64, u64)> {
+    let total_block_reward: std::result::Result<u64, anyhow::Error> =
+        scan_block_rewards(client, sysvars, vote_pubkey, epoch_info, options, "")
+            .await
+            .map(|(total_fees, leader_slots)| {
+                report_leader_slots(vote_pubkey, epoch_info.epoch - 1, &leader_slots);
+                total_fees
+            });
 
     if validator_active_stake == 0 {
         info!("No excess block reward when validator active stake is 0");
-        return Ok(0);
+        return Ok((0, total_block_reward.unwrap_or(0)));
     }
 
     match total_block_reward {
         Ok(amount) => {
+            // A divergence must abort rather than fall through to "no block reward".
+            verify_block_rewards(amount, vote_pubkey, epoch_info, options).await?;
             let excess_block_commission = compute_excess_block_commission(
                 amount,
                 pye_account_active_stake,
                 validator_active_stake,
                 reward_commissions.block_rewards_bps,
-            );
+                rounding,
+            )?;
             info!(
                 "Total Block Reward: {}, Excess Block Commission: {}\n",
-                amount, excess_block_commission
+                format_amount(amount),
+                format_amount(excess_block_commission)
             );
-            Ok(excess_block_commission)
+            Ok((excess_block_commission, amount))
+        }
+        // With --verify-rpc set, a failed scan mustn't pass for an epoch without block rewards.
+        Err(e) if options.verify.verify_rpc.is_some() => {
+            Err(e.context("Failed to fetch block rewards, not assuming none with --verify-rpc"))
         }
         Err(e) => {
             info!(
                 "Error fetching block reward: {}. Assuming no block reward earned.\n",
                 e
             );
-            Ok(0)
+            Ok((0, 0))
         }
     }
 }
@@ -206,34 +772,184 @@ pub async fn calculate_excess_block_reward(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_needs_fetch() {
+        let mut slot_history = SlotHistory::default();
+        slot_history.add(10);
+        slot_history.add(12);
+        let produced = HashSet::from([10]);
+        assert!(needs_fetch(10, Some(&produced), &slot_history));
+        // Missing from both, skipped.
+        assert!(!needs_fetch(11, Some(&produced), &slot_history));
+        // In SlotHistory but not served by getBlocks, ambiguous.
+        assert!(needs_fetch(12, Some(&produced), &slot_history));
+        assert!(needs_fetch(11, None, &slot_history));
+    }
+
+    #[test]
+    fn test_slot_fees_csv() {
+        let fees = vec![
+            SlotFees {
+                slot: 100,
+                produced: true,
+                fee_reward: 7_500,
+                counted: 5_000,
+            },
+            SlotFees {
+                slot: 101,
+                produced: false,
+                fee_reward: 0,
+                counted: 0,
+            },
+        ];
+        assert_eq!(
+            slot_fees_csv(&fees),
+            "slot,produced,fee_reward,counted\n100,true,7500,5000\n101,false,0,0\n"
+        );
+
+        let slots = LeaderSlots::of(&fees);
+        assert_eq!(
+            slots,
+            LeaderSlots {
+                produced: 1,
+                skipped: 1
+            }
+        );
+        assert_eq!(slots.skip_rate_percent(), 50.0);
+        assert_eq!(LeaderSlots::default().skip_rate_percent(), 0.0);
+    }
 
     #[test]
     fn test_partial_pye_account_stake() {
-        let result = compute_excess_block_commission(1_000_000, 500_000, 1_000_000, 5000);
-        assert_eq!(result, 250_000);
+        let result = compute_excess_block_commission(
+            1_000_000,
+            500_000,
+            1_000_000,
+            5000,
+            &mut Rounding::default(),
+        );
+        assert_eq!(result, Ok(250_000));
     }
 
     #[test]
     fn test_zero_validator_stake() {
-        let result = compute_excess_block_commission(1_000_000, 500_000, 0, 5000);
-        assert_eq!(result, 0);
+        let result =
+            compute_excess_block_commission(1_000_000, 500_000, 0, 5000, &mut Rounding::default());
+        assert_eq!(result, Ok(0));
     }
 
     #[test]
     fn test_zero_pye_account_stake() {
-        let result = compute_excess_block_commission(1_000_000, 0, 0, 5000);
-        assert_eq!(result, 0);
+        let result =
+            compute_excess_block_commission(1_000_000, 0, 0, 5000, &mut Rounding::default());
+        assert_eq!(result, Ok(0));
     }
 
     #[test]
     fn test_fully_pye_account_stake() {
-        let result = compute_excess_block_commission(1_000_000, 1_000_000, 1_000_000, 0);
-        assert_eq!(result, 1_000_000);
+        let result = compute_excess_block_commission(
+            1_000_000,
+            1_000_000,
+            1_000_000,
+            0,
+            &mut Rounding::default(),
+        );
+        assert_eq!(result, Ok(1_000_000));
     }
 
     #[test]
     fn test_fully_pye_account_stake_with_commission() {
-        let result = compute_excess_block_commission(1_000_000, 1_000_000, 1_000_000, 2000);
-        assert_eq!(result, 800_000);
+        let result = compute_excess_block_commission(
+            1_000_000,
+            1_000_000,
+            1_000_000,
+            2000,
+            &mut Rounding::default(),
+        );
+        assert_eq!(result, Ok(800_000));
+    }
+
+    #[test]
+    fn test_split_block_fee_reward() {
+        // 10 signatures pay 50_000 lamports in base fees, half of which is burned.
+        assert_eq!(split_block_fee_reward(100_000, 10), (25_000, 75_000));
+    }
+
+    #[test]
+    fn test_split_block_fee_reward_caps_base_at_reward() {
+        assert_eq!(split_block_fee_reward(10_000, 10), (10_000, 0));
+    }
+
+    #[test]
+    fn test_components_select() {
+        assert_eq!(BlockRewardComponents::All.select(100_000, 10), 100_000);
+        assert_eq!(
+            BlockRewardComponents::PriorityOnly.select(100_000, 10),
+            75_000
+        );
+        assert_eq!(BlockRewardComponents::BaseOnly.select(100_000, 10), 25_000);
+    }
+
+    #[test]
+    fn test_block_rewards_diverge() {
+        assert!(!block_rewards_diverge(1_000_000, 1_000_000, 0));
+        assert!(block_rewards_diverge(1_000_000, 999_999, 0));
+        // 10 bps of 1_000_000 is 1_000 lamports.
+        assert!(!block_rewards_diverge(1_000_000, 999_000, 10));
+        assert!(block_rewards_diverge(1_000_000, 998_999, 10));
+        assert!(!block_rewards_diverge(0, 0, 0));
+    }
+
+    #[test]
+    fn test_bps_out_of_range() {
+        let result = compute_excess_block_commission(
+            1_000_000,
+            1_000_000,
+            1_000_000,
+            10_001,
+            &mut Rounding::default(),
+        );
+        assert_eq!(result, Err(RewardMathError::BpsOutOfRange(10_001)));
+    }
+
+    #[test]
+    fn test_overflow_is_reported() {
+        let result =
+            compute_excess_block_commission(u64::MAX, u64::MAX, 1, 0, &mut Rounding::default());
+        assert_eq!(
+            result,
+            Err(RewardMathError::Overflow("excess block commission"))
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_excess_bounded_by_share(
+            total in 0..=1_000_000_000_000u64,
+            validator_stake in 1..=u64::MAX / 2,
+            pye_fraction in 0..=10_000u64,
+            bps in 0..=10_000u16,
+        ) {
+            let pye_stake = (u128::from(validator_stake) * u128::from(pye_fraction) / 10_000) as u64;
+            let excess = compute_excess_block_commission(total, pye_stake, validator_stake, bps, &mut Rounding::default()).unwrap();
+            prop_assert!(excess >= 0);
+            prop_assert!(excess as u128 <= stake_weighted_share(total, pye_stake, validator_stake, &mut Rounding::default()));
+            prop_assert!(excess as u64 <= total);
+        }
+
+        #[test]
+        fn prop_excess_decreases_with_commission(
+            total in 0..=1_000_000_000_000u64,
+            stake in 1..=1_000_000_000_000_000u64,
+            low in 0..=10_000u16,
+            high in 0..=10_000u16,
+        ) {
+            let (low, high) = (low.min(high), low.max(high));
+            let at_low = compute_excess_block_commission(total, stake, stake, low, &mut Rounding::default()).unwrap();
+            let at_high = compute_excess_block_commission(total, stake, stake, high, &mut Rounding::default()).unwrap();
+            prop_assert!(at_low >= at_high);
+        }
     }
 }
@@ -0,0 +1,424 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::{RpcBlockConfig, RpcTransactionConfig};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::epoch_schedule::EpochSchedule;
+use solana_sdk::message::VersionedMessage;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::vote::instruction::VoteInstruction;
+use solana_sdk::vote::program as vote_program;
+use solana_sdk::vote::state::VoteState;
+use solana_transaction_status_client_types::{TransactionDetails, UiTransactionEncoding};
+
+use crate::commission_guard::{
+    parse_tip_distribution_commission_bps, tip_distribution_address, TIP_DISTRIBUTION_PROGRAM_ID,
+};
+use crate::exit_code::rpc_error;
+use crate::metrics::datapoint_warn;
+
+/// getSignaturesForAddress page size.
+const SIGNATURES_PAGE_LIMIT: usize = 1_000;
+/// Upper bound on history pages scanned, for withdraw authorities that sign many transactions.
+const MAX_SIGNATURE_PAGES: usize = 20;
+
+/// Anchor discriminator of the tip distribution program's `initialize_tip_distribution_account`.
+const INITIALIZE_TIP_DISTRIBUTION_DISCRIMINATOR: [u8; 8] = [120, 191, 25, 182, 111, 49, 179, 55];
+
+/// A commission update found in the vote account's history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommissionChange {
+    pub slot: u64,
+    pub commission: u8,
+}
+
+/// The vote account's inflation commission over the target epoch.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EpochCommission {
+    /// Commission in effect at the first slot of the epoch, if known.
+    pub start: Option<u8>,
+    /// Commission in effect after the last slot of the epoch, which is the one rewards are
+    /// calculated with, if known.
+    pub end: Option<u8>,
+    /// Commission updates that landed inside the epoch.
+    pub changes: Vec<CommissionChange>,
+}
+
+impl EpochCommission {
+    /// Builds the epoch's commission from the vote account's `current` commission and the
+    /// `changes` (sorted by slot) found in its history since before `first_slot`.
+    pub fn from_history(
+        current: u8,
+        changes: &[CommissionChange],
+        first_slot: u64,
+        last_slot: u64,
+    ) -> Self {
+        let before_end = changes.iter().rev().find(|c| c.slot <= last_slot);
+        let end = match before_end {
+            Some(change) => Some(change.commission),
+            // Nothing changed since the epoch ended, so it still holds today's commission.
+            None if changes.iter().all(|c| c.slot <= last_slot) => Some(current),
+            None => None,
+        };
+        let in_epoch: Vec<CommissionChange> = changes
+            .iter()
+            .filter(|c| c.slot >= first_slot && c.slot <= last_slot)
+            .copied()
+            .collect();
+        let start = match changes.iter().rev().find(|c| c.slot < first_slot) {
+            Some(change) => Some(change.commission),
+            None if in_epoch.is_empty() => end,
+            None => None,
+        };
+        Self {
+            start,
+            end,
+            changes: in_epoch,
+        }
+    }
+
+    /// Fills in the start and end from commissions observed live at the epoch boundaries.
+    pub fn with_observed(mut self, start: Option<u8>, end: Option<u8>) -> Self {
+        self.start = self.start.or(start);
+        self.end = self.end.or(end);
+        self
+    }
+
+    pub fn changed_mid_epoch(&self) -> bool {
+        !self.changes.is_empty()
+            || matches!((self.start, self.end), (Some(start), Some(end)) if start != end)
+    }
+}
+
+/// Extracts the commissions set by UpdateCommission instructions for `vote_pubkey`.
+fn commission_updates(message: &VersionedMessage, vote_pubkey: &Pubkey) -> Vec<u8> {
+    let keys = message.static_account_keys();
+    message
+        .instructions()
+        .iter()
+        .filter(|ix| keys.get(usize::from(ix.program_id_index)) == Some(&vote_program::ID))
+        .filter(|ix| {
+            ix.accounts
+                .first()
+                .and_then(|index| keys.get(usize::from(*index)))
+                == Some(vote_pubkey)
+        })
+        .filter_map(
+            |ix| match bincode::deserialize::<VoteInstruction>(&ix.data) {
+                Ok(VoteInstruction::UpdateCommission(commission)) => Some(commission),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+/// The first transaction signature of the first block at or after `slot`. Passed as `until`, it
+/// stops a getSignaturesForAddress scan at `slot` instead of paging through older history.
+/// `None` if the block can't be read, leaving the scan bounded by slot alone.
+async fn first_signature_from(client: &RpcClient, slot: u64) -> Option<Signature> {
+    let block_slot = *client.get_blocks_with_limit(slot, 1).await.ok()?.first()?;
+    let block = client
+        .get_block_with_config(
+            block_slot,
+            RpcBlockConfig {
+                encoding: None,
+                transaction_details: Some(TransactionDetails::Signatures),
+                rewards: Some(false),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await
+        .ok()?;
+    Signature::from_str(block.signatures?.first()?).ok()
+}
+
+/// Scans the vote account withdraw authority's transactions back to `since_slot` for commission
+/// updates, since only the withdraw authority can sign them. Returns the current commission and
+/// the updates sorted by slot.
+pub async fn fetch_commission_changes(
+    client: &RpcClient,
+    vote_pubkey: &Pubkey,
+    since_slot: u64,
+) -> Result<(u8, Vec<CommissionChange>)> {
+    let vote_state = fetch_vote_state(client, vote_pubkey).await?;
+    let withdrawer = vote_state.authorized_withdrawer;
+    let until = first_signature_from(client, since_slot).await;
+
+    let mut changes = Vec::new();
+    let mut before = None;
+    for page in 0..MAX_SIGNATURE_PAGES {
+        let signatures = client
+            .get_signatures_for_address_with_config(
+                &withdrawer,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until,
+                    limit: Some(SIGNATURES_PAGE_LIMIT),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                },
+            )
+            .await?;
+        let Some(last) = signatures.last() else {
+            break;
+        };
+        before = Some(Signature::from_str(&last.signature)?);
+
+        for status in signatures.iter() {
+            if status.slot < since_slot || status.err.is_some() {
+                continue;
+            }
+            let signature = Signature::from_str(&status.signature)?;
+            let transaction = client
+                .get_transaction_with_config(
+                    &signature,
+                    RpcTransactionConfig {
+                        encoding: Some(UiTransactionEncoding::Base64),
+                        commitment: Some(CommitmentConfig::confirmed()),
+                        max_supported_transaction_version: Some(0),
+                    },
+                )
+                .await?;
+            if let Some(decoded) = transaction.transaction.transaction.decode() {
+                changes.extend(
+                    commission_updates(&decoded.message, vote_pubkey)
+                        .into_iter()
+                        .map(|commission| CommissionChange {
+                            slot: transaction.slot,
+                            commission,
+                        }),
+                );
+            }
+        }
+
+        if last.slot < since_slot || signatures.len() < SIGNATURES_PAGE_LIMIT {
+            break;
+        }
+        if page + 1 == MAX_SIGNATURE_PAGES {
+            warn!(
+                "Stopped scanning {} history after {} pages, commission changes before slot {} may be missed",
+                withdrawer, MAX_SIGNATURE_PAGES, last.slot
+            );
+        }
+    }
+    changes.sort_by_key(|change| change.slot);
+    Ok((vote_state.commission, changes))
+}
+
+/// Reads `validator_commission_bps` from an `initialize_tip_distribution_account` instruction,
+/// whose arguments are the merkle root upload authority, the commission and the bump.
+fn initialize_commission_bps(data: &[u8]) -> Option<u16> {
+    let args = data.strip_prefix(&INITIALIZE_TIP_DISTRIBUTION_DISCRIMINATOR)?;
+    let bytes = args.get(32..34)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+/// The MEV commission the validator's tip distribution account for `epoch` was created with,
+/// which is the one its tips are distributed with. It is read from the account or, once the
+/// account has been closed after the claim period, from the transaction that created it. None
+/// when the validator created no account for the epoch.
+pub async fn fetch_epoch_mev_commission(
+    client: &RpcClient,
+    vote_pubkey: &Pubkey,
+    epoch: u64,
+) -> Result<Option<u16>> {
+    let address = tip_distribution_address(vote_pubkey, epoch);
+    let account = client
+        .get_account_with_commitment(&address, CommitmentConfig::confirmed())
+        .await?
+        .value;
+    if let Some(account) = account {
+        return parse_tip_distribution_commission_bps(&account.data)
+            .map(Some)
+            .ok_or_else(|| anyhow!("Failed to parse tip distribution account {}", address));
+    }
+
+    // The account's first transaction is the one that created it.
+    let mut before = None;
+    let mut oldest = None;
+    for _ in 0..MAX_SIGNATURE_PAGES {
+        let signatures = client
+            .get_signatures_for_address_with_config(
+                &address,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until: None,
+                    limit: Some(SIGNATURES_PAGE_LIMIT),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                },
+            )
+            .await?;
+        let Some(last) = signatures.last() else {
+            break;
+        };
+        before = Some(Signature::from_str(&last.signature)?);
+        if let Some(created) = signatures.iter().rev().find(|status| status.err.is_none()) {
+            oldest = Some(Signature::from_str(&created.signature)?);
+        }
+        if signatures.len() < SIGNATURES_PAGE_LIMIT {
+            break;
+        }
+    }
+    let Some(signature) = oldest else {
+        return Ok(None);
+    };
+    let transaction = client
+        .get_transaction_with_config(
+            &signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await?;
+    let decoded = transaction
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| anyhow!("Failed to decode transaction {}", signature))?;
+    let keys = decoded.message.static_account_keys();
+    decoded
+        .message
+        .instructions()
+        .iter()
+        .filter(|ix| {
+            keys.get(usize::from(ix.program_id_index)) == Some(&TIP_DISTRIBUTION_PROGRAM_ID)
+        })
+        .find_map(|ix| initialize_commission_bps(&ix.data))
+        .map(Some)
+        .ok_or_else(|| {
+            anyhow!(
+                "Transaction {} doesn't initialize tip distribution account {}",
+                signature,
+                address
+            )
+        })
+}
+
+pub async fn fetch_vote_state(client: &RpcClient, vote_pubkey: &Pubkey) -> Result<VoteState> {
+    let account = client
+        .get_account(vote_pubkey)
+        .await
+        .map_err(|e| rpc_error(format!("Failed to fetch vote account {}", vote_pubkey), e))?;
+    VoteState::deserialize(&account.data)
+        .map_err(|e| anyhow!("Failed to deserialize vote account {}: {}", vote_pubkey, e))
+}
+
+/// Reads the vote account's current inflation commission.
+pub async fn fetch_vote_commission(client: &RpcClient, vote_pubkey: &Pubkey) -> Result<u8> {
+    Ok(fetch_vote_state(client, vote_pubkey).await?.commission)
+}
+
+/// Reconstructs the vote account's commission over `target_epoch` from its history. The scan
+/// starts one epoch earlier so the commission in effect at the start of the epoch is known.
+pub async fn track_epoch_commission(
+    client: &RpcClient,
+    vote_pubkey: &Pubkey,
+    epoch_schedule: &EpochSchedule,
+    target_epoch: u64,
+) -> Result<EpochCommission> {
+    let since_slot = epoch_schedule.get_first_slot_in_epoch(target_epoch.saturating_sub(1));
+    let (current, changes) = fetch_commission_changes(client, vote_pubkey, since_slot).await?;
+    Ok(EpochCommission::from_history(
+        current,
+        &changes,
+        epoch_schedule.get_first_slot_in_epoch(target_epoch),
+        epoch_schedule.get_last_slot_in_epoch(target_epoch),
+    ))
+}
+
+pub fn log_epoch_commission(
+    vote_pubkey: &Pubkey,
+    target_epoch: u64,
+    epoch_commission: &EpochCommission,
+) {
+    if !epoch_commission.changed_mid_epoch() {
+        info!(
+            "Commission of {} unchanged during epoch {}: {:?}",
+            vote_pubkey, target_epoch, epoch_commission.end
+        );
+        return;
+    }
+    warn!(
+        "Commission of {} changed during epoch {}: start {:?}, end {:?}, updates {:?}",
+        vote_pubkey,
+        target_epoch,
+        epoch_commission.start,
+        epoch_commission.end,
+        epoch_commission.changes
+    );
+    datapoint_warn!(
+        "commission_change",
+        ("vote_pubkey", vote_pubkey.to_string(), String),
+        ("epoch", target_epoch.to_string(), String),
+        (
+            "start_commission",
+            epoch_commission.start.map_or(-1, i64::from),
+            i64
+        ),
+        (
+            "end_commission",
+            epoch_commission.end.map_or(-1, i64::from),
+            i64
+        ),
+        ("updates", epoch_commission.changes.len() as i64, i64),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(slot: u64, commission: u8) -> CommissionChange {
+        CommissionChange { slot, commission }
+    }
+
+    #[test]
+    fn test_initialize_commission_bps() {
+        let mut data = INITIALIZE_TIP_DISTRIBUTION_DISCRIMINATOR.to_vec();
+        data.extend(Pubkey::new_unique().to_bytes());
+        data.extend(800u16.to_le_bytes());
+        data.push(255);
+        assert_eq!(initialize_commission_bps(&data), Some(800));
+        assert_eq!(initialize_commission_bps(&data[..20]), None);
+        data[0] = 0;
+        assert_eq!(initialize_commission_bps(&data), None);
+    }
+
+    #[test]
+    fn test_unchanged_commission() {
+        let epoch = EpochCommission::from_history(5, &[], 100, 199);
+        assert_eq!(epoch.start, Some(5));
+        assert_eq!(epoch.end, Some(5));
+        assert!(!epoch.changed_mid_epoch());
+    }
+
+    #[test]
+    fn test_change_inside_epoch() {
+        let epoch = EpochCommission::from_history(10, &[change(50, 5), change(150, 10)], 100, 199);
+        assert_eq!(epoch.start, Some(5));
+        assert_eq!(epoch.end, Some(10));
+        assert_eq!(epoch.changes, vec![change(150, 10)]);
+        assert!(epoch.changed_mid_epoch());
+    }
+
+    #[test]
+    fn test_change_after_epoch_hides_end() {
+        let epoch = EpochCommission::from_history(100, &[change(250, 100)], 100, 199);
+        assert_eq!(epoch.end, None);
+        assert!(!epoch.changed_mid_epoch());
+        let epoch = epoch.with_observed(Some(5), Some(5));
+        assert_eq!((epoch.start, epoch.end), (Some(5), Some(5)));
+    }
+
+    #[test]
+    fn test_observed_boundaries_detect_change() {
+        let epoch = EpochCommission::default().with_observed(Some(5), Some(100));
+        assert!(epoch.changed_mid_epoch());
+    }
+}
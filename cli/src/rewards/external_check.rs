@@ -0,0 +1,327 @@
+use anyhow::{anyhow, Result};
+use clap::{Args, ValueEnum};
+use log::{info, warn};
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::exit_code::ConfigError;
+use crate::http::http_client;
+use crate::metrics::datapoint_warn;
+use crate::rewards::block_rewards::block_rewards_diverge;
+
+const STAKEWIZ_URL: &str = "https://api.stakewiz.com/validator/{vote}";
+
+/// Third-party APIs the epoch's totals can be cross-checked against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExternalApi {
+    /// Stakewiz's validator endpoint, which reports the current inflation and MEV commissions.
+    Stakewiz,
+    /// Any JSON API, read with --external-check-url and the --external-*-pointer flags, e.g.
+    /// validators.app with its API token passed in --external-check-header.
+    Custom,
+}
+
+/// Options for cross-checking the epoch's totals against a third-party API before paying.
+#[derive(Clone, Debug, Default, Args)]
+pub struct ExternalCheckArgs {
+    /// Third-party API to cross-check the epoch's block and MEV rewards and commissions against
+    /// before any payment is sent.
+    #[arg(long, env, value_enum)]
+    pub external_check: Option<ExternalApi>,
+    /// URL of the API, with `{vote}` and `{epoch}` replaced by the vote account and the epoch
+    /// [default: the preset's URL]
+    #[arg(long, env, requires = "external_check")]
+    pub external_check_url: Option<String>,
+    /// Header sent with the request, as `Name: value`, e.g. an API token.
+    #[arg(long, env, requires = "external_check")]
+    pub external_check_header: Option<String>,
+    /// JSON pointer to the epoch's block rewards in lamports, e.g. `/block_rewards`.
+    #[arg(long, env, requires = "external_check")]
+    pub external_block_rewards_pointer: Option<String>,
+    /// JSON pointer to the epoch's MEV rewards in lamports.
+    #[arg(long, env, requires = "external_check")]
+    pub external_mev_rewards_pointer: Option<String>,
+    /// JSON pointer to the inflation commission in percent [default: the preset's]
+    #[arg(long, env, requires = "external_check")]
+    pub external_commission_pointer: Option<String>,
+    /// JSON pointer to the MEV commission in bps [default: the preset's]
+    #[arg(long, env, requires = "external_check")]
+    pub external_mev_commission_pointer: Option<String>,
+    /// Maximum divergence (in bps of the larger amount) tolerated between the API's and the
+    /// computed rewards. Commissions must match exactly.
+    #[arg(long, env, default_value = "100")]
+    pub external_check_tolerance_bps: u64,
+    /// Only warn, instead of aborting, when the API disagrees.
+    #[arg(long, env, requires = "external_check")]
+    pub external_check_warn_only: bool,
+}
+
+impl ExternalCheckArgs {
+    fn url(&self, api: ExternalApi) -> Option<&str> {
+        self.external_check_url.as_deref().or(match api {
+            ExternalApi::Stakewiz => Some(STAKEWIZ_URL),
+            ExternalApi::Custom => None,
+        })
+    }
+
+    /// The JSON pointer of each compared field, if the API reports it.
+    fn pointers(&self, api: ExternalApi) -> [(Field, Option<&str>); 4] {
+        let preset = |stakewiz: &'static str| match api {
+            ExternalApi::Stakewiz => Some(stakewiz),
+            ExternalApi::Custom => None,
+        };
+        [
+            (
+                Field::BlockRewards,
+                self.external_block_rewards_pointer.as_deref(),
+            ),
+            (
+                Field::MevRewards,
+                self.external_mev_rewards_pointer.as_deref(),
+            ),
+            (
+                Field::Commission,
+                self.external_commission_pointer
+                    .as_deref()
+                    .or(preset("/commission")),
+            ),
+            (
+                Field::MevCommissionBps,
+                self.external_mev_commission_pointer
+                    .as_deref()
+                    .or(preset("/jito_commission_bps")),
+            ),
+        ]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    BlockRewards,
+    MevRewards,
+    Commission,
+    MevCommissionBps,
+}
+
+impl Field {
+    fn name(self) -> &'static str {
+        match self {
+            Field::BlockRewards => "block_rewards",
+            Field::MevRewards => "mev_rewards",
+            Field::Commission => "commission",
+            Field::MevCommissionBps => "mev_commission_bps",
+        }
+    }
+}
+
+/// The epoch's totals as computed locally. Rewards of skipped categories are `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalTotals {
+    pub block_rewards: Option<u64>,
+    pub mev_rewards: Option<u64>,
+    /// Inflation commission in percent at the end of the epoch, when known.
+    pub commission: Option<u8>,
+    pub mev_commission_bps: Option<u64>,
+}
+
+impl LocalTotals {
+    fn get(&self, field: Field) -> Option<u64> {
+        match field {
+            Field::BlockRewards => self.block_rewards,
+            Field::MevRewards => self.mev_rewards,
+            Field::Commission => self.commission.map(u64::from),
+            Field::MevCommissionBps => self.mev_commission_bps,
+        }
+    }
+}
+
+/// A value the API and the local computation disagree on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Discrepancy {
+    field: Field,
+    local: u64,
+    external: u64,
+}
+
+/// Reads a non-negative number at `pointer`, rounding fractional values.
+fn read_number(response: &Value, pointer: &str) -> Option<u64> {
+    let value = response.pointer(pointer)?;
+    value.as_u64().or_else(|| {
+        value
+            .as_f64()
+            .filter(|value| *value >= 0.0)
+            .map(|value| value.round() as u64)
+    })
+}
+
+/// Compares every field the API reports with the local totals. Rewards may diverge by up to
+/// `tolerance_bps`, commissions must match.
+fn find_discrepancies(
+    local: &LocalTotals,
+    response: &Value,
+    pointers: &[(Field, Option<&str>)],
+    tolerance_bps: u64,
+) -> Vec<Discrepancy> {
+    let mut discrepancies = Vec::new();
+    for (field, pointer) in pointers {
+        let (Some(pointer), Some(local_value)) = (pointer, local.get(*field)) else {
+            continue;
+        };
+        let Some(external) = read_number(response, pointer) else {
+            warn!(
+                "The external API's response has no number at {} for {}",
+                pointer,
+                field.name()
+            );
+            continue;
+        };
+        let diverges = match field {
+            Field::BlockRewards | Field::MevRewards => {
+                block_rewards_diverge(local_value, external, tolerance_bps)
+            }
+            Field::Commission | Field::MevCommissionBps => local_value != external,
+        };
+        if diverges {
+            discrepancies.push(Discrepancy {
+                field: *field,
+                local: local_value,
+                external,
+            });
+        }
+    }
+    discrepancies
+}
+
+async fn fetch(url: &str, header: Option<&str>) -> Result<Value> {
+    let mut request = http_client().get(url);
+    if let Some(header) = header {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid header {}, expected `Name: value`", header))?;
+        request = request.header(name.trim(), value.trim());
+    }
+    request
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to send request: {}", e))?
+        .error_for_status()
+        .map_err(|e| anyhow!("Server returned error status: {}", e))?
+        .json()
+        .await
+        .map_err(|e| anyhow!("Failed to deserialize response: {}", e))
+}
+
+/// Cross-checks the epoch's totals against `--external-check`, failing on a discrepancy unless
+/// `--external-check-warn-only` is set. An unreachable API is only warned about.
+pub async fn check_external_totals(
+    args: &ExternalCheckArgs,
+    vote_pubkey: &Pubkey,
+    epoch: u64,
+    local: &LocalTotals,
+) -> Result<()> {
+    let Some(api) = args.external_check else {
+        return Ok(());
+    };
+    let url = args
+        .url(api)
+        .ok_or_else(|| anyhow!("--external-check custom requires --external-check-url"))
+        .map_err(|e| e.context(ConfigError))?
+        .replace("{vote}", &vote_pubkey.to_string())
+        .replace("{epoch}", &epoch.to_string());
+    let response = match fetch(&url, args.external_check_header.as_deref()).await {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Skipping the external check against {}: {}", url, e);
+            return Ok(());
+        }
+    };
+    let discrepancies = find_discrepancies(
+        local,
+        &response,
+        &args.pointers(api),
+        args.external_check_tolerance_bps,
+    );
+    if discrepancies.is_empty() {
+        info!("Epoch {} totals agree with {}", epoch, url);
+        return Ok(());
+    }
+
+    let mut message = format!("Epoch {} totals disagree with {}:", epoch, url);
+    for discrepancy in &discrepancies {
+        datapoint_warn!(
+            "external_check_divergence",
+            ("vote_pubkey", vote_pubkey.to_string(), String),
+            ("epoch", epoch.to_string(), String),
+            ("field", discrepancy.field.name().to_string(), String),
+            ("local", discrepancy.local as i64, i64),
+            ("external", discrepancy.external as i64, i64),
+        );
+        message.push_str(&format!(
+            "\n{}: computed {}, reported {}",
+            discrepancy.field.name(),
+            discrepancy.local,
+            discrepancy.external
+        ));
+    }
+    if args.external_check_warn_only {
+        warn!("{}", message);
+        Ok(())
+    } else {
+        Err(anyhow!(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_find_discrepancies() {
+        let local = LocalTotals {
+            block_rewards: Some(1_000_000),
+            mev_rewards: Some(500_000),
+            commission: Some(5),
+            mev_commission_bps: Some(800),
+        };
+        let args = ExternalCheckArgs {
+            external_block_rewards_pointer: Some("/epoch/block".to_string()),
+            external_mev_rewards_pointer: Some("/epoch/mev".to_string()),
+            ..Default::default()
+        };
+        let response = json!({
+            "commission": 5,
+            "jito_commission_bps": 1000,
+            "epoch": {"block": 1_005_000.4, "mev": 400_000}
+        });
+        let discrepancies = find_discrepancies(
+            &local,
+            &response,
+            &args.pointers(ExternalApi::Stakewiz),
+            100,
+        );
+        assert_eq!(
+            discrepancies,
+            vec![
+                Discrepancy {
+                    field: Field::MevRewards,
+                    local: 500_000,
+                    external: 400_000,
+                },
+                Discrepancy {
+                    field: Field::MevCommissionBps,
+                    local: 800,
+                    external: 1_000,
+                },
+            ]
+        );
+        // A custom API without pointers compares nothing.
+        assert!(find_discrepancies(
+            &local,
+            &response,
+            &ExternalCheckArgs::default().pointers(ExternalApi::Custom),
+            0
+        )
+        .is_empty());
+    }
+}
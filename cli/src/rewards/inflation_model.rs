@@ -0,0 +1,298 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use clap::Args;
+use log::{info, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::account::from_account;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::stake_history::StakeHistory;
+use solana_sdk::sysvar::epoch_rewards::{self, EpochRewards};
+
+use crate::accounts::{stake_account_state, EpochSysvars};
+use crate::exit_code::rpc_error;
+use crate::metrics::datapoint_warn;
+use crate::rewards::block_rewards::block_rewards_diverge;
+use crate::rewards::commission_history::fetch_vote_state;
+use crate::sol_format::format_amount;
+
+/// Options for recomputing inflation rewards locally instead of trusting getInflationReward.
+#[derive(Clone, Debug, Default, Args)]
+pub struct InflationVerifyArgs {
+    /// Recompute each stake account's inflation reward from vote credits and the EpochRewards
+    /// sysvar, and report where it disagrees with getInflationReward.
+    #[arg(long, env)]
+    pub verify_inflation: bool,
+    /// Maximum divergence (in bps of the larger reward) tolerated between the local and the
+    /// reported reward.
+    #[arg(long, env, default_value = "10")]
+    pub verify_inflation_tolerance_bps: u64,
+}
+
+/// Lamports paid per point in an epoch's reward distribution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PointValue {
+    pub rewards: u64,
+    pub points: u128,
+}
+
+/// Vote credits the validator earned during `epoch`.
+pub fn credits_in_epoch(epoch_credits: &[(u64, u64, u64)], epoch: u64) -> u64 {
+    epoch_credits
+        .iter()
+        .find(|(credits_epoch, _, _)| *credits_epoch == epoch)
+        .map_or(0, |(_, credits, prev_credits)| {
+            credits.saturating_sub(*prev_credits)
+        })
+}
+
+/// Splits the reward earned by `stake` over `credits` into the (staker, voter) portions, the way
+/// the runtime does: both portions are rounded down independently.
+pub fn expected_stake_reward(
+    stake: u64,
+    credits: u64,
+    point_value: &PointValue,
+    commission: u8,
+) -> (u64, u64) {
+    if point_value.points == 0 {
+        return (0, 0);
+    }
+    let points = u128::from(stake) * u128::from(credits);
+    let rewards = points * u128::from(point_value.rewards) / point_value.points;
+    let commission = u128::from(commission.min(100));
+    let voter = rewards * commission / 100;
+    let staker = rewards * (100 - commission) / 100;
+    (
+        u64::try_from(staker).unwrap_or(u64::MAX),
+        u64::try_from(voter).unwrap_or(u64::MAX),
+    )
+}
+
+/// Recovers the stake that earned rewards from a delegation that has already been credited with
+/// its staker reward. The reward is a tiny fraction of the stake, so a couple of rounds settle on
+/// the exact pre-reward amount.
+pub fn pre_reward_stake(
+    post_reward_stake: u64,
+    credits: u64,
+    point_value: &PointValue,
+    commission: u8,
+) -> u64 {
+    let mut stake = post_reward_stake;
+    for _ in 0..3 {
+        let (staker, _) = expected_stake_reward(stake, credits, point_value, commission);
+        stake = post_reward_stake.saturating_sub(staker);
+    }
+    stake
+}
+
+/// Everything needed to recompute the validator's delegators' rewards for the previous epoch.
+pub struct LocalInflationModel {
+    pub vote_pubkey: Pubkey,
+    pub target_epoch: u64,
+    pub point_value: PointValue,
+    pub credits: u64,
+    pub commission: u8,
+    stake_history: Arc<StakeHistory>,
+}
+
+impl LocalInflationModel {
+    /// Loads the point value from the EpochRewards sysvar, which only describes the most recent
+    /// distribution, so `target_epoch` must be the epoch before `current_epoch`.
+    pub async fn load(
+        client: &RpcClient,
+        sysvars: &EpochSysvars,
+        vote_pubkey: &Pubkey,
+        target_epoch: u64,
+        current_epoch: u64,
+        commission: Option<u8>,
+    ) -> Result<Self> {
+        if target_epoch + 1 != current_epoch {
+            return Err(anyhow!(
+                "Local inflation rewards are only available for the previous epoch ({}), not {}",
+                current_epoch.saturating_sub(1),
+                target_epoch
+            ));
+        }
+        let account = client
+            .get_account(&epoch_rewards::ID)
+            .await
+            .map_err(|e| rpc_error("Failed to fetch EpochRewards", e))?;
+        let sysvar: EpochRewards = from_account::<EpochRewards, _>(&account)
+            .ok_or_else(|| anyhow!("Failed to deserialize EpochRewards"))?;
+        let vote_state = fetch_vote_state(client, vote_pubkey).await?;
+        let model = Self {
+            vote_pubkey: *vote_pubkey,
+            target_epoch,
+            point_value: PointValue {
+                rewards: sysvar.total_rewards,
+                points: sysvar.total_points,
+            },
+            credits: credits_in_epoch(&vote_state.epoch_credits, target_epoch),
+            commission: commission.unwrap_or(vote_state.commission),
+            stake_history: sysvars.stake_history(client).await?,
+        };
+        info!(
+            "Local inflation model for epoch {}: {} over {} points, {} credits, {}% commission",
+            target_epoch,
+            format_amount(model.point_value.rewards),
+            model.point_value.points,
+            model.credits,
+            model.commission
+        );
+        Ok(model)
+    }
+
+    /// The stake that earned rewards in the target epoch, from an effective stake that already
+    /// includes the reward.
+    pub fn earning_stake(&self, effective: u64) -> u64 {
+        pre_reward_stake(effective, self.credits, &self.point_value, self.commission)
+    }
+
+    /// Recomputes the staker reward of `stake_pubkey`, or None when it isn't a delegation to the
+    /// validator.
+    pub async fn expected_reward(
+        &self,
+        client: &RpcClient,
+        stake_pubkey: &Pubkey,
+    ) -> Result<Option<u64>> {
+        let Ok(account) = client.get_account(stake_pubkey).await else {
+            return Ok(None);
+        };
+        let stake_state = stake_account_state(stake_pubkey, &account)?;
+        let Some(delegation) = stake_state.delegation() else {
+            return Ok(None);
+        };
+        if delegation.voter_pubkey != self.vote_pubkey {
+            return Ok(None);
+        }
+        let effective = delegation
+            .stake_activating_and_deactivating(self.target_epoch, &*self.stake_history, None)
+            .effective;
+        let stake = self.earning_stake(effective);
+        let (staker, _) =
+            expected_stake_reward(stake, self.credits, &self.point_value, self.commission);
+        Ok(Some(staker))
+    }
+}
+
+/// The stake accounts of a bond, leaving out the transient account when it isn't set.
+pub fn bond_stake_accounts(
+    stake_account: &Pubkey,
+    transient_stake_account: &Pubkey,
+) -> Vec<Pubkey> {
+    [*stake_account, *transient_stake_account]
+        .into_iter()
+        .filter(|pubkey| *pubkey != Pubkey::default())
+        .collect()
+}
+
+/// Compares the locally computed reward of each stake account against getInflationReward and
+/// reports every disagreement, including rewards missing from the RPC's history.
+pub async fn verify_inflation_rewards(
+    client: &RpcClient,
+    model: &LocalInflationModel,
+    stake_pubkeys: &[Pubkey],
+    tolerance_bps: u64,
+) {
+    let reported = match client
+        .get_inflation_reward(stake_pubkeys, Some(model.target_epoch))
+        .await
+    {
+        Ok(reported) => reported,
+        Err(e) => {
+            warn!("Failed to fetch inflation rewards to verify: {}", e);
+            return;
+        }
+    };
+    for (stake_pubkey, reported) in stake_pubkeys.iter().zip(reported) {
+        let expected = match model.expected_reward(client, stake_pubkey).await {
+            Ok(Some(expected)) => expected,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!(
+                    "Failed to compute local inflation reward for {}: {}",
+                    stake_pubkey, e
+                );
+                continue;
+            }
+        };
+        let reported = reported.map(|reward| reward.amount);
+        let diverged = match reported {
+            Some(amount) => block_rewards_diverge(amount, expected, tolerance_bps),
+            None => expected > 0,
+        };
+        if !diverged {
+            continue;
+        }
+        warn!(
+            "Inflation reward for {} in epoch {}: reported {:?}, computed locally {}",
+            stake_pubkey, model.target_epoch, reported, expected
+        );
+        datapoint_warn!(
+            "calc_quality",
+            ("check", "inflation_reward".to_string(), String),
+            ("epoch", model.target_epoch.to_string(), String),
+            ("stake_account", stake_pubkey.to_string(), String),
+            ("reported", reported.map_or(-1, |amount| amount as i64), i64),
+            ("expected", expected as i64, i64),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POINT_VALUE: PointValue = PointValue {
+        rewards: 1_000_000,
+        points: 1_000_000_000,
+    };
+
+    #[test]
+    fn test_credits_in_epoch() {
+        let epoch_credits = [(9, 1_000, 600), (10, 1_400, 1_000)];
+        assert_eq!(credits_in_epoch(&epoch_credits, 10), 400);
+        assert_eq!(credits_in_epoch(&epoch_credits, 11), 0);
+    }
+
+    #[test]
+    fn test_expected_stake_reward_splits_commission() {
+        // 1_000_000 points at 1 lamport per 1_000 points.
+        assert_eq!(
+            expected_stake_reward(10_000, 100, &POINT_VALUE, 10),
+            (900, 100)
+        );
+        assert_eq!(
+            expected_stake_reward(10_000, 100, &POINT_VALUE, 100),
+            (0, 1_000)
+        );
+        assert_eq!(
+            expected_stake_reward(
+                10_000,
+                100,
+                &PointValue {
+                    rewards: 1,
+                    points: 0
+                },
+                0
+            ),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn test_pre_reward_stake_removes_credited_reward() {
+        // Roughly 0.02% per epoch, as on mainnet.
+        let point_value = PointValue {
+            rewards: 1,
+            points: 2_000_000,
+        };
+        let stake = 1_000_000_000;
+        let (staker, _) = expected_stake_reward(stake, 400, &point_value, 5);
+        assert_eq!(staker, 190_000);
+        assert_eq!(
+            pre_reward_stake(stake + staker, 400, &point_value, 5),
+            stake
+        );
+    }
+}
@@ -1,132 +1,621 @@
+use std::collections::{HashMap, HashSet};
+
 use anyhow::{anyhow, Result};
-use log::{error, info};
+use log::{error, info, warn};
 use pye_core_cpi::pye_core::types::RewardCommissions;
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_response::RpcInflationReward;
 use solana_sdk::pubkey::Pubkey;
 
+use crate::accounts::EpochSysvars;
+use crate::active_stake::fetch_effective_stake;
+use crate::exit_code::rpc_error;
+use crate::metrics::datapoint_warn;
+use crate::rewards::{
+    checked_bps, stake_weighted_share, to_i64, RewardMathError, Rounding, MAX_BPS,
+};
+use crate::sol_format::format_amount;
+
+/// Addresses per batched getInflationReward request.
+const INFLATION_REWARD_BATCH_SIZE: usize = 100;
+
+/// The inflation rewards of an epoch, fetched for every bond's stake accounts and the vote
+/// account in a few batched requests and shared by the active stake and excess inflation reward
+/// calculations. Addresses the batch didn't cover are fetched on their own.
+#[derive(Clone, Debug)]
+pub struct InflationRewardCache {
+    epoch: u64,
+    rewards: HashMap<Pubkey, Option<RpcInflationReward>>,
+}
+
+impl InflationRewardCache {
+    /// A cache holding nothing, so that every address is fetched on its own.
+    pub fn empty(epoch: u64) -> Self {
+        Self {
+            epoch,
+            rewards: HashMap::new(),
+        }
+    }
+
+    /// Fetches the rewards of `addresses` for `epoch` in batches of
+    /// `INFLATION_REWARD_BATCH_SIZE`.
+    pub async fn fetch(client: &RpcClient, addresses: &[Pubkey], epoch: u64) -> Result<Self> {
+        let mut addresses = addresses.to_vec();
+        addresses.sort();
+        addresses.dedup();
+        let mut rewards = HashMap::with_capacity(addresses.len());
+        for batch in addresses.chunks(INFLATION_REWARD_BATCH_SIZE) {
+            let batch_rewards = client
+                .get_inflation_reward(batch, Some(epoch))
+                .await
+                .map_err(|e| rpc_error("Failed to fetch inflation rewards", e))?;
+            if batch_rewards.len() != batch.len() {
+                return Err(anyhow!(
+                    "Expected {} inflation rewards, got {}",
+                    batch.len(),
+                    batch_rewards.len()
+                ));
+            }
+            rewards.extend(batch.iter().copied().zip(batch_rewards));
+        }
+        info!(
+            "Fetched inflation rewards of {} accounts for epoch {} in {} requests",
+            addresses.len(),
+            epoch,
+            addresses.len().div_ceil(INFLATION_REWARD_BATCH_SIZE)
+        );
+        Ok(Self { epoch, rewards })
+    }
+
+    /// The inflation reward of `address`, `None` if it earned none.
+    pub async fn get(
+        &self,
+        client: &RpcClient,
+        address: &Pubkey,
+    ) -> Result<Option<RpcInflationReward>> {
+        if let Some(reward) = self.rewards.get(address) {
+            return Ok(reward.clone());
+        }
+        client
+            .get_inflation_reward(&[*address], Some(self.epoch))
+            .await
+            .map_err(|e| {
+                rpc_error(
+                    format!("Failed to fetch inflation reward of {}", address),
+                    e,
+                )
+            })?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No inflation rewards found for {}", address))
+    }
+}
+
+/// Validator-level inputs used when a stake account's inflation reward can't be used directly.
+pub struct InflationFallback<'a> {
+    /// The validator's vote account. Its commission reward is used to estimate the gross reward
+    /// of stake accounts reported with a 100% commission.
+    pub vote_pubkey: &'a Pubkey,
+    /// Validator's total active stake at the target epoch, computed from its stake accounts net
+    /// of the epoch's inflation rewards rather than taken from the Jito API. It's the share
+    /// denominator of the 100% commission estimate.
+    pub validator_active_stake: u64,
+    /// Commission (0-100) assumed when an inflation reward is reported without commission data.
+    /// When unset such accounts are skipped with a warning.
+    pub assumed_commission: Option<u8>,
+    /// Commission in effect when the target epoch's rewards were calculated, according to the
+    /// vote account's history. A reported commission that differs from it is warned about.
+    pub epoch_end_commission: Option<u8>,
+    /// The vote account's on-chain commission for the target epoch, used to sanity check the
+    /// commission reported by getInflationReward.
+    pub vote_account_commission: Option<u8>,
+    /// The epoch's sysvars, used to look up the effective stake of accounts with no reward entry.
+    pub sysvars: &'a EpochSysvars,
+}
+
 /// Computes the excess inflation commission owed to pye_account holders.
 ///
 /// # Arguments
 /// - `amount_after_commission`: actual reward received after commission
 /// - `commission_rate`: commission rate reported by validator (0-100)
 /// - `expected_bps`: expected commission rate in basis points (0-10_000)
+/// - `rounding`: rounding applied to each division
 pub fn compute_excess_inflation_commission(
     amount_after_commission: u64,
     commission_rate: u64,
     expected_bps: u16,
-) -> i64 {
-    let total_reward = amount_after_commission * 100 / (100 - commission_rate);
-    let actual_commission = (total_reward - amount_after_commission) as i64;
-    let expected_commission = (total_reward * expected_bps as u64 / 10000) as i64;
-    actual_commission - expected_commission
+    rounding: &mut Rounding,
+) -> Result<i64, RewardMathError> {
+    if commission_rate == 100 {
+        // The whole reward went to the validator, so it can't be reconstructed from the amount
+        // received. Callers estimate the gross reward and use compute_excess_full_commission.
+        return Err(RewardMathError::FullCommission);
+    }
+    if commission_rate > 100 {
+        return Err(RewardMathError::CommissionOutOfRange(commission_rate));
+    }
+    let expected_bps = checked_bps(u64::from(expected_bps))?;
+
+    let amount_after_commission = u128::from(amount_after_commission);
+    let total_reward = rounding.div(
+        amount_after_commission * 100,
+        u128::from(100 - commission_rate),
+    );
+    let actual_commission = to_i64(
+        total_reward - amount_after_commission,
+        "actual inflation commission",
+    )?;
+    let expected_commission = to_i64(
+        rounding.div_subtracted(total_reward * expected_bps, u128::from(MAX_BPS)),
+        "expected inflation commission",
+    )?;
+
+    actual_commission
+        .checked_sub(expected_commission)
+        .ok_or(RewardMathError::Overflow("excess inflation commission"))
+}
+
+/// Computes the excess inflation commission when the validator took the entire reward.
+///
+/// # Arguments
+/// - `gross_reward`: estimated reward before commission
+/// - `expected_bps`: expected commission rate in basis points (0-10_000)
+/// - `rounding`: rounding applied to each division
+pub fn compute_excess_full_commission(
+    gross_reward: u64,
+    expected_bps: u16,
+    rounding: &mut Rounding,
+) -> Result<i64, RewardMathError> {
+    let expected_bps = checked_bps(u64::from(expected_bps))?;
+    let gross_reward = u128::from(gross_reward);
+    let expected_commission =
+        rounding.div_subtracted(gross_reward * expected_bps, u128::from(MAX_BPS));
+    to_i64(
+        gross_reward - expected_commission,
+        "excess inflation commission",
+    )
+}
+
+/// Estimates a stake account's gross inflation reward from the validator's commission reward,
+/// which at 100% commission is the sum of all delegators' gross rewards.
+async fn estimate_full_commission_gross_reward(
+    client: &RpcClient,
+    inflation_rewards: &InflationRewardCache,
+    stake_balance: u64,
+    fallback: &InflationFallback<'_>,
+    rounding: &mut Rounding,
+) -> Result<u64> {
+    let vote_reward = inflation_rewards
+        .get(client, fallback.vote_pubkey)
+        .await
+        .map_err(|e| rpc_error("Failed to fetch vote account inflation reward", e))?
+        .map(|reward| reward.amount)
+        .unwrap_or(0);
+    let gross = stake_weighted_share(
+        vote_reward,
+        stake_balance,
+        fallback.validator_active_stake,
+        rounding,
+    );
+    u64::try_from(gross).map_err(|_| anyhow!(RewardMathError::Overflow("gross inflation reward")))
 }
 
 async fn get_excess_inflation_reward(
     client: &RpcClient,
+    inflation_rewards: &InflationRewardCache,
     address: &Pubkey,
     target_epoch: u64,
     reward_commissions: &RewardCommissions,
+    fallback: &InflationFallback<'_>,
+    rounding: &mut Rounding,
 ) -> Result<i64> {
-    let inflation_rewards = client
-        .get_inflation_reward(&[*address], Some(target_epoch))
+    let reward = inflation_rewards
+        .get(client, address)
         .await
-        .map_err(|e| anyhow!("Failed to fetch inflation reward: {}", e))?;
-
-    if inflation_rewards.is_empty() {
-        return Err(anyhow!("No inflation rewards found for {}", address));
-    }
+        .map_err(|e| rpc_error("Failed to fetch inflation reward", e))?;
 
-    if let Some(reward) = &inflation_rewards[0] {
-        let commission_rate = u64::from(
-            reward
-                .commission
-                .ok_or_else(|| anyhow!("Commission data missing for {}", address))?,
-        );
+    if let Some(reward) = &reward {
+        if let (Some(reported), Some(on_chain)) =
+            (reward.commission, fallback.vote_account_commission)
+        {
+            if reported != on_chain {
+                warn!(
+                    "getInflationReward reported {}% commission for {} but the vote account had {}% in epoch {}",
+                    reported, address, on_chain, target_epoch
+                );
+                datapoint_warn!(
+                    "calc_quality",
+                    ("check", "inflation_commission".to_string(), String),
+                    ("epoch", target_epoch.to_string(), String),
+                    ("stake_account", address.to_string(), String),
+                    ("reported", i64::from(reported), i64),
+                    ("on_chain", i64::from(on_chain), i64),
+                );
+            }
+        }
+        if let (Some(reported), Some(tracked)) = (reward.commission, fallback.epoch_end_commission)
+        {
+            if reported != tracked {
+                warn!(
+                    "Reported commission {}% for {} differs from the {}% in effect at the end of epoch {}",
+                    reported, address, tracked, target_epoch
+                );
+                datapoint_warn!(
+                    "calc_quality",
+                    ("check", "commission_history".to_string(), String),
+                    ("epoch", target_epoch.to_string(), String),
+                    ("stake_account", address.to_string(), String),
+                    ("reported", i64::from(reported), i64),
+                    ("tracked", i64::from(tracked), i64),
+                );
+            }
+        }
+        let commission_rate = match (reward.commission, fallback.assumed_commission) {
+            (Some(commission), _) => u64::from(commission),
+            (None, Some(assumed)) => {
+                warn!(
+                    "Commission data missing for {}, assuming {}% commission",
+                    address, assumed
+                );
+                log_inflation_fallback(address, target_epoch, "assumed_commission");
+                u64::from(assumed)
+            }
+            (None, None) => {
+                log_inflation_fallback(address, target_epoch, "missing_commission");
+                return Err(anyhow!(
+                    "Commission data missing for {}. Set --assumed-inflation-commission to use a fallback",
+                    address
+                ));
+            }
+        };
+        if commission_rate == 100 {
+            warn!(
+                "Validator took 100% inflation commission from {}, estimating gross reward from vote account {}",
+                address, fallback.vote_pubkey
+            );
+            log_inflation_fallback(address, target_epoch, "full_commission");
+            let gross_reward = estimate_full_commission_gross_reward(
+                client,
+                inflation_rewards,
+                reward.post_balance.saturating_sub(reward.amount),
+                fallback,
+                rounding,
+            )
+            .await?;
+            return Ok(compute_excess_full_commission(
+                gross_reward,
+                reward_commissions.inflation_bps,
+                rounding,
+            )?);
+        }
         let excess = compute_excess_inflation_commission(
             reward.amount,
             commission_rate,
             reward_commissions.inflation_bps,
-        );
+            rounding,
+        )?;
         Ok(excess)
     } else {
-        // This is the case for stake accounts that are activating
+        // Stake accounts that were activating, inactive or merged away earned nothing. At 100%
+        // commission though, getInflationReward has no entry for the delegations either.
+        let vote_reward = inflation_rewards
+            .get(client, fallback.vote_pubkey)
+            .await
+            .map_err(|e| rpc_error("Failed to fetch vote account inflation reward", e))?;
+        if vote_reward.as_ref().and_then(|reward| reward.commission) != Some(100) {
+            return Ok(0);
+        }
+        let effective_stake =
+            fetch_effective_stake(client, fallback.sysvars, address, target_epoch).await?;
+        if effective_stake > 0 {
+            warn!(
+                "No inflation reward for {} and validator commission is 100%, estimating gross reward from vote account {}",
+                address, fallback.vote_pubkey
+            );
+            log_inflation_fallback(address, target_epoch, "full_commission");
+        }
+        Ok(excess_without_reward(
+            vote_reward.as_ref(),
+            effective_stake,
+            fallback.validator_active_stake,
+            reward_commissions.inflation_bps,
+            rounding,
+        )?)
+    }
+}
+
+/// The excess owed on a stake account with no inflation reward entry. When the vote account's
+/// reward reports a 100% commission, an account with `effective_stake` in the epoch earned its
+/// stake-weighted share of the vote account's reward, all of it kept by the validator. Otherwise
+/// the account earned nothing.
+fn excess_without_reward(
+    vote_reward: Option<&RpcInflationReward>,
+    effective_stake: u64,
+    validator_active_stake: u64,
+    expected_bps: u16,
+    rounding: &mut Rounding,
+) -> Result<i64, RewardMathError> {
+    let Some(vote_reward) = vote_reward.filter(|reward| reward.commission == Some(100)) else {
+        return Ok(0);
+    };
+    if effective_stake == 0 {
         return Ok(0);
     }
+    let gross_reward = stake_weighted_share(
+        vote_reward.amount,
+        effective_stake,
+        validator_active_stake,
+        rounding,
+    );
+    let gross_reward = u64::try_from(gross_reward)
+        .map_err(|_| RewardMathError::Overflow("gross inflation reward"))?;
+    compute_excess_full_commission(gross_reward, expected_bps, rounding)
+}
+
+fn log_inflation_fallback(address: &Pubkey, target_epoch: u64, reason: &str) {
+    datapoint_warn!(
+        "inflation_commission_fallback",
+        ("epoch", target_epoch.to_string(), String),
+        ("stake_account", address.to_string(), String),
+        ("reason", reason.to_string(), String),
+    );
 }
 
 pub async fn calculate_excess_inflation_reward(
     client: &RpcClient,
+    inflation_rewards: &InflationRewardCache,
     stake_pubkey: &Pubkey,
     transient_pubkey: &Pubkey,
+    linked_stake_accounts: &[Pubkey],
+    redelegated: &HashSet<Pubkey>,
     target_epoch: u64,
     reward_commissions: &RewardCommissions,
+    fallback: &InflationFallback<'_>,
+    rounding: &mut Rounding,
 ) -> i64 {
-    let excess_stake_inflation_commission =
-        match get_excess_inflation_reward(client, stake_pubkey, target_epoch, reward_commissions)
-            .await
+    let excess_stake_inflation_commission = if redelegated.contains(stake_pubkey) {
+        0 // Delegated to another validator.
+    } else {
+        match get_excess_inflation_reward(
+            client,
+            inflation_rewards,
+            stake_pubkey,
+            target_epoch,
+            reward_commissions,
+            fallback,
+            rounding,
+        )
+        .await
         {
             Ok(amount) => {
-                info!("Excess Stake Account Inflation Commission: {:?}", amount);
+                info!(
+                    "Excess Stake Account Inflation Commission: {}",
+                    format_amount(amount)
+                );
                 amount
             }
             Err(e) => {
                 error!("Error for stake account: {}", e);
                 0 // Return 0 for stake account on error.
             }
+        }
+    };
+
+    let excess_transient_inflation_commission =
+        if !transient_pubkey.eq(&Pubkey::default()) && !redelegated.contains(transient_pubkey) {
+            match get_excess_inflation_reward(
+                client,
+                inflation_rewards,
+                transient_pubkey,
+                target_epoch,
+                reward_commissions,
+                fallback,
+                rounding,
+            )
+            .await
+            {
+                Ok(amount) => {
+                    info!(
+                        "Excess Transient Account Inflation Commission: {}\n",
+                        format_amount(amount)
+                    );
+                    amount
+                }
+                Err(e) => {
+                    error!("Error for transient account: {}\n", e);
+                    0 // Return 0 for transient account on error
+                }
+            }
+        } else {
+            0 // No transient account specified
         };
 
-    let excess_transient_inflation_commission = if !transient_pubkey.eq(&Pubkey::default()) {
+    let mut excess_linked_inflation_commission = 0;
+    for linked_pubkey in linked_stake_accounts
+        .iter()
+        .filter(|pubkey| !redelegated.contains(pubkey))
+    {
         match get_excess_inflation_reward(
             client,
-            transient_pubkey,
+            inflation_rewards,
+            linked_pubkey,
             target_epoch,
             reward_commissions,
+            fallback,
+            rounding,
         )
         .await
         {
             Ok(amount) => {
                 info!(
-                    "Excess Transient Account Inflation Commission: {:?}\n",
-                    amount
+                    "Excess Linked Account {} Inflation Commission: {}",
+                    linked_pubkey,
+                    format_amount(amount)
                 );
-                amount
-            }
-            Err(e) => {
-                error!("Error for transient account: {}\n", e);
-                0 // Return 0 for transient account on error
+                excess_linked_inflation_commission += amount;
             }
+            // Accounts merged away since the epoch have no reward of their own.
+            Err(e) => warn!(
+                "No inflation reward for linked account {}: {}",
+                linked_pubkey, e
+            ),
         }
-    } else {
-        0 // No transient account specified
-    };
+    }
 
     // Commissions in excess of stated rate taken by validator. If negative,
     // this is the amount of commission owned to validator.
-    excess_stake_inflation_commission + excess_transient_inflation_commission
+    excess_stake_inflation_commission
+        + excess_transient_inflation_commission
+        + excess_linked_inflation_commission
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    #[tokio::test]
+    async fn test_cached_rewards_skip_the_rpc() {
+        // The "fails" mock errors on every request.
+        let client = RpcClient::new_mock("fails".to_string());
+        let stake = Pubkey::new_unique();
+        let cache = InflationRewardCache {
+            epoch: 800,
+            rewards: HashMap::from([(stake, None)]),
+        };
+        assert_eq!(cache.get(&client, &stake).await.unwrap(), None);
+        assert!(cache.get(&client, &Pubkey::new_unique()).await.is_err());
+    }
 
     #[test]
     fn test_excess_inflation_commission_exact() {
         // Validator took 10% commission, expected was also 10%
-        let result = compute_excess_inflation_commission(900_000, 10, 1000);
-        assert_eq!(result, 0); // no excess
+        let result =
+            compute_excess_inflation_commission(900_000, 10, 1000, &mut Rounding::default());
+        assert_eq!(result, Ok(0)); // no excess
     }
 
     #[test]
     fn test_excess_inflation_commission_took_more() {
         // Validator took 12%, expected 10%
-        let result = compute_excess_inflation_commission(880_000, 12, 1000);
-        assert_eq!(result, 20_000);
+        let result =
+            compute_excess_inflation_commission(880_000, 12, 1000, &mut Rounding::default());
+        assert_eq!(result, Ok(20_000));
     }
 
     #[test]
     fn test_excess_inflation_commission_took_less() {
         // Validator took 8%, expected 10%
-        let result = compute_excess_inflation_commission(920_000, 8, 1000);
-        assert_eq!(result, -20_000);
+        let result =
+            compute_excess_inflation_commission(920_000, 8, 1000, &mut Rounding::default());
+        assert_eq!(result, Ok(-20_000));
+    }
+
+    #[test]
+    fn test_excess_inflation_commission_full_commission_errors() {
+        let result = compute_excess_inflation_commission(0, 100, 1000, &mut Rounding::default());
+        assert_eq!(result, Err(RewardMathError::FullCommission));
+    }
+
+    #[test]
+    fn test_excess_inflation_commission_out_of_range() {
+        let result = compute_excess_inflation_commission(0, 101, 1000, &mut Rounding::default());
+        assert_eq!(result, Err(RewardMathError::CommissionOutOfRange(101)));
+    }
+
+    #[test]
+    fn test_excess_full_commission() {
+        // Validator took 100%, expected 10%
+        let result = compute_excess_full_commission(1_000_000, 1000, &mut Rounding::default());
+        assert_eq!(result, Ok(900_000));
+    }
+
+    #[test]
+    fn test_missing_reward_at_full_commission_is_estimated() {
+        let vote_reward = |commission| RpcInflationReward {
+            epoch: 800,
+            effective_slot: 345_600_000,
+            amount: 1_000_000,
+            post_balance: 2_000_000,
+            commission,
+        };
+        // A quarter of the validator's stake earned a quarter of the vote reward, 10% expected.
+        assert_eq!(
+            excess_without_reward(
+                Some(&vote_reward(Some(100))),
+                250,
+                1_000,
+                1000,
+                &mut Rounding::default()
+            ),
+            Ok(225_000)
+        );
+        // No effective stake in the epoch, e.g. still activating.
+        assert_eq!(
+            excess_without_reward(
+                Some(&vote_reward(Some(100))),
+                0,
+                1_000,
+                1000,
+                &mut Rounding::default()
+            ),
+            Ok(0)
+        );
+        // Below 100% a missing entry means the account earned nothing.
+        assert_eq!(
+            excess_without_reward(
+                Some(&vote_reward(Some(10))),
+                250,
+                1_000,
+                1000,
+                &mut Rounding::default()
+            ),
+            Ok(0)
+        );
+        assert_eq!(
+            excess_without_reward(None, 250, 1_000, 1000, &mut Rounding::default()),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn test_excess_inflation_commission_no_overflow_at_max() {
+        assert!(
+            compute_excess_inflation_commission(u64::MAX, 0, 0, &mut Rounding::default()).is_ok()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_excess_bounded_by_total_reward(
+            amount in 0..=1_000_000_000_000_000u64,
+            commission in 0..100u64,
+            bps in 0..=10_000u16,
+        ) {
+            let excess = compute_excess_inflation_commission(amount, commission, bps, &mut Rounding::default()).unwrap();
+            let total_reward = u128::from(amount) * 100 / u128::from(100 - commission);
+            prop_assert!(excess.unsigned_abs() as u128 <= total_reward);
+        }
+
+        #[test]
+        fn prop_excess_decreases_with_expected_bps(
+            amount in 0..=1_000_000_000_000_000u64,
+            commission in 0..100u64,
+            low in 0..=10_000u16,
+            high in 0..=10_000u16,
+        ) {
+            let (low, high) = (low.min(high), low.max(high));
+            let at_low = compute_excess_inflation_commission(amount, commission, low, &mut Rounding::default()).unwrap();
+            let at_high = compute_excess_inflation_commission(amount, commission, high, &mut Rounding::default()).unwrap();
+            prop_assert!(at_low >= at_high);
+        }
+
+        #[test]
+        fn prop_zero_commission_never_owes_holders(
+            amount in 0..=1_000_000_000_000_000u64,
+            bps in 0..=10_000u16,
+        ) {
+            prop_assert!(compute_excess_inflation_commission(amount, 0, bps, &mut Rounding::default()).unwrap() <= 0);
+        }
     }
 }
@@ -1,12 +1,25 @@
 use std::time::Duration;
 
 use anyhow::{anyhow, Result};
-use log::info;
+use log::{info, warn};
 use pye_core_cpi::pye_core::types::RewardCommissions;
-use reqwest::Client;
 use serde::Deserialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::pubkey::Pubkey;
 
+use crate::accounts::EpochSysvars;
+use crate::active_stake::fetch_validator_active_stake;
+use crate::http::http_client;
+use crate::metrics::datapoint_warn;
+use crate::rewards::commission_history::fetch_epoch_mev_commission;
+use crate::rewards::{
+    checked_bps, stake_weighted_share, to_i64, RewardMathError, Rounding, MAX_BPS,
+};
+use crate::rpc_fixtures::FixtureStore;
+use crate::sol_format::format_amount;
+
+const JITO_FIXTURE_METHOD: &str = "jitoValidators";
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct ValidatorInfo {
     pub vote_account: String,
@@ -29,75 +42,228 @@ pub struct ValidatorsResponse {
 /// - `validator_active_stake`: validator's total active stake at epoch
 /// - `validator_mev_commission_bps`: actual commission rate taken by validator (0-10000)
 /// - `expected_mev_commission_bps`: expected commission rate (0-10000)
+/// - `rounding`: rounding applied to each division
 pub fn compute_excess_mev_commission(
     total_mev_rewards: u64,
     pye_account_active_stake: u64,
     validator_active_stake: u64,
     validator_mev_commission_bps: u64,
     expected_mev_commission_bps: u16,
-) -> i64 {
+    rounding: &mut Rounding,
+) -> Result<i64, RewardMathError> {
     if validator_active_stake == 0 {
-        return 0;
+        return Ok(0);
     }
 
-    let pye_account_mev_reward = ((u128::from(pye_account_active_stake) * u128::from(total_mev_rewards))
-        / u128::from(validator_active_stake)) as u64;
-    let mev_commission_taken = (pye_account_mev_reward * validator_mev_commission_bps / 10000) as i64;
-    let expected_mev_commission =
-        (pye_account_mev_reward * expected_mev_commission_bps as u64 / 10000) as i64;
+    let pye_account_mev_reward = stake_weighted_share(
+        total_mev_rewards,
+        pye_account_active_stake,
+        validator_active_stake,
+        rounding,
+    );
+    let mev_commission_taken = to_i64(
+        rounding.div(
+            pye_account_mev_reward
+                .checked_mul(checked_bps(validator_mev_commission_bps)?)
+                .ok_or(RewardMathError::Overflow("MEV commission taken"))?,
+            u128::from(MAX_BPS),
+        ),
+        "MEV commission taken",
+    )?;
+    let expected_mev_commission = to_i64(
+        rounding.div_subtracted(
+            pye_account_mev_reward
+                .checked_mul(checked_bps(u64::from(expected_mev_commission_bps))?)
+                .ok_or(RewardMathError::Overflow("expected MEV commission"))?,
+            u128::from(MAX_BPS),
+        ),
+        "expected MEV commission",
+    )?;
 
     info!(
         "Total MEV Reward: {}, pye_account's MEV Reward (incl. commission): {}",
-        total_mev_rewards, pye_account_mev_reward,
+        format_amount(total_mev_rewards),
+        format_amount(i128::try_from(pye_account_mev_reward).unwrap_or(i128::MAX)),
     );
     info!(
         "MEV Commission Taken ({:.2}%): {}",
         validator_mev_commission_bps as f64 / 100.0,
-        mev_commission_taken
+        format_amount(mev_commission_taken)
     );
     info!(
         "Expected MEV Commission ({:.2}%): {}",
         expected_mev_commission_bps as f64 / 100.0,
-        expected_mev_commission
+        format_amount(expected_mev_commission)
     );
 
-    mev_commission_taken - expected_mev_commission
+    mev_commission_taken
+        .checked_sub(expected_mev_commission)
+        .ok_or(RewardMathError::Overflow("excess MEV commission"))
 }
 
+/// The validator's MEV data for `target_epoch` from the Jito API, waiting up to 12 hours for the
+/// epoch to be published.
 pub async fn fetch_and_filter_mev_data(
+    jito_api_url: &str,
+    vote_pubkey: &Pubkey,
+    target_epoch: u64,
+    fixtures: Option<&FixtureStore>,
+) -> Result<ValidatorInfo> {
+    let response = fetch_mev_with_retry(
+        jito_api_url,
+        target_epoch,
+        12,
+        Duration::from_secs(3600),
+        fixtures,
+    )
+    .await?;
+    Ok(filter_mev_data(response, vote_pubkey, target_epoch))
+}
+
+/// Loads the validator's MEV data from the Jito API. Without a Jito API (clusters other than
+/// mainnet) MEV rewards are treated as zero. Either way the validator's active stake is computed
+/// from its stake accounts, so the block and MEV math never depend on the API's stake figure.
+pub async fn load_mev_data(
+    client: &RpcClient,
+    sysvars: &EpochSysvars,
+    jito_api_url: Option<&str>,
     vote_pubkey: &Pubkey,
     target_epoch: u64,
+    fixtures: Option<&FixtureStore>,
 ) -> Result<ValidatorInfo> {
-    let response = fetch_mev_with_retry(target_epoch, 12, Duration::from_secs(3600)).await?;
-    filter_mev_data(response, vote_pubkey)
+    let active_stake =
+        fetch_validator_active_stake(client, sysvars, vote_pubkey, target_epoch).await?;
+    let Some(jito_api_url) = jito_api_url else {
+        info!("No Jito MEV source for this cluster, treating MEV rewards as zero");
+        return Ok(without_mev(vote_pubkey, active_stake));
+    };
+
+    let mut mev_data =
+        fetch_and_filter_mev_data(jito_api_url, vote_pubkey, target_epoch, fixtures).await?;
+    use_onchain_active_stake(&mut mev_data, active_stake, target_epoch);
+    Ok(mev_data)
+}
+
+/// The validator's info with no MEV rewards, for epochs whose MEV isn't computed.
+pub fn without_mev(vote_pubkey: &Pubkey, active_stake: u64) -> ValidatorInfo {
+    ValidatorInfo {
+        vote_account: vote_pubkey.to_string(),
+        mev_commission_bps: None,
+        mev_rewards: 0,
+        running_jito: false,
+        active_stake,
+    }
+}
+
+/// Replaces the active stake reported by the Jito API with the one computed on-chain.
+pub fn use_onchain_active_stake(
+    mev_data: &mut ValidatorInfo,
+    active_stake: u64,
+    target_epoch: u64,
+) {
+    if mev_data.active_stake != active_stake {
+        info!(
+            "Jito reports {} of active stake for epoch {}, using the {} computed on-chain",
+            format_amount(mev_data.active_stake),
+            target_epoch,
+            format_amount(active_stake)
+        );
+    }
+    mev_data.active_stake = active_stake;
+}
+
+/// Replaces the MEV commission reported by the Jito API with the one the target epoch's tip
+/// distribution account was created with, which is the commission tips were distributed with.
+/// A difference is flagged; if the account can't be read the reported commission is kept.
+pub async fn use_epoch_mev_commission(
+    client: &RpcClient,
+    mev_data: &mut ValidatorInfo,
+    vote_pubkey: &Pubkey,
+    target_epoch: u64,
+) {
+    if !mev_data.running_jito {
+        return;
+    }
+    let commission_bps = match fetch_epoch_mev_commission(client, vote_pubkey, target_epoch).await {
+        Ok(Some(commission_bps)) => u64::from(commission_bps),
+        Ok(None) => {
+            warn!(
+                "No tip distribution account for {} in epoch {}, keeping the Jito API's MEV commission",
+                vote_pubkey, target_epoch
+            );
+            return;
+        }
+        Err(e) => {
+            warn!(
+                "Failed to read the epoch {} MEV commission on-chain, keeping the Jito API's: {}",
+                target_epoch, e
+            );
+            return;
+        }
+    };
+    if mev_data.mev_commission_bps != Some(commission_bps) {
+        warn!(
+            "Jito API reports a MEV commission of {:?} bps for epoch {}, but tips were distributed with {} bps",
+            mev_data.mev_commission_bps, target_epoch, commission_bps
+        );
+        datapoint_warn!(
+            "mev_commission_mismatch",
+            ("vote_pubkey", vote_pubkey.to_string(), String),
+            ("epoch", target_epoch.to_string(), String),
+            (
+                "reported_bps",
+                mev_data.mev_commission_bps.map_or(-1, |bps| bps as i64),
+                i64
+            ),
+            ("onchain_bps", commission_bps as i64, i64),
+        );
+    }
+    mev_data.mev_commission_bps = Some(commission_bps);
 }
 
 // REVIEW: When does MEV epoch data get uploaded to the API? If operators are waiting for epoch
 // transition, there could be a race condition for MEV epoch data
-pub async fn fetch_mev_data(target_epoch: u64) -> Result<ValidatorsResponse> {
-    let http = Client::new();
+pub async fn fetch_mev_data(
+    jito_api_url: &str,
+    target_epoch: u64,
+    fixtures: Option<&FixtureStore>,
+) -> Result<ValidatorsResponse> {
+    let body = serde_json::json!({ "epoch": target_epoch });
+    if let Some(store) = fixtures.filter(|store| store.is_replay()) {
+        let value = store.replay_value(JITO_FIXTURE_METHOD, &body)?;
+        return serde_json::from_value(value)
+            .map_err(|e| anyhow!("Failed to deserialize response: {}", e));
+    }
 
-    http.post("https://kobe.mainnet.jito.network/api/v1/validators")
+    let http = http_client();
+    let value = http
+        .post(jito_api_url)
         .header("Content-Type", "application/json")
-        .json(&serde_json::json!({ "epoch": target_epoch }))
+        .json(&body)
         .send()
         .await
         .map_err(|e| anyhow!("Failed to send request: {}", e))?
         .error_for_status()
         .map_err(|e| anyhow!("Server returned error status: {}", e))?
-        .json::<ValidatorsResponse>()
+        .json::<serde_json::Value>()
         .await
-        .map_err(|e| anyhow!("Failed to deserialize response: {}", e))
+        .map_err(|e| anyhow!("Failed to deserialize response: {}", e))?;
+    if let Some(store) = fixtures {
+        store.record_value(JITO_FIXTURE_METHOD, &body, &value);
+    }
+    serde_json::from_value(value).map_err(|e| anyhow!("Failed to deserialize response: {}", e))
 }
 
 pub async fn fetch_mev_with_retry(
+    jito_api_url: &str,
     target_epoch: u64,
     max_attempts: u64,
     duration: Duration,
+    fixtures: Option<&FixtureStore>,
 ) -> Result<ValidatorsResponse> {
     let mut attempt: u64 = 0;
     loop {
-        match fetch_mev_data(target_epoch).await {
+        match fetch_mev_data(jito_api_url, target_epoch, fixtures).await {
             Ok(res) => {
                 // We check the sum of rewards. If it's 0, then we know the Jito API hasn't been properly updated so we should wait
                 let total_mev_rewards = res
@@ -127,7 +293,14 @@ pub async fn fetch_mev_with_retry(
     }
 }
 
-fn filter_mev_data(response: ValidatorsResponse, vote_pubkey: &Pubkey) -> Result<ValidatorInfo> {
+/// The validator's entry in the Jito API response. A validator missing from it, e.g. one not
+/// running Jito, gets zero MEV instead of failing the epoch, so its other reward categories are
+/// still paid.
+fn filter_mev_data(
+    response: ValidatorsResponse,
+    vote_pubkey: &Pubkey,
+    target_epoch: u64,
+) -> ValidatorInfo {
     let vote_str = vote_pubkey.to_string();
     let validator = response
         .validators
@@ -135,23 +308,29 @@ fn filter_mev_data(response: ValidatorsResponse, vote_pubkey: &Pubkey) -> Result
         .find(|v| v.vote_account == vote_str);
 
     if let Some(info) = validator {
-        Ok(info.clone())
-    } else {
-        Err(anyhow!(
-            "Validator with vote account {} not found with Jito MEV API. Assuming that validator does not have MEV.\n",
-            vote_str
-        ))
+        return info;
     }
+    warn!(
+        "Validator with vote account {} not found with Jito MEV API for epoch {}. Assuming that validator does not have MEV.",
+        vote_str, target_epoch
+    );
+    datapoint_warn!(
+        "mev_validator_not_found",
+        ("vote_pubkey", vote_str, String),
+        ("epoch", target_epoch.to_string(), String),
+    );
+    without_mev(vote_pubkey, 0)
 }
 
 pub fn calculate_excess_mev_reward(
     mev_data: &ValidatorInfo,
     pye_account_active_stake: u64,
     reward_commissions: &RewardCommissions,
-) -> i64 {
+    rounding: &mut Rounding,
+) -> Result<i64> {
     if !mev_data.running_jito {
         // No MEV rewards if validator is not running Jito.
-        return 0;
+        return Ok(0);
     }
 
     let excess_mev_commission = compute_excess_mev_commission(
@@ -160,43 +339,143 @@ pub fn calculate_excess_mev_reward(
         mev_data.active_stake,
         mev_data.mev_commission_bps.unwrap_or(10_000),
         reward_commissions.mev_tips_bps,
+        rounding,
+    )?;
+    info!(
+        "Excess MEV Commission: {}",
+        format_amount(excess_mev_commission)
     );
-    println!("Excess MEV Commission: {}\n", excess_mev_commission);
 
-    excess_mev_commission
+    Ok(excess_mev_commission)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_validator_missing_from_jito_api() {
+        let vote_pubkey = Pubkey::new_unique();
+        let response = ValidatorsResponse {
+            validators: vec![ValidatorInfo {
+                vote_account: Pubkey::new_unique().to_string(),
+                mev_commission_bps: Some(800),
+                mev_rewards: 1_000,
+                running_jito: true,
+                active_stake: 5_000,
+            }],
+        };
+        let info = filter_mev_data(response, &vote_pubkey, 500);
+        assert_eq!(info.vote_account, vote_pubkey.to_string());
+        assert_eq!(info.mev_rewards, 0);
+        assert!(!info.running_jito);
+    }
 
     #[test]
     fn test_exact_mev_commission() {
-        let result = compute_excess_mev_commission(1_000_000, 500_000, 1_000_000, 500, 500);
-        assert_eq!(result, 0);
+        let result = compute_excess_mev_commission(
+            1_000_000,
+            500_000,
+            1_000_000,
+            500,
+            500,
+            &mut Rounding::default(),
+        );
+        assert_eq!(result, Ok(0));
     }
 
     #[test]
     fn test_validator_took_more_commission() {
-        let result = compute_excess_mev_commission(1_000_000, 500_000, 1_000_000, 700, 500);
-        assert_eq!(result, 10000);
+        let result = compute_excess_mev_commission(
+            1_000_000,
+            500_000,
+            1_000_000,
+            700,
+            500,
+            &mut Rounding::default(),
+        );
+        assert_eq!(result, Ok(10000));
     }
 
     #[test]
     fn test_validator_took_less_commission() {
-        let result = compute_excess_mev_commission(1_000_000, 500_000, 1_000_000, 300, 500);
-        assert_eq!(result, -10000);
+        let result = compute_excess_mev_commission(
+            1_000_000,
+            500_000,
+            1_000_000,
+            300,
+            500,
+            &mut Rounding::default(),
+        );
+        assert_eq!(result, Ok(-10000));
     }
 
     #[test]
     fn test_validator_zero_stake() {
-        let result = compute_excess_mev_commission(1_000_000, 500_000, 0, 500, 500);
-        assert_eq!(result, 0);
+        let result = compute_excess_mev_commission(
+            1_000_000,
+            500_000,
+            0,
+            500,
+            500,
+            &mut Rounding::default(),
+        );
+        assert_eq!(result, Ok(0));
     }
 
     #[test]
     fn test_pye_account_zero_stake() {
-        let result = compute_excess_mev_commission(1_000_000, 0, 1_000_000, 500, 500);
-        assert_eq!(result, 0);
+        let result = compute_excess_mev_commission(
+            1_000_000,
+            0,
+            1_000_000,
+            500,
+            500,
+            &mut Rounding::default(),
+        );
+        assert_eq!(result, Ok(0));
+    }
+
+    #[test]
+    fn test_validator_commission_out_of_range() {
+        let result = compute_excess_mev_commission(
+            1_000_000,
+            500_000,
+            1_000_000,
+            10_001,
+            500,
+            &mut Rounding::default(),
+        );
+        assert_eq!(result, Err(RewardMathError::BpsOutOfRange(10_001)));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_swapping_rates_negates_excess(
+            total in 0..=1_000_000_000_000u64,
+            validator_stake in 1..=1_000_000_000_000_000u64,
+            pye_fraction in 0..=10_000u64,
+            taken in 0..=10_000u16,
+            expected in 0..=10_000u16,
+        ) {
+            let pye_stake = (u128::from(validator_stake) * u128::from(pye_fraction) / 10_000) as u64;
+            let forward = compute_excess_mev_commission(total, pye_stake, validator_stake, u64::from(taken), expected, &mut Rounding::default()).unwrap();
+            let backward = compute_excess_mev_commission(total, pye_stake, validator_stake, u64::from(expected), taken, &mut Rounding::default()).unwrap();
+            prop_assert_eq!(forward, -backward);
+        }
+
+        #[test]
+        fn prop_excess_bounded_by_share(
+            total in 0..=1_000_000_000_000u64,
+            validator_stake in 1..=1_000_000_000_000_000u64,
+            pye_fraction in 0..=10_000u64,
+            taken in 0..=10_000u16,
+            expected in 0..=10_000u16,
+        ) {
+            let pye_stake = (u128::from(validator_stake) * u128::from(pye_fraction) / 10_000) as u64;
+            let excess = compute_excess_mev_commission(total, pye_stake, validator_stake, u64::from(taken), expected, &mut Rounding::default()).unwrap();
+            prop_assert!(excess.unsigned_abs() as u128 <= stake_weighted_share(total, pye_stake, validator_stake, &mut Rounding::default()));
+        }
     }
 }
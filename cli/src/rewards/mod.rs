@@ -1,3 +1,218 @@
+use clap::{Args, ValueEnum};
+use pye_core_cpi::pye_core::types::RewardCommissions;
+use thiserror::Error;
+
+pub mod block_archive;
 pub mod block_rewards;
+pub mod commission_history;
+pub mod external_check;
+pub mod inflation_model;
 pub mod inflation_rewards;
 pub mod mev_rewards;
+pub mod source;
+
+pub const MAX_BPS: u64 = 10_000;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum RewardMathError {
+    #[error("Arithmetic overflow while computing {0}")]
+    Overflow(&'static str),
+    #[error("Validator took 100% commission, the gross reward must be estimated")]
+    FullCommission,
+    #[error("Commission rate {0}% is out of range")]
+    CommissionOutOfRange(u64),
+    #[error("Basis points value {0} exceeds 10000")]
+    BpsOutOfRange(u64),
+}
+
+/// Converts an intermediate u128 amount into lamports signed for netting, erroring on overflow.
+pub fn to_i64(value: u128, context: &'static str) -> Result<i64, RewardMathError> {
+    i64::try_from(value).map_err(|_| RewardMathError::Overflow(context))
+}
+
+/// Validates a basis points value and widens it for u128 math.
+pub fn checked_bps(bps: u64) -> Result<u128, RewardMathError> {
+    if bps > MAX_BPS {
+        return Err(RewardMathError::BpsOutOfRange(bps));
+    }
+    Ok(u128::from(bps))
+}
+
+/// Pro-rata share of `total` for `stake` out of `total_stake`, in u128 to avoid overflow.
+pub fn stake_weighted_share(
+    total: u64,
+    stake: u64,
+    total_stake: u64,
+    rounding: &mut Rounding,
+) -> u128 {
+    if total_stake == 0 {
+        return 0;
+    }
+    rounding.div(
+        u128::from(stake) * u128::from(total),
+        u128::from(total_stake),
+    )
+}
+
+/// How integer divisions in the reward calculators are rounded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum RoundingMode {
+    /// Round toward zero (the historical behavior).
+    #[default]
+    Floor,
+    /// Round away from zero.
+    Ceil,
+    /// Round half to even.
+    Bankers,
+}
+
+/// Applies a RoundingMode to integer divisions and accumulates the difference between the
+/// rounded and the exact results, in lamports, with the sign it takes in the excess rewards.
+#[derive(Clone, Debug, Default)]
+pub struct Rounding {
+    pub mode: RoundingMode,
+    drift: f64,
+}
+
+impl Rounding {
+    pub fn new(mode: RoundingMode) -> Self {
+        Self { mode, drift: 0.0 }
+    }
+
+    pub fn div(&mut self, numerator: u128, denominator: u128) -> u128 {
+        let (quotient, error) = self.rounded(numerator, denominator);
+        self.drift += error;
+        quotient
+    }
+
+    /// Like [`Rounding::div`], for a quotient subtracted from the excess rewards, such as an
+    /// expected commission. Rounding it up lowers the excess, so its error counts negatively.
+    pub fn div_subtracted(&mut self, numerator: u128, denominator: u128) -> u128 {
+        let (quotient, error) = self.rounded(numerator, denominator);
+        self.drift -= error;
+        quotient
+    }
+
+    /// The rounded quotient and its rounded-minus-exact error.
+    fn rounded(&self, numerator: u128, denominator: u128) -> (u128, f64) {
+        let quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+        let round_up = match self.mode {
+            RoundingMode::Floor => false,
+            RoundingMode::Ceil => remainder > 0,
+            RoundingMode::Bankers => {
+                let twice = remainder * 2;
+                twice > denominator || (twice == denominator && quotient % 2 == 1)
+            }
+        };
+        if round_up {
+            (
+                quotient + 1,
+                (denominator - remainder) as f64 / denominator as f64,
+            )
+        } else {
+            (quotient, -(remainder as f64 / denominator as f64))
+        }
+    }
+
+    /// Cumulative rounded-minus-exact difference of the excess rewards computed so far.
+    pub fn drift(&self) -> f64 {
+        self.drift
+    }
+
+    pub fn merge(&mut self, other: &Rounding) {
+        self.drift += other.drift;
+    }
+}
+
+/// Hypothetical bond commission rates substituted for the on-chain ones, to model how other bond
+/// terms would have changed past payments. Only allowed with --dry-run.
+#[derive(Clone, Debug, Default, Args)]
+pub struct CommissionOverrides {
+    /// Inflation commission (bps) assumed for every bond instead of its own.
+    #[arg(
+        long,
+        env,
+        requires = "dry_run",
+        value_parser = clap::value_parser!(u16).range(0..=10_000)
+    )]
+    pub override_inflation_bps: Option<u16>,
+    /// MEV commission (bps) assumed for every bond instead of its own.
+    #[arg(
+        long,
+        env,
+        requires = "dry_run",
+        value_parser = clap::value_parser!(u16).range(0..=10_000)
+    )]
+    pub override_mev_bps: Option<u16>,
+    /// Block rewards commission (bps) assumed for every bond instead of its own.
+    #[arg(
+        long,
+        env,
+        requires = "dry_run",
+        value_parser = clap::value_parser!(u16).range(0..=10_000)
+    )]
+    pub override_block_bps: Option<u16>,
+}
+
+impl CommissionOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.override_inflation_bps.is_none()
+            && self.override_mev_bps.is_none()
+            && self.override_block_bps.is_none()
+    }
+
+    /// Replaces the overridden rates of `commissions`.
+    pub fn apply(&self, commissions: &mut RewardCommissions) {
+        if let Some(bps) = self.override_inflation_bps {
+            commissions.inflation_bps = bps;
+        }
+        if let Some(bps) = self.override_mev_bps {
+            commissions.mev_tips_bps = bps;
+        }
+        if let Some(bps) = self.override_block_bps {
+            commissions.block_rewards_bps = bps;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_floor_rounds_down() {
+        let mut rounding = Rounding::new(RoundingMode::Floor);
+        assert_eq!(rounding.div(7, 2), 3);
+        assert_eq!(rounding.drift(), -0.5);
+    }
+
+    #[test]
+    fn test_ceil_rounds_up() {
+        let mut rounding = Rounding::new(RoundingMode::Ceil);
+        assert_eq!(rounding.div(7, 2), 4);
+        assert_eq!(rounding.div(8, 2), 4);
+        assert_eq!(rounding.drift(), 0.5);
+    }
+
+    #[test]
+    fn test_bankers_rounds_half_to_even() {
+        let mut rounding = Rounding::new(RoundingMode::Bankers);
+        assert_eq!(rounding.div(5, 2), 2);
+        assert_eq!(rounding.div(7, 2), 4);
+        assert_eq!(rounding.div(10, 3), 3);
+        assert_eq!(rounding.div(11, 3), 4);
+        // Ties cancel out, leaving only the non-tie drift.
+        assert!((rounding.drift() - (-1.0 / 3.0 + 1.0 / 3.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_subtracted_rounding_counts_against_drift() {
+        let mut rounding = Rounding::new(RoundingMode::Floor);
+        // 7 - 5 / 2: flooring the subtracted half raises the result by 0.5.
+        assert_eq!(7 - rounding.div_subtracted(5, 2), 5);
+        assert_eq!(rounding.drift(), 0.5);
+        assert_eq!(rounding.div(7, 2), 3);
+        assert_eq!(rounding.drift(), 0.0);
+    }
+}
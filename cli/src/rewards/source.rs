@@ -0,0 +1,420 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use log::warn;
+use pye_core_cpi::pye_core::accounts::SoloValidatorBond as SoloValidatorPyeAccount;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::epoch_info::EpochInfo;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::accounts::EpochSysvars;
+use crate::rewards::block_rewards::{
+    calculate_block_rewards, compute_excess_block_commission, BlockScanOptions, LeaderSlots,
+};
+use crate::rewards::commission_history::EpochCommission;
+use crate::rewards::inflation_model::{
+    bond_stake_accounts, verify_inflation_rewards, InflationVerifyArgs, LocalInflationModel,
+};
+use crate::rewards::inflation_rewards::{
+    calculate_excess_inflation_reward, InflationFallback, InflationRewardCache,
+};
+use crate::rewards::mev_rewards::{calculate_excess_mev_reward, ValidatorInfo};
+use crate::rewards::Rounding;
+
+/// A stream of rewards the validator earns and owes bonds the excess commission of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, ValueEnum)]
+pub enum RewardCategory {
+    Inflation,
+    Mev,
+    Block,
+}
+
+impl fmt::Display for RewardCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RewardCategory::Inflation => write!(f, "inflation"),
+            RewardCategory::Mev => write!(f, "mev"),
+            RewardCategory::Block => write!(f, "block"),
+        }
+    }
+}
+
+/// Validator-wide state of the epoch being processed, shared by every source.
+pub struct EpochContext<'a> {
+    pub rpc_client: &'a RpcClient,
+    pub vote_pubkey: &'a Pubkey,
+    /// Epoch info of the epoch after the one processed.
+    pub epoch_info: &'a EpochInfo,
+    /// The validator's active stake and MEV earnings over the epoch.
+    pub validator: &'a ValidatorInfo,
+    pub epoch_commission: &'a EpochCommission,
+    /// The epoch's inflation rewards, batch fetched for every bond.
+    pub inflation_rewards: &'a InflationRewardCache,
+    pub sysvars: &'a EpochSysvars,
+}
+
+impl EpochContext<'_> {
+    pub fn target_epoch(&self) -> u64 {
+        self.epoch_info.epoch - 1
+    }
+}
+
+/// A bond whose excess rewards are computed.
+pub struct BondContext<'a> {
+    pub pye_account: &'a SoloValidatorPyeAccount,
+    /// The bond's active stake over the epoch.
+    pub active_stake: u64,
+    /// Stake accounts the bond's stake was split, merged or moved to since the epoch.
+    pub linked_stake_accounts: &'a [Pubkey],
+    /// The bond's stake accounts delegated to another validator, which don't count.
+    pub redelegated: &'a HashSet<Pubkey>,
+}
+
+/// A source of rewards that bonds may be owed a share of. Sources are registered in the
+/// manager's provider list, which fetches every source's epoch data once and then asks it for
+/// each bond's excess rewards.
+#[async_trait]
+pub trait RewardSource: Send + Sync {
+    fn category(&self) -> RewardCategory;
+
+    /// Fetches what the source needs to compute the excess rewards of every bond for the epoch.
+    async fn fetch_epoch(&self, epoch: &EpochContext<'_>) -> Result<Box<dyn SourceEpoch>>;
+}
+
+/// A source's data for one epoch.
+#[async_trait]
+pub trait SourceEpoch: Send + Sync {
+    /// The validator's total earnings from the source over the epoch in lamports, if the source
+    /// knows them.
+    fn validator_rewards(&self) -> Option<u64>;
+
+    /// The validator's produced and skipped leader slots over the epoch, if the source scanned
+    /// them.
+    fn leader_slots(&self) -> Option<LeaderSlots> {
+        None
+    }
+
+    /// The excess rewards the validator owes `bond` from this source, in lamports.
+    async fn excess(
+        &self,
+        epoch: &EpochContext<'_>,
+        bond: &BondContext<'_>,
+        rounding: &mut Rounding,
+    ) -> Result<i64>;
+}
+
+/// Inflation rewards, read per stake account from getInflationReward.
+pub struct InflationSource {
+    /// Commission (0-100) assumed when an inflation reward is reported without commission data.
+    pub assumed_commission: Option<u8>,
+    /// Use the commission in effect at the end of the epoch according to the vote account's
+    /// history, instead of the reported one, when they disagree.
+    pub track_commission_changes: bool,
+    pub verify: InflationVerifyArgs,
+}
+
+struct InflationEpoch {
+    epoch_end_commission: Option<u8>,
+    assumed_commission: Option<u8>,
+    model: Option<LocalInflationModel>,
+    tolerance_bps: u64,
+}
+
+#[async_trait]
+impl RewardSource for InflationSource {
+    fn category(&self) -> RewardCategory {
+        RewardCategory::Inflation
+    }
+
+    async fn fetch_epoch(&self, epoch: &EpochContext<'_>) -> Result<Box<dyn SourceEpoch>> {
+        let model = if self.verify.verify_inflation {
+            LocalInflationModel::load(
+                epoch.rpc_client,
+                epoch.sysvars,
+                epoch.vote_pubkey,
+                epoch.target_epoch(),
+                epoch.epoch_info.epoch,
+                epoch.epoch_commission.end,
+            )
+            .await
+            .map_err(|e| warn!("Skipping local inflation reward verification: {}", e))
+            .ok()
+        } else {
+            None
+        };
+        Ok(Box::new(InflationEpoch {
+            epoch_end_commission: epoch
+                .epoch_commission
+                .end
+                .filter(|_| self.track_commission_changes),
+            assumed_commission: self.assumed_commission,
+            model,
+            tolerance_bps: self.verify.verify_inflation_tolerance_bps,
+        }))
+    }
+}
+
+#[async_trait]
+impl SourceEpoch for InflationEpoch {
+    fn validator_rewards(&self) -> Option<u64> {
+        None
+    }
+
+    async fn excess(
+        &self,
+        epoch: &EpochContext<'_>,
+        bond: &BondContext<'_>,
+        rounding: &mut Rounding,
+    ) -> Result<i64> {
+        let pye_account = bond.pye_account;
+        let excess = calculate_excess_inflation_reward(
+            epoch.rpc_client,
+            epoch.inflation_rewards,
+            &pye_account.stake_account,
+            &pye_account.transient_stake_account,
+            bond.linked_stake_accounts,
+            bond.redelegated,
+            epoch.target_epoch(),
+            &pye_account.reward_commissions,
+            &InflationFallback {
+                vote_pubkey: epoch.vote_pubkey,
+                validator_active_stake: epoch.validator.active_stake,
+                assumed_commission: self.assumed_commission,
+                epoch_end_commission: self.epoch_end_commission,
+                vote_account_commission: epoch.epoch_commission.end,
+                sysvars: epoch.sysvars,
+            },
+            rounding,
+        )
+        .await;
+        if let Some(model) = &self.model {
+            verify_inflation_rewards(
+                epoch.rpc_client,
+                model,
+                &bond_stake_accounts(
+                    &pye_account.stake_account,
+                    &pye_account.transient_stake_account,
+                ),
+                self.tolerance_bps,
+            )
+            .await;
+        }
+        Ok(excess)
+    }
+}
+
+/// MEV rewards, as reported by Jito for the validator.
+pub struct MevSource;
+
+struct MevEpoch(ValidatorInfo);
+
+#[async_trait]
+impl RewardSource for MevSource {
+    fn category(&self) -> RewardCategory {
+        RewardCategory::Mev
+    }
+
+    async fn fetch_epoch(&self, epoch: &EpochContext<'_>) -> Result<Box<dyn SourceEpoch>> {
+        // The validator info is loaded before any source, since every source needs its stake.
+        Ok(Box::new(MevEpoch(epoch.validator.clone())))
+    }
+}
+
+#[async_trait]
+impl SourceEpoch for MevEpoch {
+    fn validator_rewards(&self) -> Option<u64> {
+        Some(self.0.mev_rewards)
+    }
+
+    async fn excess(
+        &self,
+        _epoch: &EpochContext<'_>,
+        bond: &BondContext<'_>,
+        rounding: &mut Rounding,
+    ) -> Result<i64> {
+        calculate_excess_mev_reward(
+            &self.0,
+            bond.active_stake,
+            &bond.pye_account.reward_commissions,
+            rounding,
+        )
+    }
+}
+
+/// Block rewards, from the fee rewards of the blocks the validator produced.
+pub struct BlockSource {
+    pub options: BlockScanOptions,
+}
+
+struct BlockEpoch {
+    total_block_rewards: u64,
+    leader_slots: LeaderSlots,
+}
+
+#[async_trait]
+impl RewardSource for BlockSource {
+    fn category(&self) -> RewardCategory {
+        RewardCategory::Block
+    }
+
+    async fn fetch_epoch(&self, epoch: &EpochContext<'_>) -> Result<Box<dyn SourceEpoch>> {
+        let (total_block_rewards, leader_slots) = calculate_block_rewards(
+            epoch.rpc_client,
+            epoch.sysvars,
+            epoch.vote_pubkey,
+            epoch.epoch_info,
+            &self.options,
+        )
+        .await?;
+        Ok(Box::new(BlockEpoch {
+            total_block_rewards,
+            leader_slots,
+        }))
+    }
+}
+
+#[async_trait]
+impl SourceEpoch for BlockEpoch {
+    fn validator_rewards(&self) -> Option<u64> {
+        Some(self.total_block_rewards)
+    }
+
+    fn leader_slots(&self) -> Option<LeaderSlots> {
+        Some(self.leader_slots)
+    }
+
+    async fn excess(
+        &self,
+        epoch: &EpochContext<'_>,
+        bond: &BondContext<'_>,
+        rounding: &mut Rounding,
+    ) -> Result<i64> {
+        Ok(compute_excess_block_commission(
+            self.total_block_rewards,
+            bond.active_stake,
+            epoch.validator.active_stake,
+            bond.pye_account.reward_commissions.block_rewards_bps,
+            rounding,
+        )?)
+    }
+}
+
+/// The reward categories left out of `selected`, which bonds are neither computed nor paid.
+pub fn skipped_categories(selected: &[RewardCategory]) -> Vec<RewardCategory> {
+    RewardCategory::value_variants()
+        .iter()
+        .copied()
+        .filter(|category| !selected.contains(category))
+        .collect()
+}
+
+/// A source's epoch data, tagged with its category.
+pub type FetchedSource = (RewardCategory, Box<dyn SourceEpoch>);
+
+/// Fetches the epoch data of every source, in order.
+pub async fn fetch_sources<'a>(
+    sources: impl IntoIterator<Item = &'a dyn RewardSource>,
+    epoch: &EpochContext<'_>,
+) -> Result<Vec<FetchedSource>> {
+    let mut fetched = Vec::new();
+    for source in sources {
+        fetched.push((source.category(), source.fetch_epoch(epoch).await?));
+    }
+    Ok(fetched)
+}
+
+/// The validator's total earnings from the source of `category`, or zero if it isn't
+/// registered or doesn't know them.
+pub fn validator_rewards(fetched: &[FetchedSource], category: RewardCategory) -> u64 {
+    fetched
+        .iter()
+        .find(|(fetched_category, _)| *fetched_category == category)
+        .and_then(|(_, epoch)| epoch.validator_rewards())
+        .unwrap_or_default()
+}
+
+/// The leader slots scanned by any of the sources, if one did.
+pub fn leader_slots(fetched: &[FetchedSource]) -> Option<LeaderSlots> {
+    fetched.iter().find_map(|(_, epoch)| epoch.leader_slots())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bond_schema::zeroed_bond;
+    use crate::dashboard::RewardBreakdown;
+
+    #[tokio::test]
+    async fn test_sources_fill_breakdown_by_category() {
+        let rpc_client = RpcClient::new_mock("succeeds".to_string());
+        let validator = ValidatorInfo {
+            vote_account: Pubkey::default().to_string(),
+            mev_commission_bps: Some(1_000),
+            mev_rewards: 1_000_000,
+            running_jito: true,
+            active_stake: 1_000_000,
+        };
+        let epoch = EpochContext {
+            rpc_client: &rpc_client,
+            vote_pubkey: &Pubkey::default(),
+            epoch_info: &EpochInfo {
+                epoch: 801,
+                slot_index: 0,
+                slots_in_epoch: 432_000,
+                absolute_slot: 0,
+                block_height: 0,
+                transaction_count: None,
+            },
+            validator: &validator,
+            epoch_commission: &EpochCommission::default(),
+            inflation_rewards: &InflationRewardCache::empty(800),
+            sysvars: &EpochSysvars::default(),
+        };
+        let fetched = fetch_sources([&MevSource as &dyn RewardSource], &epoch)
+            .await
+            .unwrap()
+            .into_iter()
+            .chain([(
+                RewardCategory::Block,
+                Box::new(BlockEpoch {
+                    total_block_rewards: 2_000_000,
+                }) as Box<dyn SourceEpoch>,
+            )])
+            .collect::<Vec<FetchedSource>>();
+        assert_eq!(validator_rewards(&fetched, RewardCategory::Mev), 1_000_000);
+        assert_eq!(validator_rewards(&fetched, RewardCategory::Inflation), 0);
+
+        let mut pye_account = zeroed_bond();
+        pye_account.reward_commissions.mev_tips_bps = 500;
+        pye_account.reward_commissions.block_rewards_bps = 500;
+        let bond = BondContext {
+            pye_account: &pye_account,
+            active_stake: 500_000,
+            linked_stake_accounts: &[],
+            redelegated: &HashSet::new(),
+        };
+        let mut rewards = RewardBreakdown::default();
+        let mut rounding = Rounding::default();
+        for (category, source) in &fetched {
+            rewards.add(
+                *category,
+                source.excess(&epoch, &bond, &mut rounding).await.unwrap(),
+            );
+        }
+        assert_eq!(rewards.mev, 25_000);
+        assert_eq!(rewards.block, 950_000);
+        assert_eq!(rewards.inflation, 0);
+    }
+
+    #[test]
+    fn test_skipped_categories() {
+        assert!(skipped_categories(RewardCategory::value_variants()).is_empty());
+        assert_eq!(
+            skipped_categories(&[RewardCategory::Inflation]),
+            vec![RewardCategory::Mev, RewardCategory::Block]
+        );
+    }
+}
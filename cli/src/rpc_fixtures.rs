@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use clap::Args;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+use solana_client::http_sender::HttpSender;
+use solana_client::rpc_request::{RpcError, RpcRequest, RpcResponseErrorData};
+use solana_client::rpc_sender::{RpcSender, RpcTransportStats};
+
+use crate::http::rpc_http_sender;
+
+/// Options controlling RPC record/replay fixtures.
+#[derive(Clone, Debug, Default, Args)]
+pub struct FixtureArgs {
+    /// Capture every RPC and Jito API request/response into this directory.
+    #[arg(long, env, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Serve RPC and Jito API responses from fixtures previously captured with --record.
+    #[arg(long, env)]
+    pub replay: Option<PathBuf>,
+}
+
+/// A single captured request and its outcome, stored as one JSON file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Fixture {
+    method: String,
+    params: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RecordedError>,
+}
+
+/// Errors are stored so that skipped slots and missing accounts replay the same way they were
+/// observed live.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct RecordedError {
+    code: Option<i64>,
+    message: String,
+}
+
+impl RecordedError {
+    fn from_client_error(err: &ClientError) -> Self {
+        match &err.kind {
+            ClientErrorKind::RpcError(RpcError::RpcResponseError { code, message, .. }) => Self {
+                code: Some(*code),
+                message: message.clone(),
+            },
+            _ => Self {
+                code: None,
+                message: err.to_string(),
+            },
+        }
+    }
+
+    fn into_client_error(self) -> ClientError {
+        match self.code {
+            Some(code) => RpcError::RpcResponseError {
+                code,
+                message: self.message,
+                data: RpcResponseErrorData::Empty,
+            }
+            .into(),
+            None => RpcError::RpcRequestError(self.message).into(),
+        }
+    }
+}
+
+/// Directory backed store of captured requests. Identical requests are numbered in the order
+/// they are made, so polling loops replay the same sequence of responses that was recorded.
+#[derive(Debug)]
+pub struct FixtureStore {
+    dir: PathBuf,
+    replay: bool,
+    sequence: Mutex<HashMap<String, usize>>,
+}
+
+impl FixtureStore {
+    pub fn from_args(args: &FixtureArgs) -> Result<Option<Arc<Self>>> {
+        let (dir, replay) = match (&args.record, &args.replay) {
+            (Some(dir), None) => (dir.clone(), false),
+            (None, Some(dir)) => (dir.clone(), true),
+            (None, None) => return Ok(None),
+            (Some(_), Some(_)) => {
+                return Err(anyhow!("--record and --replay are mutually exclusive"))
+            }
+        };
+        if replay {
+            if !dir.is_dir() {
+                return Err(anyhow!(
+                    "Fixture directory {} does not exist",
+                    dir.display()
+                ));
+            }
+            info!("Replaying RPC fixtures from {}", dir.display());
+        } else {
+            fs::create_dir_all(&dir).map_err(|e| {
+                anyhow!(
+                    "Failed to create fixture directory {}: {}",
+                    dir.display(),
+                    e
+                )
+            })?;
+            info!("Recording RPC fixtures to {}", dir.display());
+        }
+        Ok(Some(Arc::new(Self {
+            dir,
+            replay,
+            sequence: Mutex::new(HashMap::new()),
+        })))
+    }
+
+    pub fn is_replay(&self) -> bool {
+        self.replay
+    }
+
+    /// Returns the path for the next occurrence of `method` with `params`.
+    fn next_path(&self, method: &str, params: &Value) -> PathBuf {
+        let key = fixture_key(method, params);
+        let mut sequence = self.sequence.lock().unwrap();
+        let seq = sequence.entry(key.clone()).or_insert(0);
+        let mut path = self.dir.join(format!("{}-{}.json", key, seq));
+        // Once the recorded sequence is exhausted keep serving the last response.
+        if self.replay && *seq > 0 && !path.exists() {
+            path = self.dir.join(format!("{}-{}.json", key, *seq - 1));
+        } else {
+            *seq += 1;
+        }
+        path
+    }
+
+    fn save(
+        &self,
+        method: &str,
+        params: &Value,
+        result: Option<Value>,
+        error: Option<RecordedError>,
+    ) {
+        let path = self.next_path(method, params);
+        let fixture = Fixture {
+            method: method.to_string(),
+            params: params.clone(),
+            result,
+            error,
+        };
+        let write = serde_json::to_vec_pretty(&fixture)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| fs::write(&path, bytes).map_err(|e| e.to_string()));
+        if let Err(e) = write {
+            warn!("Failed to write fixture {}: {}", path.display(), e);
+        }
+    }
+
+    fn load(&self, method: &str, params: &Value) -> Result<Fixture> {
+        let path = self.next_path(method, params);
+        let bytes = fs::read(&path).map_err(|e| {
+            anyhow!(
+                "No recorded fixture for {} {} ({}): {}",
+                method,
+                params,
+                path.display(),
+                e
+            )
+        })?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| anyhow!("Failed to parse fixture {}: {}", path.display(), e))
+    }
+
+    /// Records a successful response for a non-RPC source such as the Jito API.
+    pub fn record_value(&self, method: &str, params: &Value, value: &Value) {
+        self.save(method, params, Some(value.clone()), None);
+    }
+
+    /// Replays a response for a non-RPC source such as the Jito API.
+    pub fn replay_value(&self, method: &str, params: &Value) -> Result<Value> {
+        let fixture = self.load(method, params)?;
+        match (fixture.result, fixture.error) {
+            (Some(result), _) => Ok(result),
+            (None, Some(error)) => Err(anyhow!("{}", error.message)),
+            (None, None) => Err(anyhow!(
+                "Fixture for {} has neither result nor error",
+                method
+            )),
+        }
+    }
+}
+
+/// Stable, filesystem safe key for a request. FNV-1a is used instead of the std hasher because
+/// fixture names must not change between toolchains.
+pub fn fixture_key(method: &str, params: &Value) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in params.to_string().bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{}-{:016x}", method, hash)
+}
+
+/// RpcSender that either wraps the HTTP sender and records each exchange, or serves exchanges
+/// from disk without touching the network.
+pub struct FixtureSender {
+    http: Option<HttpSender>,
+    store: Arc<FixtureStore>,
+    url: String,
+}
+
+impl FixtureSender {
+    pub fn new(url: String, store: Arc<FixtureStore>) -> Self {
+        let http = (!store.is_replay()).then(|| rpc_http_sender(url.clone()));
+        Self { http, store, url }
+    }
+}
+
+#[async_trait]
+impl RpcSender for FixtureSender {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let method = request.to_string();
+        match &self.http {
+            Some(http) => {
+                let outcome = http.send(request, params.clone()).await;
+                match &outcome {
+                    Ok(result) => self
+                        .store
+                        .save(&method, &params, Some(result.clone()), None),
+                    Err(err) => self.store.save(
+                        &method,
+                        &params,
+                        None,
+                        Some(RecordedError::from_client_error(err)),
+                    ),
+                }
+                outcome
+            }
+            None => {
+                let fixture = self
+                    .store
+                    .load(&method, &params)
+                    .map_err(|e| ClientError::from(RpcError::RpcRequestError(e.to_string())))?;
+                match (fixture.result, fixture.error) {
+                    (Some(result), _) => Ok(result),
+                    (None, Some(error)) => Err(error.into_client_error()),
+                    (None, None) => Err(RpcError::RpcRequestError(format!(
+                        "Fixture for {} has neither result nor error",
+                        method
+                    ))
+                    .into()),
+                }
+            }
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.http
+            .as_ref()
+            .map(|http| http.get_transport_stats())
+            .unwrap_or_default()
+    }
+
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_fixture_key_is_stable() {
+        let key = fixture_key("getBlock", &json!([1234, {"encoding": "json"}]));
+        assert_eq!(
+            key,
+            fixture_key("getBlock", &json!([1234, {"encoding": "json"}]))
+        );
+        assert!(key.starts_with("getBlock-"));
+    }
+
+    #[test]
+    fn test_fixture_key_differs_by_params() {
+        assert_ne!(
+            fixture_key("getBlock", &json!([1])),
+            fixture_key("getBlock", &json!([2]))
+        );
+    }
+
+    #[test]
+    fn test_replay_sequence_repeats_last_response() {
+        let dir = std::env::temp_dir().join(format!("pye-fixtures-{}", std::process::id()));
+        let args = FixtureArgs {
+            record: Some(dir.clone()),
+            replay: None,
+        };
+        let recorder = FixtureStore::from_args(&args).unwrap().unwrap();
+        recorder.record_value("getEpochInfo", &json!([]), &json!({"epoch": 1}));
+        recorder.record_value("getEpochInfo", &json!([]), &json!({"epoch": 2}));
+
+        let args = FixtureArgs {
+            record: None,
+            replay: Some(dir.clone()),
+        };
+        let replayer = FixtureStore::from_args(&args).unwrap().unwrap();
+        let epochs: Vec<Value> = (0..3)
+            .map(|_| replayer.replay_value("getEpochInfo", &json!([])).unwrap()["epoch"].clone())
+            .collect();
+        assert_eq!(epochs, vec![json!(1), json!(2), json!(2)]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
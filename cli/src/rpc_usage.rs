@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde_json::Value;
+use solana_client::client_error::Result as ClientResult;
+use solana_client::rpc_request::RpcRequest;
+use solana_client::rpc_sender::{RpcSender, RpcTransportStats};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::metrics::datapoint_info;
+
+/// RPC requests sent by a client, counted by method, for operators on metered RPC plans.
+#[derive(Debug, Default)]
+pub struct RpcUsage {
+    counts: Mutex<BTreeMap<String, u64>>,
+}
+
+impl RpcUsage {
+    fn record(&self, method: String) {
+        *self.counts.lock().unwrap().entry(method).or_default() += 1;
+    }
+
+    /// The requests counted so far.
+    pub fn snapshot(&self) -> RpcUsageCounts {
+        RpcUsageCounts(self.counts.lock().unwrap().clone())
+    }
+}
+
+/// Requests by method, e.g. `getBlock`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RpcUsageCounts(pub BTreeMap<String, u64>);
+
+impl RpcUsageCounts {
+    /// The requests made since `earlier` was taken.
+    pub fn since(&self, earlier: &RpcUsageCounts) -> RpcUsageCounts {
+        RpcUsageCounts(
+            self.0
+                .iter()
+                .map(|(method, count)| {
+                    let before = earlier.0.get(method).copied().unwrap_or_default();
+                    (method.clone(), count.saturating_sub(before))
+                })
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+        )
+    }
+
+    pub fn total(&self) -> u64 {
+        self.0.values().sum()
+    }
+
+    /// Reports the requests spent on `epoch` with an `rpc_usage` datapoint per method.
+    pub fn report(&self, vote_pubkey: &Pubkey, epoch: u64) {
+        for (method, count) in &self.0 {
+            datapoint_info!(
+                "rpc_usage",
+                ("vote_pubkey", vote_pubkey.to_string(), String),
+                ("epoch", epoch.to_string(), String),
+                ("method", method.clone(), String),
+                ("requests", *count as i64, i64),
+            );
+        }
+    }
+}
+
+impl fmt::Display for RpcUsageCounts {
+    /// Most used methods first, e.g. `1200 requests (getBlock 1000, getAccountInfo 200)`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut methods: Vec<(&String, &u64)> = self.0.iter().collect();
+        methods.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let methods: Vec<String> = methods
+            .into_iter()
+            .map(|(method, count)| format!("{} {}", method, count))
+            .collect();
+        write!(f, "{} requests", self.total())?;
+        if !methods.is_empty() {
+            write!(f, " ({})", methods.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// RpcSender counting each request it forwards to `inner` in `usage`, if given.
+pub struct UsageSender<S> {
+    inner: S,
+    usage: Option<Arc<RpcUsage>>,
+}
+
+impl<S> UsageSender<S> {
+    pub fn new(inner: S, usage: Option<Arc<RpcUsage>>) -> Self {
+        Self { inner, usage }
+    }
+}
+
+#[async_trait]
+impl<S: RpcSender + Send + Sync> RpcSender for UsageSender<S> {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        if let Some(usage) = &self.usage {
+            usage.record(request.to_string());
+        }
+        self.inner.send(request, params).await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::mock_sender::MockSender;
+    use solana_client::nonblocking::rpc_client::RpcClient;
+    use solana_client::rpc_client::RpcClientConfig;
+
+    #[tokio::test]
+    async fn test_counts_requests_by_method() {
+        let usage = Arc::new(RpcUsage::default());
+        let client = RpcClient::new_sender(
+            UsageSender::new(MockSender::new("succeeds"), Some(Arc::clone(&usage))),
+            RpcClientConfig::default(),
+        );
+        client.get_slot().await.unwrap();
+        let before = usage.snapshot();
+        client.get_slot().await.unwrap();
+        client.get_slot().await.unwrap();
+        client.get_balance(&Pubkey::new_unique()).await.unwrap();
+
+        let epoch = usage.snapshot().since(&before);
+        assert_eq!(
+            epoch.0,
+            BTreeMap::from([("getBalance".to_string(), 1), ("getSlot".to_string(), 2)])
+        );
+        assert_eq!(epoch.to_string(), "3 requests (getSlot 2, getBalance 1)");
+    }
+}
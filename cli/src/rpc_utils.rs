@@ -1,12 +1,16 @@
+use std::collections::HashSet;
+use std::sync::Arc;
 use std::time::Duration;
 
 use log::{error, info};
 use regex::Regex;
 use solana_client::client_error::ClientErrorKind;
 use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::RpcClientConfig;
 use solana_client::rpc_config::RpcBlockConfig;
 use solana_client::{client_error::ClientError, rpc_request::RpcError};
 use solana_commitment_config::CommitmentConfig;
+use solana_sdk::clock::DEFAULT_MS_PER_SLOT;
 use solana_sdk::epoch_info::EpochInfo;
 use solana_sdk::slot_history;
 use solana_sdk::sysvar::slot_history::SlotHistory;
@@ -15,6 +19,10 @@ use solana_transaction_status_client_types::{
 };
 use thiserror::Error;
 
+use crate::http::rpc_http_sender;
+use crate::rpc_fixtures::{FixtureSender, FixtureStore};
+use crate::rpc_usage::{RpcUsage, UsageSender};
+
 #[derive(Error, Debug)]
 pub enum PriorityFeeKeeperError {
     #[error("SolanaClientError error: {0}")]
@@ -33,19 +41,62 @@ pub enum PriorityFeeKeeperError {
     InSlotHistoryNotOnRpc(u64),
 }
 
+/// Builds an RpcClient on the shared HTTP connection pool, routing requests through the fixture
+/// store when recording or replaying and counting them in `usage`.
+pub fn new_rpc_client(
+    url: String,
+    commitment: CommitmentConfig,
+    fixtures: Option<Arc<FixtureStore>>,
+    usage: Option<Arc<RpcUsage>>,
+) -> RpcClient {
+    let config = RpcClientConfig::with_commitment(commitment);
+    match fixtures {
+        Some(store) => RpcClient::new_sender(
+            UsageSender::new(FixtureSender::new(url, store), usage),
+            config,
+        ),
+        None => RpcClient::new_sender(UsageSender::new(rpc_http_sender(url), usage), config),
+    }
+}
+
+/// Widest slot range a single getBlocks request may span.
+const MAX_GET_BLOCKS_RANGE: u64 = 500_000;
+
+/// The slots between `first_slot` and `last_slot` (inclusive) with a finalized block, using as
+/// few getBlocks range queries as the RPC allows.
+pub async fn get_produced_blocks(
+    client: &RpcClient,
+    first_slot: u64,
+    last_slot: u64,
+) -> Result<HashSet<u64>, ClientError> {
+    let mut produced = HashSet::new();
+    let mut start = first_slot;
+    while start <= last_slot {
+        let end = last_slot.min(start + MAX_GET_BLOCKS_RANGE - 1);
+        produced.extend(
+            client
+                .get_blocks_with_commitment(start, Some(end), CommitmentConfig::finalized())
+                .await?,
+        );
+        start = end + 1;
+    }
+    Ok(produced)
+}
+
 // rpc_utils.rs
 /// Wrapper on Solana RPC get_block, but propagates skipped blocks as PriorityFeeKeeperError
 pub async fn get_block(
     client: &RpcClient,
     slot: u64,
     slot_history: &SlotHistory,
+    transaction_details: TransactionDetails,
 ) -> Result<UiConfirmedBlock, PriorityFeeKeeperError> {
     let block_res = client
         .get_block_with_config(
             slot,
             RpcBlockConfig {
                 encoding: Some(UiTransactionEncoding::Json),
-                transaction_details: Some(TransactionDetails::None),
+                transaction_details: Some(transaction_details),
                 rewards: Some(true),
                 commitment: Some(CommitmentConfig::finalized()),
                 max_supported_transaction_version: Some(0),
@@ -69,7 +120,10 @@ pub async fn get_block(
                     // Meaning they can arise from RPC issues or lack of history (limit ledger
                     //  space, no big table) accesible  by an RPC. This is why we check
                     // SlotHistory and then follow up with redundant RPC checks.
-                    let slot_skipped_regex = Regex::new(r"^Slot [\d]+ was skipped").unwrap();
+                    // A block pruned from the node's ledger is "cleaned up", whether or not it
+                    //  was ever produced, and gets the same SlotHistory check.
+                    let slot_skipped_regex =
+                        Regex::new(r"^(Slot [\d]+ was skipped|Block [\d]+ cleaned up)").unwrap();
                     if slot_skipped_regex.is_match(&message) {
                         match slot_history.check(slot) {
                             slot_history::Check::Future => {
@@ -98,32 +152,207 @@ pub async fn get_block(
     };
 }
 
-pub async fn wait_for_next_epoch(
+impl PriorityFeeKeeperError {
+    /// Whether the request failed in transit, e.g. a dropped connection or timeout, rather than
+    /// being answered with an error. These usually succeed when sent again right away.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            PriorityFeeKeeperError::SolanaClientError(err)
+                if matches!(err.kind, ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_))
+        )
+    }
+}
+
+/// First wait between retries of a block fetch, doubled after every failure.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+/// Transient errors retried without waiting before backing off.
+const IMMEDIATE_RETRIES: u32 = 2;
+
+/// Exponential backoff with full jitter between the retries of one request, giving up once the
+/// next attempt would start past the deadline.
+pub struct Backoff {
+    failures: u32,
+    immediate_retries: u32,
+    max_delay: Duration,
+    deadline: tokio::time::Instant,
+}
+
+impl Backoff {
+    pub fn new(max_delay: Duration, timeout: Duration) -> Self {
+        Self {
+            failures: 0,
+            immediate_retries: 0,
+            max_delay,
+            deadline: tokio::time::Instant::now() + timeout,
+        }
+    }
+
+    /// How long to wait before retrying after a failure, or None once the deadline would pass.
+    /// The first few `transient` failures are retried immediately.
+    pub fn next_delay(&mut self, transient: bool) -> Option<Duration> {
+        let delay = if transient && self.immediate_retries < IMMEDIATE_RETRIES {
+            self.immediate_retries += 1;
+            Duration::ZERO
+        } else {
+            self.failures += 1;
+            backoff_delay(self.failures, self.max_delay, rand::random::<f64>())
+        };
+        (tokio::time::Instant::now() + delay <= self.deadline).then_some(delay)
+    }
+}
+
+/// The wait after the `failures`th failure: a `jitter` share, from 0 to 1, of the base delay
+/// doubled per failure and capped at `max_delay`.
+fn backoff_delay(failures: u32, max_delay: Duration, jitter: f64) -> Duration {
+    let exponent = failures.saturating_sub(1).min(31);
+    RETRY_BASE_DELAY
+        .saturating_mul(1 << exponent)
+        .min(max_delay)
+        .mul_f64(jitter.clamp(0.0, 1.0))
+}
+
+/// Slot time assumed when the RPC has no recent performance samples.
+const DEFAULT_SLOT_DURATION: Duration = Duration::from_millis(DEFAULT_MS_PER_SLOT);
+/// Performance samples, of 60 seconds each, averaged into the slot time estimate.
+const PERFORMANCE_SAMPLES: usize = 30;
+/// Share of the estimated time left in the epoch to wait before checking again, leaving room for
+/// slots getting faster.
+const BOUNDARY_WAIT_FRACTION: f64 = 0.9;
+/// Longest wait between epoch boundary checks, bounding how late a bad estimate can be.
+const MAX_BOUNDARY_CHECK_DELAY: Duration = Duration::from_secs(3600);
+
+/// Outcome of checking for the epoch boundary.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BoundaryCheck {
+    NewEpoch(EpochInfo),
+    /// The epoch hasn't ended yet, check again after this long.
+    NotYet(Duration),
+}
+
+/// Average slot time over the RPC's recent performance samples.
+pub async fn fetch_slot_duration(rpc_client: &RpcClient) -> Duration {
+    let samples = match rpc_client
+        .get_recent_performance_samples(Some(PERFORMANCE_SAMPLES))
+        .await
+    {
+        Ok(samples) => samples,
+        Err(e) => {
+            error!("Error getting recent performance samples: {:?}", e);
+            return DEFAULT_SLOT_DURATION;
+        }
+    };
+    let slots: u64 = samples.iter().map(|sample| sample.num_slots).sum();
+    let secs: u64 = samples
+        .iter()
+        .map(|sample| u64::from(sample.sample_period_secs))
+        .sum();
+    if slots == 0 || secs == 0 {
+        return DEFAULT_SLOT_DURATION;
+    }
+    Duration::from_secs_f64(secs as f64 / slots as f64)
+}
+
+/// How long to wait before checking for the boundary again with `remaining_slots` left in the
+/// epoch: most of the estimated time left far from the boundary, and `min_delay` close to it.
+pub fn boundary_check_delay(
+    remaining_slots: u64,
+    slot_duration: Duration,
+    min_delay: Duration,
+) -> Duration {
+    slot_duration
+        .mul_f64(remaining_slots as f64 * BOUNDARY_WAIT_FRACTION)
+        .min(MAX_BOUNDARY_CHECK_DELAY)
+        .max(min_delay)
+}
+
+/// Checks once whether the cluster has moved past `current_epoch`, and otherwise estimates when
+/// to check again from the slots left in the epoch. Errors are retried after `min_delay`.
+pub async fn check_for_next_epoch(
     rpc_client: &RpcClient,
     current_epoch: u64,
-    cycle_secs: u64,
-) -> EpochInfo {
-    loop {
-        tokio::time::sleep(Duration::from_secs(cycle_secs)).await;
+    min_delay: Duration,
+) -> BoundaryCheck {
+    info!(
+        "Checking for epoch boundary... current_epoch: {}",
+        current_epoch
+    );
+
+    let new_epoch_info = match rpc_client.get_epoch_info().await {
+        Ok(info) => info,
+        Err(e) => {
+            error!("Error getting epoch info: {:?}", e);
+            return BoundaryCheck::NotYet(min_delay);
+        }
+    };
+
+    if new_epoch_info.epoch > current_epoch {
         info!(
-            "Checking for epoch boundary... current_epoch: {}",
-            current_epoch
+            "New epoch detected: {} -> {}",
+            current_epoch, new_epoch_info.epoch
         );
+        return BoundaryCheck::NewEpoch(new_epoch_info);
+    }
+    let remaining_slots = new_epoch_info
+        .slots_in_epoch
+        .saturating_sub(new_epoch_info.slot_index);
+    let slot_duration = fetch_slot_duration(rpc_client).await;
+    let delay = boundary_check_delay(remaining_slots, slot_duration, min_delay);
+    info!(
+        "{} slots left in epoch {} at {:?} per slot, checking again in {}s",
+        remaining_slots,
+        current_epoch,
+        slot_duration,
+        delay.as_secs()
+    );
+    BoundaryCheck::NotYet(delay)
+}
 
-        let new_epoch_info = match rpc_client.get_epoch_info().await {
-            Ok(info) => info,
-            Err(e) => {
-                error!("Error getting epoch info: {:?}", e);
-                continue;
-            }
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if new_epoch_info.epoch > current_epoch {
-            info!(
-                "New epoch detected: {} -> {}",
-                current_epoch, new_epoch_info.epoch
-            );
-            return new_epoch_info;
-        }
+    #[test]
+    fn test_backoff_delay_doubles_up_to_the_cap() {
+        let max = Duration::from_secs(60);
+        assert_eq!(backoff_delay(1, max, 1.0), Duration::from_secs(2));
+        assert_eq!(backoff_delay(3, max, 1.0), Duration::from_secs(8));
+        assert_eq!(backoff_delay(3, max, 0.5), Duration::from_secs(4));
+        assert_eq!(backoff_delay(10, max, 1.0), max);
+        assert_eq!(backoff_delay(u32::MAX, max, 1.0), max);
+    }
+
+    #[test]
+    fn test_backoff_gives_up_at_the_deadline() {
+        let mut backoff = Backoff::new(Duration::from_secs(60), Duration::from_secs(60));
+        assert_eq!(backoff.next_delay(true), Some(Duration::ZERO));
+        assert_eq!(backoff.next_delay(true), Some(Duration::ZERO));
+        // Past the immediate retries, the first backoff waits up to 2s, well before the deadline.
+        let delay = backoff.next_delay(true).unwrap();
+        assert!(delay <= RETRY_BASE_DELAY);
+
+        // Once the deadline has passed, even an immediate retry is refused.
+        let mut backoff = Backoff::new(Duration::from_secs(60), Duration::from_millis(20));
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(backoff.next_delay(true), None);
+        assert_eq!(backoff.next_delay(false), None);
+    }
+
+    #[test]
+    fn test_boundary_check_delay_tightens_near_boundary() {
+        let slot = Duration::from_millis(400);
+        let cycle = Duration::from_secs(60);
+        // Far from the boundary the wait is capped.
+        assert_eq!(
+            boundary_check_delay(432_000, slot, cycle),
+            MAX_BOUNDARY_CHECK_DELAY
+        );
+        // 1000 slots is ~400s away, wait 90% of it.
+        assert_eq!(
+            boundary_check_delay(1_000, slot, cycle),
+            Duration::from_secs(360)
+        );
+        // Close to the boundary, poll every cycle.
+        assert_eq!(boundary_check_delay(10, slot, cycle), cycle);
     }
 }
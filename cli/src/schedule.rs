@@ -0,0 +1,122 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+
+/// Parses a cron expression, in UTC. Standard five field expressions (`min hour day month
+/// weekday`) are accepted, with weekdays numbered 0 (or 7) for Sunday to 6, as well as six and
+/// seven field ones with leading seconds and trailing years, which number them 1 for Sunday to
+/// 7 as the cron crate does.
+pub fn parse_schedule(expr: &str) -> Result<Schedule, String> {
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    let expr = match fields.as_slice() {
+        [minute, hour, day, month, weekday] => format!(
+            "0 {} {} {} {} {}",
+            minute,
+            hour,
+            day,
+            month,
+            standard_weekdays(weekday)?
+        ),
+        _ => fields.join(" "),
+    };
+    Schedule::from_str(&expr).map_err(|e| format!("Invalid cron expression: {}", e))
+}
+
+/// Translates a standard weekday field, e.g. `1-5`, to the cron crate's numbering, where each
+/// day is one more and Sunday is 1. Day names mean the same in both and are kept.
+fn standard_weekdays(field: &str) -> Result<String, String> {
+    if field == "*" || field == "?" || field.chars().any(|c| c.is_ascii_alphabetic()) {
+        return Ok(field.to_string());
+    }
+    let invalid = || format!("Invalid cron expression: invalid weekday field {}", field);
+    let day = |day: &str| day.parse::<u8>().ok().filter(|day| *day <= 7);
+    let mut days = BTreeSet::new();
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<usize>().map_err(|_| invalid())?)),
+            None => (item, None),
+        };
+        let (first, last) = match (range, range.split_once('-'), step) {
+            ("*", _, _) => (0, 6),
+            (_, Some((first, last)), _) => (day(first), day(last)),
+            // `<day>/<step>` runs from the day to the end of the week.
+            (_, None, Some(_)) => (day(range), Some(6)),
+            (_, None, None) => (day(range), day(range)),
+        };
+        let (Some(first), Some(last)) = (first, last) else {
+            return Err(invalid());
+        };
+        if first > last || step == Some(0) {
+            return Err(invalid());
+        }
+        days.extend(
+            (first..=last)
+                .step_by(step.unwrap_or(1))
+                .map(|day| day % 7 + 1),
+        );
+    }
+    Ok(days
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(","))
+}
+
+/// The first time `schedule` fires after `now`.
+pub fn next_run(schedule: &Schedule, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    schedule.after(&now).next()
+}
+
+/// Sleeps until `schedule` next fires and returns that time, forever without a schedule.
+pub async fn wait_for_next_run(schedule: Option<&Schedule>) -> DateTime<Utc> {
+    let now = Utc::now();
+    let Some(next) = schedule.and_then(|schedule| next_run(schedule, now)) else {
+        return std::future::pending().await;
+    };
+    tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_five_field_expressions_run_on_the_minute() {
+        let schedule = parse_schedule("0 2 * * *").unwrap();
+        let now = Utc.with_ymd_and_hms(2026, 10, 15, 2, 0, 0).unwrap();
+        assert_eq!(
+            next_run(&schedule, now),
+            Some(Utc.with_ymd_and_hms(2026, 10, 16, 2, 0, 0).unwrap())
+        );
+        let schedule = parse_schedule("30 0 2 * * *").unwrap();
+        assert_eq!(
+            next_run(&schedule, now),
+            Some(Utc.with_ymd_and_hms(2026, 10, 15, 2, 0, 30).unwrap())
+        );
+        assert!(parse_schedule("every day").is_err());
+    }
+
+    #[test]
+    fn test_five_field_weekdays_count_from_sunday_as_zero() {
+        // A Thursday.
+        let now = Utc.with_ymd_and_hms(2026, 10, 15, 3, 0, 0).unwrap();
+        let next = |expr: &str| next_run(&parse_schedule(expr).unwrap(), now).unwrap();
+        let monday = Utc.with_ymd_and_hms(2026, 10, 19, 2, 0, 0).unwrap();
+        let friday = Utc.with_ymd_and_hms(2026, 10, 16, 2, 0, 0).unwrap();
+        let sunday = Utc.with_ymd_and_hms(2026, 10, 18, 2, 0, 0).unwrap();
+        assert_eq!(next("0 2 * * 1"), monday);
+        assert_eq!(next("0 2 * * MON"), monday);
+        assert_eq!(next("0 2 * * 0"), sunday);
+        assert_eq!(next("0 2 * * 7"), sunday);
+        assert_eq!(next("0 2 * * 1-5"), friday);
+        assert_eq!(next("0 2 * * 0,1"), sunday);
+        assert_eq!(standard_weekdays("5-7").unwrap(), "1,6,7");
+        assert_eq!(standard_weekdays("*/2").unwrap(), "1,3,5,7");
+        assert!(parse_schedule("0 2 * * 8").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::{debug, warn};
+use serde_json::{json, Value};
+use solana_client::client_error::Result as ClientResult;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::RpcClientConfig;
+use solana_client::rpc_request::{RpcError, RpcRequest};
+use solana_client::rpc_sender::{RpcSender, RpcTransportStats};
+use solana_commitment_config::CommitmentConfig;
+use solana_sdk::clock::Slot;
+use solana_sdk::epoch_info::EpochInfo;
+
+use crate::exit_code::ConfigError;
+use crate::http::rpc_http_sender;
+use crate::rpc_fixtures::{FixtureSender, FixtureStore};
+use crate::rpc_usage::{RpcUsage, UsageSender};
+
+/// Owner of the sysvars. Their entries for past epochs and slots never change.
+const SYSVAR_OWNER: &str = "Sysvar1111111111111111111111111111111111111";
+/// Owner of vote accounts, which every vote modifies.
+const VOTE_PROGRAM_ID: &str = "Vote111111111111111111111111111111111111111";
+/// Owner of stake accounts, whose delegation the active stake is computed from.
+const STAKE_PROGRAM_ID: &str = "Stake11111111111111111111111111111111111111";
+
+/// RpcSender pinning account reads to a slot. The RPC only serves the latest state of an
+/// account, so reads are made no older than the slot with `minContextSlot`, and every account
+/// read is checked for transactions since. A stake account modified after the slot fails the
+/// request, so a run either sees the stakes exactly as they were at the slot or fails. Other
+/// accounts, such as bonds receiving their payments, are only warned about.
+pub struct SnapshotSender<S> {
+    inner: S,
+    slot: Slot,
+    /// Accounts already checked for modifications since `slot`.
+    checked: Mutex<HashSet<String>>,
+}
+
+impl<S: RpcSender> SnapshotSender<S> {
+    pub fn new(inner: S, slot: Slot) -> Self {
+        Self {
+            inner,
+            slot,
+            checked: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Checks whether a transaction touched `address` after the snapshot slot, failing if it
+    /// did and `strict`.
+    async fn check_unmodified(&self, address: &str, strict: bool) -> ClientResult<()> {
+        if !self
+            .checked
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(address.to_string())
+        {
+            return Ok(());
+        }
+        let signatures = self
+            .inner
+            .send(
+                RpcRequest::GetSignaturesForAddress,
+                json!([address, {"limit": 1, "commitment": "confirmed"}]),
+            )
+            .await?;
+        if let Some(slot) = signatures
+            .get(0)
+            .and_then(|signature| signature["slot"].as_u64())
+            .filter(|slot| *slot > self.slot)
+        {
+            if strict {
+                self.checked
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .remove(address);
+                return Err(RpcError::RpcRequestError(format!(
+                    "Account {} was modified at slot {}, after --as-of-slot {}, so its state as of that slot can't be read",
+                    address, slot, self.slot
+                ))
+                .into());
+            }
+            warn!(
+                "Account {} was modified at slot {}, after --as-of-slot {}, its state as of that slot may differ",
+                address, slot, self.slot
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Adds `minContextSlot` to the config object of an account read, creating it if missing.
+fn with_min_context_slot(mut params: Value, slot: Slot) -> Value {
+    if let Some(params) = params.as_array_mut() {
+        match params.get_mut(1) {
+            Some(Value::Object(config)) => {
+                config.insert("minContextSlot".to_string(), json!(slot));
+            }
+            Some(Value::Null) => params[1] = json!({ "minContextSlot": slot }),
+            None if params.len() == 1 => params.push(json!({ "minContextSlot": slot })),
+            _ => {}
+        }
+    }
+    params
+}
+
+/// The addresses read by a getAccountInfo or getMultipleAccounts request, paired with the
+/// accounts returned, skipping those that don't exist.
+fn read_accounts<'a>(params: &'a Value, result: &'a Value) -> Vec<(&'a str, &'a Value)> {
+    let addresses: Vec<&str> = match &params[0] {
+        Value::String(address) => vec![address.as_str()],
+        Value::Array(addresses) => addresses.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let accounts: Vec<&Value> = match &result["value"] {
+        Value::Array(accounts) => accounts.iter().collect(),
+        account => vec![account],
+    };
+    addresses
+        .into_iter()
+        .zip(accounts)
+        .filter(|(_, account)| !account.is_null())
+        .collect()
+}
+
+#[async_trait]
+impl<S: RpcSender + Send + Sync> RpcSender for SnapshotSender<S> {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        match request {
+            RpcRequest::GetAccountInfo | RpcRequest::GetMultipleAccounts => {
+                let params = with_min_context_slot(params, self.slot);
+                let result = self.inner.send(request, params.clone()).await?;
+                debug!(
+                    "{} served as of slot {}",
+                    request,
+                    result["context"]["slot"].as_u64().unwrap_or_default()
+                );
+                for (address, account) in read_accounts(&params, &result) {
+                    let owner = account["owner"].as_str().unwrap_or_default();
+                    // Vote accounts change every slot and the sysvars only append, so they
+                    // can't be checked and are served as of the node's slot.
+                    if owner != SYSVAR_OWNER && owner != VOTE_PROGRAM_ID {
+                        self.check_unmodified(address, owner == STAKE_PROGRAM_ID)
+                            .await?;
+                    }
+                }
+                Ok(result)
+            }
+            RpcRequest::GetBalance | RpcRequest::GetInflationReward => {
+                self.inner
+                    .send(request, with_min_context_slot(params, self.slot))
+                    .await
+            }
+            _ => self.inner.send(request, params).await,
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+/// Builds an RpcClient whose account reads are pinned to `slot`, see [`SnapshotSender`]. The
+/// requests sent, the modification checks included, are counted in `usage`.
+pub fn new_snapshot_rpc_client(
+    url: String,
+    commitment: CommitmentConfig,
+    fixtures: Option<Arc<FixtureStore>>,
+    usage: Option<Arc<RpcUsage>>,
+    slot: Slot,
+) -> RpcClient {
+    let config = RpcClientConfig::with_commitment(commitment);
+    match fixtures {
+        Some(store) => RpcClient::new_sender(
+            SnapshotSender::new(
+                UsageSender::new(FixtureSender::new(url, store), usage),
+                slot,
+            ),
+            config,
+        ),
+        None => RpcClient::new_sender(
+            SnapshotSender::new(UsageSender::new(rpc_http_sender(url), usage), slot),
+            config,
+        ),
+    }
+}
+
+/// The epoch info as of `slot`, which must have been reached already.
+pub async fn epoch_info_at(client: &RpcClient, slot: Slot) -> Result<EpochInfo> {
+    let current_slot = client.get_slot().await?;
+    if slot > current_slot {
+        return Err(anyhow!(
+            "--as-of-slot {} hasn't been reached yet, the current slot is {}",
+            slot,
+            current_slot
+        )
+        .context(ConfigError));
+    }
+    let epoch_schedule = client.get_epoch_schedule().await?;
+    let (epoch, slot_index) = epoch_schedule.get_epoch_and_slot_index(slot);
+    Ok(EpochInfo {
+        epoch,
+        slot_index,
+        slots_in_epoch: epoch_schedule.get_slots_in_epoch(epoch),
+        absolute_slot: slot,
+        block_height: 0,
+        transaction_count: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_min_context_slot() {
+        assert_eq!(
+            with_min_context_slot(json!(["addr", {"encoding": "base64"}]), 42),
+            json!(["addr", {"encoding": "base64", "minContextSlot": 42}])
+        );
+        assert_eq!(
+            with_min_context_slot(json!([["addr"]]), 42),
+            json!([["addr"], {"minContextSlot": 42}])
+        );
+    }
+
+    #[test]
+    fn test_read_accounts() {
+        let params = json!([["stake", "missing", "vote"]]);
+        let result = json!({
+            "context": {"slot": 50},
+            "value": [{"owner": "Stake"}, null, {"owner": VOTE_PROGRAM_ID}],
+        });
+        let read: Vec<&str> = read_accounts(&params, &result)
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect();
+        assert_eq!(read, vec!["stake", "vote"]);
+    }
+}
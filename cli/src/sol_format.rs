@@ -0,0 +1,92 @@
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+
+/// Decimal places of a lamport in SOL.
+const SOL_DECIMALS: u32 = 9;
+
+fn group_thousands(value: u128) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+/// Formats lamports as SOL with thousands separators, rounded half away from zero to `decimals`
+/// (at most 9) decimal places, e.g. `1,234.5679` with 4 decimals.
+pub fn format_sol_rounded(lamports: impl Into<i128>, decimals: u32) -> String {
+    let lamports: i128 = lamports.into();
+    let decimals = decimals.min(SOL_DECIMALS);
+    let scale = 10u128.pow(SOL_DECIMALS - decimals);
+    let rounded = (lamports.unsigned_abs() + scale / 2) / scale;
+    let unit = u128::from(LAMPORTS_PER_SOL) / scale;
+    let sign = if lamports < 0 && rounded > 0 { "-" } else { "" };
+    let whole = group_thousands(rounded / unit);
+    if decimals == 0 {
+        format!("{}{}", sign, whole)
+    } else {
+        format!(
+            "{}{}.{:0width$}",
+            sign,
+            whole,
+            rounded % unit,
+            width = decimals as usize
+        )
+    }
+}
+
+/// Formats lamports as SOL with all 9 decimals and thousands separators, e.g. `1,234.567890123`.
+pub fn format_sol(lamports: impl Into<i128>) -> String {
+    format_sol_rounded(lamports, SOL_DECIMALS)
+}
+
+/// Formats an amount for logs and prompts with both units, e.g.
+/// `1.234567890 SOL (1,234,567,890 lamports)`.
+pub fn format_amount(lamports: impl Into<i128>) -> String {
+    let lamports: i128 = lamports.into();
+    let sign = if lamports < 0 { "-" } else { "" };
+    format!(
+        "{} SOL ({}{} lamports)",
+        format_sol(lamports),
+        sign,
+        group_thousands(lamports.unsigned_abs())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_sol() {
+        assert_eq!(format_sol(0u64), "0.000000000");
+        assert_eq!(format_sol(1u64), "0.000000001");
+        assert_eq!(format_sol(1_234_567_890_123u64), "1,234.567890123");
+        assert_eq!(format_sol(-1_500_000_000i64), "-1.500000000");
+        assert_eq!(format_sol(u64::MAX), "18,446,744,073.709551615");
+    }
+
+    #[test]
+    fn test_format_sol_rounded() {
+        assert_eq!(format_sol_rounded(1_234_567_890_123u64, 4), "1,234.5679");
+        assert_eq!(format_sol_rounded(999_999_999_999u64, 2), "1,000.00");
+        assert_eq!(format_sol_rounded(2_500_000_000u64, 0), "3");
+        // Amounts rounding to zero aren't shown as negative.
+        assert_eq!(format_sol_rounded(-1i64, 4), "0.0000");
+    }
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(
+            format_amount(1_234_567_890u64),
+            "1.234567890 SOL (1,234,567,890 lamports)"
+        );
+        assert_eq!(
+            format_amount(-5_000i64),
+            "-0.000005000 SOL (-5,000 lamports)"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::DateTime;
+use clap::Args;
+
+use crate::receipts::PaymentReceipt;
+use crate::sol_format::format_amount;
+
+/// Length of the rolling window --max-daily-spend-lamports applies to.
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Hard limits on the lamports paid across all bonds.
+#[derive(Clone, Debug, Default, Args)]
+pub struct SpendingLimitArgs {
+    /// Most lamports paid across all bonds for one epoch. Payments that would exceed it, and the
+    /// epoch's payments after them, are withheld as `over_limit` until approved with
+    /// `retry-failed --approve-pending`.
+    #[arg(long, env, requires = "receipts_dir")]
+    pub max_epoch_spend_lamports: Option<u64>,
+    /// Most lamports paid across all bonds over any 24 hours, withheld the same way.
+    #[arg(long, env, requires = "receipts_dir")]
+    pub max_daily_spend_lamports: Option<u64>,
+}
+
+impl SpendingLimitArgs {
+    pub fn is_enabled(&self) -> bool {
+        self.max_epoch_spend_lamports.is_some() || self.max_daily_spend_lamports.is_some()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Spending {
+    /// Lamports paid for each epoch.
+    by_epoch: BTreeMap<u64, u64>,
+    /// Unix time and lamports of each payment of the last day.
+    recent: Vec<(i64, u64)>,
+    /// Epochs whose payments are withheld since one of them hit a limit.
+    withheld: BTreeSet<u64>,
+}
+
+/// Tracks the lamports paid against the spending limits.
+#[derive(Debug, Default)]
+pub struct SpendingLimits {
+    max_epoch: Option<u64>,
+    max_daily: Option<u64>,
+    spending: Mutex<Spending>,
+}
+
+impl SpendingLimits {
+    /// Limits counting the payments recorded in `receipts`, so a restart doesn't reset them.
+    pub fn new(args: &SpendingLimitArgs, receipts: &[PaymentReceipt], now: i64) -> Self {
+        let limits = Self {
+            max_epoch: args.max_epoch_spend_lamports,
+            max_daily: args.max_daily_spend_lamports,
+            spending: Mutex::default(),
+        };
+        for receipt in receipts.iter().filter(|receipt| receipt.payment == "paid") {
+            let paid_at = DateTime::parse_from_rfc3339(&receipt.settled_at)
+                .map(|settled_at| settled_at.timestamp())
+                .unwrap_or_default();
+            let lamports = receipt.amount_due().max(0) as u64;
+            limits.record(receipt.epoch, lamports, paid_at, now);
+        }
+        limits
+    }
+
+    fn spending(&self) -> MutexGuard<'_, Spending> {
+        self.spending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Why paying `lamports` for `epoch` at `now` must be withheld, if it must. Once a payment is
+    /// withheld, so are the epoch's later ones, keeping the payment priority order.
+    pub fn check(&self, epoch: u64, lamports: u64, now: i64) -> Option<String> {
+        let mut spending = self.spending();
+        if spending.withheld.contains(&epoch) {
+            return Some("an earlier payment of the epoch exceeded a spending limit".to_string());
+        }
+        let epoch_total = spending.by_epoch.get(&epoch).copied().unwrap_or_default() + lamports;
+        let daily_total = lamports
+            + spending
+                .recent
+                .iter()
+                .filter(|(paid_at, _)| *paid_at > now - DAY_SECS)
+                .map(|(_, lamports)| lamports)
+                .sum::<u64>();
+        let reason = if let Some(limit) = self.max_epoch.filter(|limit| epoch_total > *limit) {
+            format!(
+                "it would bring the epoch's payments to {}, over --max-epoch-spend-lamports of {}",
+                format_amount(epoch_total),
+                format_amount(limit)
+            )
+        } else if let Some(limit) = self.max_daily.filter(|limit| daily_total > *limit) {
+            format!(
+                "it would bring the last 24 hours' payments to {}, over --max-daily-spend-lamports of {}",
+                format_amount(daily_total),
+                format_amount(limit)
+            )
+        } else {
+            return None;
+        };
+        spending.withheld.insert(epoch);
+        Some(reason)
+    }
+
+    /// Counts a payment of `lamports` for `epoch` made at `paid_at`.
+    pub fn record(&self, epoch: u64, lamports: u64, paid_at: i64, now: i64) {
+        let mut spending = self.spending();
+        *spending.by_epoch.entry(epoch).or_default() += lamports;
+        spending
+            .recent
+            .retain(|(paid_at, _)| *paid_at > now - DAY_SECS);
+        if paid_at > now - DAY_SECS {
+            spending.recent.push((paid_at, lamports));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOL: u64 = 1_000_000_000;
+
+    #[test]
+    fn test_epoch_limit_withholds_the_rest_of_the_epoch() {
+        let args = SpendingLimitArgs {
+            max_epoch_spend_lamports: Some(10 * SOL),
+            max_daily_spend_lamports: None,
+        };
+        let limits = SpendingLimits::new(&args, &[], 0);
+        assert_eq!(limits.check(800, 6 * SOL, 0), None);
+        limits.record(800, 6 * SOL, 0, 0);
+        assert!(limits.check(800, 5 * SOL, 0).is_some());
+        // A smaller payment would fit, but comes after the withheld one.
+        assert!(limits.check(800, SOL, 0).is_some());
+        assert_eq!(limits.check(801, 10 * SOL, 0), None);
+    }
+
+    #[test]
+    fn test_daily_limit_rolls_over() {
+        let args = SpendingLimitArgs {
+            max_epoch_spend_lamports: None,
+            max_daily_spend_lamports: Some(10 * SOL),
+        };
+        let limits = SpendingLimits::new(&args, &[], 0);
+        limits.record(800, 8 * SOL, 0, 0);
+        assert!(limits.check(801, 3 * SOL, DAY_SECS - 1).is_some());
+        assert_eq!(limits.check(802, 3 * SOL, DAY_SECS), None);
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+
+use anyhow::Result;
+use log::{info, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::message::VersionedMessage;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::stake::instruction::StakeInstruction;
+use solana_sdk::stake::program as stake_program;
+use solana_transaction_status_client_types::UiTransactionEncoding;
+
+/// getSignaturesForAddress page size.
+const SIGNATURES_PAGE_LIMIT: usize = 1_000;
+/// Upper bound on history pages scanned per stake account.
+const MAX_SIGNATURE_PAGES: usize = 5;
+/// Upper bound on stake accounts followed from one bond, for accounts split many ways.
+const MAX_LINKED_ACCOUNTS: usize = 64;
+
+/// Stake accounts that one transaction moved stake to out of one of the `known` accounts: split
+/// off from it, merged out of it, or moved to with MoveStake or MoveLamports. Stake moved into a
+/// known account wasn't the bond's before, so its source isn't followed.
+pub fn linked_stake_accounts(message: &VersionedMessage, known: &HashSet<Pubkey>) -> Vec<Pubkey> {
+    let keys = message.static_account_keys();
+    let key = |ix_accounts: &[u8], position: usize| {
+        ix_accounts
+            .get(position)
+            .and_then(|index| keys.get(usize::from(*index)))
+            .copied()
+    };
+    message
+        .instructions()
+        .iter()
+        .filter(|ix| keys.get(usize::from(ix.program_id_index)) == Some(&stake_program::ID))
+        .filter_map(
+            |ix| match bincode::deserialize::<StakeInstruction>(&ix.data) {
+                // Split, MoveStake and MoveLamports: [source, destination, ..].
+                Ok(
+                    StakeInstruction::Split(_)
+                    | StakeInstruction::MoveStake(_)
+                    | StakeInstruction::MoveLamports(_),
+                ) => Some((key(&ix.accounts, 0)?, key(&ix.accounts, 1)?)),
+                // Merge: [destination, source, ..].
+                Ok(StakeInstruction::Merge) => Some((key(&ix.accounts, 1)?, key(&ix.accounts, 0)?)),
+                _ => None,
+            },
+        )
+        .filter(|(source, destination)| known.contains(source) && !known.contains(destination))
+        .map(|(_, destination)| destination)
+        .collect()
+}
+
+/// Follows the stake program history of a bond's stake accounts back to `since_slot` and returns
+/// every other stake account their stake was split, merged or moved to, so stake that left the
+/// bond's current accounts after `since_slot` is still attributed to the bond.
+pub async fn fetch_linked_stake_accounts(
+    client: &RpcClient,
+    bond_accounts: &[Pubkey],
+    since_slot: u64,
+) -> Result<Vec<Pubkey>> {
+    let mut known: HashSet<Pubkey> = bond_accounts.iter().copied().collect();
+    let mut queue: VecDeque<Pubkey> = bond_accounts.iter().copied().collect();
+    let mut seen_signatures = HashSet::new();
+    let mut linked = Vec::new();
+
+    while let Some(account) = queue.pop_front() {
+        let mut before = None;
+        for _ in 0..MAX_SIGNATURE_PAGES {
+            let signatures = client
+                .get_signatures_for_address_with_config(
+                    &account,
+                    GetConfirmedSignaturesForAddress2Config {
+                        before,
+                        until: None,
+                        limit: Some(SIGNATURES_PAGE_LIMIT),
+                        commitment: Some(CommitmentConfig::confirmed()),
+                    },
+                )
+                .await?;
+            let Some(last) = signatures.last() else {
+                break;
+            };
+            before = Some(Signature::from_str(&last.signature)?);
+
+            for status in signatures.iter() {
+                if status.slot < since_slot || status.err.is_some() {
+                    continue;
+                }
+                let signature = Signature::from_str(&status.signature)?;
+                if !seen_signatures.insert(signature) {
+                    continue;
+                }
+                let transaction = client
+                    .get_transaction_with_config(
+                        &signature,
+                        RpcTransactionConfig {
+                            encoding: Some(UiTransactionEncoding::Base64),
+                            commitment: Some(CommitmentConfig::confirmed()),
+                            max_supported_transaction_version: Some(0),
+                        },
+                    )
+                    .await?;
+                let Some(decoded) = transaction.transaction.transaction.decode() else {
+                    continue;
+                };
+                for pubkey in linked_stake_accounts(&decoded.message, &known) {
+                    if known.len() >= bond_accounts.len() + MAX_LINKED_ACCOUNTS {
+                        warn!(
+                            "Stopped following stake accounts linked to {:?} after {}",
+                            bond_accounts, MAX_LINKED_ACCOUNTS
+                        );
+                        return Ok(linked);
+                    }
+                    if known.insert(pubkey) {
+                        info!(
+                            "Stake account {} is linked to the bond by transaction {}",
+                            pubkey, signature
+                        );
+                        linked.push(pubkey);
+                        queue.push_back(pubkey);
+                    }
+                }
+            }
+
+            if last.slot < since_slot || signatures.len() < SIGNATURES_PAGE_LIMIT {
+                break;
+            }
+        }
+    }
+    Ok(linked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::message::Message;
+    use solana_sdk::stake::instruction as stake_instruction;
+
+    #[test]
+    fn test_linked_stake_accounts() {
+        let bond_stake = Pubkey::new_unique();
+        let split_stake = Pubkey::new_unique();
+        let merged_into = Pubkey::new_unique();
+        let deposited = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let mut instructions =
+            stake_instruction::split(&bond_stake, &authority, 1_000, &split_stake);
+        instructions.extend(stake_instruction::merge(
+            &merged_into,
+            &bond_stake,
+            &authority,
+        ));
+        // Stake merged into the bond's account came from outside the bond.
+        instructions.extend(stake_instruction::merge(
+            &bond_stake,
+            &deposited,
+            &authority,
+        ));
+        let message = VersionedMessage::Legacy(Message::new(&instructions, Some(&authority)));
+        let known = HashSet::from([bond_stake]);
+        assert_eq!(
+            linked_stake_accounts(&message, &known),
+            vec![split_stake, merged_into]
+        );
+    }
+}
@@ -0,0 +1,377 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::{error, info, warn};
+use rustls::{ClientConfig, RootCertStore};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tokio_postgres::types::Json;
+use tokio_postgres::{Client, Config};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::bond_state::BondState;
+use crate::metrics::datapoint_error;
+use crate::receipts::PaymentReceipt;
+
+/// Key of the advisory lock held while migrating, so managers starting together migrate once.
+const MIGRATION_LOCK: i64 = 0x7079_655f_6d69_6772;
+
+/// Schema migrations, applied in order and recorded in `schema_migrations`. Released migrations
+/// are never edited, changes go in a new one.
+const MIGRATIONS: &[(i32, &str)] = &[
+    (
+        1,
+        "CREATE TABLE bond_states (
+            vote_pubkey TEXT NOT NULL,
+            epoch BIGINT NOT NULL,
+            pye_account TEXT NOT NULL,
+            state JSONB NOT NULL,
+            updated_at BIGINT NOT NULL,
+            PRIMARY KEY (vote_pubkey, epoch, pye_account)
+        );
+        CREATE TABLE receipts (
+            id BIGSERIAL PRIMARY KEY,
+            vote_pubkey TEXT NOT NULL,
+            epoch BIGINT NOT NULL,
+            pye_account TEXT NOT NULL,
+            payment TEXT NOT NULL,
+            receipt JSONB NOT NULL
+        );
+        CREATE INDEX receipts_vote_pubkey_epoch ON receipts (vote_pubkey, epoch);",
+    ),
+    // With --defer-mev a bond has a state for each payment of an epoch.
+    (
+        2,
+        "ALTER TABLE bond_states ADD COLUMN pass TEXT NOT NULL DEFAULT 'full';
+        ALTER TABLE bond_states DROP CONSTRAINT bond_states_pkey;
+        ALTER TABLE bond_states ADD PRIMARY KEY (vote_pubkey, epoch, pye_account, pass);",
+    ),
+];
+
+/// PostgreSQL database holding the bond states and receipts of every manager using it, keyed
+/// by vote account, for teams running several instances or reporting from one place. Dropped
+/// connections are reopened on the next query.
+pub struct StateDb {
+    url: String,
+    client: Mutex<Option<Arc<Client>>>,
+}
+
+impl StateDb {
+    /// The database at `url`, connected to on the first query. TLS is used as the URL's
+    /// `sslmode` asks, verified against the webpki roots.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client: Mutex::new(None),
+        }
+    }
+
+    /// Connects to the database at `url` and applies pending migrations.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let db = Self::new(url);
+        db.migrate().await?;
+        Ok(db)
+    }
+
+    async fn client(&self) -> Result<Arc<Client>> {
+        let mut client = self.client.lock().await;
+        if let Some(client) = client.as_ref().filter(|client| !client.is_closed()) {
+            return Ok(Arc::clone(client));
+        }
+        let config: Config = self
+            .url
+            .parse()
+            .map_err(|e| anyhow!("Invalid --state-db-url: {}", e))?;
+        let (connected, connection) = config
+            .connect(tls()?)
+            .await
+            .map_err(|e| anyhow!("Failed to connect to the state database: {}", e))?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                warn!("State database connection closed: {}", e);
+            }
+        });
+        let connected = Arc::new(connected);
+        *client = Some(Arc::clone(&connected));
+        Ok(connected)
+    }
+
+    /// Applies the migrations not applied yet, returning their versions.
+    pub async fn migrate(&self) -> Result<Vec<i32>> {
+        let client = self.client().await?;
+        client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (
+                    version INTEGER PRIMARY KEY,
+                    applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+                )",
+            )
+            .await?;
+        client
+            .execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK])
+            .await?;
+        let applied = self.apply_migrations(&client).await;
+        client
+            .execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK])
+            .await?;
+        applied
+    }
+
+    async fn apply_migrations(&self, client: &Client) -> Result<Vec<i32>> {
+        let current: Option<i32> = client
+            .query_one("SELECT max(version) FROM schema_migrations", &[])
+            .await?
+            .get(0);
+        let mut applied = Vec::new();
+        for (version, sql) in MIGRATIONS
+            .iter()
+            .filter(|(version, _)| current.is_none_or(|current| *version > current))
+        {
+            // A multi-statement query runs in one transaction, so a failed migration leaves no
+            // trace and is retried on the next start.
+            client
+                .batch_execute(&format!(
+                    "{};\nINSERT INTO schema_migrations (version) VALUES ({});",
+                    sql, version
+                ))
+                .await
+                .map_err(|e| anyhow!("State database migration {} failed: {}", version, e))?;
+            info!("Applied state database migration {}", version);
+            applied.push(*version);
+        }
+        Ok(applied)
+    }
+
+    /// The bond states of `vote_pubkey`'s manager.
+    pub async fn load_bond_states(&self, vote_pubkey: &str) -> Result<Vec<BondState>> {
+        let rows = self
+            .client()
+            .await?
+            .query(
+                "SELECT state FROM bond_states WHERE vote_pubkey = $1",
+                &[&vote_pubkey],
+            )
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| row.get::<_, Json<BondState>>(0).0)
+            .collect())
+    }
+
+    async fn save_bond_state(&self, vote_pubkey: &str, state: &BondState) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "INSERT INTO bond_states (vote_pubkey, epoch, pye_account, pass, state, updated_at)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 ON CONFLICT (vote_pubkey, epoch, pye_account, pass)
+                 DO UPDATE SET state = EXCLUDED.state, updated_at = EXCLUDED.updated_at",
+                &[
+                    &vote_pubkey,
+                    &(state.epoch as i64),
+                    &state.pye_account,
+                    &state.pass.to_string(),
+                    &Json(state),
+                    &state.updated_at,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn prune_bond_states(&self, vote_pubkey: &str, oldest_kept: u64) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "DELETE FROM bond_states WHERE vote_pubkey = $1 AND epoch < $2",
+                &[&vote_pubkey, &(oldest_kept as i64)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_receipt(&self, receipt: &PaymentReceipt) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "INSERT INTO receipts (vote_pubkey, epoch, pye_account, payment, receipt)
+                 VALUES ($1, $2, $3, $4, $5)",
+                &[
+                    &receipt.vote_pubkey,
+                    &(receipt.epoch as i64),
+                    &receipt.pye_account,
+                    &receipt.payment,
+                    &Json(receipt),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Every receipt, or only `vote_pubkey`'s.
+    pub async fn load_receipts(&self, vote_pubkey: Option<&str>) -> Result<Vec<PaymentReceipt>> {
+        let rows = self
+            .client()
+            .await?
+            .query(
+                "SELECT receipt FROM receipts WHERE $1::TEXT IS NULL OR vote_pubkey = $1
+                 ORDER BY id",
+                &[&vote_pubkey],
+            )
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| row.get::<_, Json<PaymentReceipt>>(0).0)
+            .collect())
+    }
+}
+
+fn tls() -> Result<MakeRustlsConnect> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    Ok(MakeRustlsConnect::new(config))
+}
+
+/// A write queued for the state database.
+enum Write {
+    BondState(String, BondState),
+    PruneBondStates(String, u64),
+    Receipt(PaymentReceipt),
+}
+
+/// A message to the writer task.
+enum Message {
+    Write(Write),
+    /// Answered once every write queued before it was applied, with the first failure since the
+    /// previous flush.
+    Flush(oneshot::Sender<Result<(), String>>),
+}
+
+/// Queues writes to the state database for a background task, so recording a bond's stage or a
+/// receipt doesn't wait on the database. Writes are applied in order; failures are logged and
+/// reported, and [`StateDbWriter::flush`] waits for them where a write must be durable.
+#[derive(Clone, Debug)]
+pub struct StateDbWriter {
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl StateDbWriter {
+    /// Spawns the writer task, which ends once every clone of the writer is dropped and the writes
+    /// queued until then are applied.
+    pub fn spawn(db: Arc<StateDb>) -> (Self, JoinHandle<()>) {
+        Self::spawn_applying(move |write| {
+            let db = Arc::clone(&db);
+            async move {
+                match write {
+                    Write::BondState(vote_pubkey, state) => {
+                        ("bond state", db.save_bond_state(&vote_pubkey, &state).await)
+                    }
+                    Write::PruneBondStates(vote_pubkey, oldest_kept) => (
+                        "bond state pruning",
+                        db.prune_bond_states(&vote_pubkey, oldest_kept).await,
+                    ),
+                    Write::Receipt(receipt) => ("receipt", db.insert_receipt(&receipt).await),
+                }
+            }
+            .boxed()
+        })
+    }
+
+    /// Spawns the task applying the writes with `apply`, which names what it wrote.
+    fn spawn_applying<F>(apply: F) -> (Self, JoinHandle<()>)
+    where
+        F: Fn(Write) -> BoxFuture<'static, (&'static str, Result<()>)> + Send + 'static,
+    {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(async move {
+            let mut failure = None;
+            while let Some(message) = receiver.recv().await {
+                let write = match message {
+                    Message::Write(write) => write,
+                    Message::Flush(done) => {
+                        // The flusher may have given up waiting.
+                        let _ = done.send(failure.take().map_or(Ok(()), Err));
+                        continue;
+                    }
+                };
+                let (what, result) = apply(write).await;
+                if let Err(e) = result {
+                    error!("Failed to write {} to the state database: {}", what, e);
+                    datapoint_error!(
+                        "state_db_error",
+                        ("write", what.to_string(), String),
+                        ("error", e.to_string(), String),
+                    );
+                    failure.get_or_insert(format!("Failed to write {}: {}", what, e));
+                }
+            }
+        });
+        (Self { sender }, task)
+    }
+
+    /// Waits until every write queued so far was applied. Fails if one of them failed since the
+    /// last flush, or the writer stopped, so whatever depends on them can be held back.
+    pub async fn flush(&self) -> Result<()> {
+        let stopped = || anyhow!("The state database writer stopped");
+        let (done, applied) = oneshot::channel();
+        self.sender
+            .send(Message::Flush(done))
+            .map_err(|_| stopped())?;
+        applied
+            .await
+            .map_err(|_| stopped())?
+            .map_err(|e| anyhow!(e))
+    }
+
+    /// A writer keeping the bond states in memory, with its task so tests can kill it.
+    #[cfg(test)]
+    pub fn in_memory() -> (Self, Arc<std::sync::Mutex<Vec<BondState>>>, JoinHandle<()>) {
+        let written = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let states = Arc::clone(&written);
+        let (writer, task) = Self::spawn_applying(move |write| {
+            if let Write::BondState(_, state) = write {
+                states.lock().unwrap().push(state);
+            }
+            async { ("bond state", Ok(())) }.boxed()
+        });
+        (writer, written, task)
+    }
+
+    pub fn save_bond_state(&self, vote_pubkey: &str, state: BondState) {
+        self.send(Write::BondState(vote_pubkey.to_string(), state));
+    }
+
+    pub fn prune_bond_states(&self, vote_pubkey: &str, oldest_kept: u64) {
+        self.send(Write::PruneBondStates(vote_pubkey.to_string(), oldest_kept));
+    }
+
+    pub fn insert_receipt(&self, receipt: &PaymentReceipt) {
+        self.send(Write::Receipt(receipt.clone()));
+    }
+
+    fn send(&self, write: Write) {
+        if self.sender.send(Message::Write(write)).is_err() {
+            error!("State database writer stopped, dropping a write");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        let versions: Vec<i32> = MIGRATIONS.iter().map(|(version, _)| *version).collect();
+        let expected: Vec<i32> = (1..=MIGRATIONS.len() as i32).collect();
+        assert_eq!(versions, expected);
+    }
+}
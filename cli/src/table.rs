@@ -0,0 +1,216 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use clap::Args;
+
+static OPTIONS: OnceLock<TableArgs> = OnceLock::new();
+
+/// Options of the tables printed by the list, status and summary commands.
+#[derive(Clone, Debug, Default, Args)]
+pub struct TableArgs {
+    /// Table columns to print, in order, by header, e.g. `bond,total`. Headers match ignoring
+    /// case and their unit, so `total` selects `TOTAL (SOL)`. [default: every column]
+    #[arg(long, global = true, env = "TABLE_COLUMNS", value_delimiter = ',')]
+    pub columns: Vec<String>,
+    /// Print table headers without bold. They are also plain when NO_COLOR is set or the output
+    /// isn't a terminal.
+    #[arg(long, global = true, env)]
+    pub no_color: bool,
+    /// Cut table cells longer than this many characters short with `…`.
+    #[arg(long, global = true, env)]
+    pub max_column_width: Option<usize>,
+}
+
+/// Installs the options used by every table printed afterwards.
+pub fn init_tables(args: &TableArgs) -> Result<()> {
+    if args.max_column_width == Some(0) {
+        return Err(anyhow!("--max-column-width must be at least 1"));
+    }
+    OPTIONS
+        .set(args.clone())
+        .map_err(|_| anyhow!("The table options are already configured"))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// A header as matched by --columns: lowercase, without its unit, words joined by `_`.
+fn column_key(name: &str) -> String {
+    let name = name.split_once(" (").map_or(name, |(name, _)| name);
+    name.trim().to_lowercase().replace([' ', '-'], "_")
+}
+
+fn truncate(cell: &str, max_width: Option<usize>) -> String {
+    match max_width {
+        Some(max_width) if cell.chars().count() > max_width => {
+            let mut cut: String = cell.chars().take(max_width - 1).collect();
+            cut.push('…');
+            cut
+        }
+        _ => cell.to_string(),
+    }
+}
+
+/// Rows of cells printed in aligned columns under a header.
+#[derive(Debug)]
+pub struct Table {
+    headers: Vec<(&'static str, Align)>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[(&'static str, Align)]) -> Self {
+        Self {
+            headers: headers.to_vec(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds a row, one cell per header.
+    pub fn push(&mut self, row: Vec<String>) {
+        debug_assert_eq!(row.len(), self.headers.len());
+        self.rows.push(row);
+    }
+
+    /// Indexes of the columns selected by `columns`, every column when empty.
+    fn selected(&self, columns: &[String]) -> Result<Vec<usize>> {
+        if columns.is_empty() {
+            return Ok((0..self.headers.len()).collect());
+        }
+        columns
+            .iter()
+            .map(|column| {
+                self.headers
+                    .iter()
+                    .position(|(header, _)| column_key(header) == column_key(column))
+                    .ok_or_else(|| {
+                        let known: Vec<String> = self
+                            .headers
+                            .iter()
+                            .map(|(header, _)| column_key(header))
+                            .collect();
+                        anyhow!(
+                            "Unknown column {}, expected one of: {}",
+                            column,
+                            known.join(", ")
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    fn render(&self, options: &TableArgs, color: bool) -> Result<String> {
+        let selected = self.selected(&options.columns)?;
+        let cells = |row: &[String]| -> Vec<String> {
+            selected
+                .iter()
+                .map(|index| truncate(&row[*index], options.max_column_width))
+                .collect()
+        };
+        let header: Vec<String> = cells(
+            &self
+                .headers
+                .iter()
+                .map(|(header, _)| header.to_string())
+                .collect::<Vec<_>>(),
+        );
+        let rows: Vec<Vec<String>> = self.rows.iter().map(|row| cells(row)).collect();
+        let widths: Vec<usize> = (0..selected.len())
+            .map(|column| {
+                std::iter::once(&header)
+                    .chain(rows.iter())
+                    .map(|row| row[column].chars().count())
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let line = |row: &[String]| -> String {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&selected)
+                .zip(&widths)
+                .map(|((cell, index), width)| match self.headers[*index].1 {
+                    Align::Left => format!("{:<width$}", cell, width = width),
+                    Align::Right => format!("{:>width$}", cell, width = width),
+                })
+                .collect();
+            cells.join("  ").trim_end().to_string()
+        };
+        let header = line(&header);
+        let mut output = if color {
+            format!("\x1b[1m{}\x1b[0m\n", header)
+        } else {
+            format!("{}\n", header)
+        };
+        let rule_width = widths.iter().sum::<usize>() + 2 * widths.len().saturating_sub(1);
+        output.push_str(&"─".repeat(rule_width));
+        output.push('\n');
+        for row in rows.iter() {
+            output.push_str(&line(row));
+            output.push('\n');
+        }
+        Ok(output)
+    }
+
+    /// Prints the table with the options from the command line.
+    pub fn print(&self) -> Result<()> {
+        let options = OPTIONS.get().cloned().unwrap_or_default();
+        let color = !options.no_color
+            && std::env::var_os("NO_COLOR").is_none()
+            && std::io::stdout().is_terminal();
+        print!("{}", self.render(&options, color)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let mut table = Table::new(&[
+            ("BOND", Align::Left),
+            ("BONDS", Align::Right),
+            ("TOTAL (SOL)", Align::Right),
+        ]);
+        table.push(vec![
+            "7xKXtg2CW87d97TXJSDpbD5jBkheTqA83TZRuJosgAsU".to_string(),
+            "3".to_string(),
+            "1.500000000".to_string(),
+        ]);
+        table.push(vec![
+            "Acme".to_string(),
+            "12".to_string(),
+            "10.000000000".to_string(),
+        ]);
+        table
+    }
+
+    #[test]
+    fn test_render() {
+        let options = TableArgs {
+            columns: vec!["total".to_string(), "Bond".to_string()],
+            no_color: true,
+            max_column_width: Some(8),
+        };
+        assert_eq!(
+            table().render(&options, false).unwrap(),
+            "TOTAL (…  BOND\n\
+             ──────────────────\n\
+             1.50000…  7xKXtg2…\n\
+             10.0000…  Acme\n"
+        );
+        let color = table().render(&TableArgs::default(), true).unwrap();
+        assert!(color.starts_with("\x1b[1mBOND"));
+
+        let unknown = TableArgs {
+            columns: vec!["stake".to_string()],
+            ..TableArgs::default()
+        };
+        assert!(table().render(&unknown, false).is_err());
+    }
+}
@@ -1,63 +1,760 @@
-use anchor_client::{Client, Cluster};
+use anchor_client::Cluster;
+use anchor_lang::{InstructionData, ToAccountMetas};
 use anyhow::{anyhow, Result};
+use clap::{Args, ValueEnum};
+use log::{debug, warn};
+use pye_core_cpi::pye_core;
 use pye_core_cpi::pye_core::accounts::SoloValidatorBond as SoloValidatorPyeAccount;
-use pye_core_cpi::pye_core::ID as PYE_PROGRAM_ID;
-use solana_sdk::message::Message;
-use solana_sdk::signer::keypair::read_keypair_file;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcSendTransactionConfig, RpcTransactionConfig};
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::{v0, AddressLookupTableAccount, Message, VersionedMessage};
+use solana_sdk::signature::Signature;
 use solana_sdk::signer::Signer;
 use solana_sdk::system_instruction::transfer;
-use solana_sdk::transaction::Transaction;
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use solana_sdk::transaction::{TransactionError, VersionedTransaction};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey, pubkey::Pubkey};
+use solana_sdk::{stake, system_program, sysvar};
+use solana_transaction_status_client_types::option_serializer::OptionSerializer;
+use solana_transaction_status_client_types::UiTransactionEncoding;
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::sleep;
 
+use crate::exit_code::rpc_error;
+use crate::keypairs::SharedSigner;
+use crate::lookup_tables::{extend_table, fetch_lookup_table, lookup_candidates};
+use crate::metrics::datapoint_info;
+use crate::rpc_utils::new_rpc_client;
+
+/// How often an unconfirmed transaction is rebroadcast.
+const REBROADCAST_INTERVAL: Duration = Duration::from_secs(2);
+/// Blockhashes tried before giving up on a transfer.
+const MAX_BLOCKHASH_ATTEMPTS: usize = 3;
+/// Status checks made for a transaction that was processed just before its blockhash expired.
+const FINAL_STATUS_CHECKS: usize = 15;
+/// Status or block height lookups in a row that may fail before a send gives up on tracking its
+/// transaction.
+const MAX_STATUS_FAILURES: u32 = 10;
+/// Lookups of a landed transaction's fee, which may take a moment to be served by the RPC.
+const FEE_LOOKUPS: usize = 5;
+/// Lookups of a landed payment at finalized commitment before it is deemed dropped, about a
+/// minute apart from the first.
+const FINALIZATION_CHECKS: usize = 30;
+
+const GLOBAL_SETTINGS_SEED: &[u8] = b"global_settings";
+const STAKE_CONFIG_ID: Pubkey = pubkey!("StakeConfig11111111111111111111111111111111");
+const MEMO_PROGRAM_ID: Pubkey = pubkey!("MemoSq4gqABAXKb96qnH8TyNYNxDmfQb1c1Dd1hAm7w");
+
+/// How excess rewards are paid into a bond.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PaymentMethod {
+    /// Plain system transfer to the bond account.
+    Transfer,
+    /// System transfer followed by the pye program's solo_validator_delegate_tips, which stakes
+    /// the payment into the bond's stake account.
+    #[default]
+    DelegateTips,
+}
+
+impl fmt::Display for PaymentMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentMethod::Transfer => write!(f, "transfer"),
+            PaymentMethod::DelegateTips => write!(f, "delegate-tips"),
+        }
+    }
+}
+
+/// Options controlling how excess reward payments are built and broadcast.
+#[derive(Clone, Debug, Default, Args)]
+pub struct SendOptions {
+    /// How excess rewards are paid into the bond.
+    #[arg(long, env, value_enum, default_value_t = PaymentMethod::DelegateTips)]
+    pub payment_method: PaymentMethod,
+    /// Skip the RPC node's preflight checks when sending. The transaction is still simulated
+    /// before it is sent.
+    #[arg(long, env)]
+    pub skip_preflight: bool,
+    /// Maximum number of times the RPC node retries sending the transaction [default: the
+    /// node's own retry policy]
+    #[arg(long, env)]
+    pub max_retries: Option<usize>,
+    /// Transaction format used when sending.
+    #[arg(long, env, value_enum, default_value_t = TransactionVersion::Legacy)]
+    pub tx_version: TransactionVersion,
+    /// Address lookup table used to compress v0 transactions.
+    #[arg(long, env)]
+    pub lookup_table: Option<Pubkey>,
+    /// Add accounts missing from the lookup table before sending. The fee payer must be the
+    /// table's authority.
+    #[arg(long, env, requires = "lookup_table")]
+    pub extend_lookup_table: bool,
+    /// Don't wait for landed payments to finalize and check the bond's balance change, e.g. to
+    /// pay a large fleet faster. Payments are then only known to be confirmed.
+    #[arg(long, env)]
+    pub skip_payment_verification: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TransactionVersion {
+    #[default]
+    Legacy,
+    V0,
+}
+
+/// Simulates `tx` and fails with the program logs if it wouldn't succeed.
+async fn simulate(client: &RpcClient, tx: &VersionedTransaction) -> Result<()> {
+    let simulation = client
+        .simulate_transaction(tx)
+        .await
+        .map_err(|e| rpc_error("Failed to simulate transaction", e))?
+        .value;
+    if let Some(err) = simulation.err {
+        let logs = simulation.logs.unwrap_or_default();
+        return Err(anyhow!(
+            "Transaction simulation failed: {}\n{}",
+            err,
+            logs.join("\n")
+        ));
+    }
+    Ok(())
+}
+
+/// Builds the pye program's instruction that stakes the lamports sitting in the bond into its
+/// stake account, so paid rewards accrue to bond holders instead of idling in the bond. The
+/// program only delegates into the main stake account; transient stake is merged at maturity.
+pub fn delegate_tips_instruction(
+    program_id: &Pubkey,
+    bond_pubkey: &Pubkey,
+    bond: &SoloValidatorPyeAccount,
+) -> Instruction {
+    let (global_settings, _) = Pubkey::find_program_address(&[GLOBAL_SETTINGS_SEED], program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: pye_core::client::accounts::SoloValidatorDelegateTips {
+            bond: *bond_pubkey,
+            validator_vote_account: bond.validator_vote_account,
+            stake_account: bond.stake_account,
+            clock: sysvar::clock::ID,
+            stake_program: stake::program::ID,
+            stake_history: sysvar::stake_history::ID,
+            stake_config: STAKE_CONFIG_ID,
+            global_settings,
+            rent: sysvar::rent::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: pye_core::client::args::SoloValidatorDelegateTips {}.data(),
+    }
+}
+
+/// Memo identifying the calculation a payment was made from, e.g. `pye:800:<fingerprint>`.
+pub fn fingerprint_memo(epoch: u64, fingerprint: &str) -> String {
+    format!("pye:{}:{}", epoch, fingerprint)
+}
+
+/// Builds an SPL memo instruction. It has no signers, so anyone can check the memo against a
+/// recomputation without it authorizing anything.
+fn memo_instruction(memo: &str) -> Instruction {
+    Instruction::new_with_bytes(MEMO_PROGRAM_ID, memo.as_bytes(), vec![])
+}
+
+/// A landed excess rewards payment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Payment {
+    pub signature: Signature,
+    /// Fee charged for the transaction in lamports, priority fees included, if the RPC served it.
+    pub fee: Option<u64>,
+    /// Slot the transaction was confirmed in, if the RPC served it.
+    pub slot: Option<u64>,
+    /// The check of the payment against the finalized ledger, unless it was skipped.
+    pub verification: Option<PaymentVerification>,
+}
+
+/// Outcome of checking a landed payment against the finalized ledger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaymentVerification {
+    /// The transaction finalized and credited the bond with the full payment.
+    Verified,
+    /// The transaction finalized but credited the bond with `credited` lamports instead.
+    Mismatch { credited: i64 },
+    /// The transaction didn't finalize in time, e.g. because it landed on a dropped fork.
+    NotFinalized,
+}
+
+impl fmt::Display for PaymentVerification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentVerification::Verified => write!(f, "verified"),
+            PaymentVerification::Mismatch { credited } => {
+                write!(f, "mismatch ({} lamports credited)", credited)
+            }
+            PaymentVerification::NotFinalized => write!(f, "not finalized"),
+        }
+    }
+}
+
+impl PaymentVerification {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PaymentVerification::Verified => "verified",
+            PaymentVerification::Mismatch { .. } => "mismatch",
+            PaymentVerification::NotFinalized => "not_finalized",
+        }
+    }
+}
+
+/// Lamports a transaction credited to `accounts` in total, from its balances before and after.
+/// `account_keys` lists the transaction's accounts in the order of the balances.
+fn credited_lamports(
+    account_keys: &[Pubkey],
+    pre_balances: &[u64],
+    post_balances: &[u64],
+    accounts: &[Pubkey],
+) -> i64 {
+    accounts
+        .iter()
+        .filter_map(|account| {
+            let index = account_keys.iter().position(|key| key == account)?;
+            Some(*post_balances.get(index)? as i64 - *pre_balances.get(index)? as i64)
+        })
+        .sum()
+}
+
+/// The fee a landed transaction was charged and the slot it was confirmed in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Landed {
+    fee: u64,
+    slot: u64,
+}
+
+/// Fetches a transaction at finalized commitment, returning its fee and slot and the lamports it
+/// credited to `accounts`.
+async fn fetch_finalized_credit(
+    client: &RpcClient,
+    signature: &Signature,
+    accounts: &[Pubkey],
+) -> Result<(Landed, i64)> {
+    let transaction = client
+        .get_transaction_with_config(
+            signature,
+            RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(CommitmentConfig::finalized()),
+                max_supported_transaction_version: Some(0),
+            },
+        )
+        .await?;
+    let meta = transaction
+        .transaction
+        .meta
+        .ok_or_else(|| anyhow!("The RPC served no transaction status"))?;
+    let decoded = transaction
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| anyhow!("Failed to decode the transaction"))?;
+    // Balances list the static keys, then the writable and readonly keys loaded from lookup
+    // tables.
+    let mut account_keys = decoded.message.static_account_keys().to_vec();
+    if let OptionSerializer::Some(loaded) = &meta.loaded_addresses {
+        for key in loaded.writable.iter().chain(loaded.readonly.iter()) {
+            account_keys.push(Pubkey::from_str(key)?);
+        }
+    }
+    let credited = credited_lamports(
+        &account_keys,
+        &meta.pre_balances,
+        &meta.post_balances,
+        accounts,
+    );
+    let landed = Landed {
+        fee: meta.fee,
+        slot: transaction.slot,
+    };
+    Ok((landed, credited))
+}
+
+/// Waits for a landed payment to finalize and checks that it credited `accounts` with
+/// `lamports`. A confirmed transaction can still be dropped with its fork, so only the finalized
+/// balance change proves the payment. Returns the transaction's fee and slot along with the
+/// result.
+async fn verify_payment(
+    client: &RpcClient,
+    signature: &Signature,
+    accounts: &[Pubkey],
+    lamports: u64,
+) -> (Option<Landed>, PaymentVerification) {
+    for _ in 0..FINALIZATION_CHECKS {
+        match fetch_finalized_credit(client, signature, accounts).await {
+            Ok((landed, credited)) if credited == lamports as i64 => {
+                return (Some(landed), PaymentVerification::Verified)
+            }
+            Ok((landed, credited)) => {
+                return (Some(landed), PaymentVerification::Mismatch { credited })
+            }
+            // Not finalized yet, or not served by the RPC yet.
+            Err(e) => debug!("Transaction {} not finalized yet: {}", signature, e),
+        }
+        sleep(REBROADCAST_INTERVAL).await;
+    }
+    (None, PaymentVerification::NotFinalized)
+}
+
+/// Looks up the fee charged for a landed transaction and the slot it was confirmed in, retrying
+/// while the RPC doesn't serve it yet.
+async fn fetch_landed(client: &RpcClient, signature: &Signature) -> Option<Landed> {
+    for _ in 0..FEE_LOOKUPS {
+        let transaction = client
+            .get_transaction_with_config(
+                signature,
+                RpcTransactionConfig {
+                    encoding: Some(UiTransactionEncoding::Base64),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    max_supported_transaction_version: Some(0),
+                },
+            )
+            .await;
+        match transaction {
+            Ok(transaction) => {
+                return transaction.transaction.meta.map(|meta| Landed {
+                    fee: meta.fee,
+                    slot: transaction.slot,
+                })
+            }
+            Err(e) => warn!(
+                "Failed to fetch the fee of transaction {}: {}",
+                signature, e
+            ),
+        }
+        sleep(REBROADCAST_INTERVAL).await;
+    }
+    None
+}
+
+/// Transfers `excess_rewards` from the payer to the pye account. When `fee_payer` is set, it
+/// pays the transaction fee and co-signs, so the payer only funds the rewards. A `memo`, such
+/// as a [`fingerprint_memo`], is attached to the transaction.
 pub async fn transfer_excess_rewards(
-    payer_file_path: String,
+    payer: SharedSigner,
+    fee_payer: Option<SharedSigner>,
     cluster: Cluster,
+    program_id: &Pubkey,
     pye_account_pubkey: &Pubkey,
-    _pye_account: &SoloValidatorPyeAccount,
+    pye_account: &SoloValidatorPyeAccount,
     excess_rewards: u64,
-) -> Result<()> {
+    memo: Option<&str>,
+    send_options: &SendOptions,
+) -> Result<Payment> {
     if excess_rewards == 0 {
         return Err(anyhow!("No excess rewards to transfer"));
     }
 
-    let payer = Arc::new(read_keypair_file(&payer_file_path).map_err(|e| {
-        anyhow!(
-            "Failed to read payer keypair from {}: {}",
-            payer_file_path,
-            e
-        )
-    })?);
     let payer_pubkey = payer.pubkey();
     println!("Payer: {:?}", payer_pubkey);
+    let fee_payer = fee_payer.unwrap_or_else(|| Arc::clone(&payer));
+    let fee_payer_pubkey = fee_payer.pubkey();
+    if fee_payer_pubkey != payer_pubkey {
+        println!("Fee payer: {:?}", fee_payer_pubkey);
+    }
 
-    let client =
-        Client::new_with_options(cluster, Arc::clone(&payer), CommitmentConfig::processed());
+    let client = new_rpc_client(
+        cluster.url().to_string(),
+        CommitmentConfig::processed(),
+        None,
+        None,
+    );
 
     // TODO: check balance and send notification if not enough balance
 
-    let program = client.program(PYE_PROGRAM_ID)?;
-    let (recent_blockhash, _last_valid_block_height) = program
-        .rpc()
-        .get_latest_blockhash_with_commitment(CommitmentConfig::finalized())
-        .await
-        .map_err(|e| anyhow!("Failed to fetch latest blockhash: {}", e))?;
-
     let mut transfer_ixs = vec![];
 
     // Transfer excess rewards from payer to stake account.
     let transfer_ix = transfer(&payer_pubkey, pye_account_pubkey, excess_rewards);
     transfer_ixs.push(transfer_ix);
+    if send_options.payment_method == PaymentMethod::DelegateTips {
+        transfer_ixs.push(delegate_tips_instruction(
+            program_id,
+            pye_account_pubkey,
+            pye_account,
+        ));
+    }
+    if let Some(memo) = memo {
+        transfer_ixs.push(memo_instruction(memo));
+    }
+
+    let mut signers: Vec<&dyn Signer> = vec![&*fee_payer];
+    if fee_payer_pubkey != payer_pubkey {
+        signers.push(&*payer);
+    }
 
-    let message = Message::new(&[transfer_ixs].concat(), Some(&payer_pubkey));
+    let (signature, attempts) =
+        send_instructions(&client, &transfer_ixs, &signers, send_options).await?;
+    println!("Transaction Landed: {}\n", signature);
+    let (landed, verification) = if send_options.skip_payment_verification {
+        (fetch_landed(&client, &signature).await, None)
+    } else {
+        // Tips delegated in the same transaction move from the bond into its stake account.
+        let credited_accounts = [*pye_account_pubkey, pye_account.stake_account];
+        let (landed, verification) =
+            verify_payment(&client, &signature, &credited_accounts, excess_rewards).await;
+        if verification != PaymentVerification::Verified {
+            warn!("Payment {} is {}", signature, verification);
+        }
+        (landed, Some(verification))
+    };
+    let (fee, slot) = (
+        landed.map(|landed| landed.fee),
+        landed.map(|landed| landed.slot),
+    );
+    datapoint_info!(
+        "excess_reward_payment",
+        ("pye_account", pye_account_pubkey.to_string(), String),
+        ("lamports", excess_rewards as i64, i64),
+        ("signature", signature.to_string(), String),
+        (
+            "payment_method",
+            send_options.payment_method.to_string(),
+            String
+        ),
+        ("attempts", attempts as i64, i64),
+        ("fee", fee.unwrap_or_default() as i64, i64),
+        ("fee_known", fee.is_some(), bool),
+        ("slot", slot.unwrap_or_default() as i64, i64),
+        ("memo", memo.unwrap_or_default().to_string(), String),
+        (
+            "verification",
+            verification.map_or("skipped", |verification| verification.name()),
+            String
+        ),
+    );
+    Ok(Payment {
+        signature,
+        fee,
+        slot,
+        verification,
+    })
+}
 
-    let tx = Transaction::new(&[payer], message, recent_blockhash);
-    let sig = program
-        .rpc()
-        .send_and_confirm_transaction_with_spinner(&tx)
-        .await
-        .map_err(|e| anyhow!("Failed to send and confirm transaction: {}", e))?;
-    println!("Transaction Sent: {}\n", sig);
+/// Compiles `instructions` into a signed transaction of the requested version.
+fn build_transaction(
+    instructions: &[Instruction],
+    signers: &[&dyn Signer],
+    recent_blockhash: Hash,
+    version: TransactionVersion,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<VersionedTransaction> {
+    let fee_payer = signers
+        .first()
+        .ok_or_else(|| anyhow!("A transaction needs at least one signer"))?
+        .pubkey();
+    let message = match version {
+        TransactionVersion::Legacy => VersionedMessage::Legacy(Message::new_with_blockhash(
+            instructions,
+            Some(&fee_payer),
+            &recent_blockhash,
+        )),
+        TransactionVersion::V0 => VersionedMessage::V0(
+            v0::Message::try_compile(&fee_payer, instructions, lookup_tables, recent_blockhash)
+                .map_err(|e| anyhow!("Failed to compile v0 message: {}", e))?,
+        ),
+    };
+    VersionedTransaction::try_new(message, signers)
+        .map_err(|e| anyhow!("Failed to sign transaction: {}", e))
+}
+
+/// Signs and sends `instructions`, with the first of `signers` paying the fee, and returns the
+/// landed signature with the number of blockhashes it took.
+pub async fn send_instructions(
+    client: &RpcClient,
+    instructions: &[Instruction],
+    signers: &[&dyn Signer],
+    send_options: &SendOptions,
+) -> Result<(Signature, usize)> {
+    let lookup_tables = match send_options.lookup_table {
+        Some(_) if send_options.tx_version == TransactionVersion::Legacy => {
+            return Err(anyhow!("--lookup-table requires --tx-version v0"));
+        }
+        Some(address) => {
+            let table = fetch_lookup_table(client, &address).await?;
+            let table = if send_options.extend_lookup_table {
+                let signer_pubkeys: Vec<Pubkey> =
+                    signers.iter().map(|signer| signer.pubkey()).collect();
+                extend_table(
+                    client,
+                    table,
+                    signers[0],
+                    &lookup_candidates(instructions, &signer_pubkeys),
+                    send_options,
+                )
+                .await?
+            } else {
+                table
+            };
+            vec![table]
+        }
+        None => vec![],
+    };
+    send_versioned(
+        client,
+        instructions,
+        signers,
+        send_options,
+        send_options.tx_version,
+        &lookup_tables,
+    )
+    .await
+}
+
+/// Sends `instructions` as a transaction of `version` compiled against `lookup_tables`,
+/// re-signing with a fresh blockhash whenever one expires before the transaction lands.
+pub async fn send_versioned(
+    client: &RpcClient,
+    instructions: &[Instruction],
+    signers: &[&dyn Signer],
+    send_options: &SendOptions,
+    version: TransactionVersion,
+    lookup_tables: &[AddressLookupTableAccount],
+) -> Result<(Signature, usize)> {
+    let send_config = RpcSendTransactionConfig {
+        skip_preflight: send_options.skip_preflight,
+        preflight_commitment: Some(CommitmentConfig::processed().commitment),
+        max_retries: send_options.max_retries,
+        ..RpcSendTransactionConfig::default()
+    };
+
+    // A transaction that hasn't landed by the time its blockhash expires can never land, so it is
+    // safe to re-sign the same instructions with a fresh blockhash without risking a double
+    // payment.
+    for attempt in 1..=MAX_BLOCKHASH_ATTEMPTS {
+        let (recent_blockhash, last_valid_block_height) = client
+            .get_latest_blockhash_with_commitment(CommitmentConfig::finalized())
+            .await
+            .map_err(|e| rpc_error("Failed to fetch latest blockhash", e))?;
+        let tx = build_transaction(
+            instructions,
+            signers,
+            recent_blockhash,
+            version,
+            lookup_tables,
+        )?;
+        simulate(client, &tx).await?;
+
+        match send_until_expired(client, &tx, last_valid_block_height, send_config).await? {
+            SendOutcome::Landed(signature) => return Ok((signature, attempt)),
+            SendOutcome::Failed(err) => {
+                return Err(anyhow!("Transaction {} failed: {}", tx.signatures[0], err))
+            }
+            SendOutcome::Expired => warn!(
+                "Transaction {} expired at block height {} without landing (attempt {}/{})",
+                tx.signatures[0], last_valid_block_height, attempt, MAX_BLOCKHASH_ATTEMPTS
+            ),
+        }
+    }
+    Err(anyhow!(
+        "Transaction didn't land after {} blockhashes",
+        MAX_BLOCKHASH_ATTEMPTS
+    ))
+}
+
+/// Final state of a transaction broadcast until its blockhash expired.
+enum SendOutcome {
+    Landed(Signature),
+    Failed(TransactionError),
+    Expired,
+}
+
+/// The error of a send that stopped tracking its transaction before knowing whether it landed,
+/// e.g. because the RPC kept failing to report its status. Unlike other send errors, the payment
+/// may have been made, so it must be checked before being sent again.
+#[derive(Debug, Error)]
+#[error("Transaction {signature} may have landed, check it before retrying: {reason}")]
+pub struct OutcomeUnknown {
+    pub signature: Signature,
+    pub reason: String,
+}
 
+/// Rebroadcasts `tx` every few seconds until it is confirmed or its blockhash expires, then checks
+/// the signature's status once more to tell a late landing apart from an expired transaction.
+async fn send_until_expired(
+    client: &RpcClient,
+    tx: &VersionedTransaction,
+    last_valid_block_height: u64,
+    send_config: RpcSendTransactionConfig,
+) -> Result<SendOutcome> {
+    let signature = tx.signatures[0];
+    let mut status_failures = 0;
+    loop {
+        if let Err(e) = client.send_transaction_with_config(tx, send_config).await {
+            warn!("Failed to broadcast transaction {}: {}", signature, e);
+        }
+        sleep(REBROADCAST_INTERVAL).await;
+
+        let expired = match confirmed_outcome(client, &signature, false).await {
+            Ok(Some(outcome)) => return Ok(outcome),
+            Ok(None) => client
+                .get_block_height_with_commitment(CommitmentConfig::confirmed())
+                .await
+                .map(|block_height| block_height > last_valid_block_height)
+                .map_err(|e| rpc_error("Failed to fetch block height", e)),
+            Err(e) => Err(e),
+        };
+        match expired {
+            Ok(true) => break,
+            Ok(false) => status_failures = 0,
+            Err(e) => status_check_failed(&signature, e, &mut status_failures).await?,
+        }
+    }
+
+    // The blockhash expired: the transaction has either landed by now or never will.
+    let mut checks = 0;
+    while checks < FINAL_STATUS_CHECKS {
+        let processed = match confirmed_outcome(client, &signature, true).await {
+            Ok(Some(outcome)) => return Ok(outcome),
+            Ok(None) => is_processed(client, &signature).await,
+            Err(e) => Err(e),
+        };
+        match processed {
+            Ok(false) => return Ok(SendOutcome::Expired),
+            // Processed but not yet confirmed, wait for it to settle.
+            Ok(true) => {
+                status_failures = 0;
+                checks += 1;
+                sleep(REBROADCAST_INTERVAL).await;
+            }
+            Err(e) => status_check_failed(&signature, e, &mut status_failures).await?,
+        }
+    }
+    Err(OutcomeUnknown {
+        signature,
+        reason: "processed but never confirmed".to_string(),
+    }
+    .into())
+}
+
+/// Logs a failed status or block height lookup for `signature` and backs off before the next
+/// one, giving up with [`OutcomeUnknown`] after [`MAX_STATUS_FAILURES`] in a row.
+async fn status_check_failed(
+    signature: &Signature,
+    error: anyhow::Error,
+    failures: &mut u32,
+) -> Result<()> {
+    *failures += 1;
+    if *failures >= MAX_STATUS_FAILURES {
+        return Err(OutcomeUnknown {
+            signature: *signature,
+            reason: format!("{} status lookups in a row failed: {}", failures, error),
+        }
+        .into());
+    }
+    warn!(
+        "Failed to check the status of transaction {} ({}/{}): {}",
+        signature, failures, MAX_STATUS_FAILURES, error
+    );
+    sleep(REBROADCAST_INTERVAL * *failures).await;
     Ok(())
 }
+
+/// Returns Landed once the signature is confirmed, and Failed if it landed but failed.
+async fn confirmed_outcome(
+    client: &RpcClient,
+    signature: &Signature,
+    search_history: bool,
+) -> Result<Option<SendOutcome>> {
+    let statuses = if search_history {
+        client
+            .get_signature_statuses_with_history(&[*signature])
+            .await
+    } else {
+        client.get_signature_statuses(&[*signature]).await
+    }
+    .map_err(|e| rpc_error("Failed to fetch signature status", e))?
+    .value;
+    let Some(Some(status)) = statuses.into_iter().next() else {
+        return Ok(None);
+    };
+    if let Some(err) = status.err {
+        return Ok(Some(SendOutcome::Failed(err)));
+    }
+    if status.satisfies_commitment(CommitmentConfig::confirmed()) {
+        return Ok(Some(SendOutcome::Landed(*signature)));
+    }
+    Ok(None)
+}
+
+async fn is_processed(client: &RpcClient, signature: &Signature) -> Result<bool> {
+    let statuses = client
+        .get_signature_statuses_with_history(&[*signature])
+        .await
+        .map_err(|e| rpc_error("Failed to fetch signature status", e))?
+        .value;
+    Ok(statuses.first().is_some_and(|status| status.is_some()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bond_schema::zeroed_bond;
+
+    #[test]
+    fn test_delegate_tips_instruction_matches_idl() {
+        let program_id = pye_core::ID;
+        let bond_pubkey = Pubkey::new_unique();
+        let mut bond = zeroed_bond();
+        bond.validator_vote_account = Pubkey::new_unique();
+        bond.stake_account = Pubkey::new_unique();
+        let instruction = delegate_tips_instruction(&program_id, &bond_pubkey, &bond);
+        assert_eq!(instruction.data, vec![148, 200, 124, 170, 5, 96, 231, 139]);
+        let accounts: Vec<(Pubkey, bool)> = instruction
+            .accounts
+            .iter()
+            .map(|meta| (meta.pubkey, meta.is_writable))
+            .collect();
+        assert_eq!(accounts[0], (bond_pubkey, true));
+        assert_eq!(accounts[1], (bond.validator_vote_account, false));
+        assert_eq!(accounts[2], (bond.stake_account, true));
+        assert_eq!(accounts[6].0, STAKE_CONFIG_ID);
+        assert!(instruction.accounts.iter().all(|meta| !meta.is_signer));
+    }
+
+    #[test]
+    fn test_credited_lamports() {
+        let (payer, bond, stake_account) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let account_keys = [payer, bond, stake_account];
+        // The payment passes through the bond into its stake account.
+        let pre_balances = [10_000_000, 2_000_000, 1_000_000_000];
+        let post_balances = [7_995_000, 2_000_000, 1_002_000_000];
+        assert_eq!(
+            credited_lamports(
+                &account_keys,
+                &pre_balances,
+                &post_balances,
+                &[bond, stake_account]
+            ),
+            2_000_000
+        );
+        assert_eq!(
+            credited_lamports(&account_keys, &pre_balances, &post_balances, &[bond]),
+            0
+        );
+        // A failed transaction only charges its fee.
+        assert_eq!(
+            credited_lamports(
+                &account_keys,
+                &pre_balances,
+                &pre_balances,
+                &[bond, stake_account]
+            ),
+            0
+        );
+    }
+}
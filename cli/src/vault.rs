@@ -0,0 +1,253 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::Client;
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::signer::{Signer, SignerError};
+use tokio::runtime::Handle;
+
+use crate::http::http_client;
+
+/// Scheme of keypair sources held in Vault's transit engine, e.g. `vault://payer?mount=transit`.
+const VAULT_SCHEME: &str = "vault://";
+const DEFAULT_TRANSIT_MOUNT: &str = "transit";
+/// Standard Vault CLI environment variables.
+const VAULT_ADDR_ENV: &str = "VAULT_ADDR";
+const VAULT_TOKEN_ENV: &str = "VAULT_TOKEN";
+const VAULT_NAMESPACE_ENV: &str = "VAULT_NAMESPACE";
+
+/// Location of an ed25519 key in a transit secrets engine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VaultKey {
+    pub mount: String,
+    pub name: String,
+}
+
+/// Parses a `vault://<key>[?mount=<mount>]` keypair source. Returns None for other sources.
+pub fn parse_vault_source(source: &str) -> Option<Result<VaultKey>> {
+    let uri = source.strip_prefix(VAULT_SCHEME)?;
+    let (name, query) = uri.split_once('?').unwrap_or((uri, ""));
+    Some(vault_key(name, query))
+}
+
+fn vault_key(name: &str, query: &str) -> Result<VaultKey> {
+    if name.is_empty() {
+        return Err(anyhow!("Vault keypair source is missing the key name"));
+    }
+    let mut mount = DEFAULT_TRANSIT_MOUNT.to_string();
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        match param.split_once('=') {
+            Some(("mount", value)) if !value.is_empty() => {
+                mount = value.trim_matches('/').to_string()
+            }
+            _ => return Err(anyhow!("Unsupported vault parameter {}", param)),
+        }
+    }
+    Ok(VaultKey {
+        mount,
+        name: name.to_string(),
+    })
+}
+
+/// Decodes a transit signature of the form `vault:v<version>:<base64>`.
+pub fn parse_vault_signature(signature: &str) -> Result<Signature> {
+    let encoded = signature
+        .strip_prefix("vault:v")
+        .and_then(|rest| rest.split_once(':'))
+        .map(|(_, encoded)| encoded)
+        .ok_or_else(|| anyhow!("Unexpected Vault signature format {}", signature))?;
+    let bytes: [u8; 64] = STANDARD
+        .decode(encoded)
+        .map_err(|e| anyhow!("Invalid Vault signature encoding: {}", e))?
+        .try_into()
+        .map_err(|bytes: Vec<u8>| {
+            anyhow!("Vault signature is {} bytes, expected 64", bytes.len())
+        })?;
+    Ok(Signature::from(bytes))
+}
+
+/// Reads the public key of the latest version out of a transit `keys/<name>` response.
+fn parse_vault_pubkey(response: &Value) -> Result<Pubkey> {
+    let data = &response["data"];
+    if data["type"] != "ed25519" {
+        return Err(anyhow!(
+            "Vault key must be of type ed25519, got {}",
+            data["type"]
+        ));
+    }
+    let version = data["latest_version"]
+        .as_u64()
+        .ok_or_else(|| anyhow!("Vault key response is missing latest_version"))?;
+    let encoded = data["keys"][version.to_string()]["public_key"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Vault key response is missing the public key"))?;
+    let bytes: [u8; 32] = STANDARD
+        .decode(encoded)
+        .map_err(|e| anyhow!("Invalid Vault public key encoding: {}", e))?
+        .try_into()
+        .map_err(|bytes: Vec<u8>| {
+            anyhow!("Vault public key is {} bytes, expected 32", bytes.len())
+        })?;
+    Ok(Pubkey::from(bytes))
+}
+
+/// Signs with a key that never leaves Vault's transit engine. The address, token and optional
+/// namespace come from the standard `VAULT_ADDR`, `VAULT_TOKEN` and `VAULT_NAMESPACE` variables.
+pub struct VaultSigner {
+    http: Client,
+    address: String,
+    token: String,
+    namespace: Option<String>,
+    key: VaultKey,
+    pubkey: Pubkey,
+}
+
+impl VaultSigner {
+    pub async fn connect(key: VaultKey) -> Result<Self> {
+        let address = std::env::var(VAULT_ADDR_ENV)
+            .map_err(|_| anyhow!("{} must be set to use a Vault signer", VAULT_ADDR_ENV))?;
+        let token = std::env::var(VAULT_TOKEN_ENV)
+            .map_err(|_| anyhow!("{} must be set to use a Vault signer", VAULT_TOKEN_ENV))?;
+        let mut signer = Self {
+            http: http_client(),
+            address: address.trim_end_matches('/').to_string(),
+            token,
+            namespace: std::env::var(VAULT_NAMESPACE_ENV).ok(),
+            key,
+            pubkey: Pubkey::default(),
+        };
+        let response = signer
+            .request(reqwest::Method::GET, "keys", None)
+            .await
+            .map_err(|e| anyhow!("Failed to read Vault key {}: {}", signer.key.name, e))?;
+        signer.pubkey = parse_vault_pubkey(&response)?;
+        Ok(signer)
+    }
+
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        action: &str,
+        body: Option<Value>,
+    ) -> Result<Value> {
+        let url = format!(
+            "{}/v1/{}/{}/{}",
+            self.address, self.key.mount, action, self.key.name
+        );
+        let mut request = self
+            .http
+            .request(method, url)
+            .header("X-Vault-Token", &self.token);
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        Ok(request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?)
+    }
+
+    async fn sign(&self, message: &[u8]) -> Result<Signature> {
+        let response = self
+            .request(
+                reqwest::Method::POST,
+                "sign",
+                Some(json!({ "input": STANDARD.encode(message) })),
+            )
+            .await?;
+        let signature = parse_vault_signature(
+            response["data"]["signature"]
+                .as_str()
+                .ok_or_else(|| anyhow!("Vault sign response is missing the signature"))?,
+        )?;
+        // Catch a rotated or mismatched key before the transaction is rejected on chain.
+        if !signature.verify(self.pubkey.as_ref(), message) {
+            return Err(anyhow!(
+                "Vault signature doesn't verify against {}",
+                self.pubkey
+            ));
+        }
+        Ok(signature)
+    }
+}
+
+impl Signer for VaultSigner {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        Ok(self.pubkey)
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        // Signers are synchronous, so block this worker thread on the request.
+        tokio::task::block_in_place(|| Handle::current().block_on(self.sign(message)))
+            .map_err(|e| SignerError::Custom(e.to_string()))
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vault_source() {
+        assert!(parse_vault_source("/path/to/id.json").is_none());
+        assert_eq!(
+            parse_vault_source("vault://payer").unwrap().unwrap(),
+            VaultKey {
+                mount: "transit".to_string(),
+                name: "payer".to_string()
+            }
+        );
+        assert_eq!(
+            parse_vault_source("vault://payer?mount=solana-transit/")
+                .unwrap()
+                .unwrap()
+                .mount,
+            "solana-transit"
+        );
+        assert!(parse_vault_source("vault://").unwrap().is_err());
+        assert!(parse_vault_source("vault://payer?token=x")
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_vault_signature() {
+        let encoded = format!("vault:v3:{}", STANDARD.encode([7u8; 64]));
+        assert_eq!(
+            parse_vault_signature(&encoded).unwrap(),
+            Signature::from([7u8; 64])
+        );
+        assert!(parse_vault_signature(&STANDARD.encode([7u8; 64])).is_err());
+        assert!(
+            parse_vault_signature(&format!("vault:v1:{}", STANDARD.encode([7u8; 32]))).is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_vault_pubkey() {
+        let pubkey = Pubkey::new_unique();
+        let response = json!({
+            "data": {
+                "type": "ed25519",
+                "latest_version": 2,
+                "keys": {
+                    "1": { "public_key": STANDARD.encode([1u8; 32]) },
+                    "2": { "public_key": STANDARD.encode(pubkey.to_bytes()) },
+                },
+            }
+        });
+        assert_eq!(parse_vault_pubkey(&response).unwrap(), pubkey);
+        let response = json!({ "data": { "type": "aes256-gcm96" } });
+        assert!(parse_vault_pubkey(&response).is_err());
+    }
+}
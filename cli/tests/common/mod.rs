@@ -0,0 +1,372 @@
+//! Harness for running pye-cli end-to-end against a local `solana-test-validator`.
+//!
+//! Bond accounts are written into genesis with `--account`, so the harness does not depend on
+//! the pye program binary. When `PYE_CORE_SO` points at a build of the program it is loaded at
+//! the canonical program ID as well, so instructions against it can be exercised.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signer::keypair::{write_keypair_file, Keypair};
+use solana_sdk::signer::Signer;
+use solana_sdk::stake::instruction::create_account_and_delegate_stake;
+use solana_sdk::stake::state::{Authorized, Lockup};
+use solana_sdk::transaction::Transaction;
+use solana_sdk::vote::instruction::{create_account_with_config, CreateVoteAccountConfig};
+use solana_sdk::vote::state::{VoteInit, VoteState};
+
+pub const PYE_PROGRAM_ID: &str = "PYEQZ2qYHPQapnw8Ms8MSPMNzoq59NHHfNwAtuV26wx";
+pub const SLOTS_PER_EPOCH: u64 = 32;
+const SOLO_VALIDATOR_BOND_DISCRIMINATOR: [u8; 8] = [178, 232, 232, 66, 149, 243, 39, 168];
+const SOLO_VALIDATOR_BOND_SPACE: usize = 448;
+
+/// Only one validator can bind the default ports at a time.
+static VALIDATOR_LOCK: Mutex<()> = Mutex::new(());
+
+/// Keys generated before the validator starts, since genesis accounts reference them.
+pub struct TestKeys {
+    pub payer: Keypair,
+    pub node: Keypair,
+    pub vote: Keypair,
+    pub stake: Keypair,
+    pub issuer: Keypair,
+    pub bond: Keypair,
+}
+
+impl TestKeys {
+    pub fn new() -> Self {
+        Self {
+            payer: Keypair::new(),
+            node: Keypair::new(),
+            vote: Keypair::new(),
+            stake: Keypair::new(),
+            issuer: Keypair::new(),
+            bond: Keypair::new(),
+        }
+    }
+}
+
+pub struct TestValidator {
+    child: Child,
+    pub dir: PathBuf,
+    pub rpc_url: String,
+    pub client: RpcClient,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl TestValidator {
+    /// Starts a validator with the given genesis accounts. Returns None (and the test should
+    /// return early) when `solana-test-validator` is not installed.
+    pub fn start(name: &str, accounts: &[(Pubkey, Vec<u8>)]) -> Option<Self> {
+        if Command::new("solana-test-validator")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            eprintln!("solana-test-validator not found on PATH, skipping {}", name);
+            return None;
+        }
+        let guard = VALIDATOR_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let dir = std::env::temp_dir().join(format!("pye-cli-it-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut command = Command::new("solana-test-validator");
+        command
+            .arg("--ledger")
+            .arg(dir.join("ledger"))
+            .args(["--reset", "--quiet"])
+            .args(["--slots-per-epoch", &SLOTS_PER_EPOCH.to_string()]);
+        for (pubkey, data) in accounts {
+            let path = dir.join(format!("{}.json", pubkey));
+            write_account_json(&path, pubkey, data);
+            command.arg("--account").arg(pubkey.to_string()).arg(&path);
+        }
+        if let Ok(program_path) = std::env::var("PYE_CORE_SO") {
+            command.args(["--bpf-program", PYE_PROGRAM_ID, &program_path]);
+        }
+        let child = command
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to spawn solana-test-validator");
+
+        let rpc_url = "http://127.0.0.1:8899".to_string();
+        let client = RpcClient::new_with_commitment(rpc_url.clone(), CommitmentConfig::confirmed());
+        let validator = Self {
+            child,
+            dir,
+            rpc_url,
+            client,
+            _guard: guard,
+        };
+        let deadline = Instant::now() + Duration::from_secs(60);
+        while validator.client.get_health().is_err() {
+            assert!(
+                Instant::now() < deadline,
+                "solana-test-validator did not become healthy"
+            );
+            thread::sleep(Duration::from_millis(500));
+        }
+        Some(validator)
+    }
+
+    pub fn airdrop(&self, to: &Pubkey, lamports: u64) {
+        let sig = self.client.request_airdrop(to, lamports).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while !self.client.confirm_transaction(&sig).unwrap() {
+            assert!(Instant::now() < deadline, "airdrop was not confirmed");
+            thread::sleep(Duration::from_millis(250));
+        }
+    }
+
+    /// Creates the vote account referenced by the test bonds. It never votes, which is fine for
+    /// the decoding and inflation paths; block rewards fall back to zero.
+    pub fn create_vote_account(&self, keys: &TestKeys) {
+        let lamports = self
+            .client
+            .get_minimum_balance_for_rent_exemption(VoteState::size_of())
+            .unwrap();
+        let ixs = create_account_with_config(
+            &keys.payer.pubkey(),
+            &keys.vote.pubkey(),
+            &VoteInit {
+                node_pubkey: keys.node.pubkey(),
+                authorized_voter: keys.node.pubkey(),
+                authorized_withdrawer: keys.payer.pubkey(),
+                commission: 10,
+            },
+            lamports,
+            CreateVoteAccountConfig::default(),
+        );
+        self.send(&ixs, &[&keys.payer, &keys.vote, &keys.node]);
+    }
+
+    pub fn create_delegated_stake(&self, keys: &TestKeys, lamports: u64) {
+        let ixs = create_account_and_delegate_stake(
+            &keys.payer.pubkey(),
+            &keys.stake.pubkey(),
+            &keys.vote.pubkey(),
+            &Authorized::auto(&keys.payer.pubkey()),
+            &Lockup::default(),
+            lamports,
+        );
+        self.send(&ixs, &[&keys.payer, &keys.stake]);
+    }
+
+    fn send(&self, ixs: &[solana_sdk::instruction::Instruction], signers: &[&Keypair]) {
+        let blockhash = self.client.get_latest_blockhash().unwrap();
+        let tx =
+            Transaction::new_signed_with_payer(ixs, Some(&signers[0].pubkey()), signers, blockhash);
+        self.client.send_and_confirm_transaction(&tx).unwrap();
+    }
+
+    pub fn wait_for_epoch(&self, epoch: u64) {
+        let deadline = Instant::now() + Duration::from_secs(SLOTS_PER_EPOCH * 2 * (epoch + 1));
+        while self.client.get_epoch_info().unwrap().epoch < epoch {
+            assert!(Instant::now() < deadline, "epoch {} was not reached", epoch);
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    pub fn write_keypair(&self, name: &str, keypair: &Keypair) -> PathBuf {
+        let path = self.dir.join(format!("{}.json", name));
+        write_keypair_file(keypair, &path).unwrap();
+        path
+    }
+}
+
+impl Drop for TestValidator {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn write_account_json(path: &Path, pubkey: &Pubkey, data: &[u8]) {
+    let json = serde_json::json!({
+        "pubkey": pubkey.to_string(),
+        "account": {
+            "lamports": 10_000_000u64,
+            "data": [base64::engine::general_purpose::STANDARD.encode(data), "base64"],
+            "owner": PYE_PROGRAM_ID,
+            "executable": false,
+            "rentEpoch": 0,
+            "space": data.len(),
+        }
+    });
+    fs::write(path, serde_json::to_vec_pretty(&json).unwrap()).unwrap();
+}
+
+/// Serializes a SoloValidatorBond with the on-chain layout (anchor discriminator + borsh).
+pub fn solo_validator_bond_data(
+    vote: &Pubkey,
+    stake: &Pubkey,
+    issuer: &Pubkey,
+    maturity_ts: i64,
+    commissions_bps: (u16, u16, u16),
+) -> Vec<u8> {
+    let mut data = Vec::with_capacity(SOLO_VALIDATOR_BOND_SPACE);
+    data.extend_from_slice(&SOLO_VALIDATOR_BOND_DISCRIMINATOR);
+    data.extend_from_slice(vote.as_ref());
+    data.extend_from_slice(stake.as_ref());
+    data.extend_from_slice(Pubkey::default().as_ref()); // transient_stake_account
+    data.extend_from_slice(&0i64.to_le_bytes()); // issuance_ts
+    data.extend_from_slice(&maturity_ts.to_le_bytes());
+    data.extend_from_slice(Pubkey::new_unique().as_ref()); // principal_token_mint
+    data.extend_from_slice(Pubkey::new_unique().as_ref()); // yield_token_mint
+    data.push(255); // bump_seed
+    data.push(0); // maturity_handled
+    data.push(0); // completely_unstaked
+    data.push(0); // stake_withdrawn
+    data.extend_from_slice(&[0; 4]); // _padding
+    data.extend_from_slice(&0u64.to_le_bytes()); // transient_lamports
+    data.extend_from_slice(&[0; 24]); // redemption_cache
+    data.extend_from_slice(&0i64.to_le_bytes()); // issuance_close_ts
+    data.extend_from_slice(&commissions_bps.0.to_le_bytes());
+    data.extend_from_slice(&commissions_bps.1.to_le_bytes());
+    data.extend_from_slice(&commissions_bps.2.to_le_bytes());
+    data.extend_from_slice(&[0; 2]); // reward_commissions.padding
+    data.extend_from_slice(issuer.as_ref());
+    data.push(0); // single_depositor: None
+    data.resize(SOLO_VALIDATOR_BOND_SPACE, 0);
+    data
+}
+
+/// Minimal stand-in for the Jito kobe validators API that always reports `vote` as running Jito.
+pub struct MockJitoApi {
+    pub url: String,
+}
+
+impl MockJitoApi {
+    pub fn start(
+        vote: &Pubkey,
+        mev_commission_bps: u64,
+        mev_rewards: u64,
+        active_stake: u64,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/api/v1/validators",
+            listener.local_addr().unwrap()
+        );
+        let body = serde_json::json!({
+            "validators": [{
+                "vote_account": vote.to_string(),
+                "mev_commission_bps": mev_commission_bps,
+                "mev_rewards": mev_rewards,
+                "running_jito": true,
+                "active_stake": active_stake,
+            }]
+        })
+        .to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                respond(stream, &body);
+            }
+        });
+        Self { url }
+    }
+}
+
+fn respond(mut stream: TcpStream, body: &str) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+            content_length = value.trim().parse().unwrap_or(0);
+        }
+    }
+    let mut request_body = vec![0; content_length];
+    let _ = reader.read_exact(&mut request_body);
+    let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+}
+
+pub fn pye_cli() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_pye-cli"));
+    command.env("RUST_LOG", "info");
+    command
+}
+
+pub fn run(command: &mut Command) -> Output {
+    let output = command.output().expect("failed to run pye-cli");
+    if !output.status.success() {
+        eprintln!("stdout:\n{}", String::from_utf8_lossy(&output.stdout));
+        eprintln!("stderr:\n{}", String::from_utf8_lossy(&output.stderr));
+    }
+    output
+}
+
+/// Spawns a long-running command and streams its stderr lines (where env_logger writes).
+pub fn spawn_logging(command: &mut Command) -> (Child, Receiver<String>) {
+    let mut child = command
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn pye-cli");
+    let stderr = child.stderr.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    (child, receiver)
+}
+
+/// The first receipt under `dir` recording a payment that landed, from `--receipts-dir`.
+pub fn find_paid_receipt(dir: &Path) -> Option<serde_json::Value> {
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        let receipt = if path.is_dir() {
+            find_paid_receipt(&path)
+        } else {
+            fs::read(&path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+                .filter(|receipt| receipt["payment"] == "paid")
+        };
+        if receipt.is_some() {
+            return receipt;
+        }
+    }
+    None
+}
+
+pub fn wait_for_line(lines: &Receiver<String>, needle: &str, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match lines.recv_timeout(remaining) {
+            Ok(line) if line.contains(needle) => return true,
+            Ok(_) => continue,
+            Err(_) => return false,
+        }
+    }
+    false
+}
@@ -0,0 +1,128 @@
+//! End-to-end tests against `solana-test-validator`. Each test skips itself when the validator
+//! isn't on PATH, or when what else it needs, like `PYE_CORE_SO`, isn't set, so `cargo test`
+//! runs them wherever the validator is installed. Validators are started one at a time.
+
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use solana_sdk::signer::Signer;
+
+const STAKE_LAMPORTS: u64 = 100_000_000_000;
+
+fn start_with_bond(name: &str, keys: &TestKeys) -> Option<TestValidator> {
+    let bond_data = solo_validator_bond_data(
+        &keys.vote.pubkey(),
+        &keys.stake.pubkey(),
+        &keys.issuer.pubkey(),
+        i64::MAX,
+        (500, 500, 500),
+    );
+    let validator = TestValidator::start(name, &[(keys.bond.pubkey(), bond_data)])?;
+    validator.airdrop(&keys.payer.pubkey(), 2 * STAKE_LAMPORTS);
+    validator.create_vote_account(keys);
+    validator.create_delegated_stake(keys, STAKE_LAMPORTS);
+    Some(validator)
+}
+
+#[test]
+fn test_transfer_excess_rewards_dry_run() {
+    let keys = TestKeys::new();
+    let Some(validator) = start_with_bond("transfer", &keys) else {
+        return;
+    };
+    let jito = MockJitoApi::start(&keys.vote.pubkey(), 800, 1_000_000_000, 2 * STAKE_LAMPORTS);
+    let payer_path = validator.write_keypair("payer", &keys.payer);
+    // The stake activates at the next epoch boundary, give it one full epoch of activity.
+    validator.wait_for_epoch(3);
+
+    let output = run(pye_cli()
+        .arg("transfer-excess-rewards")
+        .args(["--rpc", &validator.rpc_url])
+        .arg("--payer")
+        .arg(&payer_path)
+        .args(["--pye-account", &keys.bond.pubkey().to_string()])
+        .args(["--jito-api-url", &jito.url])
+        .arg("--dry-run"));
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Total Excess Rewards"),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_validator_manager_pays_bond() {
+    // The payment delegates the tips through the pye program, which has to be loaded.
+    if std::env::var("PYE_CORE_SO").is_err() {
+        eprintln!("PYE_CORE_SO is not set, skipping test_validator_manager_pays_bond");
+        return;
+    }
+    let keys = TestKeys::new();
+    let Some(validator) = start_with_bond("pay", &keys) else {
+        return;
+    };
+    let jito = MockJitoApi::start(&keys.vote.pubkey(), 800, 1_000_000_000, 2 * STAKE_LAMPORTS);
+    let payer_path = validator.write_keypair("payer", &keys.payer);
+    let receipts_dir = validator.dir.join("receipts");
+    validator.wait_for_epoch(3);
+    // The payment lands in the bond and is delegated on to its stake account. The vote account
+    // never votes, so the stake earns no inflation rewards meanwhile.
+    let credited_balance = || {
+        [keys.bond.pubkey(), keys.stake.pubkey()]
+            .iter()
+            .map(|pubkey| validator.client.get_balance(pubkey).unwrap())
+            .sum::<u64>()
+    };
+    let balance_before = credited_balance();
+
+    let output = run(pye_cli()
+        .arg("validator-pye-account-manager")
+        .args(["--rpc", &validator.rpc_url])
+        .arg("--payer")
+        .arg(&payer_path)
+        .args(["--vote-pubkey", &keys.vote.pubkey().to_string()])
+        .args(["--issuers", &keys.issuer.pubkey().to_string()])
+        .args(["--jito-api-url", &jito.url])
+        .arg("--bond-state-file")
+        .arg(validator.dir.join("bond-states.json"))
+        .arg("--receipts-dir")
+        .arg(&receipts_dir)
+        .arg("--once"));
+    assert!(output.status.success());
+    let receipt = find_paid_receipt(&receipts_dir).expect("no paid receipt was written");
+    let paid = receipt["excess_rewards"]["total"].as_i64().unwrap();
+    // The mock Jito API reports an 8% MEV commission against the bond's 5%.
+    assert!(paid > 0, "receipt: {}", receipt);
+    assert_eq!(credited_balance() - balance_before, paid as u64);
+}
+
+#[test]
+fn test_validator_manager_discovers_bond() {
+    let keys = TestKeys::new();
+    let Some(validator) = start_with_bond("manager", &keys) else {
+        return;
+    };
+    let jito = MockJitoApi::start(&keys.vote.pubkey(), 800, 1_000_000_000, 2 * STAKE_LAMPORTS);
+    let payer_path = validator.write_keypair("payer", &keys.payer);
+
+    let (mut child, lines) = spawn_logging(
+        pye_cli()
+            .arg("validator-pye-account-manager")
+            .args(["--rpc", &validator.rpc_url])
+            .arg("--payer")
+            .arg(&payer_path)
+            .args(["--vote-pubkey", &keys.vote.pubkey().to_string()])
+            .args(["--issuers", &keys.issuer.pubkey().to_string()])
+            .args(["--jito-api-url", &jito.url])
+            .args(["--cycle-secs", "1"])
+            .arg("--dry-run"),
+    );
+    let discovered = wait_for_line(&lines, "Monitoring 1 pye_accounts", Duration::from_secs(60));
+    let _ = child.kill();
+    let _ = child.wait();
+    assert!(discovered, "manager did not discover the test bond");
+}